@@ -0,0 +1,41 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+/// Records build information shown by `cloudsurf version --verbose`.
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=CLOUDSURF_GIT_HASH={}", git_hash);
+
+    let target = env::var("TARGET").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=CLOUDSURF_TARGET={}", target);
+
+    let rustls_version = fs::read_to_string("Cargo.lock")
+        .ok()
+        .and_then(|lock| locked_version(&lock, "rustls"))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!(
+        "cargo:rustc-env=CLOUDSURF_RUSTLS_VERSION={}",
+        rustls_version
+    );
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+}
+
+/// Returns the version of `package` in the contents of a `Cargo.lock` file.
+fn locked_version(lock: &str, package: &str) -> Option<String> {
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines().skip_while(|line| *line != name);
+    lines.next()?;
+    let version = lines.next()?.strip_prefix("version = \"")?;
+
+    Some(version.trim_end_matches('"').to_string())
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::result::ScanResult;
+
+/// The prefix length IPv6 addresses are aggregated at, that of Cloudflare's published prefixes.
+const IPV6_BLOCK_PREFIX: u8 = 32;
+
+/// The number of heatmap cells per line.
+const HEATMAP_WIDTH: usize = 64;
+
+/// Statistics of the probes in one aggregated block of addresses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockStats {
+    /// The first address of the block.
+    pub network: IpAddr,
+    /// The prefix length of the block.
+    pub prefix: u8,
+    /// The number of addresses probed in the block.
+    pub probed: usize,
+    /// The number of probed addresses that were reachable.
+    pub reachable: usize,
+    /// The median latency of the reachable addresses, in milliseconds.
+    pub median_latency_ms: Option<u128>,
+}
+
+impl BlockStats {
+    /// The share of probed addresses that were reachable, in percent.
+    pub fn success_rate(&self) -> f64 {
+        if self.probed == 0 {
+            return 0.0;
+        }
+        self.reachable as f64 / self.probed as f64 * 100.0
+    }
+}
+
+/// Aggregates the probes of `targets` into blocks of `v4_prefix` bits, in address order.
+///
+/// `results` are the reachable targets. IPv6 addresses are aggregated per /32.
+pub fn aggregate(targets: &[IpAddr], results: &[ScanResult], v4_prefix: u8) -> Vec<BlockStats> {
+    let mut blocks: BTreeMap<IpAddr, (usize, Vec<u128>)> = BTreeMap::new();
+    for &ip in targets {
+        blocks.entry(block_of(ip, v4_prefix)).or_default().0 += 1;
+    }
+    for result in results {
+        blocks
+            .entry(block_of(result.ip, v4_prefix))
+            .or_default()
+            .1
+            .push(result.latency_ms);
+    }
+
+    blocks
+        .into_iter()
+        .map(|(network, (probed, mut latencies))| {
+            latencies.sort_unstable();
+            BlockStats {
+                network,
+                prefix: if network.is_ipv4() {
+                    v4_prefix
+                } else {
+                    IPV6_BLOCK_PREFIX
+                },
+                probed,
+                reachable: latencies.len(),
+                median_latency_ms: latencies.get(latencies.len() / 2).copied(),
+            }
+        })
+        .collect()
+}
+
+/// Returns the first address of the block containing `ip`.
+fn block_of(ip: IpAddr, v4_prefix: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(v4_prefix)).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX << (128 - u32::from(IPV6_BLOCK_PREFIX));
+            IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+        }
+    }
+}
+
+/// Renders `blocks` as a heatmap, one cell per block and one line per /8 (or IPv6 /16).
+///
+/// The shade of a cell grows with the success rate of its block, and a dot marks a block where
+/// nothing was reachable.
+pub fn heatmap(blocks: &[BlockStats]) -> String {
+    // Rows are keyed by family, then first octet or segment, so that they sort numerically.
+    let mut rows: BTreeMap<(bool, u16), Vec<char>> = BTreeMap::new();
+    for block in blocks {
+        let row = match block.network {
+            IpAddr::V4(ip) => (false, u16::from(ip.octets()[0])),
+            IpAddr::V6(ip) => (true, ip.segments()[0]),
+        };
+        rows.entry(row).or_default().push(shade(block));
+    }
+
+    let labels: Vec<String> = rows
+        .keys()
+        .map(|&(is_ipv6, first)| {
+            if is_ipv6 {
+                format!("{:x}:", first)
+            } else {
+                format!("{}.", first)
+            }
+        })
+        .collect();
+    let width = labels.iter().map(String::len).max().unwrap_or(0);
+    let mut output = String::new();
+    for (label, cells) in labels.iter().zip(rows.values()) {
+        for (index, line) in cells.chunks(HEATMAP_WIDTH).enumerate() {
+            let label = if index == 0 { label.as_str() } else { "" };
+            let line: String = line.iter().collect();
+            let _ = writeln!(output, "{:>width$} {}", label, line, width = width);
+        }
+    }
+    output.push_str("· none  ░ <25%  ▒ <50%  ▓ <75%  █ ≥75%\n");
+
+    output
+}
+
+/// The heatmap cell of `block`.
+fn shade(block: &BlockStats) -> char {
+    if block.reachable == 0 {
+        return '·';
+    }
+    match block.success_rate() {
+        rate if rate < 25.0 => '░',
+        rate if rate < 50.0 => '▒',
+        rate if rate < 75.0 => '▓',
+        _ => '█',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate() {
+        let targets: Vec<IpAddr> = [
+            "104.16.0.1",
+            "104.16.200.1",
+            "104.17.0.1",
+            "172.64.0.1",
+            "2606:4700::1",
+        ]
+        .iter()
+        .map(|ip| ip.parse().unwrap())
+        .collect();
+        let results = vec![
+            ScanResult::new(targets[0], 50),
+            ScanResult::new(targets[1], 70),
+            ScanResult::new(targets[4], 90),
+        ];
+
+        let blocks = aggregate(&targets, &results, 16);
+
+        let networks: Vec<_> = blocks
+            .iter()
+            .map(|block| format!("{}/{}", block.network, block.prefix))
+            .collect();
+        assert_eq!(
+            networks,
+            [
+                "104.16.0.0/16",
+                "104.17.0.0/16",
+                "172.64.0.0/16",
+                "2606:4700::/32"
+            ]
+        );
+        assert_eq!(blocks[0].probed, 2);
+        assert_eq!(blocks[0].reachable, 2);
+        assert_eq!(blocks[0].median_latency_ms, Some(70));
+        assert_eq!(blocks[1].success_rate(), 0.0);
+        assert_eq!(blocks[1].median_latency_ms, None);
+        assert_eq!(blocks[3].success_rate(), 100.0);
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let block = |network: &str, probed, reachable| BlockStats {
+            network: network.parse().unwrap(),
+            prefix: 16,
+            probed,
+            reachable,
+            median_latency_ms: None,
+        };
+        let blocks = [
+            block("104.16.0.0", 10, 10),
+            block("104.17.0.0", 10, 0),
+            block("104.18.0.0", 10, 3),
+            block("172.64.0.0", 10, 6),
+            block("8.6.0.0", 10, 1),
+        ];
+
+        let map = heatmap(&blocks);
+        let lines: Vec<_> = map.lines().collect();
+
+        assert_eq!(lines[0], "  8. ░");
+        assert_eq!(lines[1], "104. █·▒");
+        assert_eq!(lines[2], "172. ▓");
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+
+use crate::history::subnet_of;
+use crate::report::ScanReport;
+
+/// Returns a copy of `report` fit for public datasets, which doesn't reveal the exact IPs found.
+///
+/// Every address is truncated to its subnet, the /24 of IPv4 addresses or the /48 of IPv6 ones,
+/// see `subnet_of`, and ray IDs, which identify single requests, are dropped. Only the best
+/// result of each subnet is kept, so that no address is listed twice, while the summary still
+/// covers all of them.
+pub fn anonymize(report: &ScanReport) -> ScanReport {
+    let mut report = report.clone();
+    let mut subnets = HashSet::new();
+    report.results.retain_mut(|result| {
+        result.ip = subnet_of(result.ip);
+        result.ray_id = None;
+        subnets.insert(result.ip)
+    });
+    if let Some(baseline) = &mut report.summary.baseline {
+        baseline.ip = subnet_of(baseline.ip);
+    }
+    if let Some(matrix) = &mut report.matrix {
+        for row in &mut matrix.rows {
+            row.ip = subnet_of(row.ip);
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::{verify_ips, write_ips_to_file, OutputFormat, TextOptions};
+    use crate::result::ScanResult;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_anonymize() {
+        let results = vec![
+            ScanResult {
+                ray_id: Some("8a1b2c3d4e5f6789-FRA".to_string()),
+                ..ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 40)
+            },
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 200), 50),
+            ScanResult::new(Ipv4Addr::new(172, 64, 3, 9), 60),
+        ];
+        let report = ScanReport::new(None, results, 10);
+
+        let anonymized = anonymize(&report);
+
+        assert_eq!(
+            anonymized
+                .results
+                .iter()
+                .map(|result| result.ip)
+                .collect::<Vec<_>>(),
+            [Ipv4Addr::new(104, 16, 0, 0), Ipv4Addr::new(172, 64, 3, 0)].map(IpAddr::V4)
+        );
+        assert_eq!(anonymized.results[0].latency_ms, 40);
+        assert!(anonymized.results[0].ray_id.is_none());
+        assert_eq!(anonymized.summary, report.summary);
+    }
+
+    #[test]
+    fn test_anonymized_file_verifies() {
+        let path = std::env::temp_dir().join("cloudsurf_anonymize_test.txt");
+        let path = path.to_str().unwrap();
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 40),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 200), 50),
+        ];
+        let anonymized = anonymize(&ScanReport::new(None, results, 10));
+        let options = TextOptions::default();
+
+        write_ips_to_file(&anonymized.results, path, &options).unwrap();
+        let verified = verify_ips(
+            path,
+            OutputFormat::Text,
+            &options.listed_ips(&anonymized.results),
+        );
+        std::fs::remove_file(path).unwrap();
+
+        assert!(verified.is_ok());
+    }
+}
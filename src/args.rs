@@ -1,7 +1,43 @@
-use clap::Parser;
+use clap::builder::RangedU64ValueParser;
+use clap::parser::ValueSource;
+use clap::{
+    ArgAction, ArgGroup, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
+use ipnetwork::IpNetwork;
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::time::Duration;
 
+use crate::config;
+use crate::file::OutputFormat;
+use crate::i18n::Lang;
+use crate::import::ImportFormat;
+use crate::logger::{LogFormat, LogRotation, LogTarget};
+use crate::merge::MergeStrategy;
+use crate::network::{FragmentHello, ProbeKind, Provider, SkipPrefix};
+use crate::profile::{self, Profile, ProfileName};
+use crate::publish::PublishTarget;
+use crate::result::SortKey;
+use crate::sampling::{IpVersion, SampleSize, ScanOrder, Weighting};
+use crate::sink::OutputSink;
+
+/// An always-on hostname proxied by Cloudflare, verified against when no domain is given.
+pub const DEFAULT_DOMAIN: &str = "cloudflare.com";
 const DEFAULT_COUNT: usize = 100;
 const DEFAULT_MAX_VALID_IPS: usize = 5;
+const DEFAULT_SPEEDTEST_BYTES: &str = "10MB";
+const DEFAULT_SPEEDTEST_MAX_TIME: u64 = 10;
+const DEFAULT_SPEEDTEST_CONCURRENCY: usize = 1;
+const DEFAULT_H2_CHECK_DURATION: u64 = 10;
+const DEFAULT_REQUEST_BYTES: &str = "1KB";
+const DEFAULT_EXPLORATION: f64 = 0.2;
+const DEFAULT_V6_HOSTS_PER_PREFIX: usize = 20;
+
+/// The group of options shaping TLS handshakes, which only `--probe tls` accepts.
+const TLS_OPTIONS: &str = "tls_options";
+
+/// The group of options the results are exported to, which `--anonymize` applies to.
+const EXPORTS: &str = "exports";
 
 /// Defines command-line arguments for the application.
 ///
@@ -10,23 +46,280 @@ const DEFAULT_MAX_VALID_IPS: usize = 5;
 #[derive(Parser, Debug)]
 #[clap(
     version = "1.0",
-    about = "Checks the validity of IP addresses using TLS connections."
+    about = "Checks the validity of IP addresses using TLS connections.",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    group(ArgGroup::new(TLS_OPTIONS).multiple(true).args(["sni_file", "sni_fallback", "race_ports", "warmup"])),
+    group(ArgGroup::new(EXPORTS).multiple(true).args(["file_path", "output"]))
 )]
 pub struct Args {
+    /// An optional subcommand. When omitted, the application scans Cloudflare IP addresses.
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    /// A bundle of defaults for the sample size, timeouts, concurrency and passes.
+    ///
+    /// Either a built-in preset or the name of a profile saved with `cloudsurf profile save`.
+    /// Options given on the command line take precedence over the profile, see `Profile`.
+    #[clap(
+        long,
+        value_parser = parse_profile_name,
+        help = "Preset for count, timeout, concurrency and passes: fast, thorough, stealth or the name of a saved profile."
+    )]
+    pub profile: Option<ProfileName>,
+
     /// Number of IP addresses to check.
     ///
-    /// Specifies how many IP addresses the application should attempt to connect to.
-    #[clap(short, long, default_value_t = DEFAULT_COUNT)]
-    pub count: usize,
+    /// Specifies how many IP addresses the application should attempt to connect to. A
+    /// percentage such as `0.5%` is a share of the IPv4 addresses left after `--skip-prefixes`,
+    /// so that the sample scales with the size of the ranges.
+    #[clap(
+        short,
+        long,
+        default_value_t = SampleSize::Count(DEFAULT_COUNT),
+        value_parser = parse_sample_size,
+        help = "Number of IP addresses to check, or a share of the address space such as 0.5%."
+    )]
+    pub count: SampleSize,
+
+    /// A hard cap on the connections made by a run, for metered links.
+    ///
+    /// Every TCP connection counts: warm-up handshakes, each port tried, and the connections of
+    /// the HTTP check, the speed test and the HTTP/2 check. Once the cap is reached, the remaining
+    /// probes are skipped and count as unreachable.
+    #[clap(
+        long,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Maximum number of connection attempts in a run."
+    )]
+    pub max_probes: Option<usize>,
+
+    /// The maximum number of IP addresses probed at the same time.
+    ///
+    /// All selected IPs are probed at once by default. The concurrency is lowered to fit the
+    /// open file limit unless `--no-auto-tune` is given.
+    #[clap(
+        long,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Maximum number of IPs probed at the same time (all at once by default)."
+    )]
+    pub concurrency: Option<usize>,
+
+    /// Keeps the requested concurrency even when it exceeds the open file limit.
+    #[clap(
+        long,
+        help = "Don't lower the concurrency to fit the open file limit, only warn."
+    )]
+    pub no_auto_tune: bool,
+
+    /// Lowers the concurrency while many probes time out or are reset, and raises it back when
+    /// they subside.
+    ///
+    /// Such spikes usually come from a full NAT or conntrack table, or from ISP throttling,
+    /// rather than from the probed IPs. `--concurrency` remains the upper bound.
+    #[clap(
+        long,
+        help = "Lower the concurrency while timeouts and resets spike, and raise it back when they subside."
+    )]
+    pub adaptive_concurrency: bool,
+
+    /// Probes the IPs that timed out or were reset once more, after all others.
+    ///
+    /// The link is less saturated at that point, so some of them complete their handshake. Late
+    /// successes are ranked with the other results.
+    #[clap(
+        long,
+        help = "Probe IPs that timed out or were reset once more at the end of the scan."
+    )]
+    pub retry_timeouts: bool,
+
+    /// Probes the sampled IPs this many times and combines the measurements of each IP.
+    ///
+    /// Repeated passes smooth out transient congestion: latencies are combined according to
+    /// `--pass-strategy`, and IPs reachable in more passes rank first.
+    #[clap(
+        long,
+        default_value_t = 1,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with = "spill_results",
+        help = "Number of times the sampled IPs are probed, combining the measurements."
+    )]
+    pub passes: usize,
+
+    /// The pause between two probe passes, e.g. `30s` or `5m`.
+    #[clap(
+        long,
+        value_parser = parse_duration,
+        help = "Pause between two probe passes, e.g. 30s or 5m."
+    )]
+    pub pass_delay: Option<Duration>,
+
+    /// How the measurements of an IP over several passes are combined.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = MergeStrategy::Average,
+        help = "Average the latencies of an IP over all passes or keep the best one."
+    )]
+    pub pass_strategy: MergeStrategy,
+
+    /// The CDNs whose published IP ranges are scanned.
+    ///
+    /// The ranges of all providers are sampled and probed together, so that their IPs can be
+    /// compared side by side. When more than Cloudflare is scanned, every result records the
+    /// provider it belongs to.
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "cloudflare",
+        help = "Comma-separated CDNs whose IP ranges are scanned together: cloudflare, fastly."
+    )]
+    pub provider: Vec<Provider>,
+
+    /// The IP address families to scan.
+    ///
+    /// IPv4 addresses are selected from Cloudflare's ranges according to `--count` and
+    /// `--order`, while IPv6 prefixes are too large to be walked and are sampled with
+    /// `--v6-hosts-per-prefix` instead. With `both`, the two families are interleaved.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = IpVersion::V4,
+        help = "IP address families to scan: 4, 6 or both."
+    )]
+    pub ip_version: IpVersion,
+
+    /// The number of random addresses probed in each of Cloudflare's IPv6 prefixes.
+    #[clap(
+        long,
+        default_value_t = DEFAULT_V6_HOSTS_PER_PREFIX,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Number of random IPv6 addresses to probe per prefix."
+    )]
+    pub v6_hosts_per_prefix: usize,
+
+    /// The factor IPv6 latencies are multiplied by when ranking results of both families.
+    ///
+    /// Below 1 favors IPv6 addresses, above 1 favors IPv4 addresses. Reported latencies are not
+    /// affected.
+    #[clap(
+        long,
+        default_value_t = 1.0,
+        value_parser = parse_bias,
+        help = "Weight of IPv6 latencies in the ranking, e.g. 0.8 to favor IPv6."
+    )]
+    pub ipv6_bias: f64,
+
+    /// How the IP addresses to check are selected.
+    ///
+    /// `random` samples addresses across all ranges, `sequential` walks them in order and
+    /// `interleaved` walks the /24 subnets round-robin. The latter two are deterministic, which
+    /// matters for full scans and for resuming a scan.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = ScanOrder::Random,
+        help = "Order in which IP addresses are selected for checking."
+    )]
+    pub order: ScanOrder,
+
+    /// How a random sample is distributed across Cloudflare's published ranges.
+    ///
+    /// With `proportional`, every address is equally likely to be picked, so the large /13 ranges
+    /// get most of the probes. With `uniform-per-subnet`, every range gets the same share, so
+    /// small /22 ranges are explored as much as large ones.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = Weighting::Proportional,
+        help = "How random samples are distributed across Cloudflare's ranges."
+    )]
+    pub weighting: Weighting,
+
+    /// A Rhai script deciding which results are kept.
+    ///
+    /// The script is evaluated for each result with `ip`, `port`, `latency`, `tcp_ms`, `tls_ms`,
+    /// `speed` and `colo` in scope, and returns `true` to keep the result.
+    #[clap(
+        long,
+        help = "Rhai script evaluated for each result, returning true to keep it."
+    )]
+    pub filter_script: Option<String>,
+
+    /// File in which the outcome of each scan is recorded to guide the following ones.
+    ///
+    /// The file is created if it does not exist. Sampling then favors the /24 subnets that
+    /// produced valid IPs in past scans, so that routine rescans converge faster. Without a
+    /// path, the history kept in the state directory is used, see `cloudsurf paths`.
+    #[clap(
+        long,
+        num_args = 0..=1,
+        help = "File recording past scans, used to favor subnets that worked before. Defaults to the state directory when given without a path."
+    )]
+    pub history: Option<Option<String>>,
+
+    /// The share of the sample drawn from subnets without a good history.
+    ///
+    /// Keeps new ranges explored when `--history` is set; 0 only probes subnets that worked
+    /// before, 1 ignores the history when sampling.
+    #[clap(
+        long,
+        default_value_t = DEFAULT_EXPLORATION,
+        value_parser = parse_fraction,
+        requires = "history",
+        help = "Share of the sample used to explore subnets without a good history, between 0 and 1."
+    )]
+    pub exploration: f64,
+
+    /// The key the results are ordered by.
+    ///
+    /// With `reliability`, IPs valid in the largest share of the scans recorded in the history
+    /// come first, so that consistently working IPs are preferred over ones that were fast once.
+    /// Requires `--history`.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = SortKey::Latency,
+        help = "Order the results by latency, or by their success rate in the history."
+    )]
+    pub sort_by: SortKey,
+
+    /// IP address prefixes to skip, comma-separated or given several times.
+    ///
+    /// Provides the capability to exclude certain IP prefixes from being checked. Each entry is
+    /// the start of an address, such as `104.16` or `2400:cb00`, or a CIDR block such as
+    /// `104.16.0.0/16`, and is validated so that a typo does not silently skip nothing.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_skip_prefix,
+        help = "List of comma-separated IP address prefixes or CIDR blocks to skip, can be repeated. Example: --skip-prefixes \"192.168,10.0,172.64.0.0/13\""
+    )]
+    pub skip_prefixes: Vec<SkipPrefix>,
+
+    /// URLs of shared skip lists, whose prefixes are skipped along with `--skip-prefixes`.
+    ///
+    /// Lets communities maintain lists of prefixes known to be dead on particular ISPs. Each
+    /// list holds one prefix or CIDR block per line, with `#` comments, and is cached for an hour.
+    #[clap(
+        long,
+        value_name = "URL",
+        help = "URL of a list of prefixes or CIDR blocks to skip, one per line, fetched at startup and cached. Can be repeated."
+    )]
+    pub skip_list_url: Vec<String>,
 
-    /// Comma-separated list of IP address prefixes to skip.
+    /// URLs of shared lists of candidate IPs, probed ahead of the random sample.
     ///
-    /// Provides the capability to exclude certain IP prefixes from being checked.
+    /// Lets communities share IPs known to work well, on top of `--count` sampled ones. Each list
+    /// holds lines starting with an IP address, and is cached for an hour. Results from these
+    /// lists are marked as seeded in the output.
     #[clap(
         long,
-        help = "List of comma-separated IP address prefixes to skip. Example: --skip-prefixes \"192.168,10.0,172\""
+        value_name = "URL",
+        help = "URL of a list of candidate IPs, one per line, probed ahead of the random sample and marked in the results. Can be repeated."
     )]
-    pub skip_prefixes: Option<String>,
+    pub seed_list_url: Vec<String>,
 
     /// File path to read or write IP addresses.
     ///
@@ -39,16 +332,60 @@ pub struct Args {
     )]
     pub file_path: Option<String>,
 
-    /// Logging level to control the verbosity of the application's output.
+    /// A file previously written in the text format to refresh.
     ///
-    /// Determines the amount of log information the application will output
+    /// Instead of sampling Cloudflare's ranges, exactly the IP addresses listed in the file are
+    /// probed again, and the file is rewritten with those that are still valid, keeping its
+    /// operators and domain sections.
     #[clap(
         long,
-        env = "RUST_LOG",
-        default_value = "info",
-        help = "Sets the logging level for the application's output."
+        conflicts_with = "file_path",
+        help = "Re-probe the IPs of a previously written text output file and rewrite it."
     )]
-    pub log_level: String,
+    pub refresh: Option<String>,
+
+    /// The age after which the output file is refreshed with a new scan.
+    ///
+    /// cloudsurf then keeps running and owns `--file-path`: whenever the file is deleted,
+    /// emptied, or older than this age, a scan is run and the file is rewritten, so that clients
+    /// always read reasonably fresh IPs.
+    #[clap(
+        long,
+        value_parser = parse_duration,
+        requires = "file_path",
+        conflicts_with = "refresh",
+        help = "Keep running and rescan whenever the output file is missing or older than this, e.g. 30m or 6h."
+    )]
+    pub max_age: Option<Duration>,
+
+    /// Options controlling the application's log output.
+    #[clap(flatten)]
+    pub log: LogArgs,
+
+    /// The language of the tables, summaries and errors printed.
+    ///
+    /// Detected from the `LC_ALL`, `LC_MESSAGES` and `LANG` environment variables when not given,
+    /// falling back to English. Logs and written files are always in English.
+    #[clap(
+        long,
+        value_enum,
+        global = true,
+        help = "Language of the printed output, en or fa. Detected from the locale by default."
+    )]
+    pub lang: Option<Lang>,
+
+    /// Verifies certificates against the operating system's trust store instead of the bundled
+    /// Mozilla roots.
+    ///
+    /// Required behind TLS-inspecting proxies, whose enterprise CA is only installed in the system
+    /// store. It applies to every handshake, including those of subcommands. Requires the
+    /// `native-certs` feature.
+    #[clap(
+        long,
+        global = true,
+        help = "Verify certificates against the system's trust store, e.g. for enterprise CAs, instead of the bundled roots."
+    )]
+    pub native_certs: bool,
 
     /// The domain name used for TLS connection verification.
     ///
@@ -56,85 +393,2260 @@ pub struct Args {
     /// to verify the identity of the remote server. It should match the domain expected
     /// in the server's SSL certificate. For example, if you're checking IPs that should
     /// have certificates for `example.com`, you would use `example.com` as the domain.
+    ///
+    /// It only needs to be set to validate the certificate chain of your own domain: by
+    /// default, `DEFAULT_DOMAIN` is used. When it is missing and the input is a terminal, it is
+    /// asked for, with that default.
     #[clap(
         long,
-        help = "The domain name to use for verifying TLS connections against the provided IP addresses."
+        help = "The domain name to use for verifying TLS connections against the provided IP addresses. Defaults to cloudflare.com."
     )]
-    pub domain: String,
+    pub domain: Option<String>,
 
-    /// The port number to use for establishing TCP connections.
+    /// Other domains the valid IPs are probed through, reported as a matrix of latencies.
+    ///
+    /// For users hosting several Cloudflare-proxied services, to find IPs that work for all of
+    /// them. The matrix is printed as a table and written to JSON and YAML reports.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_name = "DOMAINS",
+        help = "Comma-separated domains to also probe the valid IPs through, printing a matrix of latencies per domain."
+    )]
+    pub matrix: Vec<String>,
+
+    /// A file of server names presented in turn by successive probes, instead of the domain.
+    ///
+    /// For studying SNI-based filtering: each result records the server name it was reached
+    /// with. Handshakes still verify the certificate for the presented name, so only names served
+    /// by Cloudflare can succeed.
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "File listing server names, one per line, that successive probes present in turn instead of the domain."
+    )]
+    pub sni_file: Option<String>,
+
+    /// A file of server names an IP is retried with when its handshake looks blocked.
+    ///
+    /// When the TCP connection is established but the handshake is reset or times out, as SNI
+    /// filtering does, the names are tried in order and the first that succeeds is recorded as
+    /// the one to use with that IP.
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "File listing server names, one per line, to retry IPs with when their handshake looks blocked by SNI filtering."
+    )]
+    pub sni_fallback: Option<String>,
+
+    /// The check an IP must pass to be reachable.
+    ///
+    /// With `doh`, each IP must answer a DNS-over-HTTPS query for the domain, presenting
+    /// `cloudflare-dns.com`, to find reachable alternatives to 1.1.1.1. The latency then covers
+    /// the query as well as the handshake. The options shaping TLS handshakes, grouped as
+    /// `TLS_OPTIONS`, are rejected with `doh`, see `check_probe_options`.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = ProbeKind::Tls,
+        help = "Check run against each IP: a TLS handshake, or a DNS-over-HTTPS query through it."
+    )]
+    pub probe: ProbeKind,
+
+    /// Splits the ClientHello into TLS records of `SIZE` bytes, sent `DELAY` milliseconds apart.
+    ///
+    /// For testing whether fragmentation gets past SNI filtering: the IPs are probed once more
+    /// without fragmentation, and each result records its unfragmented latency, or that it was
+    /// only reachable fragmented.
+    #[clap(
+        long,
+        value_name = "SIZE,DELAY",
+        value_parser = parse_fragment_hello,
+        help = "Split the ClientHello into records of SIZE bytes sent DELAY ms apart, e.g. 5,10, and compare with unfragmented probes."
+    )]
+    pub fragment_hello: Option<FragmentHello>,
+
+    /// Aborts the scan instead of warning when `--domain` does not look proxied by Cloudflare,
+    /// or when `--count` or `--max-valid-ips` can't be met.
+    ///
+    /// Before scanning, the A records of the domain are compared with Cloudflare's ranges, since
+    /// a hostname that is not proxied cannot be served by any of the scanned IPs. Without this
+    /// flag, a `--count` larger than the addresses left after skipping prefixes, and a
+    /// `--max-valid-ips` larger than `--count`, are lowered to what can be met.
+    #[clap(
+        long,
+        help = "Abort instead of warning when --domain doesn't resolve to Cloudflare's ranges, or --count or --max-valid-ips can't be met."
+    )]
+    pub strict: bool,
+
+    /// Skips measuring the latency of the domain through its DNS-resolved address.
+    ///
+    /// By default that baseline is measured before scanning and every result is compared to it.
+    #[clap(
+        long,
+        help = "Don't measure the domain's latency through its DNS-resolved address before scanning."
+    )]
+    pub no_baseline: bool,
+
+    /// The port numbers to use for establishing TCP connections.
     ///
-    /// Specifies the port number on which to attempt TCP connections before initiating
+    /// Specifies the port numbers on which to attempt TCP connections before initiating
     /// the TLS handshake. This is typically set to 443 for HTTPS connections but can
-    /// be set to any port number required by your specific use case.
+    /// be set to any port numbers required by your specific use case. When several ports
+    /// are given, they are tried in order for each IP and the first one that succeeds is kept.
+    #[clap(
+        long = "port",
+        value_delimiter = ',',
+        default_value = "443",
+        help = "Comma-separated port numbers to use for TCP connections, tried in order. Default is 443, the standard port for HTTPS."
+    )]
+    pub ports: Vec<u16>,
+
+    /// Whether to connect to all ports of an IP at once and keep the first success.
+    ///
+    /// Racing the ports reduces the total scan time compared to trying them one after the other,
+    /// at the cost of more simultaneous connections.
     #[clap(
         long,
-        default_value_t = 443,
-        help = "The port number to use for TCP connections. Default is 443, the standard port for HTTPS."
+        help = "Connect to all ports of an IP concurrently and keep the first success."
     )]
-    pub port: u16,
+    pub race_ports: bool,
+
+    /// Whether to disable Nagle's algorithm on probe sockets, as most proxy clients do.
+    #[clap(long, help = "Disable Nagle's algorithm on probe sockets.")]
+    pub tcp_nodelay: bool,
+
+    /// The idle time before TCP keep-alive probes are sent on probe sockets, e.g. `30s`.
+    #[clap(
+        long,
+        value_parser = parse_duration,
+        help = "Enable TCP keep-alive on probe sockets after this idle time, e.g. 30s."
+    )]
+    pub tcp_keepalive: Option<Duration>,
+
+    /// The size of the send buffer of probe sockets, e.g. `64KB`.
+    #[clap(
+        long,
+        value_parser = parse_buffer_size,
+        help = "Send buffer size of probe sockets, e.g. 64KB."
+    )]
+    pub tcp_send_buffer: Option<u32>,
+
+    /// The size of the receive buffer of probe sockets, e.g. `64KB`.
+    #[clap(
+        long,
+        value_parser = parse_buffer_size,
+        help = "Receive buffer size of probe sockets, e.g. 64KB."
+    )]
+    pub tcp_recv_buffer: Option<u32>,
+
+    /// The IP time-to-live of probe packets, or the hop limit for IPv6.
+    ///
+    /// Probes with a TTL lower than the path length to an edge never complete, which gives a
+    /// rough measure of the number of hops, and shows whether low-TTL tricks alter reachability.
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..=255),
+        help = "IP time-to-live (IPv6 hop limit) of probe packets, from 1 to 255."
+    )]
+    pub ttl: Option<u32>,
 
-    /// The maximum number of valid IPs to return.
+    /// The maximum number of valid IPs to return, or 0 to return all of them.
+    ///
+    /// With 0, every IP that passed the checks is returned in ranking order, e.g. to export a
+    /// full dataset.
     #[clap(
         long,
         default_value_t = DEFAULT_MAX_VALID_IPS,
-        help = "Maximum number of valid IPs to return."
+        help = "Maximum number of valid IPs to return, 0 returns every valid IP."
     )]
     pub max_valid_ips: usize,
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use clap::error::ErrorKind;
+    /// The number of `--max-valid-ips` slots reserved for each address family.
+    ///
+    /// Only used with `--ip-version both`, so that the output contains some IPs of each family
+    /// even when one of them is consistently slower.
+    #[clap(
+        long,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Number of valid IPs reserved for each family with --ip-version both."
+    )]
+    pub family_quota: Option<usize>,
 
-    #[test]
-    fn test_default_values() {
-        let args = Args::parse_from(["testapp", "--domain", "example.com"]);
-        assert_eq!(args.count, DEFAULT_COUNT);
-        assert_eq!(args.log_level, "info");
-        assert!(args.skip_prefixes.is_none());
-        assert!(args.file_path.is_none());
-        assert_eq!(args.domain, "example.com");
-        assert_eq!(args.port, 443);
-        assert_eq!(args.max_valid_ips, DEFAULT_MAX_VALID_IPS)
-    }
+    /// The maximum number of returned IPs that may share the same /24 subnet.
+    ///
+    /// Useful for clients that round-robin over the list, since IPs from the same subnet
+    /// tend to fail together.
+    #[clap(
+        long,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Maximum number of returned IPs from the same /24 subnet."
+    )]
+    pub top_per_subnet: Option<usize>,
 
-    #[test]
-    fn test_valid_input() {
-        let args = Args::parse_from([
-            "testapp",
-            "--count",
-            "10",
-            "--skip-prefixes",
-            "192.168,10.0",
-            "-f",
-            "/path/to/file",
-            "--log-level",
-            "debug",
-            "--domain",
-            "example.com",
-            "--port",
-            "443",
-            "--max-valid-ips",
-            "20",
-        ]);
+    /// A file every reachable IP is written to as a JSON line while scanning.
+    ///
+    /// Only the best `--max-valid-ips` results are kept in memory, which bounds memory use for
+    /// exhaustive scans finding millions of IPs. Limits that need every result to be ranked first
+    /// are not supported with it.
+    #[clap(
+        long,
+        conflicts_with_all = ["top_per_subnet", "family_quota"],
+        help = "Stream all reachable IPs to this JSON lines file and keep only the best in memory."
+    )]
+    pub spill_results: Option<String>,
 
-        assert_eq!(args.count, 10);
-        assert_eq!(args.skip_prefixes, Some("192.168,10.0".to_string()));
-        assert_eq!(args.file_path, Some("/path/to/file".to_string()));
-        assert_eq!(args.log_level, "debug");
-        assert_eq!(args.domain, "example.com");
-        assert_eq!(args.port, 443);
-        assert_eq!(args.max_valid_ips, 20)
-    }
+    /// Aggregates the probes into blocks with this IPv4 prefix length, such as `/16`.
+    ///
+    /// The success rate and median latency of the best blocks are printed along with a heatmap
+    /// of all of them, to find the ranges worth focusing later scans on. IPv6 addresses are
+    /// aggregated per /32.
+    #[clap(
+        long,
+        value_parser = parse_prefix_length,
+        conflicts_with = "spill_results",
+        help = "Summarize success rate and latency per block of this size, e.g. /16."
+    )]
+    pub aggregate: Option<u8>,
 
-    #[test]
-    fn test_invalid_count() {
-        let result = Args::try_parse_from(["testapp", "--count", "not_a_number"]);
-        assert!(result.is_err());
-        let err = result.unwrap_err();
-        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+    /// A database the results of every scan are appended to, such as `sqlite://scans.db`.
+    ///
+    /// Unlike `--file-path`, which is overwritten by each scan, the database keeps all scans so
+    /// that other tools can query them.
+    #[clap(
+        long,
+        value_parser = OutputSink::from_url,
+        help = "Database to append results to, e.g. sqlite://scans.db or postgres://host/db."
+    )]
+    pub output: Option<OutputSink>,
+
+    /// A label for the machine or network this scan runs from, stored with each scan in `--output`.
+    ///
+    /// Lets several machines on different ISPs share one database.
+    #[clap(
+        long,
+        requires = "output",
+        help = "Vantage point label stored with the scans written to --output, e.g. isp-a."
+    )]
+    pub label: Option<String>,
+
+    /// A StatsD server probe counts and latencies are sent to while scanning, as `HOST:PORT`.
+    #[clap(
+        long,
+        help = "StatsD server to send metrics to during the scan, e.g. localhost:8125."
+    )]
+    pub statsd_host: Option<String>,
+
+    /// The prefix of the StatsD metric names.
+    #[clap(
+        long,
+        default_value = "cloudsurf",
+        requires = "statsd_host",
+        help = "Prefix of the StatsD metric names."
+    )]
+    pub statsd_prefix: String,
+
+    /// Whether to perform and discard one handshake per IP before the measured one.
+    ///
+    /// TLS session setup and routing caches make the first handshake systematically slower,
+    /// so discarding it gives latencies closer to what a long-running client observes.
+    #[clap(
+        long,
+        help = "Perform and discard a warm-up handshake per IP before measuring."
+    )]
+    pub warmup: bool,
+
+    /// A fixed timeout for each probe, in milliseconds.
+    ///
+    /// By default the timeout adapts to the run: once enough handshakes have completed, probes are
+    /// cut off at three times the 95th percentile of the observed latencies.
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Fixed timeout for each probe in milliseconds, instead of an adaptive one."
+    )]
+    pub timeout_ms: Option<u64>,
+
+    /// Whether to send an HTTP request through each valid IP and verify the responder.
+    ///
+    /// IPs whose response carries neither a `cf-ray` nor a `server: cloudflare` header are
+    /// dropped, which filters out transparent proxies that complete the TLS handshake.
+    #[clap(
+        long,
+        help = "Drop IPs whose HTTP response was not served by Cloudflare."
+    )]
+    pub http_check: bool,
+
+    /// The path requested by the HTTP check.
+    ///
+    /// Pointing it at a path served by the user's own origin validates the full chain through
+    /// Cloudflare, rather than only the edge.
+    #[clap(
+        long,
+        default_value = "/",
+        requires = "http_check",
+        help = "Path requested by the HTTP check, e.g. /healthz."
+    )]
+    pub check_path: String,
+
+    /// The status code the HTTP check response must have for an IP to be kept.
+    ///
+    /// Any status is accepted when unset.
+    #[clap(
+        long,
+        requires = "http_check",
+        value_parser = clap::value_parser!(u16).range(100..=599),
+        help = "Status code the HTTP check response must have, e.g. 204."
+    )]
+    pub expect_status: Option<u16>,
+
+    /// Whether to measure the download throughput through each valid IP.
+    ///
+    /// The speed test runs after the TLS checks, only for the IPs that are returned.
+    #[clap(long, help = "Measure the download speed through each valid IP.")]
+    pub speedtest: bool,
+
+    /// The size of the download used by the speed test.
+    ///
+    /// Accepts a plain number of bytes or a decimal unit, e.g. `1MB` for a quick sanity check
+    /// or `100MB` for a thorough measurement.
+    #[clap(
+        long,
+        default_value = DEFAULT_SPEEDTEST_BYTES,
+        value_parser = parse_byte_size,
+        help = "Size of the speed test download, e.g. 1MB or 100MB."
+    )]
+    pub speedtest_bytes: u64,
+
+    /// The maximum duration of a single speed test download, in seconds.
+    ///
+    /// Downloads that take longer are stopped, and the speed is computed from the data received so
+    /// far.
+    #[clap(
+        long,
+        default_value_t = DEFAULT_SPEEDTEST_MAX_TIME,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Maximum duration of each speed test download, in seconds."
+    )]
+    pub speedtest_max_time: u64,
+
+    /// The maximum number of speed test downloads running at the same time.
+    ///
+    /// Bandwidth measurements interfere with each other when run in parallel, so they are
+    /// serialized by default, independently of how many TLS checks run concurrently.
+    #[clap(
+        long,
+        default_value_t = DEFAULT_SPEEDTEST_CONCURRENCY,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Maximum number of simultaneous speed test downloads."
+    )]
+    pub speedtest_concurrency: usize,
+
+    /// The minimum download speed an IP must reach to be kept, in megabits per second.
+    ///
+    /// Accepts values like `5mbps`, `500kbps` or a plain number of Mbps. Setting this enables
+    /// the speed test; IPs whose speed test fails are dropped as well.
+    #[clap(
+        long,
+        value_parser = parse_speed,
+        help = "Drop IPs slower than this speed, e.g. 5mbps. Implies --speedtest."
+    )]
+    pub min_speed: Option<f64>,
+
+    /// Whether to check that each valid IP sustains a multiplexed HTTP/2 connection.
+    ///
+    /// Parallel requests are issued over a single connection for `--h2-check-duration` seconds,
+    /// and stream errors and GOAWAY frames are reported.
+    #[clap(
+        long,
+        help = "Check each valid IP with sustained, multiplexed HTTP/2 requests."
+    )]
+    pub h2_check: bool,
+
+    /// How long requests are issued over each HTTP/2 connection, in seconds.
+    #[clap(
+        long,
+        default_value_t = DEFAULT_H2_CHECK_DURATION,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Duration of the HTTP/2 keep-alive check per IP, in seconds."
+    )]
+    pub h2_check_duration: u64,
+
+    /// Whether to check that each valid IP relays gRPC calls, as Xray's gRPC transport makes.
+    ///
+    /// A minimal call to the `Tun` method of `--grpc-service` is made over HTTP/2, and the IP
+    /// passes when a gRPC response comes back from the origin.
+    #[clap(
+        long,
+        requires = "grpc_service",
+        help = "Check that each valid IP relays a minimal gRPC call to --grpc-service."
+    )]
+    pub grpc_check: bool,
+
+    /// The gRPC service name called by the gRPC check, the `serviceName` of the Xray
+    /// configuration.
+    #[clap(
+        long,
+        value_name = "NAME",
+        help = "gRPC service name called by --grpc-check, e.g. the serviceName of an Xray config."
+    )]
+    pub grpc_service: Option<String>,
+
+    /// Whether to measure the round trip of a small `POST` request through each valid IP.
+    ///
+    /// Unlike the handshake latency, the round trip includes the edge answering a request, which
+    /// better reflects interactive traffic such as API calls.
+    #[clap(
+        long,
+        help = "Measure the round trip of a small POST request through each valid IP."
+    )]
+    pub request_check: bool,
+
+    /// The size of the body posted by the request check.
+    #[clap(
+        long,
+        default_value = DEFAULT_REQUEST_BYTES,
+        value_parser = parse_request_size,
+        help = "Size of the request check body, at most 1MB."
+    )]
+    pub request_bytes: u64,
+
+    /// A command run through the shell before probing begins.
+    ///
+    /// The scan fails if the command fails, e.g. when a firewall rule or VPN state couldn't be
+    /// set up. Scan parameters are exported as `CLOUDSURF_*` environment variables.
+    #[clap(
+        long,
+        help = "Command to run before probing begins, e.g. to toggle a VPN off."
+    )]
+    pub on_start: Option<String>,
+
+    /// A command run through the shell once the results have been written.
+    ///
+    /// The placeholders `{file}` and `{best_ip}` are replaced by the output file path and the
+    /// fastest IP, or by empty strings if there is none, e.g. to restart a proxy service with
+    /// the new IPs. Scan parameters are exported as `CLOUDSURF_*` environment variables, along
+    /// with the number of valid IPs in `CLOUDSURF_VALID_IPS`.
+    #[clap(
+        long,
+        help = "Command to run after results are written, e.g. \"restart.sh {file} {best_ip}\"."
+    )]
+    pub on_complete: Option<String>,
+
+    /// An SSH destination the results file is uploaded to once it has been written.
+    ///
+    /// The file is copied with the system's `scp` using key authentication only, e.g. to a
+    /// router that consumes the results while the scan runs on another machine.
+    #[clap(
+        long,
+        value_parser = parse_ssh_destination,
+        help = "Upload the results file with scp, e.g. user@router:/etc/storage/ips.txt."
+    )]
+    pub push_ssh: Option<String>,
+
+    /// Whether to write the SHA-256 checksum of the results file next to it.
+    ///
+    /// The checksum is written to `<file>.sha256` in the format of `sha256sum`, so that devices
+    /// fetching the file can check it with `sha256sum -c`.
+    #[clap(
+        long,
+        help = "Write the SHA-256 checksum of the results file to <file>.sha256."
+    )]
+    pub checksum: bool,
+
+    /// A PKCS#8 Ed25519 private key the results file is signed with.
+    ///
+    /// The signature is written to `<file>.minisig` in the minisign format, so that devices
+    /// fetching the file over untrusted channels can verify it with `minisign -V`.
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Sign the results file with this Ed25519 private key, writing <file>.minisig."
+    )]
+    pub sign_key: Option<String>,
+
+    /// age recipients the results file is encrypted to, written to `<file>.age`.
+    ///
+    /// Only the encrypted copy is signed, checksummed and uploaded with `--push-ssh`, so that
+    /// lists shared through untrusted channels can't be harvested. Requires the `age` feature.
+    #[clap(
+        long,
+        value_name = "RECIPIENT",
+        value_parser = parse_age_recipient,
+        help = "Encrypt the results file to this age public key (age1...), writing <file>.age. Can be repeated."
+    )]
+    pub encrypt_to: Vec<String>,
+
+    /// Whether to show a desktop notification when the scan finishes.
+    ///
+    /// Useful for long scans running in the background. Requires the `notify` feature.
+    #[clap(
+        long,
+        help = "Show a desktop notification with the results when the scan finishes."
+    )]
+    pub notify: bool,
+
+    /// The format used when writing results to `--file-path`.
+    ///
+    /// The `text` format produces the "IP OPERATOR" list consumed by proxy clients, while
+    /// `json` and `yaml` write a versioned `ScanReport` that keeps the measured latency and
+    /// scan parameters, so that files can later be combined with `merge`.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Format of the output file."
+    )]
+    pub format: OutputFormat,
+
+    /// The maximum number of IP lines written for each operator in the text format.
+    ///
+    /// The text format lists every IP once per operator, so its size grows with both. Only the
+    /// best IPs are kept for each operator, for clients that load the whole file into memory.
+    #[clap(
+        long,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Maximum number of IPs listed for each operator in the text output file."
+    )]
+    pub per_operator_limit: Option<usize>,
+
+    /// Whether to leave out the commented header of the text output file.
+    ///
+    /// The header records when and how the file was produced, for clients that don't skip
+    /// `#` comment lines.
+    #[clap(
+        long,
+        help = "Don't write the commented scan metadata header at the top of the text output file."
+    )]
+    pub no_header: bool,
+
+    /// Whether to anonymize the results written to `--file-path` and `--output`, for sharing them
+    /// publicly.
+    ///
+    /// IP addresses are truncated to their /24 (/48 for IPv6) and only the best result of each is
+    /// kept, ray IDs are dropped and the text header records no time. The table printed to the
+    /// terminal still shows the exact IPs.
+    #[clap(
+        long,
+        requires = EXPORTS,
+        conflicts_with_all = ["refresh", "rotate"],
+        help = "Truncate IPs to their /24 and strip timestamps in --file-path and --output, for public datasets."
+    )]
+    pub anonymize: bool,
+
+    /// Waits for another run writing the same files to finish, which is the default.
+    ///
+    /// The output file, or else the history file, is locked for the duration of the scan, so
+    /// that overlapping runs such as cron jobs can't interleave their writes.
+    #[clap(
+        long,
+        overrides_with = "fail_if_locked",
+        help = "Wait for another run writing the same output file to finish (default)."
+    )]
+    pub wait: bool,
+
+    /// Fails right away instead of waiting when another run writes the same files.
+    #[clap(
+        long,
+        overrides_with = "wait",
+        help = "Fail instead of waiting when another run writes the same output file."
+    )]
+    pub fail_if_locked: bool,
+
+    /// Writes each scan to a new timestamped file next to `--file-path`, which then links to it.
+    ///
+    /// With `-f ips.txt`, results are written to `ips-20240501T120000.txt` and `ips.txt` is
+    /// replaced by a link to that file, so clients keep reading the same path while past scans
+    /// are archived.
+    #[clap(
+        long,
+        requires = "file_path",
+        help = "Write each scan to a timestamped file next to --file-path, which links to the latest."
+    )]
+    pub rotate: bool,
+
+    /// The number of timestamped files kept by `--rotate`, the oldest being deleted.
+    #[clap(
+        long,
+        default_value_t = 10,
+        requires = "rotate",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Number of timestamped result files kept with --rotate."
+    )]
+    pub keep: usize,
+}
+
+impl Args {
+    /// Parses the command line like `Parser::parse`, preceded for scans by the options of the
+    /// configuration file, then applies `--profile` to the options that were not given
+    /// explicitly.
+    pub fn parse_with_profile() -> Self {
+        let argv: Vec<OsString> = std::env::args_os().collect();
+        let matches = Self::command()
+            .try_get_matches_from(&argv)
+            .unwrap_or_else(|e| e.exit());
+        // Subcommands take no scan options, so the configuration file only applies to scans.
+        let config = match matches.subcommand_name() {
+            Some(_) => Vec::new(),
+            None => config::load().unwrap_or_else(|e| {
+                Self::command()
+                    .error(clap::error::ErrorKind::Io, format!("{:#}", e))
+                    .exit()
+            }),
+        };
+
+        // Options of the configuration file that are also on the command line are dropped.
+        let config = profile::without_explicit(&config, |name| {
+            !matches!(
+                matches.value_source(name),
+                None | Some(ValueSource::DefaultValue)
+            )
+        });
+        let argv = argv
+            .iter()
+            .take(1)
+            .cloned()
+            .chain(config.into_iter().map(OsString::from))
+            .chain(argv.iter().skip(1).cloned());
+        Self::try_parse_with_profile(argv).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Like `parse_with_profile`, but parses `args` and returns an error instead of exiting.
+    pub fn try_parse_with_profile<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let argv: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        let matches = Self::command().try_get_matches_from(&argv)?;
+        check_probe_options(&matches)?;
+        let is_explicit = |name: &str| {
+            !matches!(
+                matches.value_source(name),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+        let mut args = Self::from_arg_matches(&matches)?;
+        match args.profile.clone() {
+            Some(ProfileName::BuiltIn(profile)) => profile.apply(&mut args, is_explicit),
+            Some(ProfileName::Saved(name)) => {
+                // Parse again with the saved options inserted before those of the command line.
+                let saved = profile::load(&name).map_err(|e| {
+                    Self::command().error(clap::error::ErrorKind::InvalidValue, format!("{:#}", e))
+                })?;
+                let saved = profile::without_explicit(&saved, is_explicit);
+                let argv = argv
+                    .iter()
+                    .take(1)
+                    .cloned()
+                    .chain(saved.into_iter().map(OsString::from))
+                    .chain(argv.iter().skip(1).cloned());
+                let matches = Self::command().try_get_matches_from(argv)?;
+                check_probe_options(&matches)?;
+                args = Self::from_arg_matches(&matches)?;
+            }
+            None => {}
+        }
+
+        Ok(args)
+    }
+
+    /// Returns the file the results are written to, either `--file-path` or `--refresh`.
+    pub fn output_path(&self) -> Option<&str> {
+        self.file_path.as_deref().or(self.refresh.as_deref())
+    }
+}
+
+/// Rejects the options of the `TLS_OPTIONS` group when `--probe` selects a check without a TLS
+/// handshake of its own, naming the probe and the options given.
+///
+/// Clap's `conflicts_with` applies whenever `--probe` is present, even as `--probe tls`, so the
+/// value is checked once parsed. Options only set by their default value are not rejected.
+fn check_probe_options(matches: &ArgMatches) -> Result<(), clap::Error> {
+    let probe = matches
+        .get_one::<ProbeKind>("probe")
+        .copied()
+        .unwrap_or_default();
+    if probe == ProbeKind::Tls {
+        return Ok(());
+    }
+    let mut command = Args::command();
+    let given: Vec<String> = matches
+        .get_many::<clap::Id>(TLS_OPTIONS)
+        .into_iter()
+        .flatten()
+        .filter(|id| {
+            !matches!(
+                matches.value_source(id.as_str()),
+                None | Some(ValueSource::DefaultValue)
+            )
+        })
+        .filter_map(|id| command.get_arguments().find(|arg| arg.get_id() == id))
+        .filter_map(|arg| arg.get_long())
+        .map(|long| format!("--{}", long))
+        .collect();
+    if given.is_empty() {
+        return Ok(());
+    }
+
+    let name = probe
+        .to_possible_value()
+        .map(|value| value.get_name().to_string());
+    Err(command.error(
+        clap::error::ErrorKind::ArgumentConflict,
+        format!(
+            "{} only shape TLS handshakes and can't be used with --probe {}",
+            given.join(", "),
+            name.unwrap_or_default()
+        ),
+    ))
+}
+
+/// Parses `--profile` as a built-in preset, or else as the name of a saved profile.
+fn parse_profile_name(value: &str) -> Result<ProfileName, String> {
+    if let Ok(profile) = Profile::from_str(value, true) {
+        return Ok(ProfileName::BuiltIn(profile));
+    }
+    if value.is_empty()
+        || !value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "'{}' is not a valid profile name, use letters, digits, '-' and '_'",
+            value
+        ));
+    }
+
+    Ok(ProfileName::Saved(value.to_string()))
+}
+
+/// Parses the name a profile is saved under, which can't be that of a built-in preset.
+fn parse_saved_profile_name(value: &str) -> Result<String, String> {
+    match parse_profile_name(value)? {
+        ProfileName::Saved(name) => Ok(name),
+        ProfileName::BuiltIn(_) => Err(format!(
+            "'{}' is a built-in profile, choose another name",
+            value
+        )),
+    }
+}
+
+/// Parses an scp destination such as `user@router:/etc/storage/ips.txt`.
+fn parse_ssh_destination(value: &str) -> Result<String, String> {
+    match value.split_once(':') {
+        Some((host, path)) if !host.is_empty() && !path.is_empty() && !host.starts_with('-') => {
+            Ok(value.to_string())
+        }
+        _ => Err(format!(
+            "Invalid destination '{}', expected [user@]host:path",
+            value
+        )),
+    }
+}
+
+/// Checks that `value` looks like an age X25519 public key, which is fully parsed when the
+/// results are encrypted.
+fn parse_age_recipient(value: &str) -> Result<String, String> {
+    match value.strip_prefix("age1") {
+        Some(key) if !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric()) => {
+            Ok(value.to_string())
+        }
+        _ => Err(format!(
+            "Invalid age recipient '{}', expected a public key starting with age1",
+            value
+        )),
+    }
+}
+
+/// Parses a byte count such as `1048576`, `500KB`, `10MB` or `1GB`, using decimal units.
+fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size '{}'", value))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1_000,
+        "M" | "MB" => 1_000_000,
+        "G" | "GB" => 1_000_000_000,
+        _ => {
+            return Err(format!(
+                "Unknown unit in '{}', expected B, KB, MB or GB",
+                value
+            ))
+        }
+    };
+
+    number
+        .checked_mul(multiplier)
+        .filter(|bytes| *bytes > 0)
+        .ok_or_else(|| format!("Invalid size '{}'", value))
+}
+
+/// Parses the `SIZE,DELAY` of `--fragment-hello`, a record size in bytes and a delay in
+/// milliseconds.
+fn parse_fragment_hello(value: &str) -> Result<FragmentHello, String> {
+    let (size, delay_ms) = value
+        .split_once(',')
+        .ok_or_else(|| format!("Expected SIZE,DELAY, got '{}'", value))?;
+    let size = size
+        .trim()
+        .parse()
+        .ok()
+        .filter(|size| *size > 0)
+        .ok_or_else(|| format!("Invalid fragment size '{}'", size))?;
+    let delay_ms = delay_ms
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid fragment delay '{}'", delay_ms))?;
+
+    Ok(FragmentHello { size, delay_ms })
+}
+
+/// Parses a socket buffer size, which the operating system takes as a 32-bit number.
+fn parse_buffer_size(value: &str) -> Result<u32, String> {
+    parse_byte_size(value)?
+        .try_into()
+        .map_err(|_| format!("Buffer size '{}' is too large", value))
+}
+
+/// Parses the size of the request check body, which is held in memory and kept small.
+fn parse_request_size(value: &str) -> Result<u64, String> {
+    let bytes = parse_byte_size(value)?;
+    if bytes > 1_000_000 {
+        return Err(format!("Request size '{}' exceeds 1MB", value));
+    }
+
+    Ok(bytes)
+}
+
+/// Parses a duration such as `90s`, `30m`, `6h`, `1d` or `90`, where a bare number is in seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}'", value))?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Unknown unit in '{}', expected s, m, h or d",
+                value
+            ))
+        }
+    };
+
+    number
+        .checked_mul(multiplier)
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("Invalid duration '{}'", value))
+}
+
+/// Parses an entry of `--skip-prefixes`: the start of an IPv4 address such as `104.16.`, the start
+/// of an IPv6 address such as `2400:cb00`, or a CIDR block such as `104.16.0.0/16`.
+pub fn parse_skip_prefix(value: &str) -> Result<SkipPrefix, String> {
+    let value = value.trim();
+    let prefix = if value.contains('/') {
+        value.parse::<IpNetwork>().ok().map(SkipPrefix::Network)
+    } else {
+        (is_ipv4_start(value) || is_ipv6_start(value)).then(|| SkipPrefix::Text(value.to_string()))
+    };
+
+    prefix.ok_or_else(|| {
+        format!(
+            "Invalid prefix '{}', expected the start of an address such as 104.16 or 2400:cb00, or a CIDR block such as 104.16.0.0/16",
+            value
+        )
+    })
+}
+
+/// Whether `value` is the start of a dotted IPv4 address, such as `104`, `104.16` or `104.16.`.
+fn is_ipv4_start(value: &str) -> bool {
+    let octets: Vec<&str> = value
+        .strip_suffix('.')
+        .unwrap_or(value)
+        .split('.')
+        .collect();
+    octets.len() <= 4
+        && octets
+            .iter()
+            .all(|octet| !octet.is_empty() && octet.len() <= 3 && octet.parse::<u8>().is_ok())
+}
+
+/// Whether `value` is the start of an IPv6 address, such as `2400` or `2606:4700:`.
+fn is_ipv6_start(value: &str) -> bool {
+    !value.is_empty()
+        && value.split(':').count() <= 8
+        && value
+            .split(':')
+            .all(|group| group.len() <= 4 && group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Parses a number of IP addresses such as `500`, or a percentage such as `0.5%`.
+fn parse_sample_size(value: &str) -> Result<SampleSize, String> {
+    let value = value.trim();
+    match value.strip_suffix('%') {
+        Some(percent) => match percent.trim().parse::<f64>() {
+            Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(SampleSize::Percent(percent)),
+            _ => Err(format!(
+                "Invalid percentage '{}', expected more than 0% and at most 100%",
+                value
+            )),
+        },
+        None => value
+            .parse()
+            .map(SampleSize::Count)
+            .map_err(|_| format!("Invalid count '{}'", value)),
+    }
+}
+
+/// Parses a fraction between 0 and 1, such as `0.2`.
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!(
+            "Invalid fraction '{}', expected a number between 0 and 1",
+            value
+        )),
+    }
+}
+
+/// Parses an IPv4 prefix length such as `/16` or `16`.
+fn parse_prefix_length(value: &str) -> Result<u8, String> {
+    let trimmed = value.trim();
+    match trimmed.strip_prefix('/').unwrap_or(trimmed).parse::<u8>() {
+        Ok(prefix) if (1..=32).contains(&prefix) => Ok(prefix),
+        _ => Err(format!(
+            "Invalid prefix length '{}', expected a value like /16",
+            value
+        )),
+    }
+}
+
+/// Parses a positive ranking weight, such as `0.8`.
+fn parse_bias(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(bias) if bias.is_finite() && bias > 0.0 => Ok(bias),
+        _ => Err(format!(
+            "Invalid bias '{}', expected a positive number such as 0.8",
+            value
+        )),
+    }
+}
+
+/// Parses a speed such as `5mbps`, `500kbps`, `1gbps` or `5` into megabits per second.
+fn parse_speed(value: &str) -> Result<f64, String> {
+    let lowercase = value.trim().to_ascii_lowercase();
+    let (number, multiplier) = if let Some(number) = lowercase.strip_suffix("kbps") {
+        (number, 0.001)
+    } else if let Some(number) = lowercase.strip_suffix("mbps") {
+        (number, 1.0)
+    } else if let Some(number) = lowercase.strip_suffix("gbps") {
+        (number, 1_000.0)
+    } else {
+        (lowercase.as_str(), 1.0)
+    };
+
+    match number.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() && number >= 0.0 => Ok(number * multiplier),
+        _ => Err(format!(
+            "Invalid speed '{}', expected a value like 5mbps or 500kbps",
+            value
+        )),
+    }
+}
+
+/// Defines the command-line arguments that control logging.
+///
+/// These arguments are global, so they are also accepted after a subcommand.
+#[derive(clap::Args, Debug)]
+pub struct LogArgs {
+    /// Logging level to control the verbosity of the application's output.
+    ///
+    /// Determines the amount of log information the application will output. Besides a single
+    /// level, `RUST_LOG`-style directives are accepted to set levels per module, for example
+    /// `cloudsurf::network=debug,info`.
+    #[clap(
+        long,
+        global = true,
+        env = "RUST_LOG",
+        default_value = "info",
+        help = "Sets the logging level, or per-module directives like \"cloudsurf::network=debug,info\"."
+    )]
+    pub log_level: String,
+
+    /// Verbosity flags as an alternative to `--log-level`.
+    ///
+    /// The number of occurrences sets the level: `-v` is warn, `-vv` info, `-vvv` debug and
+    /// `-vvvv` trace. Since the default level is info, a single `-v` shows less than none. When
+    /// given, this takes precedence over `--log-level` and `RUST_LOG`. There is no long form, so
+    /// that `version --verbose` asks for the build details without changing the log level.
+    #[clap(
+        short = 'v',
+        global = true,
+        action = ArgAction::Count,
+        help = "Sets the log level by count: -v warn, -vv info, -vvv debug, -vvvv trace; overrides --log-level and RUST_LOG."
+    )]
+    pub verbose: u8,
+
+    /// File path to write logs to.
+    ///
+    /// When set, logs are written to this file instead of stderr, unless `--log-stderr` is also
+    /// given.
+    #[clap(
+        long,
+        global = true,
+        help = "Path to a file for writing logs. This argument is optional."
+    )]
+    pub log_file: Option<String>,
+
+    /// How often the log file is rotated.
+    ///
+    /// Rotated files are suffixed with the date (and hour, for hourly rotation) they were written
+    /// on.
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogRotation::Never,
+        requires = "log_file",
+        help = "How often to start a new log file."
+    )]
+    pub log_rotation: LogRotation,
+
+    /// Whether logs are also written to stderr when `--log-file` is set.
+    #[clap(
+        long,
+        global = true,
+        requires = "log_file",
+        help = "Also write logs to stderr when --log-file is set."
+    )]
+    pub log_stderr: bool,
+
+    /// The format of log records.
+    ///
+    /// The `json` format emits one object per line, suitable for ingestion by log aggregators.
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogFormat::Text,
+        help = "Format of log records, either text or json."
+    )]
+    pub log_format: LogFormat,
+
+    /// Where log records are sent, in addition to `--log-file`.
+    ///
+    /// The `syslog` and `journald` targets are only available when the corresponding
+    /// cargo feature is enabled.
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogTarget::Stderr,
+        help = "Where to send logs: stderr, syslog or journald."
+    )]
+    pub log_target: LogTarget,
+
+    /// The OTLP collector spans and scan metrics are exported to, e.g. `http://localhost:4317`.
+    ///
+    /// Only available when the `otel` cargo feature is enabled. Without the option, the
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable is used, see `logger::otlp_endpoint`.
+    #[clap(
+        long,
+        global = true,
+        help = "OpenTelemetry collector to export spans and metrics to (gRPC)."
+    )]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Subcommands that operate on previously written results instead of scanning.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Combines the report files of several runs into a single report.
+    Merge(MergeArgs),
+    /// Prints the JSON Schema of the structured report written by `--format json|yaml`.
+    Schema,
+    /// Checks the environment and prints a diagnostic report, useful for troubleshooting.
+    Selftest(SelftestArgs),
+    /// Probes several candidate IP lists under identical conditions and compares them.
+    Bench(BenchArgs),
+    /// Answers DNS queries for a name with the best IPs of a results file.
+    Dns(DnsArgs),
+    /// Relays TCP connections to the best reachable IP of a results file.
+    Forward(ForwardArgs),
+    /// Converts the results of another scanner into a report, optionally recording them.
+    Import(ImportArgs),
+    /// Uploads a results file to a paste service and prints its shareable URL.
+    Publish(PublishArgs),
+    /// Saves scan options under a name, to apply them later with `--profile <name>`.
+    Profile(ProfileArgs),
+    /// Prints where the configuration, cache and state files are stored.
+    Paths,
+    /// Asks for the domain, ports, output and schedule, and writes them to the configuration file.
+    Init(InitArgs),
+    /// Prints the version, with the commit, target, features and TLS library with `--verbose`.
+    Version(VersionArgs),
+}
+
+/// Defines the arguments of the `version` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct VersionArgs {
+    /// Whether to print the build details useful in bug reports.
+    ///
+    /// This is separate from the global `-v` flags, which only set the log level.
+    #[clap(
+        long = "verbose",
+        help = "Also print the commit, target, enabled features and TLS library."
+    )]
+    pub details: bool,
+}
+
+/// Defines the arguments of the `init` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct InitArgs {
+    /// Whether to replace an existing configuration file.
+    #[clap(long, help = "Overwrite the configuration file if it already exists.")]
+    pub force: bool,
+}
+
+/// Defines the arguments of the `profile` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ProfileArgs {
+    #[clap(subcommand)]
+    pub action: ProfileAction,
+}
+
+/// The actions of the `profile` subcommand.
+#[derive(Subcommand, Debug)]
+pub enum ProfileAction {
+    /// Saves the given scan options under a name, e.g. `profile save home-isp --count 500`.
+    Save(ProfileSaveArgs),
+    /// Lists the saved profiles.
+    List,
+}
+
+/// Defines the arguments of `profile save`.
+#[derive(clap::Args, Debug)]
+pub struct ProfileSaveArgs {
+    /// The name the options are saved under, and passed to `--profile`.
+    #[clap(
+        value_parser = parse_saved_profile_name,
+        help = "Name of the profile, such as home-isp."
+    )]
+    pub name: String,
+
+    /// The scan options to save, as they would be given on the command line.
+    #[clap(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        help = "Scan options to save, such as --count 500 --concurrency 50."
+    )]
+    pub options: Vec<String>,
+}
+
+/// Defines the arguments of the `import` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    /// The results file of another scanner.
+    #[clap(help = "The results file to import, such as CloudflareSpeedTest's result.csv.")]
+    pub file: String,
+
+    /// The format of the imported file.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = ImportFormat::Cfst,
+        help = "Format of the imported file."
+    )]
+    pub format: ImportFormat,
+
+    /// File path to write the imported results to as a report, which can then be merged with
+    /// the reports of cloudsurf's own scans.
+    #[clap(
+        short,
+        long,
+        help = "Path to the file for writing the imported report. This argument is optional."
+    )]
+    pub output: Option<String>,
+
+    /// The format of the written report.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Json,
+        help = "Format of the imported report file, either json or yaml."
+    )]
+    pub output_format: OutputFormat,
+
+    /// History file the imported results are recorded in, as if they came from a scan.
+    #[clap(
+        long,
+        num_args = 0..=1,
+        help = "File recording past scans to add the imported results to. Defaults to the state directory when given without a path."
+    )]
+    pub history: Option<Option<String>>,
+}
+
+/// Defines the arguments of the `publish` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct PublishArgs {
+    /// The results file to upload, in whichever format it was written.
+    #[clap(help = "The results file to upload, such as result.txt or report.json.")]
+    pub file: String,
+
+    /// The service the file is uploaded to.
+    #[clap(long, value_enum, help = "Service to upload the file to.")]
+    pub to: PublishTarget,
+
+    /// The GitHub token used to create gists, which needs the gist scope.
+    #[clap(
+        long,
+        env = "GITHUB_TOKEN",
+        hide_env_values = true,
+        help = "GitHub token with the gist scope, required by --to gist."
+    )]
+    pub token: Option<String>,
+
+    /// Whether the gist is listed publicly, rather than only reachable through its URL.
+    #[clap(long, help = "Create a public gist instead of a secret one.")]
+    pub public: bool,
+}
+
+/// Defines the arguments of the `forward` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ForwardArgs {
+    /// The address and TCP port to accept connections on.
+    #[clap(
+        long,
+        default_value = "127.0.0.1:8443",
+        help = "The address and TCP port to accept connections on."
+    )]
+    pub listen: SocketAddr,
+
+    /// The results file to forward to, such as a file written by `--file-path` or kept fresh
+    /// with `--max-age`. It is read again whenever it changes.
+    #[clap(
+        short = 'f',
+        long,
+        help = "File with one IP address per line (or \"IP OPERATOR\" lines), best first."
+    )]
+    pub file_path: String,
+
+    /// The port connections are relayed to on the upstream IPs.
+    #[clap(
+        long,
+        default_value_t = 443,
+        help = "The port to connect to on the upstream IPs."
+    )]
+    pub port: u16,
+
+    /// How long to wait for an upstream IP to accept a connection before trying the next one.
+    #[clap(
+        long,
+        default_value_t = 2000,
+        value_parser = RangedU64ValueParser::<u64>::new().range(1..),
+        help = "Timeout for connecting to an upstream IP, in milliseconds."
+    )]
+    pub connect_timeout_ms: u64,
+
+    /// How often the IPs of the file are health checked, in seconds.
+    #[clap(
+        long,
+        default_value_t = 10,
+        value_parser = RangedU64ValueParser::<u64>::new().range(1..),
+        help = "Interval between health checks of the upstream IPs, in seconds."
+    )]
+    pub health_interval: u64,
+
+    /// How much faster another IP must be to replace the active IP, as a fraction of the active
+    /// IP's latency. Keeps the active IP from flapping between IPs of similar latency.
+    #[clap(
+        long,
+        default_value_t = 0.2,
+        value_parser = parse_fraction,
+        help = "Latency improvement required to switch the active IP, between 0 and 1."
+    )]
+    pub switch_margin: f64,
+
+    /// How long connections to a replaced IP may continue before they are closed, in seconds.
+    #[clap(
+        long,
+        default_value_t = 60,
+        help = "Grace period for connections to a replaced IP, in seconds."
+    )]
+    pub drain_timeout: u64,
+}
+
+/// Defines the arguments of the `dns` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct DnsArgs {
+    /// The address and UDP port to listen on.
+    #[clap(
+        long,
+        default_value = "127.0.0.1:53",
+        help = "The address and UDP port to listen on."
+    )]
+    pub listen: SocketAddr,
+
+    /// The domain name answered with the best IPs; queries for other names are refused.
+    #[clap(long, help = "The domain name to answer, e.g. fast.cf.local.")]
+    pub name: String,
+
+    /// The results file to serve, such as a file written by `--file-path` or kept fresh with
+    /// `--max-age`. It is read again whenever it changes.
+    #[clap(
+        short = 'f',
+        long,
+        help = "File with one IP address per line (or \"IP OPERATOR\" lines), best first."
+    )]
+    pub file_path: String,
+
+    /// The maximum number of IPs in each answer, taken from the top of the file.
+    #[clap(
+        long,
+        default_value_t = 1,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..=32),
+        help = "The maximum number of IPs in each answer."
+    )]
+    pub answers: usize,
+
+    /// How long resolvers may cache an answer, in seconds.
+    #[clap(
+        long,
+        default_value_t = 60,
+        help = "The time to live of the answers, in seconds."
+    )]
+    pub ttl: u32,
+}
+
+/// Defines the arguments of the `bench` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Text files listing candidate IP addresses, such as files written by `--file-path`.
+    #[clap(
+        required = true,
+        num_args = 2..,
+        help = "Files with one IP address per line (or \"IP OPERATOR\" lines) to compare."
+    )]
+    pub files: Vec<String>,
+
+    /// The domain name used for TLS connection verification.
+    #[clap(
+        long,
+        help = "The domain name to use for verifying TLS connections against the listed IP addresses."
+    )]
+    pub domain: String,
+
+    /// The port number to use for establishing TCP connections.
+    #[clap(
+        long,
+        default_value_t = 443,
+        help = "The port number to use for TCP connections. Default is 443, the standard port for HTTPS."
+    )]
+    pub port: u16,
+}
+
+/// Defines the arguments of the `selftest` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct SelftestArgs {
+    /// The domain name resolved and used for the reference TLS connection.
+    #[clap(
+        long,
+        default_value = DEFAULT_DOMAIN,
+        help = "The domain name to resolve and use for the reference TLS connection."
+    )]
+    pub domain: String,
+
+    /// The port number used for the reference TLS connection.
+    #[clap(
+        long,
+        default_value_t = 443,
+        help = "The port number to use for the reference TLS connection."
+    )]
+    pub port: u16,
+
+    /// The output file path whose write permission is verified.
+    #[clap(
+        short = 'f',
+        long,
+        help = "Path to the output file to check for write permission. This argument is optional."
+    )]
+    pub file_path: Option<String>,
+
+    /// The number of IP addresses a scan would check, compared against the open file limit.
+    #[clap(short, long, default_value_t = DEFAULT_COUNT)]
+    pub count: usize,
+}
+
+/// Defines the arguments of the `merge` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+    /// Report files produced with `--format json` or `--format yaml`.
+    #[clap(required = true, help = "JSON or YAML report files to merge.")]
+    pub files: Vec<String>,
+
+    /// File path to write the merged report to.
+    #[clap(
+        short,
+        long,
+        help = "Path to the file for writing the merged report. This argument is optional."
+    )]
+    pub output: Option<String>,
+
+    /// The format of the merged report.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Json,
+        help = "Format of the merged report file, either json or yaml."
+    )]
+    pub format: OutputFormat,
+
+    /// How multiple measurements of the same IP are combined.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = MergeStrategy::Best,
+        help = "Keep the best measurement per IP or average all of them."
+    )]
+    pub strategy: MergeStrategy,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+
+    #[test]
+    fn test_default_values() {
+        let args = Args::parse_from(["testapp", "--domain", "example.com"]);
+        assert_eq!(args.count, SampleSize::Count(DEFAULT_COUNT));
+        assert_eq!(args.order, ScanOrder::Random);
+        assert_eq!(args.concurrency, None);
+        assert_eq!(args.max_probes, None);
+        assert!(!args.no_auto_tune);
+        assert!(!args.strict);
+        assert!(args.profile.is_none());
+        assert!(!args.no_baseline);
+        assert!(!args.adaptive_concurrency);
+        assert!(!args.retry_timeouts);
+        assert_eq!(args.passes, 1);
+        assert!(args.pass_delay.is_none());
+        assert_eq!(args.pass_strategy, MergeStrategy::Average);
+        assert_eq!(args.provider, [Provider::Cloudflare]);
+        assert_eq!(args.ip_version, IpVersion::V4);
+        assert_eq!(args.v6_hosts_per_prefix, DEFAULT_V6_HOSTS_PER_PREFIX);
+        assert_eq!(args.ipv6_bias, 1.0);
+        assert_eq!(args.family_quota, None);
+        assert_eq!(args.weighting, Weighting::Proportional);
+        assert!(args.history.is_none());
+        assert_eq!(args.sort_by, SortKey::Latency);
+        assert!(!args.anonymize);
+        assert!(args.lang.is_none());
+        assert!(!args.native_certs);
+        assert!(args.filter_script.is_none());
+        assert!(!args.notify);
+        assert!(args.on_start.is_none());
+        assert!(args.on_complete.is_none());
+        assert!(args.push_ssh.is_none());
+        assert!(!args.checksum);
+        assert!(args.sign_key.is_none());
+        assert!(args.encrypt_to.is_empty());
+        assert_eq!(args.exploration, DEFAULT_EXPLORATION);
+        assert_eq!(args.log.log_level, "info");
+        assert_eq!(args.log.verbose, 0);
+        assert!(args.log.log_file.is_none());
+        assert_eq!(args.log.log_rotation, LogRotation::Never);
+        assert!(!args.log.log_stderr);
+        assert_eq!(args.log.log_format, LogFormat::Text);
+        assert_eq!(args.log.log_target, LogTarget::Stderr);
+        assert!(args.log.otlp_endpoint.is_none());
+        assert!(args.skip_prefixes.is_empty());
+        assert!(args.skip_list_url.is_empty());
+        assert!(args.seed_list_url.is_empty());
+        assert!(args.matrix.is_empty());
+        assert!(args.sni_file.is_none());
+        assert!(args.sni_fallback.is_none());
+        assert_eq!(args.probe, ProbeKind::Tls);
+        assert_eq!(args.fragment_hello, None);
+        assert!(!args.tcp_nodelay);
+        assert_eq!(args.tcp_keepalive, None);
+        assert_eq!(args.tcp_send_buffer, None);
+        assert_eq!(args.tcp_recv_buffer, None);
+        assert_eq!(args.ttl, None);
+        assert!(args.file_path.is_none());
+        assert!(args.refresh.is_none());
+        assert!(args.spill_results.is_none());
+        assert!(args.aggregate.is_none());
+        assert!(args.output.is_none());
+        assert!(args.label.is_none());
+        assert!(args.statsd_host.is_none());
+        assert_eq!(args.statsd_prefix, "cloudsurf");
+        assert!(args.max_age.is_none());
+        assert_eq!(args.domain.as_deref(), Some("example.com"));
+        assert_eq!(args.ports, vec![443]);
+        assert!(!args.race_ports);
+        assert_eq!(args.max_valid_ips, DEFAULT_MAX_VALID_IPS);
+        assert_eq!(args.format, OutputFormat::Text);
+        assert!(args.top_per_subnet.is_none());
+        assert!(!args.warmup);
+        assert!(args.timeout_ms.is_none());
+        assert!(!args.http_check);
+        assert_eq!(args.check_path, "/");
+        assert!(args.expect_status.is_none());
+        assert!(!args.speedtest);
+        assert_eq!(args.speedtest_bytes, 10_000_000);
+        assert_eq!(args.speedtest_max_time, DEFAULT_SPEEDTEST_MAX_TIME);
+        assert_eq!(args.speedtest_concurrency, DEFAULT_SPEEDTEST_CONCURRENCY);
+        assert!(args.min_speed.is_none());
+        assert!(!args.h2_check);
+        assert_eq!(args.h2_check_duration, DEFAULT_H2_CHECK_DURATION);
+        assert!(!args.request_check);
+        assert!(!args.grpc_check);
+        assert_eq!(args.request_bytes, 1_000);
+        assert!(args.command.is_none());
+    }
+
+    #[test]
+    fn test_valid_input() {
+        let args = Args::parse_from([
+            "testapp",
+            "--count",
+            "10",
+            "--skip-prefixes",
+            "192.168,10.0",
+            "-f",
+            "/path/to/file",
+            "--log-level",
+            "debug",
+            "--domain",
+            "example.com",
+            "--port",
+            "443,2053,8443",
+            "--race-ports",
+            "--max-valid-ips",
+            "20",
+            "--top-per-subnet",
+            "2",
+            "--timeout-ms",
+            "800",
+            "--order",
+            "interleaved",
+            "--weighting",
+            "uniform-per-subnet",
+        ]);
+
+        assert_eq!(args.count, SampleSize::Count(10));
+        assert_eq!(
+            args.skip_prefixes,
+            vec![
+                SkipPrefix::Text("192.168".to_string()),
+                SkipPrefix::Text("10.0".to_string())
+            ]
+        );
+        assert_eq!(args.file_path, Some("/path/to/file".to_string()));
+        assert_eq!(args.log.log_level, "debug");
+        assert_eq!(args.domain.as_deref(), Some("example.com"));
+        assert_eq!(args.ports, vec![443, 2053, 8443]);
+        assert!(args.race_ports);
+        assert_eq!(args.max_valid_ips, 20);
+        assert_eq!(args.top_per_subnet, Some(2));
+        assert_eq!(args.timeout_ms, Some(800));
+        assert_eq!(args.order, ScanOrder::Interleaved);
+        assert_eq!(args.weighting, Weighting::UniformPerSubnet);
+    }
+
+    #[test]
+    fn test_invalid_count() {
+        let result = Args::try_parse_from(["testapp", "--count", "not_a_number"]);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_speedtest_options() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--speedtest",
+            "--speedtest-bytes",
+            "100MB",
+            "--speedtest-max-time",
+            "30",
+            "--speedtest-concurrency",
+            "2",
+        ]);
+
+        assert!(args.speedtest);
+        assert_eq!(args.speedtest_bytes, 100_000_000);
+        assert_eq!(args.speedtest_max_time, 30);
+        assert_eq!(args.speedtest_concurrency, 2);
+    }
+
+    #[test]
+    fn test_http_check_options() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--http-check",
+            "--check-path",
+            "/healthz",
+            "--expect-status",
+            "204",
+        ]);
+
+        assert!(args.http_check);
+        assert_eq!(args.check_path, "/healthz");
+        assert_eq!(args.expect_status, Some(204));
+
+        let result = Args::try_parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--expect-status",
+            "204",
+        ]);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("1048576"), Ok(1_048_576));
+        assert_eq!(parse_byte_size("500KB"), Ok(500_000));
+        assert_eq!(parse_byte_size("1mb"), Ok(1_000_000));
+        assert_eq!(parse_byte_size("2 GB"), Ok(2_000_000_000));
+        assert!(parse_byte_size("0").is_err());
+        assert!(parse_byte_size("MB").is_err());
+        assert!(parse_byte_size("10TB").is_err());
+    }
+
+    #[test]
+    fn test_parse_fragment_hello() {
+        assert_eq!(
+            parse_fragment_hello("5,10"),
+            Ok(FragmentHello {
+                size: 5,
+                delay_ms: 10
+            })
+        );
+        assert!(parse_fragment_hello("0,10").is_err());
+        assert!(parse_fragment_hello("5").is_err());
+        assert!(parse_fragment_hello("5,fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_request_size() {
+        assert_eq!(parse_request_size("1KB"), Ok(1_000));
+        assert_eq!(parse_request_size("1MB"), Ok(1_000_000));
+        assert!(parse_request_size("2MB").is_err());
+    }
+
+    #[test]
+    fn test_history_options() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--history",
+            "history.json",
+            "--exploration",
+            "0.5",
+        ]);
+
+        assert_eq!(args.history, Some(Some("history.json".to_string())));
+        assert_eq!(args.exploration, 0.5);
+
+        let args = Args::parse_from(["testapp", "--history", "--domain", "example.com"]);
+        assert_eq!(args.history, Some(None));
+        assert_eq!(args.domain.as_deref(), Some("example.com"));
+
+        let result =
+            Args::try_parse_from(["testapp", "--domain", "example.com", "--exploration", "0.5"]);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn test_refresh_conflicts_with_file_path() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--refresh",
+            "result.txt",
+        ]);
+        assert_eq!(args.refresh.as_deref(), Some("result.txt"));
+
+        let result = Args::try_parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--refresh",
+            "result.txt",
+            "--file-path",
+            "other.txt",
+        ]);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn test_probe_conflicts() {
+        let args = Args::parse_from(["testapp", "--probe", "doh"]);
+        assert_eq!(args.probe, ProbeKind::Doh);
+
+        for option in ["--warmup", "--race-ports"] {
+            let err =
+                Args::try_parse_with_profile(["testapp", "--probe", "doh", option]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+            let message = err.to_string();
+            assert!(
+                message.contains(option) && message.contains("--probe doh"),
+                "{}",
+                message
+            );
+        }
+        for option in ["--sni-file", "--sni-fallback"] {
+            let result =
+                Args::try_parse_with_profile(["testapp", "--probe", "doh", option, "names.txt"]);
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        }
+
+        let args = Args::try_parse_with_profile(["testapp", "--probe", "tls", "--warmup"]).unwrap();
+        assert_eq!(args.probe, ProbeKind::Tls);
+        assert!(args.warmup);
+        let args =
+            Args::try_parse_with_profile(["testapp", "--probe", "tls", "--race-ports"]).unwrap();
+        assert!(args.race_ports);
+    }
+
+    #[test]
+    fn test_max_age_requires_file_path() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "-f",
+            "result.txt",
+            "--max-age",
+            "30m",
+        ]);
+        assert_eq!(args.max_age, Some(Duration::from_secs(30 * 60)));
+
+        let result =
+            Args::try_parse_from(["testapp", "--domain", "example.com", "--max-age", "1h"]);
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn test_skip_prefixes() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--skip-prefixes",
+            "104.16., 172.64.0.0/13",
+            "--skip-prefixes",
+            "2400:cb00",
+        ]);
+        assert_eq!(
+            args.skip_prefixes,
+            vec![
+                SkipPrefix::Text("104.16.".to_string()),
+                SkipPrefix::Network("172.64.0.0/13".parse().unwrap()),
+                SkipPrefix::Text("2400:cb00".to_string()),
+            ]
+        );
+
+        for invalid in ["192.168.300", "104.16.0.0/33", "10..0", "example.com"] {
+            let result = Args::try_parse_from([
+                "testapp",
+                "--domain",
+                "example.com",
+                "--skip-prefixes",
+                invalid,
+            ]);
+            assert_eq!(
+                result.unwrap_err().kind(),
+                ErrorKind::ValueValidation,
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_sample_size() {
+        assert_eq!(parse_sample_size("500"), Ok(SampleSize::Count(500)));
+        assert_eq!(parse_sample_size("0.5%"), Ok(SampleSize::Percent(0.5)));
+        assert_eq!(parse_sample_size("100%"), Ok(SampleSize::Percent(100.0)));
+        assert!(parse_sample_size("0%").is_err());
+        assert!(parse_sample_size("150%").is_err());
+        assert!(parse_sample_size("half").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1_800)));
+        assert_eq!(parse_duration("6h"), Ok(Duration::from_secs(21_600)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86_400)));
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("5w").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn test_parse_ssh_destination() {
+        assert_eq!(
+            parse_ssh_destination("root@router:/etc/storage/ips.txt"),
+            Ok("root@router:/etc/storage/ips.txt".to_string())
+        );
+        assert!(parse_ssh_destination("router:ips.txt").is_ok());
+        assert!(parse_ssh_destination("router").is_err());
+        assert!(parse_ssh_destination("router:").is_err());
+        assert!(parse_ssh_destination(":/tmp/ips.txt").is_err());
+        assert!(parse_ssh_destination("-oProxyCommand=x:y").is_err());
+    }
+
+    #[test]
+    fn test_parse_age_recipient() {
+        let key = "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p";
+        assert_eq!(parse_age_recipient(key), Ok(key.to_string()));
+        assert!(parse_age_recipient("age1").is_err());
+        assert!(parse_age_recipient("ssh-ed25519 AAAA").is_err());
+    }
+
+    #[test]
+    fn test_output_sink() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--output",
+            "sqlite://scans.db",
+        ]);
+        assert_eq!(
+            args.output,
+            Some(OutputSink::Sqlite("scans.db".to_string()))
+        );
+
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--output",
+            "postgres://db.example.com/scans",
+            "--label",
+            "isp-a",
+        ]);
+        assert_eq!(args.label.as_deref(), Some("isp-a"));
+
+        let result =
+            Args::try_parse_from(["testapp", "--domain", "example.com", "--label", "isp-a"]);
+        assert!(result.is_err());
+
+        let result =
+            Args::try_parse_from(["testapp", "--domain", "example.com", "--output", "scans.db"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_prefix_length() {
+        assert_eq!(parse_prefix_length("/16"), Ok(16));
+        assert_eq!(parse_prefix_length("24"), Ok(24));
+        assert!(parse_prefix_length("/0").is_err());
+        assert!(parse_prefix_length("/33").is_err());
+        assert!(parse_prefix_length("sixteen").is_err());
+    }
+
+    #[test]
+    fn test_anonymize_requires_an_export() {
+        for export in [["-f", "shared.json"], ["--output", "sqlite://scans.db"]] {
+            let mut argv = vec!["testapp", "--domain", "example.com", "--anonymize"];
+            argv.extend(export);
+            assert!(Args::try_parse_from(argv).unwrap().anonymize);
+        }
+
+        let err = Args::try_parse_from(["testapp", "--domain", "example.com", "--anonymize"])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn test_spill_results_conflicts() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--spill-results",
+            "results.jsonl",
+        ]);
+        assert_eq!(args.spill_results.as_deref(), Some("results.jsonl"));
+
+        let result = Args::try_parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--spill-results",
+            "results.jsonl",
+            "--top-per-subnet",
+            "1",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_ip_version_options() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--ip-version",
+            "both",
+            "--v6-hosts-per-prefix",
+            "5",
+        ]);
+        assert_eq!(args.ip_version, IpVersion::Both);
+        assert_eq!(args.v6_hosts_per_prefix, 5);
+
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--ip-version",
+            "both",
+            "--ipv6-bias",
+            "0.8",
+            "--family-quota",
+            "2",
+        ]);
+        assert_eq!(args.ipv6_bias, 0.8);
+        assert_eq!(args.family_quota, Some(2));
+        assert!(parse_bias("0").is_err());
+        assert!(parse_bias("-1").is_err());
+
+        let args = Args::parse_from(["testapp", "--domain", "example.com", "--ip-version", "6"]);
+        assert_eq!(args.ip_version, IpVersion::V6);
+
+        let result = Args::try_parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--v6-hosts-per-prefix",
+            "0",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_provider_option() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--provider",
+            "cloudflare,fastly",
+        ]);
+        assert_eq!(args.provider, [Provider::Cloudflare, Provider::Fastly]);
+
+        let result =
+            Args::try_parse_from(["testapp", "--domain", "example.com", "--provider", "akamai"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_fraction() {
+        assert_eq!(parse_fraction("0"), Ok(0.0));
+        assert_eq!(parse_fraction("0.2"), Ok(0.2));
+        assert_eq!(parse_fraction("1"), Ok(1.0));
+        assert!(parse_fraction("1.5").is_err());
+        assert!(parse_fraction("-0.1").is_err());
+        assert!(parse_fraction("NaN").is_err());
+    }
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("5mbps"), Ok(5.0));
+        assert_eq!(parse_speed("5Mbps"), Ok(5.0));
+        assert_eq!(parse_speed("500kbps"), Ok(0.5));
+        assert_eq!(parse_speed("1gbps"), Ok(1_000.0));
+        assert_eq!(parse_speed("2.5"), Ok(2.5));
+        assert!(parse_speed("fast").is_err());
+        assert!(parse_speed("-1mbps").is_err());
+    }
+
+    #[test]
+    fn test_missing_domain() {
+        // The domain is asked for interactively, or defaults to `DEFAULT_DOMAIN`.
+        let args = Args::try_parse_from(["testapp"]).unwrap();
+        assert!(args.domain.is_none());
+        assert!(args.command.is_none());
+    }
+
+    #[test]
+    fn test_verbosity_flags() {
+        let args = Args::parse_from(["testapp", "--domain", "example.com", "-vvv"]);
+        assert_eq!(args.log.verbose, 3);
+
+        let args = Args::parse_from(["testapp", "merge", "a.json", "-v"]);
+        assert_eq!(args.log.verbose, 1);
+    }
+
+    #[test]
+    fn test_log_file() {
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--log-file",
+            "cloudsurf.log",
+            "--log-rotation",
+            "daily",
+            "--log-stderr",
+            "--log-format",
+            "json",
+        ]);
+
+        assert_eq!(args.log.log_file, Some("cloudsurf.log".to_string()));
+        assert_eq!(args.log.log_rotation, LogRotation::Daily);
+        assert!(args.log.log_stderr);
+        assert_eq!(args.log.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn test_log_rotation_requires_log_file() {
+        let result = Args::try_parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--log-rotation",
+            "daily",
+        ]);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn test_merge_subcommand() {
+        let args = Args::parse_from([
+            "testapp",
+            "merge",
+            "a.json",
+            "b.json",
+            "-o",
+            "best.json",
+            "--strategy",
+            "average",
+        ]);
+
+        match args.command {
+            Some(Command::Merge(merge)) => {
+                assert_eq!(merge.files, vec!["a.json", "b.json"]);
+                assert_eq!(merge.output, Some("best.json".to_string()));
+                assert_eq!(merge.strategy, MergeStrategy::Average);
+            }
+            _ => panic!("Expected the merge subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_selftest_subcommand() {
+        let args = Args::parse_from(["testapp", "selftest", "-f", "ips.txt"]);
+
+        match args.command {
+            Some(Command::Selftest(selftest)) => {
+                assert_eq!(selftest.domain, "cloudflare.com");
+                assert_eq!(selftest.port, 443);
+                assert_eq!(selftest.file_path, Some("ips.txt".to_string()));
+                assert_eq!(selftest.count, DEFAULT_COUNT);
+            }
+            _ => panic!("Expected the selftest subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_bench_subcommand() {
+        let args = Args::parse_from([
+            "testapp",
+            "bench",
+            "a.txt",
+            "b.txt",
+            "--domain",
+            "example.com",
+        ]);
+
+        match args.command {
+            Some(Command::Bench(bench)) => {
+                assert_eq!(bench.files, vec!["a.txt", "b.txt"]);
+                assert_eq!(bench.domain, "example.com");
+                assert_eq!(bench.port, 443);
+            }
+            _ => panic!("Expected the bench subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_bench_requires_two_files() {
+        let result = Args::try_parse_from(["testapp", "bench", "a.txt", "--domain", "example.com"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_dns_subcommand() {
+        let args = Args::parse_from([
+            "testapp",
+            "dns",
+            "--name",
+            "fast.cf.local",
+            "-f",
+            "result.txt",
+            "--answers",
+            "2",
+        ]);
+
+        match args.command {
+            Some(Command::Dns(dns)) => {
+                assert_eq!(dns.listen, "127.0.0.1:53".parse().unwrap());
+                assert_eq!(dns.name, "fast.cf.local");
+                assert_eq!(dns.file_path, "result.txt");
+                assert_eq!(dns.answers, 2);
+                assert_eq!(dns.ttl, 60);
+            }
+            _ => panic!("Expected the dns subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_forward_subcommand() {
+        let args = Args::parse_from(["testapp", "forward", "-f", "result.txt", "--port", "2053"]);
+
+        match args.command {
+            Some(Command::Forward(forward)) => {
+                assert_eq!(forward.listen, "127.0.0.1:8443".parse().unwrap());
+                assert_eq!(forward.file_path, "result.txt");
+                assert_eq!(forward.port, 2053);
+                assert_eq!(forward.connect_timeout_ms, 2000);
+                assert_eq!(forward.health_interval, 10);
+                assert_eq!(forward.switch_margin, 0.2);
+                assert_eq!(forward.drain_timeout, 60);
+            }
+            _ => panic!("Expected the forward subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_import_subcommand() {
+        let args = Args::parse_from([
+            "testapp",
+            "import",
+            "result.csv",
+            "--format",
+            "cfst",
+            "-o",
+            "imported.json",
+        ]);
+
+        match args.command {
+            Some(Command::Import(import)) => {
+                assert_eq!(import.file, "result.csv");
+                assert_eq!(import.format, ImportFormat::Cfst);
+                assert_eq!(import.output.as_deref(), Some("imported.json"));
+                assert_eq!(import.output_format, OutputFormat::Json);
+                assert!(import.history.is_none());
+            }
+            _ => panic!("Expected the import subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_profile_subcommand() {
+        let args = Args::try_parse_from([
+            "testapp", "profile", "save", "home-isp", "--count", "500", "--warmup",
+        ])
+        .unwrap();
+
+        match args.command {
+            Some(Command::Profile(ProfileArgs {
+                action: ProfileAction::Save(save),
+            })) => {
+                assert_eq!(save.name, "home-isp");
+                assert_eq!(save.options, ["--count", "500", "--warmup"]);
+            }
+            _ => panic!("Expected the profile save subcommand"),
+        }
+
+        assert!(Args::try_parse_from(["testapp", "profile", "save", "fast"]).is_err());
+        assert!(Args::try_parse_from(["testapp", "profile", "save", "../home"]).is_err());
+    }
+
+    #[test]
+    fn test_init_subcommand() {
+        let args = Args::try_parse_from(["testapp", "init", "--force"]).unwrap();
+
+        match args.command {
+            Some(Command::Init(init)) => assert!(init.force),
+            _ => panic!("Expected the init subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_version_subcommand() {
+        let args = Args::try_parse_from(["testapp", "version", "--verbose"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Version(VersionArgs { details: true }))
+        ));
+        assert_eq!(args.log.verbose, 0);
+
+        let args = Args::try_parse_from(["testapp", "version", "-v"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Version(VersionArgs { details: false }))
+        ));
+        assert_eq!(args.log.verbose, 1);
+    }
+
+    #[test]
+    fn test_profile_name() {
+        assert_eq!(
+            parse_profile_name("Thorough"),
+            Ok(ProfileName::BuiltIn(Profile::Thorough))
+        );
+        assert_eq!(
+            parse_profile_name("home-isp"),
+            Ok(ProfileName::Saved("home-isp".to_string()))
+        );
+        assert!(parse_profile_name("").is_err());
+        assert!(parse_profile_name("home/isp").is_err());
     }
 
     #[test]
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::result::ScanResult;
+
+/// Statistics about how one candidate list performed in a benchmark.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListStats {
+    /// The name of the list, usually its file path.
+    pub name: String,
+    /// The number of distinct IP addresses in the list.
+    pub total: usize,
+    /// The number of IP addresses that completed a TLS handshake.
+    pub reachable: usize,
+    /// The lowest latency in the list, in milliseconds.
+    pub best_latency_ms: Option<u128>,
+    /// The median latency of the reachable IPs, in milliseconds.
+    pub median_latency_ms: Option<u128>,
+    /// The mean latency of the reachable IPs, in milliseconds.
+    pub mean_latency_ms: Option<u128>,
+}
+
+impl ListStats {
+    /// The share of IP addresses in the list that were reachable, in percent.
+    pub fn success_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.reachable as f64 * 100.0 / self.total as f64
+    }
+}
+
+/// Computes the statistics of a candidate list from the results of a probe run.
+///
+/// All lists of a benchmark are probed together in a single run so that they are measured
+/// under identical network conditions; this function picks the results belonging to `ips`.
+///
+/// # Arguments
+///
+/// * `name` - The name of the list.
+/// * `ips` - The IP addresses of the list.
+/// * `results` - The results of the probe run covering all lists.
+pub fn summarize(name: &str, ips: &[IpAddr], results: &[ScanResult]) -> ListStats {
+    let latencies: HashMap<IpAddr, u128> = results
+        .iter()
+        .map(|result| (result.ip, result.latency_ms))
+        .collect();
+
+    let mut list_latencies: Vec<u128> = ips
+        .iter()
+        .filter_map(|ip| latencies.get(ip).copied())
+        .collect();
+    list_latencies.sort_unstable();
+
+    let reachable = list_latencies.len();
+    let mean_latency_ms =
+        (reachable > 0).then(|| list_latencies.iter().sum::<u128>() / reachable as u128);
+
+    ListStats {
+        name: name.to_string(),
+        total: ips.len(),
+        reachable,
+        best_latency_ms: list_latencies.first().copied(),
+        median_latency_ms: list_latencies.get(reachable / 2).copied(),
+        mean_latency_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_summarize() {
+        let ips = [
+            Ipv4Addr::new(104, 16, 0, 1),
+            Ipv4Addr::new(104, 16, 0, 2),
+            Ipv4Addr::new(104, 16, 0, 3),
+            Ipv4Addr::new(104, 16, 0, 4),
+        ]
+        .map(IpAddr::V4);
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 90),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 3), 30),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 4), 60),
+            // Belongs to another list and must be ignored.
+            ScanResult::new(Ipv4Addr::new(172, 64, 0, 1), 10),
+        ];
+
+        let stats = summarize("a.txt", &ips, &results);
+
+        assert_eq!(stats.total, 4);
+        assert_eq!(stats.reachable, 3);
+        assert_eq!(stats.best_latency_ms, Some(30));
+        assert_eq!(stats.median_latency_ms, Some(60));
+        assert_eq!(stats.mean_latency_ms, Some(60));
+        assert_eq!(stats.success_rate(), 75.0);
+    }
+
+    #[test]
+    fn test_summarize_unreachable_list() {
+        let ips = [IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1))];
+
+        let stats = summarize("a.txt", &ips, &[]);
+
+        assert_eq!(stats.reachable, 0);
+        assert_eq!(stats.best_latency_ms, None);
+        assert_eq!(stats.median_latency_ms, None);
+        assert_eq!(stats.mean_latency_ms, None);
+        assert_eq!(stats.success_rate(), 0.0);
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+/// Whether this build can read and write zstd-compressed files.
+pub const ZSTD_SUPPORTED: bool = cfg!(feature = "zstd");
+
+/// The compression of a file, chosen by the extension of its path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Files ending in `.gz`.
+    Gzip,
+    /// Files ending in `.zst`, which requires a build with the `zstd` feature.
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the compression of the file at `path`, or `None` if it is not compressed.
+    pub fn of(path: &str) -> Option<Self> {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Some(Compression::Gzip),
+            Some("zst") => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Returns `path` without the extension of its compression, e.g. `report.json` for
+    /// `report.json.gz`, so that the format can be told from the remaining extension.
+    pub fn strip(path: &str) -> &str {
+        match Compression::of(path) {
+            Some(_) => path.rsplit_once('.').map_or(path, |(stem, _)| stem),
+            None => path,
+        }
+    }
+}
+
+/// A file being written, compressed according to its extension.
+///
+/// `finish` must be called once everything was written, to flush the compressed stream.
+pub struct OutputFile {
+    encoder: Encoder,
+}
+
+enum Encoder {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl OutputFile {
+    /// Wraps `file`, created at `path`, in the encoder its extension calls for.
+    pub fn new(file: File, path: &str) -> Result<Self> {
+        let file = BufWriter::new(file);
+        let encoder = match Compression::of(path) {
+            None => Encoder::Plain(file),
+            Some(Compression::Gzip) => {
+                Encoder::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            #[cfg(feature = "zstd")]
+            Some(Compression::Zstd) => Encoder::Zstd(zstd::Encoder::new(file, 0)?),
+            #[cfg(not(feature = "zstd"))]
+            Some(Compression::Zstd) => anyhow::bail!(
+                "Writing {} requires a build with the 'zstd' feature enabled",
+                path
+            ),
+        };
+
+        Ok(OutputFile { encoder })
+    }
+
+    /// Completes the compressed stream and flushes it to the file.
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = match self.encoder {
+            Encoder::Plain(file) => file,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        file.flush()
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(file) => file.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(file) => file.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Returns a reader of the decompressed contents of `file`, opened at `path`.
+pub fn reader(file: File, path: &str) -> Result<Box<dyn BufRead>> {
+    Ok(match Compression::of(path) {
+        None => Box::new(BufReader::new(file)),
+        Some(Compression::Gzip) => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        #[cfg(feature = "zstd")]
+        Some(Compression::Zstd) => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+        #[cfg(not(feature = "zstd"))]
+        Some(Compression::Zstd) => anyhow::bail!(
+            "Reading {} requires a build with the 'zstd' feature enabled",
+            path
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_gzip_roundtrip() {
+        let path = std::env::temp_dir().join(format!("cloudsurf-{}.txt.gz", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut output = OutputFile::new(File::create(path).unwrap(), path).unwrap();
+        output.write_all(b"104.16.0.1 MCI\n").unwrap();
+        output.finish().unwrap();
+
+        let compressed = std::fs::read(path).unwrap();
+        assert_eq!(&compressed[..2], [0x1f, 0x8b]);
+
+        let mut contents = String::new();
+        reader(File::open(path).unwrap(), path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(contents, "104.16.0.1 MCI\n");
+    }
+
+    #[test]
+    fn test_strip() {
+        assert_eq!(Compression::strip("report.yaml.zst"), "report.yaml");
+        assert_eq!(Compression::strip("report.json"), "report.json");
+        assert_eq!(Compression::of("ips.txt.gz"), Some(Compression::Gzip));
+    }
+}
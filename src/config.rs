@@ -0,0 +1,175 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::file::OutputFormat;
+use crate::paths;
+
+/// The settings asked for by `cloudsurf init`.
+#[derive(Debug, PartialEq)]
+pub struct Settings {
+    /// The domain TLS handshakes are verified against.
+    pub domain: String,
+    /// The ports tried in order for each IP.
+    pub ports: Vec<u16>,
+    /// The format of the output file.
+    pub format: OutputFormat,
+    /// The file the results are written to, if any.
+    pub output: Option<String>,
+    /// The number of hours between scheduled scans, if scans are scheduled.
+    pub schedule_hours: Option<u32>,
+}
+
+impl Settings {
+    /// Returns the crontab line running a scan every `schedule_hours`, if scans are scheduled.
+    pub fn crontab_entry(&self) -> Option<String> {
+        self.schedule_hours.map(|hours| match hours {
+            24 => "0 3 * * * cloudsurf".to_string(),
+            hours => format!("0 */{} * * * cloudsurf", hours),
+        })
+    }
+
+    /// Renders the settings as a commented configuration file.
+    pub fn render(&self) -> String {
+        let mut contents = String::from(
+            "# cloudsurf configuration, written by `cloudsurf init`.\n\
+             #\n\
+             # Each line holds one command-line option and its value. The options apply to every\n\
+             # scan as if they were given first on the command line, so options given on the\n\
+             # command line itself take precedence.\n",
+        );
+
+        let _ = write!(
+            contents,
+            "\n# The domain TLS handshakes are verified against.\n--domain {}\n",
+            self.domain
+        );
+        let _ = write!(
+            contents,
+            "\n# The ports tried in order for each IP.\n--port {}\n",
+            join_ports(&self.ports)
+        );
+        match &self.output {
+            Some(output) => {
+                let _ = write!(
+                    contents,
+                    "\n# The file the results are written to, and its format.\n--file-path {}\n--format {}\n",
+                    output,
+                    format_name(self.format)
+                );
+            }
+            None => contents.push_str(
+                "\n# The file the results are written to, and its format.\n# --file-path result.txt\n# --format text\n",
+            ),
+        }
+        if let Some(entry) = self.crontab_entry() {
+            let _ = write!(
+                contents,
+                "\n# Scans are scheduled with cron, add this line with `crontab -e`:\n# {}\n",
+                entry
+            );
+        }
+
+        contents
+    }
+}
+
+fn join_ports(ports: &[u16]) -> String {
+    ports
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn format_name(format: OutputFormat) -> String {
+    clap::ValueEnum::to_possible_value(&format)
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Reads the options of the configuration file, or none if there is no configuration file.
+pub fn load() -> Result<Vec<String>> {
+    let path = paths::config_file()?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => Ok(parse(&contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+    }
+}
+
+/// Splits a configuration file into arguments, skipping blank lines and comments.
+///
+/// Each line is split once on whitespace, so that values may contain spaces.
+fn parse(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .flat_map(|line| match line.split_once(char::is_whitespace) {
+            Some((option, value)) => vec![option.to_string(), value.trim().to_string()],
+            None => vec![line.to_string()],
+        })
+        .collect()
+}
+
+/// Writes `settings` to the configuration file at `path`, replacing it if it exists.
+pub fn write(path: &Path, settings: &Settings) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Couldn't create {}", dir.display()))?;
+    }
+    fs::write(path, settings.render()).with_context(|| format!("Couldn't write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::args::Args;
+
+    /// Returns the settings as the command-line options their configuration file holds.
+    fn to_args(settings: &Settings) -> Vec<String> {
+        let mut args = vec![
+            "--domain".to_string(),
+            settings.domain.clone(),
+            "--port".to_string(),
+            join_ports(&settings.ports),
+        ];
+        if let Some(output) = &settings.output {
+            args.extend([
+                "--file-path".to_string(),
+                output.clone(),
+                "--format".to_string(),
+                format_name(settings.format),
+            ]);
+        }
+
+        args
+    }
+
+    #[test]
+    fn test_rendered_config_parses() {
+        let settings = Settings {
+            domain: "example.com".to_string(),
+            ports: vec![443, 2053],
+            format: OutputFormat::CfstCsv,
+            output: Some("my results.csv".to_string()),
+            schedule_hours: Some(6),
+        };
+
+        let rendered = settings.render();
+        assert!(rendered.contains("# 0 */6 * * * cloudsurf"));
+
+        let args = parse(&rendered);
+        assert_eq!(args, to_args(&settings));
+        assert_eq!(args[args.len() - 3], "my results.csv");
+
+        let args =
+            Args::try_parse_with_profile(std::iter::once("cloudsurf".to_string()).chain(args))
+                .unwrap();
+        assert_eq!(args.ports, [443, 2053]);
+        assert_eq!(args.format, OutputFormat::CfstCsv);
+    }
+}
@@ -0,0 +1,267 @@
+use std::net::IpAddr;
+
+use anyhow::{Context, Result};
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use crate::args::DnsArgs;
+use crate::file::IpFile;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const OPCODE_MASK: u16 = 0x7800;
+
+const RCODE_FORMAT_ERROR: u16 = 1;
+const RCODE_SERVER_FAILURE: u16 = 2;
+const RCODE_NOT_IMPLEMENTED: u16 = 4;
+const RCODE_REFUSED: u16 = 5;
+
+/// Answers A and AAAA queries for `args.name` with the best IPs listed in `args.file_path`.
+///
+/// The file is read again whenever it changes, so a file kept fresh by a scan with `--max-age`
+/// is picked up without restarting. Runs until interrupted.
+pub async fn serve(args: &DnsArgs) -> Result<()> {
+    let socket = UdpSocket::bind(args.listen)
+        .await
+        .with_context(|| format!("Couldn't listen on {}", args.listen))?;
+    info!(listen = %args.listen, name = args.name, "Serving DNS");
+
+    let mut ips = IpFile::new(args.file_path.clone());
+    let mut buffer = [0; 512];
+    loop {
+        let (len, peer) = socket
+            .recv_from(&mut buffer)
+            .await
+            .context("Couldn't receive DNS query")?;
+
+        let answers = best_per_family(ips.current(), args.answers);
+        let Some(response) = respond(&buffer[..len], &args.name, &answers, args.ttl) else {
+            debug!(%peer, "Ignoring malformed DNS message");
+            continue;
+        };
+
+        if let Err(e) = socket.send_to(&response, peer).await {
+            warn!(%peer, error = %e, "Couldn't send DNS response");
+        }
+    }
+}
+
+/// Returns the first `count` IPv4 and the first `count` IPv6 addresses of `ips`, in order.
+fn best_per_family(ips: &[IpAddr], count: usize) -> Vec<IpAddr> {
+    let v4 = ips.iter().filter(|ip| ip.is_ipv4()).take(count);
+    let v6 = ips.iter().filter(|ip| ip.is_ipv6()).take(count);
+    v4.chain(v6).copied().collect()
+}
+
+/// Builds the response to the DNS `query`, or returns `None` if it should be ignored.
+///
+/// A and AAAA queries for `name` are answered with the addresses of `ips` of the matching
+/// family, other query types for `name` with an empty answer, and queries for any other name are
+/// refused. When there is no IP to answer with, the server reports a failure so that clients fall
+/// back to another resolver.
+fn respond(query: &[u8], name: &str, ips: &[IpAddr], ttl: u32) -> Option<Vec<u8>> {
+    let header = query.get(..HEADER_LEN)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    if flags & FLAG_RESPONSE != 0 {
+        return None;
+    }
+
+    let response_flags = FLAG_RESPONSE | FLAG_AUTHORITATIVE | (flags & FLAG_RECURSION_DESIRED);
+    let mut response = Vec::with_capacity(512);
+    response.extend_from_slice(&header[..2]);
+
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+    if flags & OPCODE_MASK != 0 || question_count != 1 {
+        let rcode = if flags & OPCODE_MASK != 0 {
+            RCODE_NOT_IMPLEMENTED
+        } else {
+            RCODE_FORMAT_ERROR
+        };
+        response.extend_from_slice(&(response_flags | rcode).to_be_bytes());
+        response.extend_from_slice(&[0; 8]);
+        return Some(response);
+    }
+
+    let Some((question_name, question_end)) = parse_name(query, HEADER_LEN) else {
+        response.extend_from_slice(&(response_flags | RCODE_FORMAT_ERROR).to_be_bytes());
+        response.extend_from_slice(&[0; 8]);
+        return Some(response);
+    };
+    let question = query.get(HEADER_LEN..question_end + 4)?;
+    let query_type = u16::from_be_bytes([query[question_end], query[question_end + 1]]);
+    let query_class = u16::from_be_bytes([query[question_end + 2], query[question_end + 3]]);
+
+    let answers: Vec<IpAddr> = if !question_name.eq_ignore_ascii_case(name.trim_end_matches('.')) {
+        response.extend_from_slice(&(response_flags | RCODE_REFUSED).to_be_bytes());
+        Vec::new()
+    } else if ips.is_empty() {
+        response.extend_from_slice(&(response_flags | RCODE_SERVER_FAILURE).to_be_bytes());
+        Vec::new()
+    } else {
+        response.extend_from_slice(&response_flags.to_be_bytes());
+        let matches_type = |ip: &&IpAddr| match query_type {
+            TYPE_A => ip.is_ipv4(),
+            TYPE_AAAA => ip.is_ipv6(),
+            TYPE_ANY => true,
+            _ => false,
+        };
+        if query_class == CLASS_IN {
+            ips.iter().filter(matches_type).copied().collect()
+        } else {
+            Vec::new()
+        }
+    };
+
+    response.extend_from_slice(&1u16.to_be_bytes());
+    response.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    response.extend_from_slice(&[0; 4]);
+    response.extend_from_slice(question);
+
+    for ip in answers {
+        let (record_type, address) = match ip {
+            IpAddr::V4(ip) => (TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (TYPE_AAAA, ip.octets().to_vec()),
+        };
+        // A pointer to the name in the question, which directly follows the header.
+        response.extend_from_slice(&(0xC000 | HEADER_LEN as u16).to_be_bytes());
+        response.extend_from_slice(&record_type.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&ttl.to_be_bytes());
+        response.extend_from_slice(&(address.len() as u16).to_be_bytes());
+        response.extend_from_slice(&address);
+    }
+
+    Some(response)
+}
+
+/// Parses the uncompressed domain name starting at `offset`, returning it without the trailing
+/// dot along with the offset right after it.
+fn parse_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    loop {
+        let len = *message.get(offset)? as usize;
+        offset += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers and extended label types are not expected in a question.
+        if len > 63 {
+            return None;
+        }
+        let label = message.get(offset..offset + len)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += len;
+    }
+
+    Some((labels.join("."), offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn query(name: &str, query_type: u16) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&query_type.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    fn rcode(response: &[u8]) -> u16 {
+        u16::from_be_bytes([response[2], response[3]]) & 0x000F
+    }
+
+    fn answer_count(response: &[u8]) -> u16 {
+        u16::from_be_bytes([response[6], response[7]])
+    }
+
+    #[test]
+    fn test_respond_with_ips() {
+        let ips: [IpAddr; 3] = [
+            Ipv4Addr::new(104, 16, 0, 1).into(),
+            "2606:4700::6810:1".parse().unwrap(),
+            Ipv4Addr::new(172, 64, 0, 1).into(),
+        ];
+        let a_query = query("Fast.CF.local", TYPE_A);
+
+        let response = respond(&a_query, "fast.cf.local.", &ips, 60).unwrap();
+
+        assert_eq!(&response[..2], &[0x12, 0x34]);
+        assert_eq!(rcode(&response), 0);
+        assert_eq!(answer_count(&response), 2);
+        assert_eq!(&response[response.len() - 4..], &[172, 64, 0, 1]);
+
+        let aaaa = respond(
+            &query("fast.cf.local", TYPE_AAAA),
+            "fast.cf.local",
+            &ips,
+            60,
+        )
+        .unwrap();
+        assert_eq!(answer_count(&aaaa), 1);
+        assert_eq!(
+            &aaaa[aaaa.len() - 16..],
+            &"2606:4700::6810:1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets()
+        );
+    }
+
+    #[test]
+    fn test_best_per_family() {
+        let ips: Vec<IpAddr> = ["104.16.0.1", "2606:4700::1", "104.16.0.2", "2606:4700::2"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+
+        assert_eq!(best_per_family(&ips, 1), vec![ips[0], ips[1]]);
+    }
+
+    #[test]
+    fn test_respond_to_other_queries() {
+        let ips = [IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1))];
+
+        let response = respond(&query("other.local", TYPE_A), "fast.cf.local", &ips, 60).unwrap();
+        assert_eq!(rcode(&response), RCODE_REFUSED);
+        assert_eq!(answer_count(&response), 0);
+
+        let aaaa = respond(
+            &query("fast.cf.local", TYPE_AAAA),
+            "fast.cf.local",
+            &ips,
+            60,
+        )
+        .unwrap();
+        assert_eq!(rcode(&aaaa), 0);
+        assert_eq!(answer_count(&aaaa), 0);
+
+        let empty = respond(&query("fast.cf.local", TYPE_A), "fast.cf.local", &[], 60).unwrap();
+        assert_eq!(rcode(&empty), RCODE_SERVER_FAILURE);
+    }
+
+    #[test]
+    fn test_respond_to_malformed_queries() {
+        let query = query("fast.cf.local", TYPE_A);
+
+        assert!(respond(&query[..6], "fast.cf.local", &[], 60).is_none());
+        assert!(respond(&query[..query.len() - 2], "fast.cf.local", &[], 60).is_none());
+
+        let mut response_message = query.clone();
+        response_message[2] |= 0x80;
+        assert!(respond(&response_message, "fast.cf.local", &[], 60).is_none());
+    }
+}
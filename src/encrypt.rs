@@ -0,0 +1,78 @@
+use anyhow::Result;
+
+/// Whether this build can encrypt results with age.
+pub const SUPPORTED: bool = cfg!(feature = "age");
+
+/// The age recipients results are encrypted to.
+#[cfg(feature = "age")]
+pub struct Recipients(Vec<age::x25519::Recipient>);
+
+#[cfg(not(feature = "age"))]
+#[allow(dead_code)]
+pub struct Recipients;
+
+impl Recipients {
+    /// Parses age X25519 public keys, such as the ones printed by `age-keygen`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a key is invalid, or if this build doesn't support age.
+    #[cfg(feature = "age")]
+    pub fn parse(keys: &[String]) -> Result<Self> {
+        keys.iter()
+            .map(|key| {
+                key.parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid age recipient '{}': {}", key, e))
+            })
+            .collect::<Result<_>>()
+            .map(Recipients)
+    }
+
+    #[cfg(not(feature = "age"))]
+    pub fn parse(_keys: &[String]) -> Result<Self> {
+        anyhow::bail!("--encrypt-to requires a build with the 'age' feature enabled")
+    }
+
+    /// Encrypts `path` to `<path>.age`, which only the recipients can decrypt with
+    /// `age --decrypt`.
+    ///
+    /// Returns the path of the encrypted file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` cannot be read or the encrypted file cannot be written.
+    #[cfg(feature = "age")]
+    pub fn encrypt_file(&self, path: &str) -> Result<String> {
+        use anyhow::Context;
+        use std::fs::File;
+        use std::io::{self, BufWriter, Write};
+
+        let encrypted_path = format!("{}.age", path);
+        let recipients = self
+            .0
+            .iter()
+            .map(|recipient| Box::new(recipient.clone()) as Box<dyn age::Recipient + Send>)
+            .collect();
+        let encryptor = age::Encryptor::with_recipients(recipients)
+            .context("At least one age recipient is required")?;
+
+        let mut input = File::open(path).with_context(|| format!("Couldn't open file {}", path))?;
+        let output = File::create(&encrypted_path)
+            .with_context(|| format!("Couldn't create file {}", encrypted_path))?;
+        let write = || -> Result<()> {
+            let mut writer = encryptor.wrap_output(BufWriter::new(output))?;
+            io::copy(&mut input, &mut writer)?;
+            writer.finish()?.flush()?;
+            Ok(())
+        };
+        write().with_context(|| format!("Couldn't encrypt {} to {}", path, encrypted_path))?;
+        tracing::info!(path = encrypted_path.as_str(), "Wrote encrypted results");
+
+        Ok(encrypted_path)
+    }
+
+    #[cfg(not(feature = "age"))]
+    pub fn encrypt_file(&self, _path: &str) -> Result<String> {
+        anyhow::bail!("--encrypt-to requires a build with the 'age' feature enabled")
+    }
+}
@@ -1,8 +1,39 @@
 use anyhow::{Context, Result};
-use log::info;
-use std::fs::File;
-use std::io::{self, Write};
-use std::net::Ipv4Addr;
+use clap::ValueEnum;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+use crate::compression::{self, Compression, OutputFile};
+use crate::import::{self, ImportFormat};
+use crate::parquet_export;
+use crate::report::{ScanParameters, ScanReport};
+use crate::result::ScanResult;
+use crate::version;
+
+/// Supported formats for the output file.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One "IP OPERATOR" line per IP and operator, followed by the operator domains.
+    Text,
+    /// A structured JSON report, see `ScanReport`.
+    Json,
+    /// A structured YAML report, see `ScanReport`.
+    Yaml,
+    /// The `result.csv` layout of CloudflareSpeedTest, for tools built around that format.
+    CfstCsv,
+    /// One row per result with a column per measurement, for dataframe libraries.
+    ///
+    /// Requires a build with the `parquet` feature.
+    Parquet,
+}
+
+/// The header of CloudflareSpeedTest's `result.csv`, kept verbatim so that existing parsers match.
+const CFST_CSV_HEADER: &str = "IP 地址,已发送,已接收,丢包率,平均延迟,下载速度 (MB/s)";
 
 /// A predefined list of operator domains and their corresponding operator names.
 const OPERATOR_DOMAINS: [(&str, &str); 27] = [
@@ -43,9 +74,9 @@ const OPERATOR_DOMAINS: [(&str, &str); 27] = [
 ///
 /// # Arguments
 ///
-/// * `ips` - A list of tuples, each containing an `Ipv4Addr` and a latency measurement (`u128`).
-///           The latency is currently not used in the function.
+/// * `ips` - A list of scan results. Only the IP addresses are written; the latency is not used.
 /// * `file_path` - The path to the file where the data will be written.
+/// * `options` - The header and the number of IPs listed for each operator.
 ///
 /// # Errors
 ///
@@ -57,36 +88,653 @@ const OPERATOR_DOMAINS: [(&str, &str); 27] = [
 /// use std::net::Ipv4Addr;
 /// use your_crate::write_ips_to_file;
 ///
-/// let ips = vec![(Ipv4Addr::new(192, 168, 1, 1), 100)];
-/// write_ips_to_file(ips, "output.txt").expect("Failed to write IPs to file");
+/// let ips = vec![ScanResult::new(Ipv4Addr::new(192, 168, 1, 1), 100)];
+/// write_ips_to_file(&ips, "output.txt", &TextOptions::default()).expect("Failed to write IPs to file");
 /// ```
-pub fn write_ips_to_file(ips: &Vec<(Ipv4Addr, u128)>, file_path: &str) -> Result<()> {
-    let operator_list = vec!["MTN", "MCI", "RTL", "ZTL", "SHT"];
+pub fn write_ips_to_file(ips: &[ScanResult], file_path: &str, options: &TextOptions) -> Result<()> {
+    let operators = ["MTN", "MCI", "RTL", "ZTL", "SHT"].map(ToString::to_string);
+    let sections: Vec<_> = OPERATOR_DOMAINS
+        .iter()
+        .map(|(domain, operator)| format!("{} {}", domain, operator))
+        .collect();
 
-    let mut file = match File::create(file_path) {
-        Ok(file) => file,
-        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
-            // Handle permission denied error specifically
-            return Err(anyhow::Error::new(e).context(format!("Permission denied when attempting to write to '{}'. Please ensure the application has the necessary permissions, or choose a different location.", file_path)));
-        }
-        Err(e) => {
-            return Err(e.into());
-        }
+    write_text_output(ips, &operators, &sections, file_path, options)
+}
+
+/// Options of the text output file written by `write_ips_to_file` and `rewrite_text_output`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Lines written as `#` comments at the top of the file, see `text_header`.
+    pub header: Vec<String>,
+    /// If set, the maximum number of IP lines written for each operator, taking the first IPs.
+    pub per_operator: Option<usize>,
+}
+
+impl TextOptions {
+    /// Returns the IP addresses of `ips` listed in a text output file written with these
+    /// options, in order.
+    pub fn listed_ips(&self, ips: &[ScanResult]) -> Vec<IpAddr> {
+        let per_operator = self.per_operator.unwrap_or(usize::MAX);
+        ips.iter()
+            .take(per_operator)
+            .map(|result| result.ip)
+            .collect()
+    }
+}
+
+/// Returns the header describing how a text output file was produced: when, by which version,
+/// and with which of the scan parameters.
+///
+/// The time is left out when `written_at` is `None`, for anonymized files.
+pub fn text_header(parameters: &ScanParameters, written_at: Option<SystemTime>) -> Vec<String> {
+    let ports: Vec<_> = parameters.ports.iter().map(u16::to_string).collect();
+    let mut header = vec![
+        match written_at {
+            Some(written_at) => format!(
+                "Written by {} at {}",
+                version::short(),
+                humantime::format_rfc3339_seconds(written_at)
+            ),
+            None => format!("Written by {}", version::short()),
+        },
+        format!("Domain: {}", parameters.domain),
+        format!("Ports: {}", ports.join(",")),
+        format!(
+            "Count: {}, max valid IPs: {}",
+            parameters.count,
+            match parameters.max_valid_ips {
+                0 => "all".to_string(),
+                n => n.to_string(),
+            }
+        ),
+    ];
+    if !parameters.providers.is_empty() {
+        let providers: Vec<_> = parameters
+            .providers
+            .iter()
+            .map(|provider| provider.name())
+            .collect();
+        header.push(format!("Providers: {}", providers.join(",")));
+    }
+    if !parameters.skip_prefixes.is_empty() {
+        header.push(format!(
+            "Skipped prefixes: {}",
+            parameters.skip_prefixes.join(",")
+        ));
+    }
+    for url in &parameters.skip_list_urls {
+        header.push(format!("Skip list: {}", url));
+    }
+    for url in &parameters.seed_list_urls {
+        header.push(format!("Seed list: {}", url));
+    }
+
+    header
+}
+
+/// The contents of a file written by `write_ips_to_file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextOutput {
+    /// The IP addresses, in order of first appearance.
+    pub ips: Vec<IpAddr>,
+    /// The operators each IP address is listed with, in order of first appearance.
+    pub operators: Vec<String>,
+    /// The lines that don't start with an IP address, such as the operator domains, verbatim.
+    pub sections: Vec<String>,
+}
+
+/// Reads a file previously written by `write_ips_to_file`, so that it can be rewritten with
+/// `rewrite_text_output` after its IP addresses were probed again.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or doesn't contain any IP address.
+pub fn read_text_output(file_path: &str) -> Result<TextOutput> {
+    let reader = open_file(file_path)?;
+
+    let mut output = TextOutput {
+        ips: Vec::new(),
+        operators: Vec::new(),
+        sections: Vec::new(),
     };
+    for line in reader.lines() {
+        let line = line.with_context(|| format!("Couldn't read from file {}", file_path))?;
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next().map(str::parse::<IpAddr>) {
+            Some(Ok(ip)) => {
+                if !output.ips.contains(&ip) {
+                    output.ips.push(ip);
+                }
+                for operator in tokens {
+                    if !output.operators.iter().any(|known| known == operator) {
+                        output.operators.push(operator.to_string());
+                    }
+                }
+            }
+            // Comments such as the header are rewritten from the scan, not kept.
+            Some(Err(_)) if line.starts_with('#') => {}
+            Some(Err(_)) => output.sections.push(line),
+            None => {}
+        }
+    }
+
+    if output.ips.is_empty() {
+        anyhow::bail!("File {} doesn't contain any IP address", file_path);
+    }
+
+    Ok(output)
+}
+
+/// Rewrites a file read with `read_text_output`, listing `ips` in place of the previous IP
+/// addresses and keeping its operators and sections.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or if writing to the file fails at any point.
+pub fn rewrite_text_output(
+    ips: &[ScanResult],
+    output: &TextOutput,
+    file_path: &str,
+    options: &TextOptions,
+) -> Result<()> {
+    write_text_output(ips, &output.operators, &output.sections, file_path, options)
+}
+
+/// Writes the header of `options`, then one "IP OPERATOR" line per IP address and operator,
+/// followed by `sections` verbatim.
+///
+/// With `options.per_operator`, each operator is only listed with that many of the first IPs.
+fn write_text_output(
+    ips: &[ScanResult],
+    operators: &[String],
+    sections: &[String],
+    file_path: &str,
+    options: &TextOptions,
+) -> Result<()> {
+    let mut file = create_output(file_path)?;
+
+    for line in &options.header {
+        writeln!(file, "# {}", line)
+            .with_context(|| format!("Couldn't write the header to file {}", file_path))?;
+    }
 
-    for (ip, _) in ips {
-        for operator in &operator_list {
+    for ip in options.listed_ips(ips) {
+        for operator in operators {
             writeln!(file, "{} {}", ip, operator)
                 .with_context(|| format!("Couldn't write IP and operator to file {}", file_path))?;
         }
     }
 
-    for (domain, operator) in OPERATOR_DOMAINS.iter() {
-        writeln!(file, "{} {}", domain, operator)
+    for line in sections {
+        writeln!(file, "{}", line)
             .with_context(|| format!("Couldn't write new entries to file {}", file_path))?;
     }
+    file.finish()
+        .with_context(|| format!("Couldn't write to file {}", file_path))?;
 
     info!("Successfully wrote to file {}", file_path);
 
     Ok(())
 }
+
+/// Reads a list of IP addresses from a text file.
+///
+/// The first whitespace-separated token of each line is parsed as an IP address, so both plain
+/// lists with one IP per line and files written by `write_ips_to_file` are accepted. Lines that
+/// don't start with an IP address, such as blank lines, comments or operator domains, are ignored.
+/// Each address is returned once, in order of first appearance.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or read.
+pub fn read_ips_from_file(file_path: &str) -> Result<Vec<IpAddr>> {
+    let reader = open_file(file_path)?;
+
+    let mut seen = HashSet::new();
+    let mut ips = Vec::new();
+    for line in reader.lines() {
+        let line = line.with_context(|| format!("Couldn't read from file {}", file_path))?;
+        let ip = line
+            .split_whitespace()
+            .next()
+            .and_then(|token| token.parse::<IpAddr>().ok());
+
+        if let Some(ip) = ip {
+            if seen.insert(ip) {
+                ips.push(ip);
+            }
+        }
+    }
+
+    Ok(ips)
+}
+
+/// The IP addresses listed in a file, read again when the file's modification time changes.
+pub struct IpFile {
+    path: String,
+    modified: Option<SystemTime>,
+    ips: Vec<IpAddr>,
+}
+
+impl IpFile {
+    /// Creates a reader for the file at `path`, which is only read on the first call to `current`.
+    pub fn new(path: String) -> Self {
+        IpFile {
+            path,
+            modified: None,
+            ips: Vec::new(),
+        }
+    }
+
+    /// Returns the IPs of the latest readable version of the file.
+    ///
+    /// If the file cannot be read, for example while it is being rewritten, the previous IPs are
+    /// kept.
+    pub fn current(&mut self) -> &[IpAddr] {
+        let modified = fs::metadata(&self.path).and_then(|metadata| metadata.modified());
+        match modified {
+            Ok(modified) if self.modified == Some(modified) => {}
+            Ok(modified) => match read_ips_from_file(&self.path) {
+                Ok(ips) => {
+                    info!(path = self.path.as_str(), count = ips.len(), "Loaded IPs");
+                    self.ips = ips;
+                    self.modified = Some(modified);
+                }
+                Err(e) => warn!(error = %format!("{:#}", e), "Keeping the previous IPs"),
+            },
+            Err(e) => warn!(path = self.path.as_str(), error = %e, "Keeping the previous IPs"),
+        }
+
+        &self.ips
+    }
+}
+
+/// Writes the results in the `result.csv` layout of CloudflareSpeedTest.
+///
+/// Every IP was probed once per pass, so `passes` handshakes are listed as sent, and those of the
+/// passes it was reachable in as received, see `ScanResult::passes`. The download speed is
+/// converted to megabytes per second, and is 0 when it was not measured, as CloudflareSpeedTest
+/// does when its speed test is disabled.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or if writing to the file fails at any point.
+pub fn write_cfst_csv(ips: &[ScanResult], passes: usize, file_path: &str) -> Result<()> {
+    let mut writer = create_output(file_path)?;
+
+    writeln!(writer, "{}", CFST_CSV_HEADER)
+        .with_context(|| format!("Couldn't write to file {}", file_path))?;
+    for result in ips {
+        let received = result.passes.unwrap_or(1).min(passes);
+        writeln!(
+            writer,
+            "{},{},{},{:.2},{:.2},{:.2}",
+            result.ip,
+            passes,
+            received,
+            1.0 - received as f64 / passes as f64,
+            result.latency_ms as f64,
+            result.speed_mbps.unwrap_or(0.0) / 8.0
+        )
+        .with_context(|| format!("Couldn't write to file {}", file_path))?;
+    }
+    writer
+        .finish()
+        .with_context(|| format!("Couldn't write to file {}", file_path))?;
+
+    info!("Successfully wrote to file {}", file_path);
+
+    Ok(())
+}
+
+/// Writes a structured scan report to a file in JSON or YAML format.
+///
+/// Unlike `write_ips_to_file`, the latency of each IP and the scan parameters are preserved,
+/// which allows the file to be read back with `read_report`, for example by the `merge` subcommand.
+///
+/// # Arguments
+///
+/// * `report` - The report to write.
+/// * `file_path` - The path to the file where the report will be written.
+/// * `format` - Either `OutputFormat::Json` or `OutputFormat::Yaml`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created, if serialization fails, or if `format`
+/// is not a structured format.
+pub fn write_report(report: &ScanReport, file_path: &str, format: OutputFormat) -> Result<()> {
+    if format == OutputFormat::Parquet {
+        if Compression::of(file_path).is_some() {
+            anyhow::bail!("Parquet files are already compressed, remove the .gz or .zst extension");
+        }
+        parquet_export::write_report(report, create_file(file_path)?)
+            .with_context(|| format!("Couldn't write report to file {}", file_path))?;
+        info!("Successfully wrote to file {}", file_path);
+        return Ok(());
+    }
+
+    let mut file = create_output(file_path)?;
+    match format {
+        OutputFormat::Json => serde_json::to_writer_pretty(&mut file, report)
+            .with_context(|| format!("Couldn't write report to file {}", file_path))?,
+        OutputFormat::Yaml => serde_yaml::to_writer(&mut file, report)
+            .with_context(|| format!("Couldn't write report to file {}", file_path))?,
+        OutputFormat::Parquet => unreachable!("Parquet reports are written above"),
+        OutputFormat::Text => anyhow::bail!("The text format cannot hold a structured report"),
+        OutputFormat::CfstCsv => {
+            anyhow::bail!("The cfst-csv format cannot hold a structured report")
+        }
+    }
+    file.finish()
+        .with_context(|| format!("Couldn't write report to file {}", file_path))?;
+
+    info!("Successfully wrote to file {}", file_path);
+
+    Ok(())
+}
+
+/// Reads a structured scan report previously written by `write_report`.
+///
+/// Files ending in `.yaml` or `.yml` are parsed as YAML, everything else as JSON. Files ending
+/// in `.gz` or `.zst` are decompressed first, so `report.yaml.gz` is parsed as YAML.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or does not contain a valid report.
+pub fn read_report(file_path: &str) -> Result<ScanReport> {
+    let reader = open_file(file_path)?;
+
+    let is_yaml = matches!(
+        Path::new(Compression::strip(file_path))
+            .extension()
+            .and_then(|ext| ext.to_str()),
+        Some("yaml" | "yml")
+    );
+
+    if is_yaml {
+        serde_yaml::from_reader(reader)
+            .with_context(|| format!("Couldn't parse report from file {}", file_path))
+    } else {
+        serde_json::from_reader(reader)
+            .with_context(|| format!("Couldn't parse report from file {}", file_path))
+    }
+}
+
+/// Reads the server names of `--sni-file`, one per line, ignoring blank lines and `#` comments.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or doesn't list any server name.
+pub fn read_server_names(file_path: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for line in open_file(file_path)?.lines() {
+        let line = line.with_context(|| format!("Couldn't read from file {}", file_path))?;
+        let name = line.split('#').next().unwrap_or_default().trim();
+        if !name.is_empty() {
+            names.push(name.to_string());
+        }
+    }
+
+    if names.is_empty() {
+        anyhow::bail!("File {} doesn't list any server name", file_path);
+    }
+
+    Ok(names)
+}
+
+/// Replaces the file at `file_path` with the one written by `write`, once `verify` accepted it.
+///
+/// `write` and `verify` are given a temporary path next to `file_path`, with the same extension,
+/// so that a failed or malformed write never replaces the previous file. The previous file is
+/// kept as `<file_path>.bak`.
+///
+/// # Errors
+///
+/// Returns an error if writing fails, if `verify` rejects the new file, or if the previous file
+/// cannot be backed up or replaced. `file_path` is left untouched in every case but the last.
+pub fn replace_file(
+    file_path: &str,
+    write: impl FnOnce(&str) -> Result<()>,
+    verify: impl FnOnce(&str) -> Result<()>,
+) -> Result<()> {
+    let path = Path::new(file_path);
+    let temporary = path.with_file_name(format!(
+        ".tmp-{}",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let temporary = temporary
+        .to_str()
+        .context("The temporary file path is not valid UTF-8")?;
+
+    if let Err(e) = write(temporary).and_then(|()| verify(temporary)) {
+        let _ = fs::remove_file(temporary);
+        return Err(e.context(format!("Kept the previous {}", file_path)));
+    }
+
+    if path.is_file() {
+        let backup = format!("{}.bak", file_path);
+        fs::copy(file_path, &backup)
+            .with_context(|| format!("Couldn't back up {} to {}", file_path, backup))?;
+    }
+    fs::rename(temporary, file_path).with_context(|| format!("Couldn't replace {}", file_path))?;
+
+    Ok(())
+}
+
+/// Reads back the results file at `file_path`, written in `format`, and checks that it lists
+/// exactly the IP addresses of `expected`, in order.
+///
+/// Parquet files are not read back, since this build has no Parquet reader.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be parsed or lists other IP addresses.
+pub fn verify_ips(file_path: &str, format: OutputFormat, expected: &[IpAddr]) -> Result<()> {
+    let ips = match format {
+        OutputFormat::Text => read_ips_from_file(file_path)?,
+        OutputFormat::CfstCsv => import::read_results(file_path, ImportFormat::Cfst)?.probed,
+        OutputFormat::Json | OutputFormat::Yaml => read_report(file_path)?
+            .results
+            .iter()
+            .map(|result| result.ip)
+            .collect(),
+        OutputFormat::Parquet => return Ok(()),
+    };
+
+    if ips != expected {
+        anyhow::bail!(
+            "File {} lists {} IP addresses instead of the {} written",
+            file_path,
+            ips.len(),
+            expected.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Opens the file at `file_path` for reading, decompressing it if it ends in `.gz` or `.zst`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened, or if it is compressed with zstd and this
+/// build doesn't support it.
+pub fn open_file(file_path: &str) -> Result<Box<dyn BufRead>> {
+    let file =
+        File::open(file_path).with_context(|| format!("Couldn't open file {}", file_path))?;
+    compression::reader(file, file_path)
+}
+
+/// Creates the file at `file_path` for writing, compressing it if it ends in `.gz` or `.zst`.
+///
+/// The returned writer must be finished once everything was written.
+fn create_output(file_path: &str) -> Result<OutputFile> {
+    OutputFile::new(create_file(file_path)?, file_path)
+}
+
+/// Creates (or truncates) the file at `file_path`, adding a helpful message on permission errors.
+fn create_file(file_path: &str) -> Result<File> {
+    match File::create(file_path) {
+        Ok(file) => Ok(file),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            // Handle permission denied error specifically
+            Err(anyhow::Error::new(e).context(format!("Permission denied when attempting to write to '{}'. Please ensure the application has the necessary permissions, or choose a different location.", file_path)))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_read_ips_from_written_file() {
+        let path = std::env::temp_dir().join("cloudsurf_read_ips_test.txt");
+        let path = path.to_str().unwrap();
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50),
+            ScanResult::new(Ipv4Addr::new(172, 64, 0, 1), 70),
+        ];
+
+        write_ips_to_file(&results, path, &TextOptions::default()).unwrap();
+        let ips = read_ips_from_file(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            ips,
+            [Ipv4Addr::new(104, 16, 0, 1), Ipv4Addr::new(172, 64, 0, 1)].map(IpAddr::V4)
+        );
+    }
+
+    #[test]
+    fn test_rewrite_text_output() {
+        let path = std::env::temp_dir().join("cloudsurf_rewrite_test.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "104.16.0.1 MCI\n104.16.0.1 MTN\n172.64.0.1 MCI\n172.64.0.1 MTN\nmci.ircf.space MCI\n",
+        )
+        .unwrap();
+
+        let output = read_text_output(path).unwrap();
+        assert_eq!(
+            output.ips,
+            [Ipv4Addr::new(104, 16, 0, 1), Ipv4Addr::new(172, 64, 0, 1)].map(IpAddr::V4)
+        );
+        assert_eq!(output.operators, vec!["MCI", "MTN"]);
+        assert_eq!(output.sections, vec!["mci.ircf.space MCI"]);
+
+        let results = vec![ScanResult::new(Ipv4Addr::new(172, 64, 0, 1), 50)];
+        rewrite_text_output(&results, &output, path, &TextOptions::default()).unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            contents,
+            "172.64.0.1 MCI\n172.64.0.1 MTN\nmci.ircf.space MCI\n"
+        );
+    }
+
+    #[test]
+    fn test_text_options() {
+        let path = std::env::temp_dir().join("cloudsurf_per_operator_test.txt");
+        let path = path.to_str().unwrap();
+        let output = TextOutput {
+            ips: Vec::new(),
+            operators: vec!["MCI".to_string(), "MTN".to_string()],
+            sections: vec!["mci.ircf.space MCI".to_string()],
+        };
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50),
+            ScanResult::new(Ipv4Addr::new(172, 64, 0, 1), 70),
+        ];
+
+        let options = TextOptions {
+            header: vec!["Domain: example.com".to_string()],
+            per_operator: Some(1),
+        };
+        rewrite_text_output(&results, &output, path, &options).unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        // The header is not mistaken for an operator section when the file is read back.
+        let sections = read_text_output(path).unwrap().sections;
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            contents,
+            "# Domain: example.com\n104.16.0.1 MCI\n104.16.0.1 MTN\nmci.ircf.space MCI\n"
+        );
+        assert_eq!(sections, output.sections);
+    }
+
+    #[test]
+    fn test_write_cfst_csv() {
+        let path = std::env::temp_dir().join("cloudsurf_cfst_test.csv");
+        let path = path.to_str().unwrap();
+        let mut fast = ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50);
+        fast.speed_mbps = Some(80.0);
+        let results = vec![fast, ScanResult::new(Ipv4Addr::new(172, 64, 0, 1), 70)];
+
+        write_cfst_csv(&results, 1, path).unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines[0], CFST_CSV_HEADER);
+        assert_eq!(lines[1], "104.16.0.1,1,1,0.00,50.00,10.00");
+        assert_eq!(lines[2], "172.64.0.1,1,1,0.00,70.00,0.00");
+
+        // With several passes, IPs that missed some are listed with their loss.
+        let results = vec![
+            ScanResult {
+                passes: Some(4),
+                ..ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50)
+            },
+            ScanResult {
+                passes: Some(3),
+                ..ScanResult::new(Ipv4Addr::new(172, 64, 0, 1), 70)
+            },
+        ];
+
+        write_cfst_csv(&results, 4, path).unwrap();
+        let contents = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines[1], "104.16.0.1,4,4,0.00,50.00,0.00");
+        assert_eq!(lines[2], "172.64.0.1,4,3,0.25,70.00,0.00");
+    }
+
+    #[test]
+    fn test_replace_file() {
+        let dir = std::env::temp_dir().join(format!("cloudsurf-replace-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ips.txt");
+        let path = path.to_str().unwrap();
+        let results = vec![ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50)];
+        let options = TextOptions::default();
+        let listed = options.listed_ips(&results);
+        fs::write(path, "172.64.0.1 MCI\n").unwrap();
+
+        let write = |temporary: &str| write_ips_to_file(&results, temporary, &options);
+        let malformed = replace_file(path, write, |temporary| {
+            verify_ips(temporary, OutputFormat::Text, &[])
+        });
+        assert!(malformed.is_err());
+        assert_eq!(fs::read_to_string(path).unwrap(), "172.64.0.1 MCI\n");
+
+        replace_file(path, write, |temporary| {
+            verify_ips(temporary, OutputFormat::Text, &listed)
+        })
+        .unwrap();
+        assert_eq!(read_ips_from_file(path).unwrap(), listed);
+        assert_eq!(
+            fs::read_to_string(format!("{}.bak", path)).unwrap(),
+            "172.64.0.1 MCI\n"
+        );
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
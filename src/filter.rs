@@ -0,0 +1,151 @@
+#[cfg(feature = "filter-script")]
+use std::path::PathBuf;
+
+#[cfg(feature = "filter-script")]
+use anyhow::anyhow;
+use anyhow::Result;
+#[cfg(feature = "filter-script")]
+use rhai::{Dynamic, Engine, Scope, AST};
+#[cfg(feature = "filter-script")]
+use tracing::debug;
+
+use crate::result::ScanResult;
+
+/// A user-provided Rhai script deciding which results are kept.
+///
+/// The script is evaluated once per result, with the following variables in scope, and must
+/// return `true` to keep the result or `false` to discard it:
+///
+/// * `ip` - The IP address, as a string.
+/// * `port` - The port the handshake completed on, or `()` if unknown.
+/// * `latency` - The latency in milliseconds.
+/// * `tcp_ms` and `tls_ms` - The TCP connection and TLS handshake times, or `()` if unknown.
+/// * `speed` - The download speed in Mbps, or `()` if the speed test was not run.
+/// * `colo` - The data center that answered the HTTP check, e.g. `"FRA"`, or `()` if unknown.
+#[cfg(feature = "filter-script")]
+pub struct ResultFilter {
+    engine: Engine,
+    ast: AST,
+}
+
+/// Stands in for the filter in builds without the `filter-script` feature, where none can be
+/// loaded.
+#[cfg(not(feature = "filter-script"))]
+pub enum ResultFilter {}
+
+#[cfg(not(feature = "filter-script"))]
+impl ResultFilter {
+    pub fn from_file(_path: &str) -> Result<Self> {
+        anyhow::bail!("--filter-script requires a build with the 'filter-script' feature enabled")
+    }
+
+    pub fn apply(&self, _results: Vec<ScanResult>) -> Result<Vec<ScanResult>> {
+        match *self {}
+    }
+}
+
+#[cfg(feature = "filter-script")]
+impl ResultFilter {
+    /// Compiles the script at `path`.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile_file(PathBuf::from(path))
+            .map_err(|e| anyhow!("Couldn't compile filter script {}: {}", path, e))?;
+
+        Ok(ResultFilter { engine, ast })
+    }
+
+    /// Compiles the script in `source`.
+    #[cfg(test)]
+    fn from_source(source: &str) -> Result<Self> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile(source)
+            .map_err(|e| anyhow!("Couldn't compile filter script: {}", e))?;
+
+        Ok(ResultFilter { engine, ast })
+    }
+
+    /// Evaluates the script for `result` and returns whether it is kept.
+    pub fn keep(&self, result: &ScanResult) -> Result<bool> {
+        let mut scope = Scope::new();
+        scope.push("ip", result.ip.to_string());
+        scope.push("port", optional(result.port.map(i64::from)));
+        scope.push("latency", millis(result.latency_ms));
+        scope.push("tcp_ms", optional(result.tcp_ms.map(millis)));
+        scope.push("tls_ms", optional(result.tls_ms.map(millis)));
+        scope.push("speed", optional(result.speed_mbps));
+        scope.push("colo", optional(result.colo().map(ToString::to_string)));
+
+        self.engine
+            .eval_ast_with_scope::<bool>(&mut scope, &self.ast)
+            .map_err(|e| anyhow!("Filter script failed for {}: {}", result.ip, e))
+    }
+
+    /// Keeps the results for which the script returns `true`, preserving their order.
+    pub fn apply(&self, results: Vec<ScanResult>) -> Result<Vec<ScanResult>> {
+        let mut kept = Vec::with_capacity(results.len());
+        for result in results {
+            if self.keep(&result)? {
+                kept.push(result);
+            } else {
+                debug!(ip = %result.ip, "Result discarded by the filter script");
+            }
+        }
+
+        Ok(kept)
+    }
+}
+
+/// Converts a duration in milliseconds to a Rhai integer, saturating on overflow.
+#[cfg(feature = "filter-script")]
+fn millis(value: u128) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// Converts an optional value to a Rhai value, `()` standing for `None`.
+#[cfg(feature = "filter-script")]
+fn optional<T: Into<Dynamic>>(value: Option<T>) -> Dynamic {
+    value.map_or(Dynamic::UNIT, Into::into)
+}
+
+#[cfg(all(test, feature = "filter-script"))]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_apply() {
+        let filter = ResultFilter::from_source(
+            r#"latency < 100 && (colo == () || colo == "FRA") && !ip.starts_with("104.16.1.")"#,
+        )
+        .unwrap();
+        let fast = ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50);
+        let slow = ScanResult::new(Ipv4Addr::new(104, 16, 0, 2), 150);
+        let excluded = ScanResult::new(Ipv4Addr::new(104, 16, 1, 1), 50);
+        let elsewhere = ScanResult {
+            ray_id: Some("8a1b2c3d4e5f6789-LAX".to_string()),
+            ..ScanResult::new(Ipv4Addr::new(104, 16, 0, 3), 50)
+        };
+
+        let kept = filter
+            .apply(vec![fast.clone(), slow, excluded, elsewhere])
+            .unwrap();
+
+        assert_eq!(kept, vec![fast]);
+    }
+
+    #[test]
+    fn test_script_must_return_bool() {
+        let filter = ResultFilter::from_source("latency").unwrap();
+        let result = ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50);
+
+        assert!(filter.keep(&result).is_err());
+    }
+
+    #[test]
+    fn test_invalid_script() {
+        assert!(ResultFilter::from_source("latency <").is_err());
+    }
+}
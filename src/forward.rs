@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use futures::future::join_all;
+use tokio::io::copy_bidirectional;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::args::ForwardArgs;
+use crate::file::IpFile;
+
+/// How long an IP that could not be connected to is only tried after all the others.
+const FAILURE_COOLDOWN: Duration = Duration::from_secs(30);
+/// Weight of a new health check in the moving averages of an IP's latency and error rate.
+const HEALTH_EWMA_WEIGHT: f64 = 0.3;
+/// The error rate from which an IP is considered unhealthy and never made active.
+const MAX_ERROR_RATE: f64 = 0.5;
+
+/// Relays TCP connections accepted on `args.listen` to the best reachable IP of `args.file_path`.
+///
+/// The IPs of the file are health checked in the background, and new connections go to the
+/// active IP: the fastest healthy one, replaced only when it degrades or another IP is faster by
+/// `args.switch_margin`. Connections to a replaced IP are given `args.drain_timeout` seconds to
+/// finish. A file kept fresh by a scan with `--max-age` is followed without restarting. Runs
+/// until interrupted.
+pub async fn serve(args: &ForwardArgs) -> Result<()> {
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .with_context(|| format!("Couldn't listen on {}", args.listen))?;
+    info!(listen = %args.listen, port = args.port, "Forwarding connections");
+
+    let upstreams = Arc::new(Mutex::new(Upstreams::new(IpFile::new(
+        args.file_path.clone(),
+    ))));
+    let connect_timeout = Duration::from_millis(args.connect_timeout_ms);
+    let drain_timeout = Duration::from_secs(args.drain_timeout);
+    let (active_sender, _) = watch::channel(None);
+    let active_sender = Arc::new(active_sender);
+
+    tokio::spawn(check_health(
+        upstreams.clone(),
+        active_sender.clone(),
+        args.port,
+        connect_timeout,
+        Duration::from_secs(args.health_interval),
+        args.switch_margin,
+    ));
+
+    loop {
+        let (client, peer) = listener
+            .accept()
+            .await
+            .context("Couldn't accept connection")?;
+
+        let upstreams = upstreams.clone();
+        let active = active_sender.subscribe();
+        let port = args.port;
+        tokio::spawn(async move {
+            if let Err(e) = relay(
+                client,
+                &upstreams,
+                active,
+                port,
+                connect_timeout,
+                drain_timeout,
+            )
+            .await
+            {
+                debug!(%peer, error = %format!("{:#}", e), "Connection ended");
+            }
+        });
+    }
+}
+
+/// Connects to the first upstream IP that accepts a connection and relays data both ways until
+/// either side closes, or until the connection was drained after the IP stopped being active.
+async fn relay(
+    mut client: TcpStream,
+    upstreams: &Mutex<Upstreams>,
+    mut active: watch::Receiver<Option<IpAddr>>,
+    port: u16,
+    connect_timeout: Duration,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let candidates = upstreams.lock().unwrap().candidates(Instant::now());
+
+    for ip in candidates {
+        match tokio::time::timeout(connect_timeout, TcpStream::connect((ip, port))).await {
+            Ok(Ok(mut upstream)) => {
+                upstreams.lock().unwrap().mark_reachable(ip);
+                debug!(%ip, port, "Relaying connection");
+                tokio::select! {
+                    result = copy_bidirectional(&mut client, &mut upstream) => {
+                        result?;
+                    }
+                    _ = drained(&mut active, ip, drain_timeout) => {
+                        info!(%ip, "Closing a connection to a replaced IP");
+                    }
+                }
+                return Ok(());
+            }
+            Ok(Err(e)) => warn!(%ip, port, error = %e, "Upstream failed, trying the next IP"),
+            Err(_) => warn!(%ip, port, "Upstream timed out, trying the next IP"),
+        }
+        upstreams.lock().unwrap().mark_failed(ip, Instant::now());
+    }
+
+    anyhow::bail!("None of the upstream IPs accepted the connection")
+}
+
+/// Completes `drain_timeout` after another IP than `ip` becomes active.
+async fn drained(
+    active: &mut watch::Receiver<Option<IpAddr>>,
+    ip: IpAddr,
+    drain_timeout: Duration,
+) {
+    loop {
+        if active.changed().await.is_err() {
+            // Without health checks the active IP never changes.
+            return std::future::pending().await;
+        }
+        if *active.borrow() != Some(ip) {
+            break;
+        }
+    }
+
+    tokio::time::sleep(drain_timeout).await;
+}
+
+/// Periodically measures how long each IP of the file takes to accept a connection, and makes
+/// another IP active when the active one degrades.
+async fn check_health(
+    upstreams: Arc<Mutex<Upstreams>>,
+    active: Arc<watch::Sender<Option<IpAddr>>>,
+    port: u16,
+    connect_timeout: Duration,
+    interval: Duration,
+    switch_margin: f64,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+
+        let ips = upstreams.lock().unwrap().file.current().to_vec();
+        let checks = join_all(ips.iter().map(|&ip| async move {
+            let start = Instant::now();
+            let connected =
+                tokio::time::timeout(connect_timeout, TcpStream::connect((ip, port))).await;
+            (ip, matches!(connected, Ok(Ok(_))).then(|| start.elapsed()))
+        }))
+        .await;
+
+        let mut state = upstreams.lock().unwrap();
+        state.health.retain(|ip, _| ips.contains(ip));
+        for (ip, latency) in checks {
+            state.record_check(ip, latency);
+        }
+
+        let current = *active.borrow();
+        if let Some(next) = choose_active(current, &ips, &state.health, switch_margin) {
+            info!(
+                previous = ?current,
+                active = %next,
+                latency_ms = ?state.health[&next].latency_ms,
+                "Switching the active IP"
+            );
+            state.active = Some(next);
+            active.send_replace(Some(next));
+        }
+    }
+}
+
+/// Returns the IP to make active instead of `active`, if any.
+///
+/// Only healthy IPs, whose error rate is below `MAX_ERROR_RATE`, are considered. The fastest of
+/// them replaces the active IP when the active IP is unhealthy or gone from the file, or when it
+/// is faster by more than `switch_margin`, a fraction of the active IP's latency. The margin keeps
+/// the active IP from flapping between IPs of similar latency.
+fn choose_active(
+    active: Option<IpAddr>,
+    ips: &[IpAddr],
+    health: &HashMap<IpAddr, UpstreamHealth>,
+    switch_margin: f64,
+) -> Option<IpAddr> {
+    let healthy_latency = |ip: IpAddr| {
+        health
+            .get(&ip)
+            .filter(|health| health.error_rate < MAX_ERROR_RATE)
+            .and_then(|health| health.latency_ms)
+    };
+
+    let (best, best_latency) = ips
+        .iter()
+        .filter_map(|&ip| healthy_latency(ip).map(|latency| (ip, latency)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    let current = active
+        .filter(|ip| ips.contains(ip))
+        .and_then(|ip| healthy_latency(ip).map(|latency| (ip, latency)));
+    match current {
+        Some((ip, latency)) if ip == best || best_latency >= latency * (1.0 - switch_margin) => {
+            None
+        }
+        _ => Some(best),
+    }
+}
+
+/// Moving averages of the health checks of an IP.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct UpstreamHealth {
+    /// The average time to accept a connection, in milliseconds, if any check succeeded.
+    latency_ms: Option<f64>,
+    /// The average share of failed checks, between 0 and 1.
+    error_rate: f64,
+    /// The number of checks blended into the averages.
+    checks: u64,
+}
+
+/// The upstream IPs, their health, and those that recently failed.
+struct Upstreams {
+    file: IpFile,
+    failures: HashMap<IpAddr, Instant>,
+    health: HashMap<IpAddr, UpstreamHealth>,
+    active: Option<IpAddr>,
+}
+
+impl Upstreams {
+    fn new(file: IpFile) -> Self {
+        Upstreams {
+            file,
+            failures: HashMap::new(),
+            health: HashMap::new(),
+            active: None,
+        }
+    }
+
+    /// Returns the IPs to try, in order: the active IP, the other IPs that didn't fail recently
+    /// in file order, then those that did as a last resort.
+    fn candidates(&mut self, now: Instant) -> Vec<IpAddr> {
+        self.failures
+            .retain(|_, failed_at| now.duration_since(*failed_at) < FAILURE_COOLDOWN);
+
+        let (healthy, failed): (Vec<_>, Vec<_>) = self
+            .file
+            .current()
+            .iter()
+            .copied()
+            .partition(|ip| !self.failures.contains_key(ip));
+        let active = self.active.filter(|ip| healthy.contains(ip));
+
+        active
+            .into_iter()
+            .chain(healthy.into_iter().filter(|&ip| Some(ip) != active))
+            .chain(failed)
+            .collect()
+    }
+
+    /// Blends the outcome of a health check into the IP's averages, `None` meaning it failed.
+    fn record_check(&mut self, ip: IpAddr, latency: Option<Duration>) {
+        let health = self.health.entry(ip).or_default();
+        let latency_ms = latency.map(|latency| latency.as_secs_f64() * 1_000.0);
+        let error = if latency.is_some() { 0.0 } else { 1.0 };
+        let blend = |average: f64, sample: f64| {
+            HEALTH_EWMA_WEIGHT * sample + (1.0 - HEALTH_EWMA_WEIGHT) * average
+        };
+
+        if health.checks == 0 {
+            health.error_rate = error;
+            health.latency_ms = latency_ms;
+        } else {
+            health.error_rate = blend(health.error_rate, error);
+            health.latency_ms = match (health.latency_ms, latency_ms) {
+                (Some(average), Some(sample)) => Some(blend(average, sample)),
+                (average, sample) => sample.or(average),
+            };
+        }
+        health.checks += 1;
+    }
+
+    fn mark_failed(&mut self, ip: IpAddr, now: Instant) {
+        self.failures.insert(ip, now);
+    }
+
+    fn mark_reachable(&mut self, ip: IpAddr) {
+        self.failures.remove(&ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_candidates_try_failed_ips_last() {
+        let path = std::env::temp_dir().join("cloudsurf_forward_test.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "104.16.0.1\n104.16.0.2\n104.16.0.3\n").unwrap();
+        let mut upstreams = Upstreams::new(IpFile::new(path.to_string()));
+        let first = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1));
+        let now = Instant::now();
+
+        upstreams.mark_failed(first, now);
+        let during_cooldown = upstreams.candidates(now);
+        let after_cooldown = upstreams.candidates(now + FAILURE_COOLDOWN);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            during_cooldown,
+            vec![
+                IpAddr::V4(Ipv4Addr::new(104, 16, 0, 2)),
+                IpAddr::V4(Ipv4Addr::new(104, 16, 0, 3)),
+                first
+            ]
+        );
+        assert_eq!(after_cooldown[0], first);
+    }
+
+    #[test]
+    fn test_choose_active() {
+        let first = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 2));
+        let ips = [first, second];
+        let health = |first_ms: f64, first_errors: f64, second_ms: f64| {
+            HashMap::from([
+                (
+                    first,
+                    UpstreamHealth {
+                        latency_ms: Some(first_ms),
+                        error_rate: first_errors,
+                        checks: 5,
+                    },
+                ),
+                (
+                    second,
+                    UpstreamHealth {
+                        latency_ms: Some(second_ms),
+                        error_rate: 0.0,
+                        checks: 5,
+                    },
+                ),
+            ])
+        };
+
+        // Without an active IP, the fastest one is chosen.
+        assert_eq!(
+            choose_active(None, &ips, &health(100.0, 0.0, 50.0), 0.2),
+            Some(second)
+        );
+        // A slightly faster IP doesn't replace the active one...
+        assert_eq!(
+            choose_active(Some(first), &ips, &health(100.0, 0.0, 90.0), 0.2),
+            None
+        );
+        // ...but a much faster one does.
+        assert_eq!(
+            choose_active(Some(first), &ips, &health(100.0, 0.0, 70.0), 0.2),
+            Some(second)
+        );
+        // An unhealthy active IP is replaced whatever its latency.
+        assert_eq!(
+            choose_active(Some(first), &ips, &health(10.0, 0.6, 90.0), 0.2),
+            Some(second)
+        );
+    }
+
+    #[test]
+    fn test_record_check() {
+        let ip = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1));
+        let path = std::env::temp_dir().join("cloudsurf_forward_health_test.txt");
+        let mut upstreams = Upstreams::new(IpFile::new(path.to_str().unwrap().to_string()));
+
+        upstreams.record_check(ip, Some(Duration::from_millis(100)));
+        upstreams.record_check(ip, None);
+
+        let health = &upstreams.health[&ip];
+        assert_eq!(health.latency_ms, Some(100.0));
+        assert!((health.error_rate - 0.3).abs() < 1e-9);
+        assert_eq!(health.checks, 2);
+    }
+}
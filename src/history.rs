@@ -0,0 +1,330 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::net::{IpAddr, Ipv6Addr};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::result::ScanResult;
+
+/// Weight of a new measurement in the exponentially weighted moving average of an IP's latency.
+const EWMA_WEIGHT: f64 = 0.3;
+
+/// Outcomes of past scans, persisted between runs with `--history`.
+///
+/// Statistics are kept per subnet, keyed by the subnet's network address, so that later scans
+/// can focus on the parts of Cloudflare's ranges that worked before.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct History {
+    /// Statistics of every subnet that was probed at least once, see `subnet_of`.
+    #[serde(default)]
+    pub subnets: BTreeMap<IpAddr, SubnetHistory>,
+    /// Latency history of every IP address that was among the valid results at least once.
+    #[serde(default)]
+    pub ips: BTreeMap<IpAddr, IpHistory>,
+}
+
+/// Latency history of a single IP address.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IpHistory {
+    /// Exponentially weighted moving average of the measured latencies, in milliseconds.
+    pub ewma_latency_ms: f64,
+    /// The number of measurements blended into the average.
+    pub samples: u64,
+    /// The number of recorded scans that probed this IP address, since it was first valid.
+    #[serde(default)]
+    pub runs: u64,
+    /// The number of recorded scans this IP address was among the valid results of.
+    #[serde(default)]
+    pub successes: u64,
+}
+
+impl IpHistory {
+    /// The share of the recorded scans this IP address was valid in, as a percentage, or `None`
+    /// if it was never recorded as probed.
+    pub fn reliability_pct(&self) -> Option<f64> {
+        (self.runs > 0).then(|| 100.0 * self.successes as f64 / self.runs as f64)
+    }
+
+    /// Blends `latency_ms` into the moving average, which starts from the first measurement.
+    fn blend(&mut self, latency_ms: u128) {
+        let latency_ms = latency_ms as f64;
+        self.ewma_latency_ms = if self.samples == 0 {
+            latency_ms
+        } else {
+            EWMA_WEIGHT * latency_ms + (1.0 - EWMA_WEIGHT) * self.ewma_latency_ms
+        };
+        self.samples += 1;
+    }
+}
+
+/// Statistics about the IP addresses of one subnet over all recorded scans.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubnetHistory {
+    /// The number of times an IP address of the subnet was probed.
+    pub probed: u64,
+    /// The number of times an IP address of the subnet was among the valid results.
+    pub valid: u64,
+}
+
+impl History {
+    /// Loads the history stored at `path`, or an empty history if the file does not exist yet.
+    pub fn load(path: &str) -> Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!(path, "No history yet, starting a new one");
+                return Ok(History::default());
+            }
+            Err(e) => return Err(e).with_context(|| format!("Couldn't open history {}", path)),
+        };
+
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Couldn't parse history from file {}", path))
+    }
+
+    /// Writes the history to `path`, replacing the previous one.
+    ///
+    /// The history is written to a temporary file first, so that an interrupted write cannot
+    /// corrupt it.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let temporary_path = format!("{}.tmp", path);
+        let file = File::create(&temporary_path)
+            .with_context(|| format!("Couldn't create file {}", temporary_path))?;
+        serde_json::to_writer(BufWriter::new(file), self)
+            .with_context(|| format!("Couldn't write history to file {}", temporary_path))?;
+
+        fs::rename(&temporary_path, path)
+            .with_context(|| format!("Couldn't replace history {}", path))
+    }
+
+    /// Records the outcome of a scan.
+    ///
+    /// The runs of single IP addresses are only counted once they have been valid, so that the
+    /// history doesn't grow with every address ever sampled.
+    ///
+    /// # Arguments
+    ///
+    /// * `probed` - The IP addresses that were probed.
+    /// * `valid` - The IP addresses that were reachable.
+    pub fn record(&mut self, probed: &[IpAddr], valid: impl IntoIterator<Item = IpAddr>) {
+        let valid_ips: HashSet<IpAddr> = valid.into_iter().collect();
+        for &ip in probed {
+            self.subnets.entry(subnet_of(ip)).or_default().probed += 1;
+            if let Some(entry) = self.ips.get_mut(&ip).filter(|_| !valid_ips.contains(&ip)) {
+                entry.runs += 1;
+            }
+        }
+        for &ip in &valid_ips {
+            self.subnets.entry(subnet_of(ip)).or_default().valid += 1;
+            let entry = self.ips.entry(ip).or_default();
+            entry.runs += 1;
+            entry.successes += 1;
+        }
+    }
+
+    /// Records the reliability of each result's IP address in the result, see
+    /// `IpHistory::reliability_pct`.
+    pub fn annotate_reliability(&self, results: &mut [ScanResult]) {
+        for result in results {
+            if let Some(entry) = self.ips.get(&result.ip).filter(|entry| entry.runs > 0) {
+                result.reliability_pct = entry.reliability_pct();
+                result.runs_seen = Some(entry.runs);
+            }
+        }
+    }
+
+    /// Blends the latency of each result into its IP's moving average and records the average in
+    /// the result's `smoothed_latency_ms`.
+    ///
+    /// A new measurement weighs 30% of the average, so a single lucky sample cannot promote a
+    /// flaky IP to the top. IPs measured for the first time start from their current latency.
+    pub fn smooth_latencies(&mut self, results: &mut [ScanResult]) {
+        for result in results {
+            let entry = self.ips.entry(result.ip).or_default();
+            entry.blend(result.latency_ms);
+
+            result.smoothed_latency_ms = Some(entry.ewma_latency_ms.round() as u128);
+        }
+    }
+
+    /// Records in `result` the smoothed latency and reliability its IP will have once the result
+    /// is blended in by `smooth_latencies` and recorded as valid, without changing the history.
+    ///
+    /// This ranks results while probing, before the scan is recorded.
+    pub fn preview(&self, result: &mut ScanResult) {
+        let mut entry = self.ips.get(&result.ip).cloned().unwrap_or_default();
+        entry.blend(result.latency_ms);
+        entry.runs += 1;
+        entry.successes += 1;
+
+        result.smoothed_latency_ms = Some(entry.ewma_latency_ms.round() as u128);
+        result.reliability_pct = entry.reliability_pct();
+        result.runs_seen = Some(entry.runs);
+    }
+
+    /// Returns the subnets that produced at least one valid IP in a past scan.
+    pub fn good_subnets(&self) -> HashSet<IpAddr> {
+        self.subnets
+            .iter()
+            .filter(|(_, stats)| stats.valid > 0)
+            .map(|(&subnet, _)| subnet)
+            .collect()
+    }
+}
+
+/// Returns the network address of the subnet containing `ip`: its /24 for IPv4, or its /48 for
+/// IPv6, the size of the prefixes announced for a single site.
+pub fn subnet_of(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, ..] = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{select_results, CheckOptions};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_record() {
+        let mut history = History::default();
+        let probed = [
+            Ipv4Addr::new(104, 16, 0, 1),
+            Ipv4Addr::new(104, 16, 0, 2),
+            Ipv4Addr::new(172, 64, 0, 1),
+        ]
+        .map(IpAddr::V4);
+        history.record(&probed, [IpAddr::V4(Ipv4Addr::new(104, 16, 0, 2))]);
+
+        let subnet = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 0));
+        assert_eq!(
+            history.subnets[&subnet],
+            SubnetHistory {
+                probed: 2,
+                valid: 1
+            }
+        );
+        assert_eq!(history.good_subnets(), HashSet::from([subnet]));
+    }
+
+    #[test]
+    fn test_reliability() {
+        let ip = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1));
+        let mut history = History::default();
+
+        history.record(&[ip], [ip]);
+        history.record(&[ip], []);
+        history.record(&[ip], [ip]);
+        history.record(&[ip], [ip]);
+
+        let mut results = vec![ScanResult::new(ip, 40)];
+        history.annotate_reliability(&mut results);
+        assert_eq!(results[0].reliability_pct, Some(75.0));
+        assert_eq!(results[0].runs_seen, Some(4));
+
+        // Addresses that were never valid are not tracked.
+        let other = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 2));
+        history.record(&[other], []);
+        assert!(!history.ips.contains_key(&other));
+    }
+
+    #[test]
+    fn test_reliability_outside_max_ips() {
+        let fast = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1));
+        let slow = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 2));
+        let mut history = History::default();
+
+        // Both IPs are reachable in every run, but only the fastest is kept.
+        for _ in 0..3 {
+            let reachable = vec![ScanResult::new(fast, 40), ScanResult::new(slow, 60)];
+            history.record(&[fast, slow], [fast, slow]);
+            let selected = select_results(reachable, &CheckOptions::new(1));
+            assert_eq!(selected.len(), 1);
+        }
+
+        let mut results = vec![ScanResult::new(slow, 60)];
+        history.annotate_reliability(&mut results);
+        assert_eq!(results[0].reliability_pct, Some(100.0));
+        assert_eq!(results[0].runs_seen, Some(3));
+    }
+
+    #[test]
+    fn test_smooth_latencies() {
+        let ip = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1));
+        let mut history = History::default();
+
+        let mut first = vec![ScanResult::new(ip, 100)];
+        history.smooth_latencies(&mut first);
+        assert_eq!(first[0].smoothed_latency_ms, Some(100));
+
+        // A single fast sample only moves the average part of the way.
+        let mut second = vec![ScanResult::new(ip, 10)];
+        history.smooth_latencies(&mut second);
+        assert_eq!(second[0].latency_ms, 10);
+        assert_eq!(second[0].smoothed_latency_ms, Some(73));
+        assert_eq!(history.ips[&ip].samples, 2);
+    }
+
+    #[test]
+    fn test_preview() {
+        let ip = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1));
+        let mut history = History::default();
+        history.smooth_latencies(&mut [ScanResult::new(ip, 100)]);
+
+        let mut previewed = ScanResult::new(ip, 10);
+        history.preview(&mut previewed);
+        assert_eq!(previewed.smoothed_latency_ms, Some(73));
+        assert_eq!(history.ips[&ip].samples, 1);
+
+        assert_eq!(previewed.reliability_pct, Some(100.0));
+        assert_eq!(previewed.runs_seen, Some(1));
+
+        let mut smoothed = [ScanResult::new(ip, 10)];
+        history.smooth_latencies(&mut smoothed);
+        history.record(&[ip], [ip]);
+        history.annotate_reliability(&mut smoothed);
+        assert_eq!(smoothed[0], previewed);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join("cloudsurf_test_history.json");
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        assert_eq!(History::load(path).unwrap(), History::default());
+
+        let mut history = History::default();
+        history.record(
+            &[IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1))],
+            [IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1))],
+        );
+        history.save(path).unwrap();
+
+        assert_eq!(History::load(path).unwrap(), history);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_subnet_of() {
+        assert_eq!(
+            subnet_of("104.16.132.229".parse().unwrap()),
+            "104.16.132.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            subnet_of("2606:4700:10::6816:84e5".parse().unwrap()),
+            "2606:4700:10::".parse::<IpAddr>().unwrap()
+        );
+    }
+}
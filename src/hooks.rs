@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use tokio::process::Command;
+use tracing::{debug, info};
+
+use crate::args::Args;
+
+/// Returns the scan parameters exported to hook processes as environment variables.
+///
+/// Every variable is prefixed with `CLOUDSURF_`, and unset parameters are exported as empty
+/// strings.
+pub fn scan_environment(args: &Args) -> Vec<(String, String)> {
+    let ports: Vec<String> = args.ports.iter().map(ToString::to_string).collect();
+    let skip_prefixes: Vec<String> = args.skip_prefixes.iter().map(ToString::to_string).collect();
+
+    [
+        ("DOMAIN", args.domain.clone().unwrap_or_default()),
+        ("PORTS", ports.join(",")),
+        ("COUNT", args.count.to_string()),
+        ("MAX_VALID_IPS", args.max_valid_ips.to_string()),
+        ("SKIP_PREFIXES", skip_prefixes.join(",")),
+        (
+            "FILE_PATH",
+            args.output_path().unwrap_or_default().to_string(),
+        ),
+    ]
+    .into_iter()
+    .map(|(name, value)| (format!("CLOUDSURF_{}", name), value))
+    .collect()
+}
+
+/// Uploads `file` to the scp `destination` with the system's `scp`.
+///
+/// Password prompts are disabled, so the upload fails instead of hanging when no SSH key is
+/// accepted.
+///
+/// # Errors
+///
+/// Returns an error if `scp` couldn't be started or exited with a failure status.
+pub async fn push_ssh(file: &str, destination: &str) -> Result<()> {
+    info!(file, destination, "Uploading results");
+
+    let status = Command::new("scp")
+        .args(["-q", "-o", "BatchMode=yes", file, destination])
+        .status()
+        .await
+        .context("Couldn't run scp")?;
+
+    if !status.success() {
+        anyhow::bail!(
+            "Uploading {} to {} failed with {}",
+            file,
+            destination,
+            status
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs a user-provided hook command through the system shell and waits for it to finish.
+///
+/// Every `{name}` placeholder in `command` is replaced by the corresponding value of
+/// `placeholders`, quoted for the shell so that paths with spaces or special characters are
+/// passed as a single argument. The variables of `env` are added to the environment of the
+/// command.
+///
+/// # Arguments
+///
+/// * `name` - The name of the hook, used in logs and errors.
+/// * `command` - The command line to run.
+/// * `placeholders` - The placeholder names, without braces, and their values.
+/// * `env` - Environment variables to set for the command.
+///
+/// # Errors
+///
+/// Returns an error if the command couldn't be started or exited with a failure status.
+pub async fn run(
+    name: &str,
+    command: &str,
+    placeholders: &[(&str, String)],
+    env: &[(String, String)],
+) -> Result<()> {
+    let command_line = expand(command, placeholders);
+    info!(hook = name, command = %command_line, "Running hook");
+
+    let status = shell(&command_line)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .status()
+        .await
+        .with_context(|| format!("Couldn't run the {} hook", name))?;
+
+    debug!(hook = name, %status, "Hook finished");
+    if !status.success() {
+        anyhow::bail!("The {} hook failed with {}", name, status);
+    }
+
+    Ok(())
+}
+
+/// Replaces the placeholders of `command` with their quoted values.
+fn expand(command: &str, placeholders: &[(&str, String)]) -> String {
+    placeholders
+        .iter()
+        .fold(command.to_string(), |command, (name, value)| {
+            command.replace(&format!("{{{}}}", name), &quote(value))
+        })
+}
+
+/// Creates a command running `command_line` through the system shell.
+#[cfg(unix)]
+fn shell(command_line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(command_line);
+    command
+}
+
+#[cfg(windows)]
+fn shell(command_line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(command_line);
+    command
+}
+
+/// Quotes `value` so that the shell passes it as a single argument.
+#[cfg(unix)]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let placeholders = [
+            ("file", "/tmp/my results.txt".to_string()),
+            ("best_ip", "104.16.0.1".to_string()),
+        ];
+
+        assert_eq!(
+            expand("restart.sh {file} {best_ip} {unknown}", &placeholders),
+            "restart.sh '/tmp/my results.txt' '104.16.0.1' {unknown}"
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        assert!(run("test", "true", &[], &[]).await.is_ok());
+        assert!(run("test", "exit 3", &[], &[]).await.is_err());
+
+        let env = [("CLOUDSURF_DOMAIN".to_string(), "example.com".to_string())];
+        let check = r#"test "$CLOUDSURF_DOMAIN" = example.com"#;
+        assert!(run("test", check, &[], &env).await.is_ok());
+    }
+}
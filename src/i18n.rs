@@ -0,0 +1,268 @@
+use std::env;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+/// The language of the tables, summaries and errors printed for the user.
+///
+/// Logs, reports and option names are always in English, so that they can be searched for and
+/// processed regardless of the language.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Lang {
+    /// English.
+    #[default]
+    En,
+    /// Persian.
+    Fa,
+}
+
+/// The language chosen for this run, see `init`.
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Sets the language of the messages printed for the rest of the run, detecting it from the
+/// locale when `lang` is not given.
+pub fn init(lang: Option<Lang>) {
+    let _ = LANG.set(lang.unwrap_or_else(Lang::detect));
+}
+
+/// The language of the messages printed, English until `init` is called.
+pub fn current() -> Lang {
+    LANG.get().copied().unwrap_or_default()
+}
+
+impl Lang {
+    /// Picks the language of the locale in the environment, as `gettext` does, or English if it
+    /// isn't translated.
+    pub fn detect() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|locale| !locale.is_empty())
+            .map_or(Lang::En, |locale| Lang::from_locale(&locale))
+    }
+
+    /// Picks the language of a locale name such as `fa_IR.UTF-8`.
+    fn from_locale(locale: &str) -> Self {
+        let language = locale
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default();
+        if language.eq_ignore_ascii_case("fa") {
+            Lang::Fa
+        } else {
+            Lang::En
+        }
+    }
+}
+
+/// A message printed for the user, with `{}` placeholders for its arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    IpAddress,
+    Family,
+    Port,
+    LatencyMs,
+    VsBaselineMs,
+    SmoothedMs,
+    UnfragmentedMs,
+    Passes,
+    Reliability,
+    TcpMs,
+    TlsMs,
+    DnsMs,
+    SpeedMbps,
+    RequestMs,
+    Provider,
+    Source,
+    Seed,
+    Sample,
+    SniFallback,
+    ReliabilityOfRuns,
+    BlockedDomain,
+    Fragmentation,
+    Baseline,
+    MatrixSummary,
+    Colo,
+    Ips,
+    BestMs,
+    MedianMs,
+    Block,
+    Probed,
+    Reachable,
+    SuccessPct,
+    What,
+    Location,
+    Check,
+    Status,
+    Details,
+    IpAddresses,
+    SuccessRatePct,
+    BestLatencyMs,
+    MedianLatencyMs,
+    MeanLatencyMs,
+    H2Passed,
+    StreamErrors,
+    GrpcPassed,
+    GrpcPassedWithStatus,
+    Failed,
+    NotificationSummary,
+    NoValidIp,
+    Error,
+}
+
+impl Message {
+    /// The message in `lang`.
+    pub fn text(self, lang: Lang) -> &'static str {
+        let (en, fa) = match self {
+            Message::IpAddress => ("IP Address", "آدرس IP"),
+            Message::Family => ("Family", "نوع"),
+            Message::Port => ("Port", "پورت"),
+            Message::LatencyMs => ("Latency (ms)", "تأخیر (میلی‌ثانیه)"),
+            Message::VsBaselineMs => ("vs Baseline (ms)", "نسبت به مبنا (میلی‌ثانیه)"),
+            Message::SmoothedMs => ("Smoothed (ms)", "هموارشده (میلی‌ثانیه)"),
+            Message::UnfragmentedMs => ("Unfragmented (ms)", "بدون قطعه‌بندی (میلی‌ثانیه)"),
+            Message::Passes => ("Passes", "دفعات موفق"),
+            Message::Reliability => ("Reliability", "پایداری"),
+            Message::TcpMs => ("TCP (ms)", "TCP (میلی‌ثانیه)"),
+            Message::TlsMs => ("TLS (ms)", "TLS (میلی‌ثانیه)"),
+            Message::DnsMs => ("DNS (ms)", "DNS (میلی‌ثانیه)"),
+            Message::SpeedMbps => ("Speed (Mbps)", "سرعت (مگابیت بر ثانیه)"),
+            Message::RequestMs => ("Request (ms)", "درخواست (میلی‌ثانیه)"),
+            Message::Provider => ("Provider", "ارائه‌دهنده"),
+            Message::Source => ("Source", "منبع"),
+            Message::Seed => ("seed", "فهرست مشترک"),
+            Message::Sample => ("sample", "نمونه تصادفی"),
+            Message::SniFallback => ("{} (fallback)", "{} (جایگزین)"),
+            Message::ReliabilityOfRuns => ("{}% of {} runs", "{}٪ از {} اجرا"),
+            Message::BlockedDomain => (
+                "{} blocks the scanned domain, use SNI {} with it",
+                "{} دامنه اسکن‌شده را مسدود می‌کند، با آن از SNI {} استفاده کنید",
+            ),
+            Message::Fragmentation => (
+                "Fragmented ClientHello: {} IPs reachable either way, {} only fragmented, {} only unfragmented",
+                "ClientHello قطعه‌بندی‌شده: {} IP در هر دو حالت در دسترس، {} فقط با قطعه‌بندی، {} فقط بدون قطعه‌بندی",
+            ),
+            Message::Baseline => (
+                "Baseline: {} answered in {} ms through {}, its DNS-resolved address",
+                "مبنا: {} در {} میلی‌ثانیه از طریق {}، آدرس به‌دست‌آمده از DNS، پاسخ داد",
+            ),
+            Message::MatrixSummary => (
+                "{} of {} IPs work for all {} domains",
+                "{} از {} IP برای هر {} دامنه کار می‌کنند",
+            ),
+            Message::Colo => ("Colo", "دیتاسنتر"),
+            Message::Ips => ("IPs", "تعداد IP"),
+            Message::BestMs => ("Best (ms)", "بهترین (میلی‌ثانیه)"),
+            Message::MedianMs => ("Median (ms)", "میانه (میلی‌ثانیه)"),
+            Message::Block => ("Block", "بلوک"),
+            Message::Probed => ("Probed", "بررسی‌شده"),
+            Message::Reachable => ("Reachable", "در دسترس"),
+            Message::SuccessPct => ("Success (%)", "موفقیت (٪)"),
+            Message::What => ("What", "مورد"),
+            Message::Location => ("Location", "مسیر"),
+            Message::Check => ("Check", "بررسی"),
+            Message::Status => ("Status", "وضعیت"),
+            Message::Details => ("Details", "جزئیات"),
+            Message::IpAddresses => ("IP Addresses", "آدرس‌های IP"),
+            Message::SuccessRatePct => ("Success Rate (%)", "نرخ موفقیت (٪)"),
+            Message::BestLatencyMs => ("Best Latency (ms)", "بهترین تأخیر (میلی‌ثانیه)"),
+            Message::MedianLatencyMs => ("Median Latency (ms)", "میانه تأخیر (میلی‌ثانیه)"),
+            Message::MeanLatencyMs => ("Mean Latency (ms)", "میانگین تأخیر (میلی‌ثانیه)"),
+            Message::H2Passed => ("OK ({} requests)", "موفق ({} درخواست)"),
+            Message::StreamErrors => ("{} stream errors", "{} خطای جریان"),
+            Message::GrpcPassed => ("OK ({} ms)", "موفق ({} میلی‌ثانیه)"),
+            Message::GrpcPassedWithStatus => (
+                "OK ({} ms, status {})",
+                "موفق ({} میلی‌ثانیه، وضعیت {})",
+            ),
+            Message::Failed => ("Failed", "ناموفق"),
+            Message::NotificationSummary => (
+                "{} valid IPs, best latency {} ms",
+                "{} IP معتبر، بهترین تأخیر {} میلی‌ثانیه",
+            ),
+            Message::NoValidIp => ("No valid IP found", "هیچ IP معتبری پیدا نشد"),
+            Message::Error => ("Error", "خطا"),
+        };
+
+        match lang {
+            Lang::En => en,
+            Lang::Fa => fa,
+        }
+    }
+}
+
+/// The message in the current language.
+pub fn text(message: Message) -> &'static str {
+    message.text(current())
+}
+
+/// The message in the current language, with its placeholders replaced by `args` in order.
+pub fn format(message: Message, args: &[&dyn Display]) -> String {
+    fill(text(message), args)
+}
+
+/// Replaces the `{}` placeholders of `template` by `args` in order.
+///
+/// Placeholders left without an argument are kept as is, and extra arguments are ignored.
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut rest = template;
+    while let Some(position) = rest.find("{}") {
+        filled.push_str(&rest[..position]);
+        match args.next() {
+            Some(arg) => filled.push_str(&arg.to_string()),
+            None => filled.push_str("{}"),
+        }
+        rest = &rest[position + 2..];
+    }
+    filled.push_str(rest);
+
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_locale() {
+        assert_eq!(Lang::from_locale("fa_IR.UTF-8"), Lang::Fa);
+        assert_eq!(Lang::from_locale("fa"), Lang::Fa);
+        assert_eq!(Lang::from_locale("en_US.UTF-8"), Lang::En);
+        assert_eq!(Lang::from_locale("C"), Lang::En);
+        assert_eq!(Lang::from_locale("fr_FR"), Lang::En);
+    }
+
+    #[test]
+    fn test_fill() {
+        assert_eq!(fill("{} of {} IPs", &[&3, &"10"]), "3 of 10 IPs");
+        assert_eq!(fill("OK ({} ms)", &[]), "OK ({} ms)");
+    }
+
+    #[test]
+    fn test_placeholders_match() {
+        let messages = [
+            Message::SniFallback,
+            Message::ReliabilityOfRuns,
+            Message::BlockedDomain,
+            Message::Fragmentation,
+            Message::Baseline,
+            Message::MatrixSummary,
+            Message::H2Passed,
+            Message::StreamErrors,
+            Message::GrpcPassed,
+            Message::GrpcPassedWithStatus,
+            Message::NotificationSummary,
+        ];
+        for message in messages {
+            assert_eq!(
+                message.text(Lang::En).matches("{}").count(),
+                message.text(Lang::Fa).matches("{}").count(),
+                "{:?}",
+                message
+            );
+        }
+    }
+}
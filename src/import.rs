@@ -0,0 +1,132 @@
+use std::io::BufRead;
+use std::net::IpAddr;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+use crate::file::open_file;
+use crate::result::ScanResult;
+
+/// Formats of the result files written by other scanners.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// The `result.csv` of CloudflareSpeedTest: IP, sent, received, loss, latency, MB/s.
+    Cfst,
+}
+
+/// Results read from another scanner's file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Imported {
+    /// The IP addresses listed in the file, reachable or not.
+    pub probed: Vec<IpAddr>,
+    /// The reachable IP addresses, sorted by latency.
+    pub results: Vec<ScanResult>,
+}
+
+/// Reads the results of another scanner from `file_path`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or a row cannot be parsed.
+pub fn read_results(file_path: &str, format: ImportFormat) -> Result<Imported> {
+    let reader = open_file(file_path)?;
+
+    let mut imported = Imported {
+        probed: Vec::new(),
+        results: Vec::new(),
+    };
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Couldn't read from file {}", file_path))?;
+        // The first line is the header.
+        if index == 0 || line.trim().is_empty() {
+            continue;
+        }
+
+        let (ip, result) = match format {
+            ImportFormat::Cfst => parse_cfst_row(&line),
+        }
+        .with_context(|| format!("Invalid row {} in file {}", index + 1, file_path))?;
+
+        imported.probed.push(ip);
+        imported.results.extend(result);
+    }
+
+    imported.results.sort_by_key(|result| result.latency_ms);
+
+    Ok(imported)
+}
+
+/// Parses a row of CloudflareSpeedTest's `result.csv`.
+///
+/// Returns the IP without a result if none of its pings was received. A speed of 0, written when
+/// the speed test is disabled, is read as not measured.
+fn parse_cfst_row(line: &str) -> Result<(IpAddr, Option<ScanResult>)> {
+    let columns: Vec<_> = line.split(',').map(str::trim).collect();
+    let [ip, _sent, received, _loss, latency, speed, ..] = columns[..] else {
+        anyhow::bail!("Expected at least 6 columns, found {}", columns.len());
+    };
+
+    let ip: IpAddr = ip.parse().context("Invalid IP address")?;
+    let received: u64 = received.parse().context("Invalid received count")?;
+    if received == 0 {
+        return Ok((ip, None));
+    }
+
+    let latency: f64 = latency.parse().context("Invalid latency")?;
+    let speed: f64 = speed.parse().context("Invalid download speed")?;
+
+    let mut result = ScanResult::new(ip, latency.round() as u128);
+    result.speed_mbps = (speed > 0.0).then_some(speed * 8.0);
+
+    Ok((ip, Some(result)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_parse_cfst_row() {
+        let (ip, result) = parse_cfst_row("104.16.0.1,4,4,0.00,50.25,12.50,LAX").unwrap();
+        let result = result.unwrap();
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1)));
+        assert_eq!(result.latency_ms, 50);
+        assert_eq!(result.speed_mbps, Some(100.0));
+
+        let (_, unreachable) = parse_cfst_row("104.16.0.2,4,0,1.00,0.00,0.00").unwrap();
+        assert!(unreachable.is_none());
+
+        let (_, unmeasured) = parse_cfst_row("104.16.0.3,4,4,0.00,80.00,0.00").unwrap();
+        assert_eq!(unmeasured.unwrap().speed_mbps, None);
+
+        let (ipv6, _) = parse_cfst_row("2606:4700::1,4,4,0.00,50.00,0.00").unwrap();
+        assert!(ipv6.is_ipv6());
+        assert!(parse_cfst_row("104.16.0.1,4,4").is_err());
+        assert!(parse_cfst_row("not-an-ip,4,4,0.00,50.00,0.00").is_err());
+    }
+
+    #[test]
+    fn test_read_cfst_results() {
+        let path = std::env::temp_dir().join("cloudsurf_import_test.csv");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "IP 地址,已发送,已接收,丢包率,平均延迟,下载速度 (MB/s)\n\
+             172.64.0.1,4,4,0.00,70.00,0.00\n\
+             104.16.0.1,4,4,0.00,50.00,0.00\n\
+             104.16.0.2,4,0,1.00,0.00,0.00\n",
+        )
+        .unwrap();
+
+        let imported = read_results(path, ImportFormat::Cfst).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(imported.probed.len(), 3);
+        let ips: Vec<_> = imported.results.iter().map(|result| result.ip).collect();
+        assert_eq!(
+            ips,
+            [Ipv4Addr::new(104, 16, 0, 1), Ipv4Addr::new(172, 64, 0, 1)].map(IpAddr::V4)
+        );
+    }
+}
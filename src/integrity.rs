@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use tracing::info;
+
+/// Algorithm tag of minisign signatures computed over the whole file with Ed25519.
+const MINISIGN_ALGORITHM: &[u8; 2] = b"Ed";
+
+/// Writes the SHA-256 checksum of `path` to `<path>.sha256`, in the format of `sha256sum`, so
+/// that it can be verified with `sha256sum -c`.
+///
+/// Returns the path of the checksum file.
+///
+/// # Errors
+///
+/// Returns an error if `path` cannot be read or the checksum file cannot be written.
+pub fn write_checksum(path: &str) -> Result<String> {
+    let contents = fs::read(path).with_context(|| format!("Couldn't read file {}", path))?;
+    let checksum = hex(digest(&SHA256, &contents).as_ref());
+
+    let checksum_path = format!("{}.sha256", path);
+    fs::write(
+        &checksum_path,
+        format!("{}  {}\n", checksum, file_name(path)),
+    )
+    .with_context(|| format!("Couldn't write checksum to file {}", checksum_path))?;
+    info!(path = checksum_path.as_str(), "Wrote checksum");
+
+    Ok(checksum_path)
+}
+
+/// An Ed25519 key signing the result files in the minisign format.
+pub struct SigningKey {
+    pair: Ed25519KeyPair,
+    /// The key identifier written to signatures, derived from the public key since the key
+    /// file doesn't carry one.
+    key_id: [u8; 8],
+}
+
+impl SigningKey {
+    /// Loads a PKCS#8 Ed25519 private key, in PEM or DER form, such as the ones generated by
+    /// `openssl genpkey -algorithm ed25519`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or doesn't hold an Ed25519 private key.
+    pub fn load(path: &str) -> Result<Self> {
+        let contents =
+            fs::read(path).with_context(|| format!("Couldn't read key file {}", path))?;
+        let der = match std::str::from_utf8(&contents) {
+            Ok(pem) if pem.contains("-----BEGIN") => {
+                let body: String = pem
+                    .lines()
+                    .filter(|line| !line.starts_with("-----"))
+                    .collect();
+                BASE64
+                    .decode(body.trim())
+                    .with_context(|| format!("Key file {} is not valid PEM", path))?
+            }
+            _ => contents,
+        };
+
+        let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der).map_err(|e| {
+            anyhow::anyhow!("Key file {} is not an Ed25519 private key: {}", path, e)
+        })?;
+        let mut key_id = [0; 8];
+        key_id.copy_from_slice(&digest(&SHA256, pair.public_key().as_ref()).as_ref()[..8]);
+
+        Ok(SigningKey { pair, key_id })
+    }
+
+    /// Returns the public key in the format of minisign public key files, to be passed to
+    /// `minisign -V -P` on the devices verifying the results.
+    pub fn public_key(&self) -> String {
+        let mut key = Vec::with_capacity(42);
+        key.extend_from_slice(MINISIGN_ALGORITHM);
+        key.extend_from_slice(&self.key_id);
+        key.extend_from_slice(self.pair.public_key().as_ref());
+
+        BASE64.encode(key)
+    }
+
+    /// Signs `path`, writing the signature to `<path>.minisig`.
+    ///
+    /// Returns the path of the signature file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `path` cannot be read or the signature file cannot be written.
+    pub fn sign_file(&self, path: &str) -> Result<String> {
+        let contents = fs::read(path).with_context(|| format!("Couldn't read file {}", path))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let signature_path = format!("{}.minisig", path);
+        fs::write(
+            &signature_path,
+            self.signature(&contents, timestamp, file_name(path)),
+        )
+        .with_context(|| format!("Couldn't write signature to file {}", signature_path))?;
+        info!(
+            path = signature_path.as_str(),
+            public_key = self.public_key().as_str(),
+            "Wrote signature"
+        );
+
+        Ok(signature_path)
+    }
+
+    /// Returns the minisign signature file of `contents`, whose trusted comment records
+    /// `timestamp` and `file_name`.
+    fn signature(&self, contents: &[u8], timestamp: u64, file_name: &str) -> String {
+        let mut signature = Vec::with_capacity(74);
+        signature.extend_from_slice(MINISIGN_ALGORITHM);
+        signature.extend_from_slice(&self.key_id);
+        signature.extend_from_slice(self.pair.sign(contents).as_ref());
+
+        // The trusted comment is authenticated by a second signature, over the first one.
+        let trusted_comment = format!("timestamp:{}\tfile:{}", timestamp, file_name);
+        let mut global = signature[10..].to_vec();
+        global.extend_from_slice(trusted_comment.as_bytes());
+
+        format!(
+            "untrusted comment: signature from cloudsurf\n{}\ntrusted comment: {}\n{}\n",
+            BASE64.encode(&signature),
+            trusted_comment,
+            BASE64.encode(self.pair.sign(&global))
+        )
+    }
+}
+
+/// Returns the file name of `path`, as listed in checksum and signature files.
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+/// Formats `bytes` as lowercase hexadecimal.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    #[test]
+    fn test_write_checksum() {
+        let path =
+            std::env::temp_dir().join(format!("cloudsurf-checksum-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "abc").unwrap();
+
+        let checksum_path = write_checksum(path).unwrap();
+        let checksum = fs::read_to_string(&checksum_path).unwrap();
+        fs::remove_file(path).unwrap();
+        fs::remove_file(checksum_path).unwrap();
+
+        assert_eq!(
+            checksum,
+            format!(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  {}\n",
+                file_name(path)
+            )
+        );
+    }
+
+    #[test]
+    fn test_signature_verifies() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let path = std::env::temp_dir().join(format!("cloudsurf-key-{}.der", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, pkcs8.as_ref()).unwrap();
+        let key = SigningKey::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let signature = key.signature(b"104.16.0.1\n", 1_714_564_800, "ips.txt");
+        let lines: Vec<_> = signature.lines().collect();
+        assert_eq!(
+            lines[2],
+            "trusted comment: timestamp:1714564800\tfile:ips.txt"
+        );
+
+        let public_key = BASE64.decode(key.public_key()).unwrap();
+        let signature = BASE64.decode(lines[1]).unwrap();
+        assert_eq!(signature[..10], public_key[..10]);
+        UnparsedPublicKey::new(&ED25519, &public_key[10..])
+            .verify(b"104.16.0.1\n", &signature[10..])
+            .unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+use crate::report::WarningCode;
+use crate::warnings;
+
+/// File descriptors kept free for the runtime, logging and output files when comparing
+/// the open file limit against the number of concurrent probes.
+pub const RESERVED_FILE_DESCRIPTORS: usize = 32;
+
+/// Returns the number of probes to run concurrently so that their sockets fit in the open file
+/// limit of this process.
+///
+/// Each probe holds `sockets_per_probe` sockets at once. When `requested` probes would exceed the
+/// limit, a warning is logged and, if `auto_tune` is set, the concurrency is lowered to fit.
+/// Otherwise probes past the limit fail with "Too many open files" and count as unreachable.
+pub fn tune_concurrency(requested: usize, sockets_per_probe: usize, auto_tune: bool) -> usize {
+    let limit = open_file_limit();
+    let fitting = fit_to_file_limit(requested, sockets_per_probe, limit);
+    if fitting == requested {
+        return requested;
+    }
+
+    if auto_tune {
+        warnings::record(
+            WarningCode::ConcurrencyLowered,
+            format!(
+                "Lowering concurrency from {} to {} to fit the open file limit, raise it with `ulimit -n` to scan faster",
+                requested, fitting
+            ),
+        );
+        fitting
+    } else {
+        warnings::record(
+            WarningCode::FileLimitExceeded,
+            format!(
+                "A concurrency of {} exceeds the open file limit, probes may fail with 'Too many open files'",
+                requested
+            ),
+        );
+        requested
+    }
+}
+
+/// Returns the largest concurrency up to `requested` whose sockets fit in `limit`, keeping
+/// `RESERVED_FILE_DESCRIPTORS` free. At least one probe always runs.
+fn fit_to_file_limit(requested: usize, sockets_per_probe: usize, limit: Option<usize>) -> usize {
+    let Some(limit) = limit else {
+        return requested;
+    };
+    let available = limit.saturating_sub(RESERVED_FILE_DESCRIPTORS) / sockets_per_probe.max(1);
+
+    requested.min(available.max(1))
+}
+
+/// Returns the soft limit on open file descriptors of this process, if the platform has one.
+#[cfg(unix)]
+pub fn open_file_limit() -> Option<usize> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    // SAFETY: `getrlimit` only writes to the provided, properly initialized struct.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        return None;
+    }
+
+    Some(usize::try_from(limit.rlim_cur).unwrap_or(usize::MAX))
+}
+
+#[cfg(not(unix))]
+pub fn open_file_limit() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_to_file_limit() {
+        assert_eq!(fit_to_file_limit(1_000, 1, None), 1_000);
+        assert_eq!(fit_to_file_limit(1_000, 1, Some(1_024)), 992);
+        assert_eq!(fit_to_file_limit(100, 1, Some(1_024)), 100);
+        assert_eq!(fit_to_file_limit(1_000, 2, Some(1_024)), 496);
+        assert_eq!(fit_to_file_limit(1_000, 1, Some(16)), 1);
+    }
+}
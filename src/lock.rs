@@ -0,0 +1,106 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+/// How often a waiting run checks whether the lock was released.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A lock held by this run on the files it writes, released when dropped.
+///
+/// The lock is taken on a `.lock` file next to the written file, so that overlapping runs, such
+/// as cron jobs that outlast their interval, can't interleave their writes.
+#[derive(Debug)]
+pub struct RunLock {
+    /// The lock file, kept open for as long as the lock is held.
+    _file: File,
+}
+
+impl RunLock {
+    /// Locks the files written to `path` for this run.
+    ///
+    /// When another run holds the lock, waits for it to be released if `wait` is set, and
+    /// returns an error otherwise.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lock file cannot be opened, or if it is locked and `wait` is unset.
+    pub async fn acquire(path: &str, wait: bool) -> Result<Self> {
+        let path = format!("{}.lock", path);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Couldn't open lock file {}", path))?;
+
+        let mut waiting = false;
+        loop {
+            match try_lock(&file) {
+                Ok(true) => break,
+                Ok(false) if !wait => anyhow::bail!(
+                    "Another run holds {}, remove --fail-if-locked to wait for it",
+                    path
+                ),
+                Ok(false) => {
+                    if !waiting {
+                        info!(path = path.as_str(), "Waiting for another run to finish");
+                        waiting = true;
+                    }
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                }
+                Err(e) => return Err(e).with_context(|| format!("Couldn't lock {}", path)),
+            }
+        }
+
+        Ok(RunLock { _file: file })
+    }
+}
+
+/// Takes an exclusive lock on `file` without blocking, returning `false` if it is held elsewhere.
+///
+/// The lock is released by the operating system when the file is closed, even if the process
+/// is killed.
+#[cfg(unix)]
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: `flock` only operates on the file descriptor, which `file` keeps open.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+
+    let error = io::Error::last_os_error();
+    match error.kind() {
+        io::ErrorKind::WouldBlock => Ok(false),
+        _ => Err(error),
+    }
+}
+
+/// Runs are not locked on platforms without `flock`.
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lock_is_exclusive() {
+        let path = std::env::temp_dir().join(format!("cloudsurf-lock-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let lock = RunLock::acquire(path, false).await.unwrap();
+        if cfg!(unix) {
+            assert!(RunLock::acquire(path, false).await.is_err());
+        }
+
+        drop(lock);
+        assert!(RunLock::acquire(path, false).await.is_ok());
+        std::fs::remove_file(format!("{}.lock", path)).unwrap();
+    }
+}
@@ -1,25 +1,309 @@
-use env_logger::Builder;
-use log::{info, LevelFilter};
-use std::str::FromStr;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use tracing::{info, warn};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+use crate::args::LogArgs;
+use crate::telemetry;
+
+/// Determines how often a new log file is started when logging to a file.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRotation {
+    /// Always write to the same file.
+    Never,
+    /// Start a new file every hour.
+    Hourly,
+    /// Start a new file every day.
+    Daily,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Never => Rotation::NEVER,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+        }
+    }
+}
+
+/// Determines how log records are formatted.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line with the timestamp, level, target, fields and current span.
+    Json,
+}
+
+/// Determines where log records are sent, in addition to `--log-file`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogTarget {
+    /// The standard error stream.
+    Stderr,
+    /// The local syslog daemon. Requires the `syslog` feature.
+    Syslog,
+    /// The systemd journal. Requires the `journald` feature.
+    Journald,
+}
+
+/// A type-erased formatting layer, so that layers with different formats can be combined.
+pub type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync + 'static>;
 
 /// Initializes the logging framework for the application with a specified verbosity level.
 ///
 /// This function configures the global logging behavior based on the provided `log_level`
-/// argument. It parses the `log_level` string into a `LevelFilter`, applying it to the logger.
-/// If the parsing fails, it defaults to `LevelFilter::Info`, ensuring that logging is always
-/// enabled at a reasonable level. This function also prints the initial log message indicating
-/// the start of the application and the active logging level.
+/// argument. It parses the `log_level` string as `RUST_LOG`-style filter directives, such as
+/// `debug` or `cloudsurf::network=debug,info`, applying them to the logger. If the parsing fails,
+/// it defaults to `info`, ensuring that logging is always enabled at a reasonable level. This
+/// function also prints the initial log message indicating the start of the application and the
+/// active logging level.
+///
+/// Logs are sent to `--log-target`, which is stderr by default. When `--log-file` is set, logs are
+/// also written to that file (optionally rotated); in that case stderr is only used when
+/// `--log-stderr` is given. Records are formatted according to `--log-format`, except for
+/// journald, which stores structured fields natively.
+///
+/// Log records emitted through the `log` crate by dependencies are forwarded to the `tracing`
+/// subscriber as well. When `--otlp-endpoint` is set, spans are also exported to that
+/// OpenTelemetry collector, independently of the log level.
 ///
 /// # Arguments
 ///
-/// * `log_level` - A string slice that specifies the desired logging level (e.g., "info", "debug").
-pub fn init_logging(log_level: &str) {
-    let level = LevelFilter::from_str(log_level).unwrap_or_else(|_| {
-        eprintln!("Invalid log level: {}. Defaulting to 'info'.", log_level);
-        LevelFilter::Info
+/// * `args` - The logging-related command-line arguments.
+///
+/// # Errors
+///
+/// Returns an error if the log file cannot be opened, or if the selected log target is
+/// unavailable or was not enabled at build time, or if the OpenTelemetry exporter cannot be
+/// created.
+pub fn init_logging(args: &LogArgs) -> Result<()> {
+    let log_level = effective_log_level(args);
+    let filter = parse_filter(log_level).unwrap_or_else(|e| {
+        eprintln!(
+            "Invalid log level: {} ({}). Defaulting to 'info'.",
+            log_level, e
+        );
+        EnvFilter::new("info")
     });
 
-    Builder::new().filter_level(level).init();
+    let file_layer = args
+        .log_file
+        .as_deref()
+        .map(|path| -> Result<_> {
+            let appender = file_appender(path, args.log_rotation)?;
+            Ok(format_layer(appender, args.log_format, false))
+        })
+        .transpose()?;
+
+    let target_layer = match args.log_target {
+        LogTarget::Stderr => (args.log_file.is_none() || args.log_stderr)
+            .then(|| format_layer(std::io::stderr, args.log_format, true)),
+        LogTarget::Syslog => Some(syslog_layer(args.log_format)?),
+        LogTarget::Journald => Some(journald_layer()?),
+    };
+
+    let log_layers: Vec<BoxedLayer> = file_layer.into_iter().chain(target_layer).collect();
+    let mut layers = vec![log_layers.with_filter(filter).boxed()];
+    let env_endpoint = std::env::var(telemetry::ENDPOINT_ENV).ok();
+    let endpoint = otlp_endpoint(args.otlp_endpoint.as_deref(), env_endpoint.as_deref())?;
+    if let Some(endpoint) = &endpoint {
+        layers.push(telemetry::init(endpoint)?);
+    }
+
+    tracing_subscriber::registry().with(layers).init();
 
     info!("Application has started with log level: {}", log_level);
+    if endpoint.is_none() && env_endpoint.is_some_and(|value| !value.is_empty()) {
+        warn!(
+            "{} is set, but this build lacks the 'otel' feature; nothing is exported",
+            telemetry::ENDPOINT_ENV
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the OTLP collector to export to: `--otlp-endpoint`, or else the
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable given as `env`.
+///
+/// The variable is commonly set on a host for other programs, so in a build without the `otel`
+/// feature it is ignored rather than failing every command. Only the option is then an error.
+fn otlp_endpoint(option: Option<&str>, env: Option<&str>) -> Result<Option<String>> {
+    match (option, env) {
+        (Some(_), _) if !telemetry::SUPPORTED => {
+            anyhow::bail!("--otlp-endpoint requires a build with the 'otel' feature enabled")
+        }
+        (Some(endpoint), _) => Ok(Some(endpoint.to_string())),
+        (None, Some(endpoint)) if telemetry::SUPPORTED && !endpoint.is_empty() => {
+            Ok(Some(endpoint.to_string()))
+        }
+        (None, _) => Ok(None),
+    }
+}
+
+/// Returns the log filter to use, giving the `-v` flags precedence over `--log-level`.
+fn effective_log_level(args: &LogArgs) -> &str {
+    match args.verbose {
+        0 => &args.log_level,
+        1 => "warn",
+        2 => "info",
+        3 => "debug",
+        _ => "trace",
+    }
+}
+
+/// Parses `RUST_LOG`-style directives, e.g. `cloudsurf::network=debug,info`.
+///
+/// A directive without a target sets the default level for everything else.
+fn parse_filter(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::builder()
+        .parse(directives)
+        .with_context(|| format!("Couldn't parse log filter '{}'", directives))
+}
+
+/// Creates a layer writing records in the given format to `writer`.
+///
+/// `ansi` enables terminal colors for the text format and is ignored for JSON.
+fn format_layer<W>(writer: W, format: LogFormat, ansi: bool) -> BoxedLayer
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer);
+
+    match format {
+        LogFormat::Text => layer.with_ansi(ansi).boxed(),
+        LogFormat::Json => layer.json().with_current_span(true).boxed(),
+    }
+}
+
+/// Creates a layer sending records to the local syslog daemon.
+#[cfg(all(unix, feature = "syslog"))]
+fn syslog_layer(format: LogFormat) -> Result<BoxedLayer> {
+    let identity = c"cloudsurf";
+    let (options, facility) = Default::default();
+    let syslog = syslog_tracing::Syslog::new(identity, options, facility)
+        .context("A syslog writer has already been created")?;
+
+    Ok(format_layer(syslog, format, false))
+}
+
+#[cfg(not(all(unix, feature = "syslog")))]
+fn syslog_layer(_format: LogFormat) -> Result<BoxedLayer> {
+    anyhow::bail!("Logging to syslog requires a Unix build with the 'syslog' feature enabled")
+}
+
+/// Creates a layer sending records, including span fields, to the systemd journal.
+#[cfg(all(unix, feature = "journald"))]
+fn journald_layer() -> Result<BoxedLayer> {
+    let layer = tracing_journald::layer().context("Couldn't connect to the systemd journal")?;
+
+    Ok(layer
+        .with_syslog_identifier("cloudsurf".to_string())
+        .boxed())
+}
+
+#[cfg(not(all(unix, feature = "journald")))]
+fn journald_layer() -> Result<BoxedLayer> {
+    anyhow::bail!("Logging to journald requires a Unix build with the 'journald' feature enabled")
+}
+
+/// Creates an appender writing to `path`, rotated according to `rotation`.
+///
+/// With rotation enabled, the date (and hour) is appended to the file name, e.g.
+/// `cloudsurf.log.2024-05-01`.
+fn file_appender(path: &str, rotation: LogRotation) -> Result<RollingFileAppender> {
+    let path = Path::new(path);
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Invalid log file path '{}'", path.display()))?;
+
+    RollingFileAppender::builder()
+        .rotation(rotation.into())
+        .filename_prefix(file_name.to_string_lossy())
+        .build(directory)
+        .with_context(|| format!("Couldn't open log file '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_filter() {
+        assert!(parse_filter("info").is_ok());
+        assert!(parse_filter("cloudsurf::network=debug,info").is_ok());
+        assert!(parse_filter("cloudsurf=trace,reqwest=warn").is_ok());
+    }
+
+    #[test]
+    fn test_effective_log_level() {
+        let args = Args::parse_from(["testapp", "--domain", "example.com", "--log-level", "error"]);
+        assert_eq!(effective_log_level(&args.log), "error");
+
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--log-level",
+            "error",
+            "-vv",
+        ]);
+        assert_eq!(effective_log_level(&args.log), "info");
+
+        let args = Args::parse_from(["testapp", "--domain", "example.com", "-vvvvv"]);
+        assert_eq!(effective_log_level(&args.log), "trace");
+
+        // `-v` lowers a more verbose level, as given with --log-level or RUST_LOG.
+        let args = Args::parse_from([
+            "testapp",
+            "--domain",
+            "example.com",
+            "--log-level",
+            "cloudsurf=trace,debug",
+            "-v",
+        ]);
+        assert_eq!(effective_log_level(&args.log), "warn");
+    }
+
+    #[test]
+    #[cfg(not(feature = "otel"))]
+    fn test_otlp_endpoint_without_feature() {
+        assert_eq!(
+            otlp_endpoint(None, Some("http://localhost:4317")).unwrap(),
+            None
+        );
+        assert_eq!(otlp_endpoint(None, None).unwrap(), None);
+        assert!(otlp_endpoint(Some("http://localhost:4317"), None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "otel")]
+    fn test_otlp_endpoint() {
+        let endpoint = otlp_endpoint(None, Some("http://collector:4317")).unwrap();
+        assert_eq!(endpoint.as_deref(), Some("http://collector:4317"));
+        let endpoint =
+            otlp_endpoint(Some("http://localhost:4317"), Some("http://collector:4317")).unwrap();
+        assert_eq!(endpoint.as_deref(), Some("http://localhost:4317"));
+        assert_eq!(otlp_endpoint(None, Some("")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_invalid_filter() {
+        assert!(parse_filter("cloudsurf=loud").is_err());
+    }
 }
@@ -1,46 +1,976 @@
+mod aggregate;
+mod anonymize;
 mod args;
+mod bench;
+mod compression;
+mod config;
+mod dns;
+mod encrypt;
 mod file;
+mod filter;
+mod forward;
+mod history;
+mod hooks;
+mod i18n;
+mod import;
+mod integrity;
+mod limits;
+mod lock;
 mod logger;
+mod manage;
+mod merge;
 mod network;
+mod notify;
+mod parquet_export;
+mod paths;
 mod print;
+mod profile;
+mod prompt;
+mod publish;
+mod report;
+mod result;
+mod rotate;
+mod sampling;
+mod selftest;
+mod sink;
+mod statsd;
+mod telemetry;
+mod version;
+mod warnings;
 
-use crate::args::Args;
-use crate::file::write_ips_to_file;
+use crate::args::{
+    Args, BenchArgs, Command, ImportArgs, InitArgs, MergeArgs, ProfileAction, ProfileArgs,
+    SelftestArgs, DEFAULT_DOMAIN,
+};
+use crate::compression::Compression;
+use crate::encrypt::Recipients;
+use crate::file::{
+    read_ips_from_file, read_report, read_server_names, read_text_output, replace_file,
+    rewrite_text_output, text_header, verify_ips, write_cfst_csv, write_ips_to_file, write_report,
+    OutputFormat, TextOptions,
+};
+use crate::filter::ResultFilter;
+use crate::history::History;
+use crate::i18n::Message;
+use crate::integrity::SigningKey;
+use crate::lock::RunLock;
 use crate::logger::init_logging;
-use crate::network::check_tls_availability;
-use crate::network::fetch_and_filter_ipv4_list;
-use anyhow::Result;
-use clap::Parser;
+use crate::merge::merge_results;
+use crate::network::check_grpc;
+use crate::network::check_h2_keepalive;
+use crate::network::check_http;
+use crate::network::measure_download_speed;
+use crate::network::measure_request_latency;
+use crate::network::{
+    check_tls_availability, measure_baseline, probe_tls, select_results, CheckOptions,
+};
+use crate::network::{compare_unfragmented, read_spill, Provider, ProviderRanges, SocketOptions};
+use crate::network::{
+    fetch_and_filter_ipv4_ranges, fetch_ipv6_prefixes, fetch_seed_lists, fetch_skip_lists,
+    filter_ipv6_hosts, is_skipped,
+};
+use crate::report::{Baseline, DomainMatrix, ScanParameters, ScanReport, WarningCode};
+use crate::result::{ScanResult, SortKey};
+use crate::sampling::{IpVersion, SampleSize};
+use anyhow::{Context, Result};
+use std::io::{self, IsTerminal};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
-    init_logging(&args.log_level);
+    let mut args = Args::parse_with_profile();
+    init_logging(&args.log)?;
+    i18n::init(args.lang);
+    if args.native_certs {
+        network::use_native_certs()?;
+    }
+
+    // Ask for a missing domain when someone is at the terminal, otherwise use the default.
+    if args.command.is_none() && args.domain.is_none() {
+        args.domain = Some(if io::stdin().is_terminal() {
+            prompt::domain(DEFAULT_DOMAIN)?
+        } else {
+            DEFAULT_DOMAIN.to_string()
+        });
+    }
+
+    let result = match &args.command {
+        Some(Command::Merge(merge_args)) => merge(merge_args),
+        Some(Command::Schema) => {
+            println!("{}", report::json_schema());
+            Ok(())
+        }
+        Some(Command::Selftest(selftest_args)) => selftest(selftest_args).await,
+        Some(Command::Bench(bench_args)) => bench(bench_args).await,
+        Some(Command::Dns(dns_args)) => dns::serve(dns_args).await,
+        Some(Command::Forward(forward_args)) => forward::serve(forward_args).await,
+        Some(Command::Import(import_args)) => import(import_args),
+        Some(Command::Publish(publish_args)) => publish::publish(publish_args).await,
+        Some(Command::Profile(profile_args)) => manage_profiles(profile_args),
+        Some(Command::Init(init_args)) => init(init_args),
+        Some(Command::Version(version_args)) => {
+            if version_args.details {
+                println!("{}", version::verbose());
+            } else {
+                println!("{}", version::short());
+            }
+            Ok(())
+        }
+        Some(Command::Paths) => {
+            print::paths(&paths::all()?);
+            Ok(())
+        }
+        None => match args.max_age {
+            Some(max_age) => manage_file(&args, max_age).await,
+            None => scan(&args).await,
+        },
+    };
+
+    telemetry::shutdown();
+    if let Err(e) = result {
+        eprintln!("{}: {:?}", i18n::text(Message::Error), e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Fetches Cloudflare's IP ranges, checks a sample of them for TLS availability and reports
+/// the fastest IPs.
+async fn scan(args: &Args) -> Result<()> {
+    let domain = args.domain.as_deref().unwrap_or(DEFAULT_DOMAIN);
+    let started = Instant::now();
+    network::set_max_probes(args.max_probes);
+    if let Some(host) = &args.statsd_host {
+        statsd::init(host, &args.statsd_prefix)?;
+    }
+
+    // Compile the filter script before scanning, so that mistakes are reported right away.
+    let filter = args
+        .filter_script
+        .as_deref()
+        .map(ResultFilter::from_file)
+        .transpose()?;
+
+    // Fail before a potentially long scan rather than when it is over.
+    if args.notify && !notify::SUPPORTED {
+        anyhow::bail!("--notify requires a build with the 'notify' feature enabled");
+    }
+    if !args.encrypt_to.is_empty() && !encrypt::SUPPORTED {
+        anyhow::bail!("--encrypt-to requires a build with the 'age' feature enabled");
+    }
+    if args.format == OutputFormat::Parquet && !parquet_export::SUPPORTED {
+        anyhow::bail!("--format parquet requires a build with the 'parquet' feature enabled");
+    }
+    if let Some(path) = args
+        .output_path()
+        .filter(|path| Compression::of(path) == Some(Compression::Zstd))
+    {
+        if !compression::ZSTD_SUPPORTED {
+            anyhow::bail!("{} requires a build with the 'zstd' feature enabled", path);
+        }
+    }
+    if let Some(sink) = args.output.as_ref().filter(|sink| !sink.supported()) {
+        anyhow::bail!(
+            "--output {}:// requires a build with the '{}' feature enabled",
+            sink.feature(),
+            sink.feature()
+        );
+    }
+    if args.push_ssh.is_some() && args.output_path().is_none() {
+        anyhow::bail!("--push-ssh requires --file-path or --refresh");
+    }
+    if args.checksum && args.output_path().is_none() {
+        anyhow::bail!("--checksum requires --file-path or --refresh");
+    }
+    if args.sign_key.is_some() && args.output_path().is_none() {
+        anyhow::bail!("--sign-key requires --file-path or --refresh");
+    }
+    let signing_key = args.sign_key.as_deref().map(SigningKey::load).transpose()?;
+    if !args.encrypt_to.is_empty() && args.output_path().is_none() {
+        anyhow::bail!("--encrypt-to requires --file-path or --refresh");
+    }
+    let recipients = (!args.encrypt_to.is_empty())
+        .then(|| Recipients::parse(&args.encrypt_to))
+        .transpose()?;
+    let mut max_valid_ips = args.max_valid_ips;
+    if max_valid_ips == 0 && args.spill_results.is_some() {
+        anyhow::bail!(
+            "--max-valid-ips 0 keeps every result in memory, which --spill-results avoids"
+        );
+    }
+    // No more IPs than probed can be valid, so a limit above `--count` is lowered to it, unless
+    // IPv6 hosts, seed lists or a refreshed file add to `--count`. A limit of 0 returns every
+    // valid IP and is left alone.
+    if let SampleSize::Count(count) = args.count {
+        if args.refresh.is_none()
+            && args.seed_list_url.is_empty()
+            && args.ip_version == IpVersion::V4
+            && max_valid_ips != 0
+            && max_valid_ips > count
+        {
+            if args.strict {
+                anyhow::bail!(
+                    "--max-valid-ips {} exceeds --count {}",
+                    max_valid_ips,
+                    count
+                );
+            }
+            warnings::record(
+                WarningCode::MaxValidIpsClamped,
+                format!(
+                    "--max-valid-ips {} exceeds --count {}, lowering it to {}",
+                    max_valid_ips, count, count
+                ),
+            );
+            max_valid_ips = count;
+        }
+    }
+    if let Some(quota) = args.family_quota {
+        if args.ip_version != IpVersion::Both {
+            anyhow::bail!("--family-quota requires --ip-version both");
+        }
+        if max_valid_ips != 0 && quota * 2 > max_valid_ips {
+            anyhow::bail!("--family-quota must be at most half of --max-valid-ips");
+        }
+    }
+
+    // Scanning with a hostname that is not proxied by Cloudflare fails every probe. A domain
+    // scanned through several providers is served by each of them, so it isn't checked then.
+    let unproxied = if args.provider == [Provider::Cloudflare] {
+        network::unproxied_addresses(domain).await
+    } else {
+        Ok(Vec::new())
+    };
+    match unproxied {
+        Ok(outside) if outside.is_empty() => {}
+        Ok(outside) => {
+            let outside = outside
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            if args.strict {
+                anyhow::bail!(
+                    "{} resolves to {}, outside Cloudflare's ranges; is it proxied by Cloudflare?",
+                    domain,
+                    outside
+                );
+            }
+            warnings::record(
+                WarningCode::DomainNotProxied,
+                format!(
+                    "{} resolves to {}, outside Cloudflare's ranges; it is likely not proxied and probes will fail",
+                    domain, outside
+                ),
+            );
+        }
+        Err(e) if args.strict => {
+            return Err(e.context("Couldn't check that --domain is proxied by Cloudflare"))
+        }
+        Err(e) => warnings::record(
+            WarningCode::ProxyCheckFailed,
+            format!(
+                "Couldn't check that the domain is proxied by Cloudflare: {:#}",
+                e
+            ),
+        ),
+    }
+
+    let mut skip_prefixes_vec = args.skip_prefixes.clone();
+    skip_prefixes_vec.extend(fetch_skip_lists(&args.skip_list_url).await);
+
+    if let Some(command) = &args.on_start {
+        hooks::run("on-start", command, &[], &hooks::scan_environment(args)).await?;
+    }
+
+    // When refreshing a previous output file, its IPs are probed instead of a fresh sample.
+    let refresh = args.refresh.as_deref().map(read_text_output).transpose()?;
+    let history_path = paths::resolve_history(&args.history)?;
+    // Hold the written files for the whole scan, so that overlapping runs don't interleave.
+    let _lock = match args.output_path().or(history_path.as_deref()) {
+        Some(path) => Some(RunLock::acquire(path, !args.fail_if_locked).await?),
+        None => None,
+    };
+    let mut history = history_path.as_deref().map(History::load).transpose()?;
+    if args.sort_by == SortKey::Reliability && history.is_none() {
+        anyhow::bail!("--sort-by reliability requires --history");
+    }
+    // A percentage is only turned into a number of IPs once the ranges are known.
+    let mut count = match args.count {
+        SampleSize::Count(count) => count,
+        SampleSize::Percent(_) => 0,
+    };
+    // Shared candidate IPs are probed ahead of a fresh sample, in the scanned families only.
+    let seeds: Vec<IpAddr> = match &refresh {
+        Some(_) => Vec::new(),
+        None => fetch_seed_lists(&args.seed_list_url)
+            .await
+            .into_iter()
+            .filter(|ip| match ip {
+                IpAddr::V4(_) => args.ip_version.includes_v4(),
+                IpAddr::V6(_) => args.ip_version.includes_v6(),
+            })
+            .filter(|ip| !is_skipped(*ip, &skip_prefixes_vec))
+            .collect(),
+    };
+    // The ranges of each provider are sampled together, remembering which provider they are from.
+    let mut provider_ranges = ProviderRanges::default();
+    let targets = match &refresh {
+        Some(output) => output.ips.clone(),
+        None => {
+            let v4 = if args.ip_version.includes_v4() {
+                let mut ranges = Vec::new();
+                for &provider in &args.provider {
+                    let provider_v4 =
+                        fetch_and_filter_ipv4_ranges(provider, &skip_prefixes_vec).await?;
+                    provider_ranges.add_ipv4(provider, &provider_v4);
+                    ranges.extend(provider_v4);
+                }
+                count = args.count.resolve(&ranges);
+                if let SampleSize::Percent(_) = args.count {
+                    info!(count, "Sampling {} of the address space", args.count);
+                }
+                let available: usize = ranges.iter().map(Vec::len).sum();
+                if count > available {
+                    if args.strict {
+                        anyhow::bail!(
+                            "--count {} exceeds the {} IPs left after skipping prefixes",
+                            count,
+                            available
+                        );
+                    }
+                    warnings::record(
+                        WarningCode::CountClamped,
+                        format!(
+                            "--count {} exceeds the {} IPs left after skipping prefixes, lowering it to {}",
+                            count, available, available
+                        ),
+                    );
+                    count = available;
+                }
+                match &history {
+                    Some(history) => sampling::select_prioritized(
+                        &ranges,
+                        count,
+                        args.order,
+                        args.weighting,
+                        &history.good_subnets(),
+                        args.exploration,
+                    ),
+                    None => sampling::select(&ranges, count, args.order, args.weighting),
+                }
+            } else {
+                Vec::new()
+            };
+            let v6 = if args.ip_version.includes_v6() {
+                let mut prefixes = Vec::new();
+                for &provider in &args.provider {
+                    let provider_v6 = fetch_ipv6_prefixes(provider, &skip_prefixes_vec).await?;
+                    provider_ranges.add_ipv6(provider, &provider_v6);
+                    prefixes.extend(provider_v6);
+                }
+                let hosts = sampling::sample_ipv6(&prefixes, args.v6_hosts_per_prefix);
+                filter_ipv6_hosts(hosts, &skip_prefixes_vec)
+            } else {
+                Vec::new()
+            };
+            sampling::prepend_seeds(&seeds, sampling::interleave_families(v4, v6))
+        }
+    };
+
+    let server_names = args
+        .sni_file
+        .as_deref()
+        .map(read_server_names)
+        .transpose()?
+        .unwrap_or_default();
+    let sni_fallback = args
+        .sni_fallback
+        .as_deref()
+        .map(read_server_names)
+        .transpose()?
+        .unwrap_or_default();
+
+    let sockets_per_probe = if args.race_ports { args.ports.len() } else { 1 };
+    let concurrency = limits::tune_concurrency(
+        args.concurrency.unwrap_or(targets.len()),
+        sockets_per_probe,
+        !args.no_auto_tune,
+    );
+
+    let options = CheckOptions {
+        per_subnet: args.top_per_subnet,
+        warmup: args.warmup,
+        timeout: args.timeout_ms.map(Duration::from_millis),
+        race_ports: args.race_ports,
+        ipv6_bias: args.ipv6_bias,
+        family_quota: args.family_quota,
+        concurrency: Some(concurrency),
+        adaptive_concurrency: args.adaptive_concurrency,
+        retry_timeouts: args.retry_timeouts,
+        spill_path: args.spill_results.clone(),
+        sort_by: args.sort_by,
+        // Only the best results are kept while probing, so they are ranked with the history.
+        history: history
+            .clone()
+            .filter(|_| args.spill_results.is_some())
+            .map(Arc::new),
+        server_names: server_names.clone(),
+        sni_fallback: sni_fallback.clone(),
+        probe: args.probe,
+        socket: SocketOptions {
+            nodelay: args.tcp_nodelay,
+            keepalive: args.tcp_keepalive,
+            send_buffer_size: args.tcp_send_buffer,
+            recv_buffer_size: args.tcp_recv_buffer,
+            ttl: args.ttl,
+            fragment: args.fragment_hello,
+        },
+        ..CheckOptions::new(match max_valid_ips {
+            0 => usize::MAX,
+            n => n,
+        })
+    };
+
+    // Measure the default route to the domain first, for the scanned IPs to be compared with.
+    let baseline = if args.no_baseline {
+        None
+    } else {
+        match measure_baseline(domain, &args.ports, &options).await {
+            Ok(Some(result)) => Some(Baseline {
+                ip: result.ip,
+                latency_ms: result.latency_ms,
+            }),
+            Ok(None) => {
+                warnings::record(
+                    WarningCode::BaselineFailed,
+                    format!(
+                        "The DNS-resolved addresses of {} didn't complete a handshake",
+                        domain
+                    ),
+                );
+                None
+            }
+            Err(e) => {
+                warnings::record(
+                    WarningCode::BaselineFailed,
+                    format!("Couldn't measure the baseline latency: {:#}", e),
+                );
+                None
+            }
+        }
+    };
+
+    let mut passes = Vec::with_capacity(args.passes);
+    for pass in 1..=args.passes {
+        if pass > 1 {
+            if let Some(delay) = args.pass_delay {
+                tokio::time::sleep(delay).await;
+            }
+            info!(pass, passes = args.passes, "Starting probe pass");
+        }
+        passes.push(probe_tls(&targets, domain, &args.ports, &options).await?);
+    }
+    let mut reachable = merge::combine_passes(passes, args.pass_strategy, args.ipv6_bias);
+    let fragmentation = match args.fragment_hello {
+        Some(_) => Some(
+            compare_unfragmented(&mut reachable, &targets, domain, &args.ports, &options).await?,
+        ),
+        None => None,
+    };
+    if let Some(prefix) = args.aggregate {
+        print::blocks(&aggregate::aggregate(&targets, &reachable, prefix));
+    }
+    // Rank with the history before the results are limited, so that IPs that were only fast once
+    // are not kept over steadier ones.
+    if let Some(history) = &mut history {
+        rank_with_history(history, &targets, &mut reachable, args)?;
+    }
+    let mut valid_ips = select_results(reachable, &options);
+    let tag_providers = args.provider != [Provider::Cloudflare];
+    for result in &mut valid_ips {
+        result.seeded = seeds.contains(&result.ip);
+        if tag_providers {
+            result.provider = provider_ranges.provider_of(result.ip);
+        }
+    }
+
+    // Results record the port they were reached on, this is only used for those that do not.
+    let port = args.ports[0];
+
+    if args.http_check {
+        valid_ips = check_http(
+            valid_ips,
+            domain,
+            port,
+            &args.check_path,
+            args.expect_status,
+        )
+        .await?;
+    }
+
+    if args.speedtest || args.min_speed.is_some() {
+        measure_download_speed(
+            &mut valid_ips,
+            port,
+            args.speedtest_bytes,
+            Duration::from_secs(args.speedtest_max_time),
+            args.speedtest_concurrency,
+        )
+        .await?;
+    }
+
+    if args.h2_check {
+        check_h2_keepalive(
+            &mut valid_ips,
+            domain,
+            port,
+            Duration::from_secs(args.h2_check_duration),
+        )
+        .await?;
+    }
+
+    if let Some(service) = args.grpc_service.as_deref().filter(|_| args.grpc_check) {
+        check_grpc(&mut valid_ips, domain, port, service).await?;
+    }
+
+    if args.request_check {
+        measure_request_latency(&mut valid_ips, port, args.request_bytes).await?;
+    }
+
+    if let Some(min_speed) = args.min_speed {
+        valid_ips.retain(|result| result.speed_mbps.is_some_and(|speed| speed >= min_speed));
+    }
+
+    if let Some(filter) = &filter {
+        valid_ips = filter.apply(valid_ips)?;
+    }
+
+    if let (Some(history), Some(path)) = (history, &history_path) {
+        history.save(path)?;
+    }
+
+    // Probe the valid IPs through the other domains, to find those that work for all of them.
+    let mut matrix = None;
+    if !args.matrix.is_empty() {
+        let ips: Vec<IpAddr> = valid_ips.iter().map(|result| result.ip).collect();
+        let matrix_options = CheckOptions {
+            timeout: options.timeout,
+            race_ports: options.race_ports,
+            concurrency: options.concurrency,
+            ..CheckOptions::new(usize::MAX)
+        };
+        let mut others = Vec::with_capacity(args.matrix.len());
+        for other in &args.matrix {
+            info!(
+                domain = other.as_str(),
+                "Probing the valid IPs for another domain"
+            );
+            let results = probe_tls(&ips, other, &args.ports, &matrix_options).await?;
+            others.push((other.clone(), results));
+        }
+        matrix = Some(DomainMatrix::new(domain, &valid_ips, &others));
+    }
+
+    print::ips(
+        &valid_ips,
+        baseline.as_ref().map(|baseline| baseline.latency_ms),
+    );
+    if let Some(baseline) = &baseline {
+        print::baseline(domain, baseline);
+    }
+    print::sni_fallbacks(&valid_ips);
+    let colos = report::colo_summaries(&valid_ips);
+    if !colos.is_empty() {
+        print::colos(&colos);
+    }
+    if let Some(matrix) = &matrix {
+        print::matrix(matrix);
+    }
+    if let Some(fragmentation) = &fragmentation {
+        print::fragmentation(fragmentation);
+    }
+
+    let parameters = ScanParameters {
+        domain: domain.to_string(),
+        ports: args.ports.clone(),
+        race_ports: args.race_ports,
+        count,
+        count_percent: match args.count {
+            SampleSize::Percent(percent) => Some(percent),
+            SampleSize::Count(_) => None,
+        },
+        max_probes: args.max_probes,
+        passes: (args.passes > 1).then_some(args.passes),
+        concurrency: Some(concurrency),
+        adaptive_concurrency: args.adaptive_concurrency,
+        retry_timeouts: args.retry_timeouts,
+        providers: if tag_providers {
+            args.provider.clone()
+        } else {
+            Vec::new()
+        },
+        ip_version: args.ip_version,
+        v6_hosts_per_prefix: args
+            .ip_version
+            .includes_v6()
+            .then_some(args.v6_hosts_per_prefix),
+        ipv6_bias: args.ip_version.includes_v6().then_some(args.ipv6_bias),
+        order: args.order,
+        weighting: args.weighting,
+        exploration: args.history.is_some().then_some(args.exploration),
+        max_valid_ips,
+        family_quota: args.family_quota,
+        top_per_subnet: args.top_per_subnet,
+        skip_prefixes: args.skip_prefixes.iter().map(ToString::to_string).collect(),
+        skip_list_urls: args.skip_list_url.clone(),
+        seed_list_urls: args.seed_list_url.clone(),
+        server_names,
+        sni_fallback,
+        probe: args.probe,
+        fragment_hello: args.fragment_hello,
+        sort_by: args.sort_by,
+        native_certs: args.native_certs,
+        tcp_nodelay: args.tcp_nodelay,
+        tcp_keepalive_secs: args.tcp_keepalive.map(|keepalive| keepalive.as_secs()),
+        tcp_send_buffer: args.tcp_send_buffer,
+        tcp_recv_buffer: args.tcp_recv_buffer,
+        ttl: args.ttl,
+        speedtest_bytes: (args.speedtest || args.min_speed.is_some())
+            .then_some(args.speedtest_bytes),
+        min_speed_mbps: args.min_speed,
+        warmup: args.warmup,
+        timeout_ms: args.timeout_ms,
+        http_check: args.http_check.then(|| args.check_path.clone()),
+        expect_status: args.expect_status,
+        h2_check_duration: args.h2_check.then_some(args.h2_check_duration),
+        grpc_service: args.grpc_check.then(|| args.grpc_service.clone()).flatten(),
+        request_bytes: args.request_check.then_some(args.request_bytes),
+    };
 
-    let skip_prefixes_vec = args
-        .skip_prefixes
+    // The report owns the results from here on, they are only read by the outputs below.
+    let mut report = ScanReport::new(Some(parameters), valid_ips, targets.len());
+    report.summary.baseline = baseline;
+    report.summary.fragmentation = fragmentation;
+    report.warnings = warnings::take();
+    report.matrix = matrix;
+    telemetry::record_scan(&report.summary, started.elapsed());
+    statsd::scan_completed(&report.summary, started.elapsed());
+    let valid_ips = &report.results;
+
+    // With --anonymize, the results file and the sink are written from a copy without the exact
+    // IPs.
+    let anonymized = args.anonymize.then(|| anonymize::anonymize(&report));
+    let exported = anonymized.as_ref().unwrap_or(&report);
+
+    let text_options = TextOptions {
+        header: match (&report.parameters, args.no_header) {
+            (Some(parameters), false) => {
+                text_header(parameters, (!args.anonymize).then(SystemTime::now))
+            }
+            _ => Vec::new(),
+        },
+        per_operator: args.per_operator_limit,
+    };
+    if let (Some(output), Some(path)) = (&refresh, &args.refresh) {
+        // Without operators, no IP line is written back.
+        let listed = if output.operators.is_empty() {
+            Vec::new()
+        } else {
+            text_options.listed_ips(valid_ips)
+        };
+        replace_file(
+            path,
+            |temporary| rewrite_text_output(valid_ips, output, temporary, &text_options),
+            |temporary| verify_ips(temporary, OutputFormat::Text, &listed),
+        )?;
+    }
+    if let Some(file_path) = &args.file_path {
+        let rotated = args
+            .rotate
+            .then(|| rotate::rotated_path(file_path, SystemTime::now()));
+        let path = match &rotated {
+            Some(rotated) => rotated
+                .to_str()
+                .context("The rotated file path is not valid UTF-8")?,
+            None => file_path.as_str(),
+        };
+        let listed = match args.format {
+            OutputFormat::Text => text_options.listed_ips(&exported.results),
+            _ => exported.results.iter().map(|result| result.ip).collect(),
+        };
+        replace_file(
+            path,
+            |temporary| match args.format {
+                OutputFormat::Text => {
+                    write_ips_to_file(&exported.results, temporary, &text_options)
+                }
+                OutputFormat::CfstCsv => write_cfst_csv(&exported.results, args.passes, temporary),
+                format => write_report(exported, temporary, format),
+            },
+            |temporary| verify_ips(temporary, args.format, &listed),
+        )?;
+        if let Some(rotated) = &rotated {
+            rotate::finish(file_path, rotated, args.keep)?;
+        }
+    }
+
+    if let Some(sink) = &args.output {
+        sink::write(sink, exported, args.label.as_deref()).await?;
+    }
+
+    // With --encrypt-to, only the encrypted copy of the results file is distributed.
+    let distributed = match (&recipients, args.output_path()) {
+        (Some(recipients), Some(path)) => Some(recipients.encrypt_file(path)?),
+        (_, path) => path.map(str::to_string),
+    };
+
+    // Sidecar files are written next to the distributed file and uploaded along with it.
+    let mut sidecars = Vec::new();
+    if let Some(path) = &distributed {
+        if args.checksum {
+            sidecars.push(integrity::write_checksum(path)?);
+        }
+        if let Some(key) = &signing_key {
+            sidecars.push(key.sign_file(path)?);
+        }
+    }
+
+    if let (Some(destination), Some(path)) = (&args.push_ssh, args.output_path()) {
+        // Uploaded files keep their suffix, such as `.age` or `.sha256`, at the destination.
+        for file in distributed.iter().chain(&sidecars) {
+            let suffix = &file[path.len()..];
+            hooks::push_ssh(file, &format!("{}{}", destination, suffix)).await?;
+        }
+    }
+
+    if let Some(command) = &args.on_complete {
+        let placeholders = [
+            ("file", args.output_path().unwrap_or_default().to_string()),
+            (
+                "best_ip",
+                valid_ips
+                    .first()
+                    .map(|result| result.ip.to_string())
+                    .unwrap_or_default(),
+            ),
+        ];
+        let mut env = hooks::scan_environment(args);
+        env.push((
+            "CLOUDSURF_VALID_IPS".to_string(),
+            valid_ips.len().to_string(),
+        ));
+        hooks::run("on-complete", command, &placeholders, &env).await?;
+    }
+
+    if args.notify {
+        if let Err(e) = notify::scan_completed(valid_ips) {
+            warn!(error = %format!("{:#}", e), "Desktop notification failed");
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the output file fresh, rescanning whenever it is consumed or older than `max_age`.
+///
+/// Runs until interrupted. A failed scan is logged and retried at the next check rather than
+/// ending the loop.
+async fn manage_file(args: &Args, max_age: Duration) -> Result<()> {
+    let path = args
+        .file_path
         .as_deref()
-        .unwrap_or("")
-        .split(',')
-        .filter(|s| !s.is_empty())
-        .map(ToString::to_string)
-        .collect::<Vec<String>>();
-
-    let filtered_ips = fetch_and_filter_ipv4_list(&skip_prefixes_vec).await?;
-    let valid_ips = check_tls_availability(
-        &filtered_ips,
-        &args.domain,
-        args.port,
-        args.count,
-        args.max_valid_ips,
-    )
-    .await?;
-
-    print::ips(&valid_ips);
-
-    if let Some(path) = &args.file_path {
-        write_ips_to_file(&valid_ips, path)?;
+        .context("The --file-path argument is required with --max-age")?;
+
+    loop {
+        let remaining = manage::time_until_stale(path, max_age, SystemTime::now())?;
+        if remaining.is_zero() {
+            info!(path, "Output file is missing or stale, refreshing it");
+            if let Err(e) = scan(args).await {
+                warn!(error = %format!("{:#}", e), "Refreshing the output file failed");
+            }
+            // Wait even after a failure, so that a persistent error doesn't turn into a busy loop.
+            tokio::time::sleep(manage::POLL_INTERVAL).await;
+        } else {
+            tokio::time::sleep(remaining.min(manage::POLL_INTERVAL)).await;
+        }
+    }
+}
+
+/// Combines the reports of several runs, keeping one entry per IP.
+fn merge(args: &MergeArgs) -> Result<()> {
+    let reports = args
+        .files
+        .iter()
+        .map(|path| read_report(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let probed: usize = reports.iter().map(|report| report.summary.probed).sum();
+    let runs: Vec<_> = reports.into_iter().map(|report| report.results).collect();
+    let merged = merge_results(&runs, args.strategy);
+
+    print::ips(&merged, None);
+
+    if let Some(path) = &args.output {
+        write_report(&ScanReport::new(None, merged, probed), path, args.format)?;
+    }
+
+    Ok(())
+}
+
+/// Blends the latencies of every reachable IP into `history` and records the scan, then ranks
+/// `reachable` by `--sort-by` with the smoothed latencies and reliabilities.
+///
+/// Every reachable IP counts towards its reliability, not only those kept by `--max-valid-ips`.
+/// With `--spill-results`, `reachable` only holds the best results, which were already ranked with
+/// the history while probing, so the reachable IPs are read back from the spill file.
+fn rank_with_history(
+    history: &mut History,
+    targets: &[IpAddr],
+    reachable: &mut [ScanResult],
+    args: &Args,
+) -> Result<()> {
+    match &args.spill_results {
+        Some(path) => {
+            let mut valid = Vec::new();
+            for result in read_spill(path)? {
+                let mut result = result?;
+                history.smooth_latencies(std::slice::from_mut(&mut result));
+                valid.push(result.ip);
+            }
+            history.record(targets, valid);
+        }
+        None => {
+            history.smooth_latencies(reachable);
+            history.record(targets, reachable.iter().map(|result| result.ip));
+        }
+    }
+    history.annotate_reliability(reachable);
+    reachable.sort_by(|a, b| {
+        b.passes
+            .cmp(&a.passes)
+            .then_with(|| args.sort_by.compare(a, b, args.ipv6_bias))
+    });
+
+    Ok(())
+}
+
+/// Reads the results of another scanner, records them in the history and writes them as a report.
+fn import(args: &ImportArgs) -> Result<()> {
+    let imported = import::read_results(&args.file, args.format)?;
+    let mut results = imported.results;
+
+    if let Some(path) = paths::resolve_history(&args.history)? {
+        let mut history = History::load(&path)?;
+        history.smooth_latencies(&mut results);
+        history.record(&imported.probed, results.iter().map(|result| result.ip));
+        history.save(&path)?;
+    }
+
+    print::ips(&results, None);
+
+    if let Some(path) = &args.output {
+        let report = ScanReport::new(None, results, imported.probed.len());
+        write_report(&report, path, args.output_format)?;
+    }
+
+    Ok(())
+}
+
+/// Asks for the main settings and writes them to the configuration file.
+fn init(args: &InitArgs) -> Result<()> {
+    let path = paths::config_file()?;
+    if path.exists() && !args.force {
+        anyhow::bail!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        );
+    }
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("cloudsurf init asks questions, run it from a terminal");
+    }
+
+    let settings = prompt::settings(DEFAULT_DOMAIN)?;
+    config::write(&path, &settings)?;
+    println!("Wrote {}", path.display());
+    if let Some(entry) = settings.crontab_entry() {
+        println!(
+            "To scan on schedule, add this line with `crontab -e`:\n{}",
+            entry
+        );
+    }
+
+    Ok(())
+}
+
+/// Saves scan options under a name, or lists the saved profiles.
+fn manage_profiles(args: &ProfileArgs) -> Result<()> {
+    match &args.action {
+        ProfileAction::Save(save_args) => {
+            let path = profile::save(&save_args.name, &save_args.options)?;
+            println!("Saved profile '{}' to {}", save_args.name, path.display());
+        }
+        ProfileAction::List => {
+            let names = profile::list()?;
+            if names.is_empty() {
+                println!(
+                    "No saved profiles, save one with `cloudsurf profile save <name> <options>`"
+                );
+            }
+            for name in names {
+                println!("{}", name);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the diagnostic checks and prints a report, failing if any check failed.
+async fn selftest(args: &SelftestArgs) -> Result<()> {
+    let outcomes = selftest::run_checks(args).await;
+
+    print::checks(&outcomes);
+
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.status == selftest::CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, outcomes.len());
     }
 
     Ok(())
 }
+
+/// Probes all candidate lists together and prints a comparison of their statistics.
+async fn bench(args: &BenchArgs) -> Result<()> {
+    let lists = args
+        .files
+        .iter()
+        .map(|path| read_ips_from_file(path).map(|ips| (path, ips)))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Probe the union of all lists in a single run, so every list sees the same network conditions.
+    let mut candidates: Vec<_> = lists
+        .iter()
+        .flat_map(|(_, ips)| ips.iter().copied())
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let options = CheckOptions::new(candidates.len());
+    let results = check_tls_availability(&candidates, &args.domain, &[args.port], &options).await?;
+
+    let stats: Vec<_> = lists
+        .iter()
+        .map(|(path, ips)| bench::summarize(path, ips, &results))
+        .collect();
+
+    print::bench(&stats);
+
+    Ok(())
+}
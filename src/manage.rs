@@ -0,0 +1,68 @@
+use std::fs;
+use std::io;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+
+/// How often the managed file is checked while it is fresh, so that deleting it is noticed quickly.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Returns how long the file at `path` stays fresh, or zero if it should be refreshed now.
+///
+/// A file needs refreshing when it no longer exists or is empty, which is how clients mark it as
+/// consumed, or when it was last written more than `max_age` before `now`.
+///
+/// # Errors
+///
+/// Returns an error if the file's metadata cannot be read for another reason than its absence.
+pub fn time_until_stale(path: &str, max_age: Duration, now: SystemTime) -> Result<Duration> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Duration::ZERO),
+        Err(e) => return Err(e).with_context(|| format!("Couldn't read metadata of {}", path)),
+    };
+    if metadata.len() == 0 {
+        return Ok(Duration::ZERO);
+    }
+
+    let modified = metadata
+        .modified()
+        .with_context(|| format!("Couldn't read modification time of {}", path))?;
+    // A modification time in the future counts as just written.
+    let age = now.duration_since(modified).unwrap_or_default();
+
+    Ok(max_age.saturating_sub(age))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_until_stale() {
+        let path = std::env::temp_dir().join("cloudsurf_manage_test.txt");
+        let path = path.to_str().unwrap();
+        let max_age = Duration::from_secs(600);
+        let _ = fs::remove_file(path);
+
+        assert_eq!(
+            time_until_stale(path, max_age, SystemTime::now()).unwrap(),
+            Duration::ZERO
+        );
+
+        fs::write(path, "").unwrap();
+        assert_eq!(
+            time_until_stale(path, max_age, SystemTime::now()).unwrap(),
+            Duration::ZERO
+        );
+
+        fs::write(path, "104.16.0.1 MCI\n").unwrap();
+        let fresh = time_until_stale(path, max_age, SystemTime::now()).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(3_600);
+        let stale = time_until_stale(path, max_age, later).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(fresh > Duration::from_secs(590));
+        assert_eq!(stale, Duration::ZERO);
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use clap::ValueEnum;
+
+use crate::result::ScanResult;
+
+/// Determines how measurements of the same IP coming from different runs are combined.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the lowest latency and highest speed observed for each IP.
+    Best,
+    /// Keep the average latency and speed over all runs in which the IP appeared.
+    Average,
+}
+
+/// Combines the results of several probe passes over the same IPs, see `--passes`.
+///
+/// Each IP is measured according to `strategy` and records the number of passes it was reachable
+/// in. IPs reachable in more passes rank first, then by ranking latency, so that an IP that was
+/// only fast once does not beat consistently reachable ones.
+pub fn combine_passes(
+    mut passes: Vec<Vec<ScanResult>>,
+    strategy: MergeStrategy,
+    ipv6_bias: f64,
+) -> Vec<ScanResult> {
+    if passes.len() == 1 {
+        return passes.pop().unwrap_or_default();
+    }
+
+    let mut counts: HashMap<IpAddr, usize> = HashMap::new();
+    for result in passes.iter().flatten() {
+        *counts.entry(result.ip).or_default() += 1;
+    }
+
+    let mut combined = merge_results(&passes, strategy);
+    for result in &mut combined {
+        result.passes = counts.get(&result.ip).copied();
+    }
+    combined.sort_by(|a, b| {
+        b.passes.cmp(&a.passes).then(
+            a.ranking_latency(ipv6_bias)
+                .total_cmp(&b.ranking_latency(ipv6_bias)),
+        )
+    });
+
+    combined
+}
+
+/// Combines the results of several runs into a single list with one entry per IP.
+///
+/// Every IP that appears in at least one run is kept. When an IP was measured more than once,
+/// its latency and speed are reduced according to `strategy`, and the other fields are those of
+/// its fastest measurement. If the scanned name was cut in any run, the `--sni-fallback` name
+/// recommended by the fastest of those runs is kept instead. The merged list is sorted by latency,
+/// fastest first, so it can be printed or written exactly like the output of a single scan.
+///
+/// # Arguments
+///
+/// * `runs` - The results of each individual run.
+/// * `strategy` - How to combine multiple measurements of the same IP.
+///
+/// # Returns
+///
+/// A vector of `ScanResult` containing each distinct IP once, sorted by latency.
+pub fn merge_results(runs: &[Vec<ScanResult>], strategy: MergeStrategy) -> Vec<ScanResult> {
+    let mut measurements: HashMap<IpAddr, Vec<&ScanResult>> = HashMap::new();
+    for result in runs.iter().flatten() {
+        measurements.entry(result.ip).or_default().push(result);
+    }
+
+    let mut merged: Vec<ScanResult> = measurements
+        .into_values()
+        .filter_map(|results| {
+            let fastest = results.iter().min_by_key(|result| result.latency_ms)?;
+            // The scanned name may be filtered through this IP even if it was not cut every time.
+            let fallback = results
+                .iter()
+                .filter(|result| result.sni_fallback)
+                .min_by_key(|result| result.latency_ms)
+                .unwrap_or(fastest);
+            let speeds: Vec<f64> = results
+                .iter()
+                .filter_map(|result| result.speed_mbps)
+                .collect();
+
+            let speed_mbps = match strategy {
+                MergeStrategy::Best => speeds.iter().copied().reduce(f64::max),
+                MergeStrategy::Average => {
+                    (!speeds.is_empty()).then(|| speeds.iter().sum::<f64>() / speeds.len() as f64)
+                }
+            };
+
+            let latency_ms = combine_durations(
+                results.iter().map(|result| Some(result.latency_ms)),
+                strategy,
+            );
+            let tcp_ms = combine_durations(results.iter().map(|result| result.tcp_ms), strategy);
+            let tls_ms = combine_durations(results.iter().map(|result| result.tls_ms), strategy);
+            let dns_ms = combine_durations(results.iter().map(|result| result.dns_ms), strategy);
+
+            // An IP may have been reached on different ports; keep the one of the fastest run.
+            let port = results
+                .iter()
+                .filter(|result| result.port.is_some())
+                .min_by_key(|result| result.latency_ms)
+                .and_then(|result| result.port);
+
+            Some(ScanResult {
+                latency_ms: latency_ms.unwrap_or_default(),
+                port,
+                speed_mbps,
+                tcp_ms,
+                tls_ms,
+                dns_ms,
+                sni: fallback.sni.clone(),
+                sni_fallback: fallback.sni_fallback,
+                ..(*fastest).clone()
+            })
+        })
+        .collect();
+
+    // Sort by latency and then by IP so the output is stable across invocations.
+    merged.sort_by_key(|result| (result.latency_ms, result.ip));
+
+    merged
+}
+
+/// Combines the durations that were recorded, ignoring missing ones.
+///
+/// Returns `None` if no duration was recorded.
+fn combine_durations(
+    durations: impl Iterator<Item = Option<u128>>,
+    strategy: MergeStrategy,
+) -> Option<u128> {
+    let durations: Vec<u128> = durations.flatten().collect();
+
+    match strategy {
+        MergeStrategy::Best => durations.iter().copied().min(),
+        MergeStrategy::Average => (!durations.is_empty())
+            .then(|| durations.iter().sum::<u128>() / durations.len() as u128),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn result(ip: [u8; 4], latency_ms: u128) -> ScanResult {
+        ScanResult::new(Ipv4Addr::from(ip), latency_ms)
+    }
+
+    #[test]
+    fn test_merge_keeps_best_measurement() {
+        let runs = vec![
+            vec![result([104, 16, 0, 1], 120), result([104, 16, 0, 2], 80)],
+            vec![result([104, 16, 0, 1], 60), result([172, 64, 0, 1], 90)],
+        ];
+
+        let merged = merge_results(&runs, MergeStrategy::Best);
+
+        assert_eq!(
+            merged,
+            vec![
+                result([104, 16, 0, 1], 60),
+                result([104, 16, 0, 2], 80),
+                result([172, 64, 0, 1], 90),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_averages_measurements() {
+        let runs = vec![
+            vec![result([104, 16, 0, 1], 120)],
+            vec![result([104, 16, 0, 1], 60), result([104, 16, 0, 2], 70)],
+        ];
+
+        let merged = merge_results(&runs, MergeStrategy::Average);
+
+        assert_eq!(
+            merged,
+            vec![result([104, 16, 0, 2], 70), result([104, 16, 0, 1], 90)]
+        );
+    }
+
+    #[test]
+    fn test_merge_speeds() {
+        let fast = ScanResult {
+            speed_mbps: Some(40.0),
+            ..result([104, 16, 0, 1], 60)
+        };
+        let slow = ScanResult {
+            speed_mbps: Some(20.0),
+            ..result([104, 16, 0, 1], 80)
+        };
+        let runs = vec![vec![fast], vec![slow], vec![result([104, 16, 0, 1], 70)]];
+
+        let best = merge_results(&runs, MergeStrategy::Best);
+        assert_eq!(best[0].speed_mbps, Some(40.0));
+
+        let average = merge_results(&runs, MergeStrategy::Average);
+        assert_eq!(average[0].speed_mbps, Some(30.0));
+    }
+
+    #[test]
+    fn test_merge_connection_times() {
+        let first = ScanResult {
+            tcp_ms: Some(20),
+            tls_ms: Some(40),
+            ..result([104, 16, 0, 1], 60)
+        };
+        let second = ScanResult {
+            tcp_ms: Some(40),
+            tls_ms: Some(60),
+            ..result([104, 16, 0, 1], 100)
+        };
+        // Reports written before the split was recorded have no connection times.
+        let runs = vec![vec![first], vec![second], vec![result([104, 16, 0, 1], 70)]];
+
+        let best = merge_results(&runs, MergeStrategy::Best);
+        assert_eq!((best[0].tcp_ms, best[0].tls_ms), (Some(20), Some(40)));
+
+        let average = merge_results(&runs, MergeStrategy::Average);
+        assert_eq!((average[0].tcp_ms, average[0].tls_ms), (Some(30), Some(50)));
+        assert_eq!(average[0].latency_ms, 76);
+    }
+
+    #[test]
+    fn test_merge_dns_times() {
+        let first = ScanResult {
+            dns_ms: Some(10),
+            ..result([104, 16, 0, 1], 60)
+        };
+        let second = ScanResult {
+            dns_ms: Some(30),
+            ..result([104, 16, 0, 1], 50)
+        };
+        let runs = vec![vec![first], vec![second], vec![result([104, 16, 0, 1], 70)]];
+
+        let best = merge_results(&runs, MergeStrategy::Best);
+        assert_eq!(best[0].dns_ms, Some(10));
+
+        let average = merge_results(&runs, MergeStrategy::Average);
+        assert_eq!(average[0].dns_ms, Some(20));
+    }
+
+    #[test]
+    fn test_merge_keeps_fastest_fields() {
+        let fastest = ScanResult {
+            sni: Some("a.example.com".to_string()),
+            ray_id: Some("8a1b2c3d4e5f6a7b-AMS".to_string()),
+            ..result([104, 16, 0, 1], 60)
+        };
+        let slowest = ScanResult {
+            sni: Some("b.example.com".to_string()),
+            ..result([104, 16, 0, 1], 100)
+        };
+        let runs = vec![vec![slowest], vec![fastest]];
+
+        let average = merge_results(&runs, MergeStrategy::Average);
+        assert_eq!(average[0].latency_ms, 80);
+        assert_eq!(average[0].sni.as_deref(), Some("a.example.com"));
+        assert_eq!(average[0].ray_id.as_deref(), Some("8a1b2c3d4e5f6a7b-AMS"));
+    }
+
+    #[test]
+    fn test_merge_sni_fallback() {
+        let cut = ScanResult {
+            sni: Some("www.example.com".to_string()),
+            sni_fallback: true,
+            ..result([104, 16, 0, 1], 90)
+        };
+        let runs = vec![vec![result([104, 16, 0, 1], 60)], vec![cut]];
+
+        let best = merge_results(&runs, MergeStrategy::Best);
+        assert_eq!(best[0].latency_ms, 60);
+        assert_eq!(best[0].sni.as_deref(), Some("www.example.com"));
+        assert!(best[0].sni_fallback);
+    }
+
+    #[test]
+    fn test_merge_empty_runs() {
+        let merged = merge_results(&[], MergeStrategy::Best);
+        assert!(merged.is_empty());
+    }
+
+    #[test]
+    fn test_combine_passes() {
+        let passes = vec![
+            vec![result([104, 16, 0, 1], 40), result([104, 16, 0, 2], 90)],
+            vec![result([104, 16, 0, 2], 70)],
+            vec![result([104, 16, 0, 2], 80)],
+        ];
+
+        let combined = combine_passes(passes, MergeStrategy::Average, 1.0);
+
+        assert_eq!(
+            combined,
+            vec![
+                ScanResult {
+                    passes: Some(3),
+                    ..result([104, 16, 0, 2], 80)
+                },
+                ScanResult {
+                    passes: Some(1),
+                    ..result([104, 16, 0, 1], 40)
+                },
+            ]
+        );
+        assert_eq!(
+            combine_passes(
+                vec![vec![result([104, 16, 0, 1], 40)]],
+                MergeStrategy::Best,
+                1.0
+            ),
+            vec![result([104, 16, 0, 1], 40)]
+        );
+    }
+}
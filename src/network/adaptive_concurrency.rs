@@ -0,0 +1,207 @@
+use std::sync::Mutex;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::info;
+
+/// Minimum number of outcomes the error rate is computed over.
+const MIN_WINDOW: usize = 50;
+
+/// Share of interrupted probes above which the concurrency is halved.
+const HIGH_ERROR_RATE: f64 = 0.3;
+
+/// Share of interrupted probes below which the concurrency is raised again.
+const LOW_ERROR_RATE: f64 = 0.05;
+
+/// Lower bound of the concurrency, unless the maximum is lower.
+const MIN_CONCURRENCY: usize = 8;
+
+/// Fraction of the maximum concurrency added back each time errors stay low.
+const RAISE_FRACTION: usize = 10;
+
+/// A probe concurrency lowered when timeouts and resets spike, and raised back when they subside.
+///
+/// A sudden share of interrupted connections usually means the local NAT or conntrack table is
+/// full, or the ISP throttles new connections, rather than that the probed IPs are unreachable.
+/// The error rate is computed over windows of at least 50 outcomes, or the current concurrency if
+/// larger, so that each window mostly reflects probes started at the current concurrency. The
+/// concurrency is halved when more than 30% of a window was interrupted, and raised by a tenth of
+/// the maximum when less than 5% was, never exceeding the requested concurrency.
+#[derive(Debug)]
+pub(crate) struct AdaptiveConcurrency {
+    /// One permit per probe allowed to run.
+    semaphore: Semaphore,
+    /// The requested concurrency, which is never exceeded.
+    max: usize,
+    /// The concurrency is never lowered below this.
+    min: usize,
+    /// The current concurrency and the outcomes of the current window.
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// The current concurrency.
+    limit: usize,
+    /// The number of outcomes recorded in the current window.
+    outcomes: usize,
+    /// The number of interrupted probes in the current window.
+    interrupted: usize,
+    /// Permits to forget as they are released, after the concurrency was lowered.
+    debt: usize,
+}
+
+/// The right to run one probe, released when dropped.
+pub(crate) struct Slot<'a> {
+    concurrency: &'a AdaptiveConcurrency,
+    permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut state = self.concurrency.lock();
+        if state.debt > 0 {
+            state.debt -= 1;
+            if let Some(permit) = self.permit.take() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl AdaptiveConcurrency {
+    /// Creates a concurrency starting at, and never exceeding, `max`.
+    pub(crate) fn new(max: usize) -> Self {
+        let max = max.max(1);
+        AdaptiveConcurrency {
+            semaphore: Semaphore::new(max),
+            max,
+            min: MIN_CONCURRENCY.min(max),
+            state: Mutex::new(State {
+                limit: max,
+                outcomes: 0,
+                interrupted: 0,
+                debt: 0,
+            }),
+        }
+    }
+
+    /// Returns the current concurrency.
+    #[cfg(test)]
+    pub(crate) fn current(&self) -> usize {
+        self.lock().limit
+    }
+
+    /// Waits until one more probe may run.
+    pub(crate) async fn acquire(&self) -> Slot<'_> {
+        let permit = self
+            .semaphore
+            .acquire()
+            .await
+            .expect("concurrency semaphore closed");
+        Slot {
+            concurrency: self,
+            permit: Some(permit),
+        }
+    }
+
+    /// Records the outcome of a probe, and adjusts the concurrency once a window is complete.
+    pub(crate) fn record(&self, interrupted: bool) {
+        let mut state = self.lock();
+        state.outcomes += 1;
+        state.interrupted += usize::from(interrupted);
+        if state.outcomes < MIN_WINDOW.max(state.limit) {
+            return;
+        }
+
+        let error_rate = state.interrupted as f64 / state.outcomes as f64;
+        state.outcomes = 0;
+        state.interrupted = 0;
+
+        if error_rate > HIGH_ERROR_RATE && state.limit > self.min {
+            let limit = (state.limit / 2).max(self.min);
+            state.debt += state.limit - limit;
+            state.limit = limit;
+            info!(
+                error_rate = format!("{:.0}%", error_rate * 100.0),
+                concurrency = limit,
+                "Lowering concurrency, timeouts and resets are spiking"
+            );
+        } else if error_rate < LOW_ERROR_RATE && state.limit < self.max {
+            let limit = (state.limit + (self.max / RAISE_FRACTION).max(1)).min(self.max);
+            let added = limit - state.limit;
+            let cancelled = added.min(state.debt);
+            state.debt -= cancelled;
+            state.limit = limit;
+            self.semaphore.add_permits(added - cancelled);
+            info!(
+                error_rate = format!("{:.0}%", error_rate * 100.0),
+                concurrency = limit,
+                "Raising concurrency, timeouts and resets subsided"
+            );
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("concurrency state lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(concurrency: &AdaptiveConcurrency, outcomes: usize, interrupted: usize) {
+        for i in 0..outcomes {
+            concurrency.record(i < interrupted);
+        }
+    }
+
+    #[test]
+    fn test_lowers_and_raises_concurrency() {
+        let concurrency = AdaptiveConcurrency::new(100);
+
+        record(&concurrency, 100, 50);
+        assert_eq!(concurrency.current(), 50);
+
+        // The window shrinks with the concurrency, down to `MIN_WINDOW`.
+        record(&concurrency, 50, 20);
+        assert_eq!(concurrency.current(), 25);
+
+        record(&concurrency, 50, 0);
+        assert_eq!(concurrency.current(), 35);
+
+        record(&concurrency, 50, 5);
+        assert_eq!(concurrency.current(), 35);
+
+        for _ in 0..10 {
+            record(&concurrency, 100, 0);
+        }
+        assert_eq!(concurrency.current(), 100);
+    }
+
+    #[test]
+    fn test_concurrency_is_bounded() {
+        let concurrency = AdaptiveConcurrency::new(20);
+        for _ in 0..10 {
+            record(&concurrency, MIN_WINDOW, MIN_WINDOW);
+        }
+        assert_eq!(concurrency.current(), MIN_CONCURRENCY);
+
+        let single = AdaptiveConcurrency::new(1);
+        record(&single, MIN_WINDOW, MIN_WINDOW);
+        assert_eq!(single.current(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_released_permits_pay_off_lowering() {
+        let concurrency = AdaptiveConcurrency::new(MIN_CONCURRENCY * 2);
+        let slots: Vec<_> =
+            futures::future::join_all((0..MIN_CONCURRENCY * 2).map(|_| concurrency.acquire()))
+                .await;
+
+        record(&concurrency, MIN_WINDOW, MIN_WINDOW);
+        drop(slots);
+
+        assert_eq!(concurrency.semaphore.available_permits(), MIN_CONCURRENCY);
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::time::{timeout_at, Instant};
+
+/// Timeout used until enough latencies have been observed to estimate one.
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Lower bound of the estimated timeout, so that jitter on fast networks does not reject valid IPs.
+const MIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Upper bound of the estimated timeout, so that a few stragglers cannot stall the scan.
+const MAX_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of latencies observed before the estimate replaces the initial timeout.
+const MIN_SAMPLES: usize = 20;
+
+/// Percentile of the observed latencies the timeout is derived from.
+const PERCENTILE: f64 = 0.95;
+
+/// Factor applied to the percentile to obtain the timeout.
+const MULTIPLIER: u32 = 3;
+
+/// How often a pending operation re-reads the timeout, which may change while it is pending.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A probe timeout derived from the latencies observed so far during a run.
+///
+/// The timeout is the 95th percentile of the observed latencies multiplied by 3, clamped
+/// between 100 ms and 5 s. This cuts off hopeless probes early on fast networks without
+/// rejecting everything on slow ones. A fixed timeout can be set instead, in which case the
+/// observed latencies are ignored.
+#[derive(Debug)]
+pub(crate) struct AdaptiveTimeout {
+    /// Observed latencies in milliseconds, kept sorted.
+    samples: Mutex<Vec<u64>>,
+    /// The current timeout in milliseconds.
+    current_ms: AtomicU64,
+    /// Whether the timeout was set explicitly and must not be adapted.
+    fixed: bool,
+}
+
+impl AdaptiveTimeout {
+    /// Creates a timeout that adapts to the observed latencies, or a fixed one if `fixed` is set.
+    pub(crate) fn new(fixed: Option<Duration>) -> Self {
+        AdaptiveTimeout {
+            samples: Mutex::new(Vec::new()),
+            current_ms: AtomicU64::new(duration_ms(fixed.unwrap_or(INITIAL_TIMEOUT))),
+            fixed: fixed.is_some(),
+        }
+    }
+
+    /// Returns the current timeout.
+    pub(crate) fn current(&self) -> Duration {
+        Duration::from_millis(self.current_ms.load(Ordering::Relaxed))
+    }
+
+    /// Records the latency of a successful operation and updates the timeout.
+    pub(crate) fn observe(&self, latency: Duration) {
+        if self.fixed {
+            return;
+        }
+
+        let latency_ms = duration_ms(latency);
+        let mut samples = self.samples.lock().expect("latency samples lock poisoned");
+        let position = samples.partition_point(|&sample| sample <= latency_ms);
+        samples.insert(position, latency_ms);
+
+        if samples.len() >= MIN_SAMPLES {
+            let index = ((samples.len() - 1) as f64 * PERCENTILE).round() as usize;
+            let estimate = Duration::from_millis(samples[index]) * MULTIPLIER;
+            let estimate = estimate.clamp(MIN_TIMEOUT, MAX_TIMEOUT);
+            self.current_ms
+                .store(duration_ms(estimate), Ordering::Relaxed);
+        }
+    }
+
+    /// Runs `future` until it completes or the timeout, counted from `start`, elapses.
+    ///
+    /// The timeout is re-read while the future is pending, so probes started before the estimate
+    /// was available still benefit from it.
+    ///
+    /// # Returns
+    /// The output of the future, or `None` if it timed out.
+    pub(crate) async fn run<F: Future>(&self, start: Instant, future: F) -> Option<F::Output> {
+        tokio::pin!(future);
+
+        loop {
+            let deadline = start + self.current();
+            if deadline <= Instant::now() {
+                return None;
+            }
+
+            let wake_up = deadline.min(Instant::now() + POLL_INTERVAL);
+            if let Ok(output) = timeout_at(wake_up, &mut future).await {
+                return Some(output);
+            }
+        }
+    }
+}
+
+/// Converts a duration to whole milliseconds, saturating on overflow.
+fn duration_ms(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_initial_timeout() {
+        let timeout = AdaptiveTimeout::new(None);
+        for _ in 0..MIN_SAMPLES - 1 {
+            timeout.observe(Duration::from_millis(10));
+        }
+
+        assert_eq!(timeout.current(), INITIAL_TIMEOUT);
+    }
+
+    #[test]
+    fn test_adapts_to_observed_latencies() {
+        let timeout = AdaptiveTimeout::new(None);
+        for latency_ms in 1..=100 {
+            timeout.observe(Duration::from_millis(latency_ms));
+        }
+
+        // The 95th percentile of 1..=100 is 95 ms.
+        assert_eq!(timeout.current(), Duration::from_millis(285));
+    }
+
+    #[test]
+    fn test_estimate_is_clamped() {
+        let fast = AdaptiveTimeout::new(None);
+        let slow = AdaptiveTimeout::new(None);
+        for _ in 0..MIN_SAMPLES {
+            fast.observe(Duration::from_millis(5));
+            slow.observe(Duration::from_secs(4));
+        }
+
+        assert_eq!(fast.current(), MIN_TIMEOUT);
+        assert_eq!(slow.current(), MAX_TIMEOUT);
+    }
+
+    #[test]
+    fn test_fixed_timeout() {
+        let timeout = AdaptiveTimeout::new(Some(Duration::from_millis(700)));
+        for _ in 0..MIN_SAMPLES {
+            timeout.observe(Duration::from_millis(10));
+        }
+
+        assert_eq!(timeout.current(), Duration::from_millis(700));
+    }
+
+    #[tokio::test]
+    async fn test_run_times_out() {
+        let timeout = AdaptiveTimeout::new(Some(Duration::from_millis(10)));
+
+        let output = timeout
+            .run(Instant::now(), tokio::time::sleep(Duration::from_secs(1)))
+            .await;
+
+        assert!(output.is_none());
+    }
+}
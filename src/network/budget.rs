@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::report::WarningCode;
+use crate::warnings;
+
+/// The connection budget of the current run, shared by every check.
+static BUDGET: Budget = Budget::new();
+
+/// Limits the connection attempts of the current run to `max_probes`, or lifts the limit.
+///
+/// Every TCP connection made by a check counts, including warm-up handshakes and each port tried.
+pub fn set_max_probes(max_probes: Option<usize>) {
+    BUDGET.set_limit(max_probes);
+}
+
+/// Takes one connection attempt from the budget, returning `false` if it is exhausted.
+pub fn try_acquire() -> bool {
+    BUDGET.try_acquire()
+}
+
+/// A number of connection attempts that can be taken concurrently.
+struct Budget {
+    /// Whether the number of attempts is limited at all.
+    limited: AtomicBool,
+    /// The attempts left, when limited.
+    remaining: AtomicUsize,
+    /// Whether running out was already reported.
+    warned: AtomicBool,
+}
+
+impl Budget {
+    const fn new() -> Self {
+        Budget {
+            limited: AtomicBool::new(false),
+            remaining: AtomicUsize::new(0),
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    fn set_limit(&self, max_probes: Option<usize>) {
+        self.remaining
+            .store(max_probes.unwrap_or(0), Ordering::SeqCst);
+        self.warned.store(false, Ordering::SeqCst);
+        self.limited.store(max_probes.is_some(), Ordering::SeqCst);
+    }
+
+    fn try_acquire(&self) -> bool {
+        if !self.limited.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        let acquired = self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+                remaining.checked_sub(1)
+            })
+            .is_ok();
+        if !acquired && !self.warned.swap(true, Ordering::SeqCst) {
+            warnings::record(
+                WarningCode::ProbeBudgetExhausted,
+                "The --max-probes budget is exhausted, the remaining connections are skipped",
+            );
+        }
+
+        acquired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = Budget::new();
+        assert!(budget.try_acquire());
+
+        budget.set_limit(Some(2));
+        assert!(budget.try_acquire());
+        assert!(budget.try_acquire());
+        assert!(!budget.try_acquire());
+
+        budget.set_limit(None);
+        assert!(budget.try_acquire());
+    }
+}
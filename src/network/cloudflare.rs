@@ -1,9 +1,18 @@
-use std::net::Ipv4Addr;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
 
 use anyhow::{Context, Result};
-use ipnetwork::Ipv4Network;
+use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
 use reqwest::header;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::fastly::{self, FASTLY_API_URL};
+use super::provider::Provider;
+use crate::paths;
+use crate::report::WarningCode;
+use crate::warnings;
 
 const CLOUDFLARE_API_URL: &str = "https://api.cloudflare.com/client/v4/ips";
 
@@ -18,7 +27,7 @@ struct CloudflareIps {
     success: bool,
     /// Contains the detailed result of the API request, including
     /// lists of IPv4 and IPv6 CIDR blocks and an etag for caching purposes.
-    result: CloudflareIpResult,
+    result: CidrLists,
     /// A list of error messages, if any were returned by the API.
     /// This field is typically empty if `success` is `true`.
     errors: Vec<String>,
@@ -26,20 +35,19 @@ struct CloudflareIps {
 
 /// Represents the detailed result of a successful request to Cloudflare's IP list API.
 ///
-/// Includes lists of CIDR blocks for both IPv4 and IPv6 addresses that Cloudflare uses,
-/// as well as an etag that can be used for caching and conditional requests.
-#[derive(Deserialize, Debug)]
-struct CloudflareIpResult {
-    /// A list of CIDR blocks representing the IPv4 addresses used by Cloudflare.
-    ipv4_cidrs: Vec<String>,
+/// Includes lists of CIDR blocks for both IPv4 and IPv6 addresses that Cloudflare uses. The
+/// ranges of other providers are converted to it, so that they are cached and expanded alike.
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct CidrLists {
+    /// A list of CIDR blocks representing the IPv4 addresses used by the provider.
+    pub(super) ipv4_cidrs: Vec<String>,
+    /// A list of CIDR blocks representing the IPv6 addresses used by the provider.
+    #[serde(default)]
+    pub(super) ipv6_cidrs: Vec<String>,
 }
 
-/// Fetches the list of IPv4 CIDRs from Cloudflare's API, excluding any that start with specified prefixes.
-///
-/// # Returns
-///
-/// A `Result` wrapping a vector of filtered IPv4 CIDR strings on success, or an `anyhow::Error` on failure.
-async fn fetch_ipv4_cidr_list(url: &str) -> Result<Vec<String>> {
+/// Fetches the lists of IPv4 and IPv6 CIDRs from Cloudflare's API.
+async fn fetch_cidr_lists(url: &str) -> Result<CidrLists> {
     let client = reqwest::Client::new();
     let res = client
         .get(url)
@@ -59,7 +67,75 @@ async fn fetch_ipv4_cidr_list(url: &str) -> Result<Vec<String>> {
         );
     }
 
-    Ok(res.result.ipv4_cidrs)
+    Ok(res.result)
+}
+
+/// Fetches the CIDR lists from the API of `provider`, keeping a copy in the cache directory that
+/// is used instead when the API can't be reached.
+async fn fetch_published_cidr_lists(provider: Provider) -> Result<CidrLists> {
+    let cache = paths::ranges_cache_file(provider).ok();
+    let url = match provider {
+        Provider::Cloudflare => CLOUDFLARE_API_URL,
+        Provider::Fastly => FASTLY_API_URL,
+    };
+    fetch_cidr_lists_cached(provider, url, cache.as_deref()).await
+}
+
+/// Fetches the CIDR lists of `provider` from `url`, saving them to `cache`, or reading them from
+/// `cache` if the request fails.
+async fn fetch_cidr_lists_cached(
+    provider: Provider,
+    url: &str,
+    cache: Option<&Path>,
+) -> Result<CidrLists> {
+    let fetched = match provider {
+        Provider::Cloudflare => fetch_cidr_lists(url).await,
+        Provider::Fastly => fastly::fetch_cidr_lists(url).await,
+    };
+    let Some(cache) = cache else {
+        return fetched;
+    };
+
+    match fetched {
+        Ok(lists) => {
+            if let Err(e) = save_cidr_lists(&lists, cache) {
+                debug!(
+                    "Couldn't cache the IP ranges of {}: {:#}",
+                    provider.name(),
+                    e
+                );
+            }
+            Ok(lists)
+        }
+        Err(e) => match std::fs::read_to_string(cache) {
+            Ok(contents) => {
+                let lists = serde_json::from_str(&contents)
+                    .with_context(|| format!("Couldn't parse {}", cache.display()))?;
+                warnings::record(
+                    WarningCode::CachedRanges,
+                    format!("{:#}, using the IP ranges cached in {}", e, cache.display()),
+                );
+                Ok(lists)
+            }
+            Err(_) => Err(e),
+        },
+    }
+}
+
+fn save_cidr_lists(lists: &CidrLists, cache: &Path) -> Result<()> {
+    if let Some(dir) = cache.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(cache, serde_json::to_string(lists)?)?;
+
+    Ok(())
+}
+
+/// Fetches the list of IPv4 CIDRs currently published by Cloudflare's API, without expanding them.
+pub async fn fetch_cloudflare_ipv4_cidrs() -> Result<Vec<String>> {
+    Ok(fetch_published_cidr_lists(Provider::Cloudflare)
+        .await?
+        .ipv4_cidrs)
 }
 
 /// Attempts to expand a list of CIDR blocks into individual IP addresses.
@@ -96,45 +172,88 @@ fn expand_cidrs_to_ips(cidrs: &[String]) -> Result<Vec<Ipv4Addr>> {
 /// # Arguments
 ///
 /// * `ips` - A vector of `Ipv4Addr` representing the IP addresses to filter.
-/// * `skip_prefixes` - A slice of strings representing the prefixes to filter by.
+/// * `skip_prefixes` - The prefixes to filter by, see `is_skipped`.
 ///
 /// # Returns
 ///
 /// A vector of `Ipv4Addr` that do not start with any of the given prefixes, or all IPs if no prefixes are provided.
-fn filter_ips_by_prefix(ips: Vec<Ipv4Addr>, skip_prefixes: &[String]) -> Vec<Ipv4Addr> {
+fn filter_ips_by_prefix(ips: Vec<Ipv4Addr>, skip_prefixes: &[SkipPrefix]) -> Vec<Ipv4Addr> {
     // If skip_prefixes is empty, return all IPs without filtering
     if skip_prefixes.is_empty() {
         return ips;
     }
 
     ips.into_iter()
-        .filter(|ip| {
-            !skip_prefixes
-                .iter()
-                .any(|prefix| ip.to_string().starts_with(prefix))
-        })
+        .filter(|&ip| !is_skipped(IpAddr::V4(ip), skip_prefixes))
         .collect()
 }
 
-/// Fetches the list of IPv4 addresses used by Cloudflare, expands them from CIDR notation,
+/// An entry of `--skip-prefixes` or of a skip list, validated by `args::parse_skip_prefix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipPrefix {
+    /// A CIDR block such as `104.16.0.0/16`, which matches the addresses it contains.
+    Network(IpNetwork),
+    /// The start of an address such as `104.16` or `2400:cb00`, matched as text.
+    Text(String),
+}
+
+impl fmt::Display for SkipPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipPrefix::Network(network) => write!(f, "{}", network),
+            SkipPrefix::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+/// Filters out the sampled IPv6 hosts that match one of `skip_prefixes`, as
+/// `filter_ips_by_prefix` does for IPv4 addresses.
+pub fn filter_ipv6_hosts(hosts: Vec<Ipv6Addr>, skip_prefixes: &[SkipPrefix]) -> Vec<Ipv6Addr> {
+    if skip_prefixes.is_empty() {
+        return hosts;
+    }
+
+    hosts
+        .into_iter()
+        .filter(|&ip| !is_skipped(IpAddr::V6(ip), skip_prefixes))
+        .collect()
+}
+
+/// Whether `ip` matches one of `skip_prefixes`.
+pub fn is_skipped(ip: IpAddr, skip_prefixes: &[SkipPrefix]) -> bool {
+    // Only formatted when a textual prefix needs it, and then only once.
+    let mut text = None;
+    skip_prefixes.iter().any(|prefix| match prefix {
+        SkipPrefix::Network(network) => network.contains(ip),
+        SkipPrefix::Text(start) => text
+            .get_or_insert_with(|| ip.to_string())
+            .starts_with(start.as_str()),
+    })
+}
+
+/// Fetches the list of IPv4 addresses used by `provider`, expands them from CIDR notation,
 /// and filters out any addresses that start with the specified prefixes.
 ///
+/// The addresses are grouped by the CIDR block they belong to, in the order published by
+/// the provider, so that sampling can weigh the blocks. Blocks left empty by the filter are
+/// omitted.
+///
 /// The purpose of this function is to provide a filtered list of IPv4 addresses based on
 /// Cloudflare's publicly used IP ranges, potentially excluding specific subnets as required.
 ///
 /// # Arguments
 ///
-/// * `skip_prefixes` - A vector of string slices (`&str`) representing the prefixes to be excluded
-///   from the final list of IP addresses. Each prefix is matched at the start of the IP address strings.
-///   If this vector is empty, no filtering is applied, and all IP addresses are returned.
+/// * `provider` - The CDN whose published ranges are fetched.
+/// * `skip_prefixes` - The prefixes to be excluded from the final list of IP addresses, see
+///   `is_skipped`. If this slice is empty, no filtering is applied, and all IP addresses are
+///   returned.
 ///
 /// # Returns
 ///
-/// A `Result<Vec<Ipv4Addr>, anyhow::Error>` which is:
-/// - Ok(`Vec<Ipv4Addr>`): A vector of `Ipv4Addr` representing the filtered IPv4 addresses.
+/// A `Result<Vec<Vec<Ipv4Addr>>, anyhow::Error>` which is:
+/// - Ok(`Vec<Vec<Ipv4Addr>>`): The filtered IPv4 addresses of each CIDR block.
 /// - Err(`anyhow::Error`): An error encountered during any step of the process, including issues with
-///   fetching data from Cloudflare's API, deserializing the response, parsing the CIDR blocks,
-///   or handling invalid prefixes in `skip_prefixes`.
+///   fetching data from Cloudflare's API, deserializing the response or parsing the CIDR blocks.
 ///
 /// # Examples
 ///
@@ -143,21 +262,112 @@ fn filter_ips_by_prefix(ips: Vec<Ipv4Addr>, skip_prefixes: &[String]) -> Vec<Ipv
 ///
 /// #[tokio::main]
 /// async fn main() -> Result<()> {
-///     let skip_prefixes = vec!["192.0.2".to_string(), "198.51.100".to_string()];
-///     let filtered_ips = fetch_and_filter_ipv4_list(&skip_prefixes).await?;
-///     println!("{:?}", filtered_ips);
+///     let skip_prefixes = vec![
+///         SkipPrefix::Text("192.0.2".to_string()),
+///         SkipPrefix::Network("198.51.100.0/24".parse()?),
+///     ];
+///     let ranges = fetch_and_filter_ipv4_ranges(Provider::Cloudflare, &skip_prefixes).await?;
+///     println!("{:?}", ranges);
 ///     Ok(())
 /// }
 /// ```
 ///
 /// This function makes asynchronous network requests and thus must be awaited. Ensure it is called
 /// within an async context.
-pub async fn fetch_and_filter_ipv4_list(skip_prefixes: &[String]) -> Result<Vec<Ipv4Addr>> {
-    let cidr_list = fetch_ipv4_cidr_list(CLOUDFLARE_API_URL).await?;
-    let all_ips = expand_cidrs_to_ips(&cidr_list)?;
-    let filtered_ips = filter_ips_by_prefix(all_ips, skip_prefixes);
+pub async fn fetch_and_filter_ipv4_ranges(
+    provider: Provider,
+    skip_prefixes: &[SkipPrefix],
+) -> Result<Vec<Vec<Ipv4Addr>>> {
+    let cidr_list = fetch_published_cidr_lists(provider).await?.ipv4_cidrs;
+
+    let mut ranges = Vec::with_capacity(cidr_list.len());
+    for cidr in &cidr_list {
+        let ips = expand_cidrs_to_ips(std::slice::from_ref(cidr))?;
+        let filtered_ips = filter_ips_by_prefix(ips, skip_prefixes);
+        if !filtered_ips.is_empty() {
+            ranges.push(filtered_ips);
+        }
+    }
 
-    Ok(filtered_ips)
+    Ok(ranges)
+}
+
+/// Fetches the IPv6 prefixes used by `provider`, excluding those entirely skipped by
+/// `skip_prefixes`. The hosts sampled from the others still need `filter_ipv6_hosts`.
+///
+/// Unlike IPv4 ranges, the prefixes are not expanded, since they hold far too many addresses;
+/// hosts are sampled from them with `sampling::sample_ipv6`.
+pub async fn fetch_ipv6_prefixes(
+    provider: Provider,
+    skip_prefixes: &[SkipPrefix],
+) -> Result<Vec<Ipv6Network>> {
+    let cidrs = fetch_published_cidr_lists(provider).await?.ipv6_cidrs;
+    parse_ipv6_prefixes(&cidrs, skip_prefixes)
+}
+
+/// Resolves `domain` and returns those of its IPv4 addresses outside the ranges published by
+/// Cloudflare.
+///
+/// A domain that is not proxied by Cloudflare is not served by the scanned IPs, so every probe
+/// would fail or be measured against the wrong certificate. Only A records are checked.
+pub async fn unproxied_addresses(domain: &str) -> Result<Vec<Ipv4Addr>> {
+    let cidrs = fetch_published_cidr_lists(Provider::Cloudflare)
+        .await?
+        .ipv4_cidrs;
+    let addresses: Vec<Ipv4Addr> = tokio::net::lookup_host((domain, 443))
+        .await
+        .with_context(|| format!("Couldn't resolve {}", domain))?
+        .filter_map(|addr| match addr.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .collect();
+
+    outside_ranges(addresses, &cidrs)
+}
+
+/// Returns the distinct `addresses` not contained in any of `cidrs`, in order.
+fn outside_ranges(addresses: Vec<Ipv4Addr>, cidrs: &[String]) -> Result<Vec<Ipv4Addr>> {
+    let networks = cidrs
+        .iter()
+        .map(|cidr| {
+            cidr.parse::<Ipv4Network>()
+                .map_err(|e| anyhow::anyhow!("Invalid CIDR '{}': {}", cidr, e))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut outside = Vec::new();
+    for ip in addresses {
+        if !networks.iter().any(|network| network.contains(ip)) && !outside.contains(&ip) {
+            outside.push(ip);
+        }
+    }
+
+    Ok(outside)
+}
+
+/// Parses IPv6 CIDR blocks, dropping those that lie entirely in a CIDR block of `skip_prefixes`.
+///
+/// Other entries may only cover part of a prefix, so they are applied to the hosts sampled from
+/// it, see `filter_ipv6_hosts`.
+fn parse_ipv6_prefixes(cidrs: &[String], skip_prefixes: &[SkipPrefix]) -> Result<Vec<Ipv6Network>> {
+    let mut prefixes = Vec::with_capacity(cidrs.len());
+    for cidr in cidrs {
+        let prefix = cidr
+            .parse::<Ipv6Network>()
+            .map_err(|e| anyhow::anyhow!("Invalid CIDR '{}': {}", cidr, e))?;
+        let covered = skip_prefixes.iter().any(|skip| match skip {
+            SkipPrefix::Network(IpNetwork::V6(network)) => {
+                network.prefix() <= prefix.prefix() && network.contains(prefix.network())
+            }
+            _ => false,
+        });
+        if !covered {
+            prefixes.push(prefix);
+        }
+    }
+
+    Ok(prefixes)
 }
 
 #[cfg(test)]
@@ -170,7 +380,7 @@ mod tests {
     };
 
     #[tokio::test]
-    async fn test_fetch_ipv4_cidr_list() -> Result<()> {
+    async fn test_fetch_cidr_lists() -> Result<()> {
         let mock_server = MockServer::start().await;
         let response = ResponseTemplate::new(200)
             .insert_header("Content-Type", "application/json")
@@ -216,7 +426,7 @@ mod tests {
             .await;
 
         let url = format!("{}/client/v4/ips", mock_server.uri());
-        let result = fetch_ipv4_cidr_list(&url).await;
+        let result = fetch_cidr_lists(&url).await.map(|lists| lists.ipv4_cidrs);
 
         assert!(result.is_ok(), "Error: {:?}", result.err());
 
@@ -226,6 +436,41 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_cached_cidr_lists() -> Result<()> {
+        let mock_server = MockServer::start().await;
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true,
+            "result": { "ipv4_cidrs": ["104.16.0.0/13"], "ipv6_cidrs": ["2606:4700::/32"] },
+            "errors": []
+        }));
+        Mock::given(method("GET"))
+            .and(path("/client/v4/ips"))
+            .respond_with(response)
+            .mount(&mock_server)
+            .await;
+        let cache = std::env::temp_dir().join(format!("cloudsurf-ips-{}.json", std::process::id()));
+
+        let url = format!("{}/client/v4/ips", mock_server.uri());
+        let fetched = fetch_cidr_lists_cached(Provider::Cloudflare, &url, Some(&cache)).await?;
+        assert_eq!(fetched.ipv4_cidrs, ["104.16.0.0/13"]);
+
+        // The API is unreachable, so the cached lists are returned.
+        let unreachable = format!("{}/unknown", mock_server.uri());
+        let cached =
+            fetch_cidr_lists_cached(Provider::Cloudflare, &unreachable, Some(&cache)).await?;
+        assert_eq!(cached.ipv6_cidrs, ["2606:4700::/32"]);
+
+        std::fs::remove_file(&cache)?;
+        assert!(
+            fetch_cidr_lists_cached(Provider::Cloudflare, &unreachable, Some(&cache))
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_expand_cidrs_to_ips() {
         let cidrs = vec!["173.245.48.0/20".to_string(), "104.24.0.0/14".to_string()];
@@ -234,6 +479,24 @@ mod tests {
         assert_eq!(expanded.len(), 262144 + 4096);
     }
 
+    #[test]
+    fn test_outside_ranges() {
+        let cidrs = vec!["104.16.0.0/13".to_string(), "172.64.0.0/13".to_string()];
+        let addresses = [
+            "104.21.32.1",
+            "93.184.216.34",
+            "172.67.1.1",
+            "93.184.216.34",
+        ]
+        .iter()
+        .map(|ip| ip.parse().unwrap())
+        .collect();
+
+        let outside = outside_ranges(addresses, &cidrs).unwrap();
+
+        assert_eq!(outside, vec!["93.184.216.34".parse::<Ipv4Addr>().unwrap()]);
+    }
+
     #[test]
     fn test_filter_ips_by_prefix() {
         let ips = vec![
@@ -241,10 +504,58 @@ mod tests {
             "198.51.100.1".parse().unwrap(),
             "203.0.113.1".parse().unwrap(),
         ];
-        let skip_prefixes = vec!["198.51".to_string(), "203".to_string()];
-        let filtered = filter_ips_by_prefix(ips, &skip_prefixes);
+        let skip_prefixes = vec![
+            SkipPrefix::Text("198.51".to_string()),
+            SkipPrefix::Text("203".to_string()),
+        ];
+        let filtered = filter_ips_by_prefix(ips.clone(), &skip_prefixes);
 
         assert_eq!(filtered.len(), 1);
         assert_eq!(filtered[0], "192.0.2.1".parse::<Ipv4Addr>().unwrap());
+
+        let skip_cidrs = vec![SkipPrefix::Network("198.51.100.0/24".parse().unwrap())];
+        assert_eq!(filter_ips_by_prefix(ips, &skip_cidrs).len(), 2);
+    }
+
+    #[test]
+    fn test_parse_ipv6_prefixes() {
+        let cidrs = vec!["2400:cb00::/32".to_string(), "2606:4700::/32".to_string()];
+
+        let skip = [SkipPrefix::Network("2400::/16".parse().unwrap())];
+        let prefixes = parse_ipv6_prefixes(&cidrs, &skip).unwrap();
+        assert_eq!(prefixes, vec!["2606:4700::/32".parse().unwrap()]);
+
+        // Narrower blocks and textual prefixes are left to `filter_ipv6_hosts`.
+        let skip = [
+            SkipPrefix::Network("2400:cb00::/48".parse().unwrap()),
+            SkipPrefix::Text("2606".to_string()),
+        ];
+        assert_eq!(parse_ipv6_prefixes(&cidrs, &skip).unwrap().len(), 2);
+
+        assert!(parse_ipv6_prefixes(&["104.16.0.0/13".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_filter_ipv6_hosts() {
+        // Covers the upper half of the prefix, but not its network address.
+        let upper_half: Ipv6Network = "2400:cb00:8000::/33".parse().unwrap();
+        let skip = [SkipPrefix::Network(IpNetwork::V6(upper_half))];
+
+        let prefixes = parse_ipv6_prefixes(&["2400:cb00::/32".to_string()], &skip).unwrap();
+        let hosts = crate::sampling::sample_ipv6(&prefixes, 200);
+        let filtered = filter_ipv6_hosts(hosts, &skip);
+
+        assert!(!filtered.is_empty());
+        assert!(filtered.iter().all(|&ip| !upper_half.contains(ip)));
+
+        let hosts = vec![
+            "2400:cb00::1".parse().unwrap(),
+            "2606:4700::1".parse().unwrap(),
+        ];
+        let skip = [SkipPrefix::Text("2606:4700".to_string())];
+        assert_eq!(
+            filter_ipv6_hosts(hosts, &skip),
+            vec!["2400:cb00::1".parse::<Ipv6Addr>().unwrap()]
+        );
     }
 }
@@ -0,0 +1,317 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rustls::pki_types::ServerName;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::Instant,
+};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, debug_span, trace, Instrument};
+
+use super::adaptive_timeout::AdaptiveTimeout;
+use super::budget;
+use super::probe::{Probe, ProbeOutcome};
+use super::socket::SocketOptions;
+use super::tls_checker::{prepare_tls_config, CheckOptions};
+use crate::result::ScanResult;
+
+/// The server name of Cloudflare's public resolver, presented in every handshake.
+const RESOLVER_NAME: &str = "cloudflare-dns.com";
+
+/// Maximum size of the response that is read, headers included.
+const MAX_RESPONSE_SIZE: usize = 16 * 1024;
+
+/// The DNS record type queried, `A`.
+const QUERY_TYPE: u16 = 1;
+
+/// Probes IP addresses by resolving a name through them with DNS-over-HTTPS.
+///
+/// An IP is reachable when a handshake for `cloudflare-dns.com` completes and a `GET
+/// /dns-query` request returns a successful DNS answer, which makes it a usable alternative to
+/// 1.1.1.1. Its latency covers the connection, the handshake and the query.
+pub struct DohProbe {
+    /// The connector used for TLS handshakes.
+    connector: TlsConnector,
+    /// The server name of the resolver.
+    server_name: ServerName<'static>,
+    /// The request sent through each IP, querying the `A` records of the domain.
+    request: Vec<u8>,
+    /// The port numbers to try for each IP, in order of preference.
+    ports: Vec<u16>,
+    /// The settings of the sockets connected with.
+    socket: SocketOptions,
+    /// The probe timeout, fed with the latencies of successful queries.
+    timeout: AdaptiveTimeout,
+}
+
+impl DohProbe {
+    /// Creates a probe resolving `domain` on `ports`.
+    pub fn new(domain: &str, ports: &[u16], options: &CheckOptions) -> Result<Self> {
+        let query = dns_query(domain)?;
+        let request = format!(
+            "GET /dns-query?dns={} HTTP/1.1\r\nHost: {}\r\nUser-Agent: cloudsurf\r\nAccept: application/dns-message\r\nConnection: close\r\n\r\n",
+            URL_SAFE_NO_PAD.encode(query),
+            RESOLVER_NAME
+        );
+
+        Ok(DohProbe {
+            connector: TlsConnector::from(prepare_tls_config()?),
+            server_name: ServerName::try_from(RESOLVER_NAME)?,
+            request: request.into_bytes(),
+            ports: ports.to_vec(),
+            socket: options.socket,
+            timeout: AdaptiveTimeout::new(options.timeout),
+        })
+    }
+
+    /// Queries the resolver through `addr` and returns the TCP, TLS and query times.
+    async fn query(&self, addr: SocketAddr) -> Result<(u128, u128, u128), QueryError> {
+        if !budget::try_acquire() {
+            debug!("Skipped, the connection budget is exhausted");
+            return Err(QueryError::Failed);
+        }
+        let start = Instant::now();
+
+        let stream = match self.timeout.run(start, self.socket.connect(addr)).await {
+            Some(Ok(stream)) => stream,
+            Some(Err(e)) => {
+                debug!(error = %e, "TCP connection failed");
+                return Err(QueryError::from(&e));
+            }
+            None => return Err(QueryError::Interrupted),
+        };
+        let connected = Instant::now();
+
+        let connect = self.connector.connect(self.server_name.clone(), stream);
+        let mut stream = match self.timeout.run(start, connect).await {
+            Some(Ok(stream)) => stream,
+            Some(Err(e)) => {
+                debug!(error = %e, "TLS handshake failed");
+                return Err(QueryError::from(&e));
+            }
+            None => return Err(QueryError::Interrupted),
+        };
+        let handshaken = Instant::now();
+
+        let exchange = async {
+            stream.write_all(&self.request).await?;
+            let mut response = Vec::new();
+            (&mut stream)
+                .take(MAX_RESPONSE_SIZE as u64)
+                .read_to_end(&mut response)
+                .await?;
+            Ok::<_, io::Error>(response)
+        };
+        let response = match self.timeout.run(start, exchange).await {
+            Some(Ok(response)) => response,
+            Some(Err(e)) => {
+                debug!(error = %e, "DNS query failed");
+                return Err(QueryError::from(&e));
+            }
+            None => return Err(QueryError::Interrupted),
+        };
+        if let Err(e) = check_response(&response) {
+            debug!(error = %format!("{:#}", e), "Invalid DNS answer");
+            return Err(QueryError::Failed);
+        }
+        self.timeout.observe(start.elapsed());
+
+        Ok((
+            (connected - start).as_millis(),
+            (handshaken - connected).as_millis(),
+            handshaken.elapsed().as_millis(),
+        ))
+    }
+}
+
+impl Probe for DohProbe {
+    async fn check(&self, ip: IpAddr) -> ProbeOutcome {
+        let mut error = QueryError::Failed;
+        for &port in &self.ports {
+            let addr = SocketAddr::new(ip, port);
+            match self.query(addr).instrument(debug_span!("port", port)).await {
+                Ok((tcp_ms, tls_ms, dns_ms)) => {
+                    debug!(port, tcp_ms, tls_ms, dns_ms, "DNS answer received");
+                    return ProbeOutcome::Reachable(Box::new(ScanResult {
+                        port: Some(port),
+                        tcp_ms: Some(tcp_ms),
+                        tls_ms: Some(tls_ms),
+                        dns_ms: Some(dns_ms),
+                        ..ScanResult::new(ip, tcp_ms + tls_ms + dns_ms)
+                    }));
+                }
+                Err(e) => error = error.max(e),
+            }
+        }
+
+        trace!("No port returned a DNS answer");
+        match error {
+            QueryError::Failed => ProbeOutcome::Unreachable,
+            QueryError::Interrupted => ProbeOutcome::Interrupted,
+        }
+    }
+}
+
+/// Why a query did not return an answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum QueryError {
+    /// The connection was refused, the answer was invalid, or no connection was attempted.
+    Failed,
+    /// The query timed out or the connection was reset.
+    Interrupted,
+}
+
+impl From<&io::Error> for QueryError {
+    fn from(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+                QueryError::Interrupted
+            }
+            _ => QueryError::Failed,
+        }
+    }
+}
+
+/// Encodes a recursive DNS query for the `A` records of `name`, in wire format.
+///
+/// The ID is 0, as recommended by RFC 8484 for the query to be cacheable.
+fn dns_query(name: &str) -> Result<Vec<u8>> {
+    let mut query = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.trim_end_matches('.').split('.') {
+        anyhow::ensure!(
+            !label.is_empty() && label.len() < 64,
+            "Invalid domain name '{}'",
+            name
+        );
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&QUERY_TYPE.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+
+    Ok(query)
+}
+
+/// Checks that `response` is a successful HTTP response carrying a DNS answer with at least one
+/// record.
+///
+/// The body is delimited by `Content-Length` when given, and decoded when sent with
+/// `Transfer-Encoding: chunked`.
+fn check_response(response: &[u8]) -> Result<()> {
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .context("Incomplete HTTP response")?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let status_line = head.lines().next().unwrap_or_default();
+    anyhow::ensure!(
+        status_line.split_whitespace().nth(1) == Some("200"),
+        "Unexpected response: {}",
+        status_line
+    );
+
+    let header = |name: &str| {
+        head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    };
+    let body = &response[split + 4..];
+    let message = match (header("Transfer-Encoding"), header("Content-Length")) {
+        (Some(encoding), _) if encoding.to_ascii_lowercase().contains("chunked") => dechunk(body)?,
+        (_, Some(length)) => {
+            let length: usize = length
+                .parse()
+                .with_context(|| format!("Invalid Content-Length '{}'", length))?;
+            anyhow::ensure!(body.len() >= length, "Truncated HTTP body");
+            body[..length].to_vec()
+        }
+        _ => body.to_vec(),
+    };
+    anyhow::ensure!(message.len() >= 12, "Truncated DNS message");
+    anyhow::ensure!(message[2] & 0x80 != 0, "Not a DNS response");
+    let rcode = message[3] & 0x0f;
+    anyhow::ensure!(rcode == 0, "DNS error code {}", rcode);
+    let answers = u16::from_be_bytes([message[6], message[7]]);
+    anyhow::ensure!(answers > 0, "Empty DNS answer");
+
+    Ok(())
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`, ignoring chunk extensions and trailers.
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let end = body
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .context("Truncated chunked body")?;
+        let line = String::from_utf8_lossy(&body[..end]);
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16)
+            .with_context(|| format!("Invalid chunk size '{}'", size))?;
+        body = &body[end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+
+        anyhow::ensure!(
+            body.len() >= size + 2 && &body[size..size + 2] == b"\r\n",
+            "Truncated chunked body"
+        );
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_query() {
+        let query = dns_query("example.com").unwrap();
+
+        assert_eq!(&query[..12], &[0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&query[12..], b"\x07example\x03com\x00\x00\x01\x00\x01");
+        assert!(dns_query("example..com").is_err());
+    }
+
+    #[test]
+    fn test_check_response() {
+        let mut response =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\n\r\n".to_vec();
+        response.extend_from_slice(&[0, 0, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+        assert!(check_response(&response).is_ok());
+
+        // NXDOMAIN
+        let last = response.len() - 9;
+        response[last] = 0x83;
+        assert!(check_response(&response).is_err());
+
+        assert!(check_response(b"HTTP/1.1 403 Forbidden\r\n\r\n").is_err());
+
+        // The message split over two chunks, followed by a trailer.
+        let mut response =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\n".to_vec();
+        response.extend_from_slice(&[0, 0, 0x81, 0x80, 0]);
+        response.extend_from_slice(b"\r\n7\r\n");
+        response.extend_from_slice(&[1, 0, 1, 0, 0, 0, 0]);
+        response.extend_from_slice(b"\r\n0\r\nX-Trailer: 1\r\n\r\n");
+        assert!(check_response(&response).is_ok());
+        assert!(check_response(&response[..response.len() - 30]).is_err());
+
+        // Bytes past Content-Length are not part of the message.
+        let mut response = b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\n".to_vec();
+        response.extend_from_slice(&[0, 0, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0, 0xff]);
+        assert!(check_response(&response).is_ok());
+        assert!(check_response(&response[..response.len() - 2]).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use super::cloudflare::CidrLists;
+
+pub(super) const FASTLY_API_URL: &str = "https://api.fastly.com/public-ip-list";
+
+/// The response of Fastly's public IP list API.
+#[derive(Deserialize, Debug)]
+struct FastlyIps {
+    /// The IPv4 CIDR blocks of Fastly's edge servers.
+    addresses: Vec<String>,
+    /// The IPv6 CIDR blocks of Fastly's edge servers.
+    #[serde(default)]
+    ipv6_addresses: Vec<String>,
+}
+
+/// Fetches the lists of IPv4 and IPv6 CIDRs from Fastly's API.
+pub(super) async fn fetch_cidr_lists(url: &str) -> Result<CidrLists> {
+    let res = reqwest::get(url)
+        .await
+        .context("Failed to send request to Fastly API")?
+        .error_for_status()
+        .context("Fastly API returned an error")?
+        .json::<FastlyIps>()
+        .await
+        .context("Failed to deserialize Fastly API response")?;
+
+    Ok(CidrLists {
+        ipv4_cidrs: res.addresses,
+        ipv6_cidrs: res.ipv6_addresses,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn test_fetch_cidr_lists() -> Result<()> {
+        let mock_server = MockServer::start().await;
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "addresses": ["151.101.0.0/16", "199.232.0.0/16"],
+            "ipv6_addresses": ["2a04:4e40::/32"]
+        }));
+        Mock::given(method("GET"))
+            .and(path("/public-ip-list"))
+            .respond_with(response)
+            .mount(&mock_server)
+            .await;
+
+        let url = format!("{}/public-ip-list", mock_server.uri());
+        let lists = fetch_cidr_lists(&url).await?;
+
+        assert_eq!(lists.ipv4_cidrs, ["151.101.0.0/16", "199.232.0.0/16"]);
+        assert_eq!(lists.ipv6_cidrs, ["2a04:4e40::/32"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep, Sleep},
+};
+use tracing::info;
+
+use super::socket::SocketOptions;
+use super::tls_checker::{probe_tls, CheckOptions};
+use crate::report::FragmentComparison;
+use crate::result::{FragmentDifference, ScanResult};
+
+/// The content type of TLS handshake records.
+const HANDSHAKE_RECORD: u8 = 0x16;
+
+/// Length of a TLS record header: content type, version and payload length.
+const RECORD_HEADER_LEN: usize = 5;
+
+/// How the ClientHello is split up, see `--fragment-hello`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentHello {
+    /// The number of handshake bytes carried by each record.
+    pub size: usize,
+    /// The pause between two records, in milliseconds.
+    pub delay_ms: u64,
+}
+
+/// Probes `ips` again without fragmenting the ClientHello, and records the difference in
+/// `reachable`, the results of probing them with `options.socket.fragment`.
+///
+/// # Returns
+/// How many IPs were reachable either way, only with fragmentation, or only without it.
+pub async fn compare_unfragmented(
+    reachable: &mut [ScanResult],
+    ips: &[IpAddr],
+    domain: &str,
+    ports: &[u16],
+    options: &CheckOptions,
+) -> Result<FragmentComparison> {
+    let options = CheckOptions {
+        socket: SocketOptions {
+            fragment: None,
+            ..options.socket
+        },
+        spill_path: None,
+        ..options.clone()
+    };
+    info!("Probing again without fragmenting the ClientHello");
+    let unfragmented: HashMap<IpAddr, u128> = probe_tls(ips, domain, ports, &options)
+        .await?
+        .into_iter()
+        .map(|result| (result.ip, result.latency_ms))
+        .collect();
+
+    for result in reachable.iter_mut() {
+        result.fragment = Some(FragmentDifference {
+            unfragmented_ms: unfragmented.get(&result.ip).copied(),
+        });
+    }
+    let fragmented: HashSet<IpAddr> = reachable.iter().map(|result| result.ip).collect();
+    let both = fragmented
+        .iter()
+        .filter(|ip| unfragmented.contains_key(ip))
+        .count();
+
+    Ok(FragmentComparison {
+        both,
+        only_fragmented: fragmented.len() - both,
+        only_unfragmented: unfragmented.len() - both,
+    })
+}
+
+/// A stream splitting the first handshake record written to it into records of at most
+/// `size` bytes, each sent in its own write after a pause.
+///
+/// SNI filters that only inspect the first segment, or don't reassemble records, then can't
+/// read the server name. Everything after the first record is passed through unchanged.
+pub struct FragmentedStream<S> {
+    inner: S,
+    /// How to split the first record, until it has been written.
+    fragment: Option<FragmentHello>,
+    /// The records still to be written for the first record.
+    pending: Option<Pending>,
+}
+
+/// The progress of writing the fragments of the first record.
+struct Pending {
+    /// The records left to write, the first of which may be partially written.
+    records: VecDeque<Vec<u8>>,
+    /// The number of bytes of the first record already written.
+    offset: usize,
+    /// The pause before the next record, if one is running.
+    pause: Option<Pin<Box<Sleep>>>,
+    /// The length of the original record, reported as written once every fragment is.
+    len: usize,
+    /// The pause between two records.
+    delay: Duration,
+}
+
+impl<S> FragmentedStream<S> {
+    /// Wraps `inner`, splitting its first handshake record according to `fragment`, if any.
+    pub fn new(inner: S, fragment: Option<FragmentHello>) -> Self {
+        FragmentedStream {
+            inner,
+            fragment,
+            pending: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FragmentedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FragmentedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.pending.is_none() {
+            let split = this
+                .fragment
+                .take()
+                .and_then(|fragment| split_record(buf, fragment.size).zip(Some(fragment)));
+            let Some(((records, len), fragment)) = split else {
+                return Pin::new(&mut this.inner).poll_write(cx, buf);
+            };
+            this.pending = Some(Pending {
+                records,
+                offset: 0,
+                pause: None,
+                len,
+                delay: Duration::from_millis(fragment.delay_ms),
+            });
+        }
+
+        let pending = this.pending.as_mut().expect("set above");
+        loop {
+            if let Some(pause) = &mut pending.pause {
+                ready!(pause.as_mut().poll(cx));
+                pending.pause = None;
+            }
+            let Some(record) = pending.records.front() else {
+                let len = pending.len;
+                this.pending = None;
+                return Poll::Ready(Ok(len));
+            };
+
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &record[pending.offset..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            pending.offset += n;
+            if pending.offset == record.len() {
+                pending.records.pop_front();
+                pending.offset = 0;
+                if !pending.records.is_empty() && !pending.delay.is_zero() {
+                    pending.pause = Some(Box::pin(sleep(pending.delay)));
+                }
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Splits the handshake record at the start of `buf` into records carrying at most `size` bytes
+/// of it each.
+///
+/// Returns the records and the length of the original record, or `None` if `buf` doesn't start
+/// with a complete handshake record.
+fn split_record(buf: &[u8], size: usize) -> Option<(VecDeque<Vec<u8>>, usize)> {
+    if buf.len() < RECORD_HEADER_LEN || buf[0] != HANDSHAKE_RECORD {
+        return None;
+    }
+    let len = RECORD_HEADER_LEN + u16::from_be_bytes([buf[3], buf[4]]) as usize;
+    let payload = buf.get(RECORD_HEADER_LEN..len)?;
+
+    let records = payload
+        .chunks(size.max(1))
+        .map(|chunk| {
+            let mut record = Vec::with_capacity(RECORD_HEADER_LEN + chunk.len());
+            record.extend_from_slice(&buf[..3]);
+            record.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            record.extend_from_slice(chunk);
+            record
+        })
+        .collect();
+
+    Some((records, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_split_record() {
+        let record = [0x16, 0x03, 0x01, 0x00, 0x05, 1, 2, 3, 4, 5];
+
+        let (records, len) = split_record(&record, 2).unwrap();
+
+        assert_eq!(len, 10);
+        assert_eq!(
+            records,
+            [
+                vec![0x16, 0x03, 0x01, 0x00, 0x02, 1, 2],
+                vec![0x16, 0x03, 0x01, 0x00, 0x02, 3, 4],
+                vec![0x16, 0x03, 0x01, 0x00, 0x01, 5],
+            ]
+        );
+        assert!(split_record(&record[..8], 2).is_none());
+        assert!(split_record(&[0x17, 0x03, 0x03, 0x00, 0x00], 2).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fragments_first_record_only() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let fragment = FragmentHello {
+            size: 3,
+            delay_ms: 1,
+        };
+        let mut stream = FragmentedStream::new(client, Some(fragment));
+
+        stream
+            .write_all(&[0x16, 0x03, 0x01, 0x00, 0x04, 1, 2, 3, 4])
+            .await
+            .unwrap();
+        stream
+            .write_all(&[0x16, 0x03, 0x03, 0x00, 0x01, 9])
+            .await
+            .unwrap();
+        drop(stream);
+
+        let mut written = Vec::new();
+        server.read_to_end(&mut written).await.unwrap();
+        assert_eq!(
+            written,
+            [
+                0x16, 0x03, 0x01, 0x00, 0x03, 1, 2, 3, // first fragment
+                0x16, 0x03, 0x01, 0x00, 0x01, 4, // second fragment
+                0x16, 0x03, 0x03, 0x00, 0x01, 9, // passed through
+            ]
+        );
+    }
+}
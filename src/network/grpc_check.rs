@@ -0,0 +1,194 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::{stream, StreamExt};
+use http::{HeaderMap, Request, Uri};
+use rustls::pki_types::ServerName;
+use tokio::time::{timeout, Instant};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, debug_span, info, Instrument};
+
+use super::h2_check::{connect, h2_connector};
+use crate::result::{GrpcCheckResult, ScanResult};
+
+/// Timeout for the call, from sending the request until the response headers arrive.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the server may keep the call open after its response headers before the status is
+/// given up on. Streaming services like Xray's keep it open until the client hangs up.
+const TRAILERS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The method called on the service, the bidirectional stream of Xray's gRPC transport.
+const METHOD: &str = "Tun";
+
+/// An empty gRPC message: an uncompressed flag followed by a zero length.
+const EMPTY_MESSAGE: &[u8] = &[0, 0, 0, 0, 0];
+
+/// Maximum number of IPs checked at the same time.
+const MAX_CONCURRENT_CHECKS: usize = 16;
+
+/// Checks that a gRPC call to `service` goes through each IP and records the outcome.
+///
+/// For each result, an HTTP/2 connection to `domain` is opened through the IP and a minimal call
+/// to the `Tun` method of `service` is made, the way Xray's gRPC transport calls it. The check
+/// passes when the response is a gRPC response, which proves that the edge relays gRPC to the
+/// origin. The `grpc-status` of the call is recorded when the server ends it.
+///
+/// # Arguments
+///
+/// * `results` - The results to check. Their `grpc` field is updated in place.
+/// * `domain` - The domain name used for the TLS connection and the call.
+/// * `port` - The port number to connect to, unless a result records the port it was reached on.
+/// * `service` - The gRPC service name, such as the `serviceName` of an Xray configuration.
+pub async fn check_grpc(
+    results: &mut [ScanResult],
+    domain: &str,
+    port: u16,
+    service: &str,
+) -> Result<()> {
+    let connector = h2_connector()?;
+    let domain_name = ServerName::try_from(domain.to_string())?;
+    let uri: Uri = format!(
+        "https://{}/{}/{}",
+        domain,
+        service.trim_matches('/'),
+        METHOD
+    )
+    .parse()
+    .with_context(|| format!("Invalid gRPC service '{}'", service))?;
+
+    let checks = results.iter_mut().map(|result| {
+        let connector = &connector;
+        let domain_name = domain_name.clone();
+        let uri = &uri;
+        let port = result.port.unwrap_or(port);
+        let span = debug_span!("grpc_check", ip = %result.ip, port);
+
+        async move {
+            let outcome = run_check(connector, domain_name, uri, result.ip, port).await;
+            match &outcome.error {
+                Some(error) => info!(ip = %result.ip, error = error.as_str(), "gRPC check failed"),
+                None => debug!(
+                    latency_ms = outcome.latency_ms,
+                    status = outcome.status,
+                    "gRPC response received"
+                ),
+            }
+            result.grpc = Some(outcome);
+        }
+        .instrument(span)
+    });
+
+    stream::iter(checks)
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .for_each(|_| async {})
+        .await;
+
+    Ok(())
+}
+
+/// Runs the gRPC check against a single IP.
+async fn run_check(
+    connector: &TlsConnector,
+    domain_name: ServerName<'static>,
+    uri: &Uri,
+    ip: IpAddr,
+    port: u16,
+) -> GrpcCheckResult {
+    let outcome = async {
+        let send_request = connect(connector, domain_name, ip, port).await?;
+        call(send_request, uri.clone()).await
+    };
+
+    match outcome.await {
+        Ok(outcome) => outcome,
+        Err(e) => GrpcCheckResult {
+            error: Some(format!("{:#}", e)),
+            ..GrpcCheckResult::default()
+        },
+    }
+}
+
+/// Makes the call over `send_request` and checks that the response is a gRPC response.
+async fn call(send_request: h2::client::SendRequest<Bytes>, uri: Uri) -> Result<GrpcCheckResult> {
+    let request = Request::post(uri)
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .header("user-agent", "cloudsurf")
+        .body(())
+        .expect("a POST request with a valid URI is always valid");
+
+    let start = Instant::now();
+    let response = timeout(REQUEST_TIMEOUT, async {
+        let mut send_request = send_request.ready().await?;
+        let (response, mut body) = send_request.send_request(request, false)?;
+        body.send_data(Bytes::from_static(EMPTY_MESSAGE), true)?;
+        response.await
+    })
+    .await
+    .context("gRPC call timed out")?
+    .context("gRPC call failed")?;
+    let latency_ms = start.elapsed().as_millis();
+
+    let (parts, mut body) = response.into_parts();
+    anyhow::ensure!(
+        parts.status == http::StatusCode::OK,
+        "Unexpected HTTP status {}",
+        parts.status
+    );
+    let content_type = parts
+        .headers
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    anyhow::ensure!(
+        content_type.starts_with("application/grpc"),
+        "Not a gRPC response, content type '{}'",
+        content_type
+    );
+
+    // A call that fails right away carries its status in the headers, others in the trailers.
+    let mut status = grpc_status(&parts.headers);
+    if status.is_none() {
+        let trailers = timeout(TRAILERS_TIMEOUT, async {
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk?;
+                let _ = body.flow_control().release_capacity(chunk.len());
+            }
+            body.trailers().await
+        })
+        .await;
+        if let Ok(Ok(Some(trailers))) = trailers {
+            status = grpc_status(&trailers);
+        }
+    }
+
+    Ok(GrpcCheckResult {
+        latency_ms: Some(latency_ms),
+        status,
+        error: None,
+    })
+}
+
+/// Returns the `grpc-status` in `headers`, if any.
+fn grpc_status(headers: &HeaderMap) -> Option<u32> {
+    headers
+        .get("grpc-status")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grpc_status() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(grpc_status(&headers), None);
+
+        headers.insert("grpc-status", "12".parse().unwrap());
+        assert_eq!(grpc_status(&headers), Some(12));
+    }
+}
@@ -0,0 +1,203 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::{future::join_all, stream, StreamExt};
+use h2::client::SendRequest;
+use http::{Request, Uri};
+use rustls::pki_types::ServerName;
+use tokio::{
+    net::TcpStream,
+    time::{sleep, timeout, Instant},
+};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, debug_span, info, Instrument};
+
+use super::budget;
+use super::tls_checker::prepare_tls_config;
+use crate::result::{H2CheckResult, ScanResult};
+
+/// Timeout for establishing the TCP connection and completing the TLS and HTTP/2 handshakes.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Timeout for a single request, including reading the response body.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of requests multiplexed over the connection in each round.
+const PARALLEL_STREAMS: usize = 4;
+
+/// Pause between two rounds of requests.
+const ROUND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of IPs checked at the same time.
+const MAX_CONCURRENT_CHECKS: usize = 16;
+
+/// Checks that each IP sustains a multiplexed HTTP/2 connection and records the outcome.
+///
+/// For each result, a single HTTP/2 connection to `domain` is opened through the IP. Until
+/// `duration` has elapsed, rounds of parallel requests are issued over that connection, and
+/// stream errors as well as GOAWAY frames sent by the server are counted. This catches edges
+/// that complete a handshake but degrade under sustained use.
+///
+/// # Arguments
+///
+/// * `results` - The results to check. Their `h2` field is updated in place.
+/// * `domain` - The domain name used for the TLS connection and the requests.
+/// * `port` - The port number to connect to, unless a result records the port it was reached on.
+/// * `duration` - How long requests are issued over each connection.
+pub async fn check_h2_keepalive(
+    results: &mut [ScanResult],
+    domain: &str,
+    port: u16,
+    duration: Duration,
+) -> Result<()> {
+    let connector = h2_connector()?;
+
+    let domain_name = ServerName::try_from(domain.to_string())?;
+    let uri: Uri = format!("https://{}/", domain)
+        .parse()
+        .with_context(|| format!("Invalid domain '{}'", domain))?;
+
+    let checks = results.iter_mut().map(|result| {
+        let connector = &connector;
+        let domain_name = domain_name.clone();
+        let uri = &uri;
+        let port = result.port.unwrap_or(port);
+        let span = debug_span!("h2_check", ip = %result.ip, port);
+
+        async move {
+            let outcome = run_check(connector, domain_name, uri, result.ip, port, duration).await;
+            if !outcome.passed() {
+                info!(
+                    ip = %result.ip,
+                    completed = outcome.completed,
+                    stream_errors = outcome.stream_errors,
+                    goaway = outcome.goaway,
+                    error = outcome.error.as_deref().unwrap_or_default(),
+                    "HTTP/2 keep-alive check failed"
+                );
+            }
+            result.h2 = Some(outcome);
+        }
+        .instrument(span)
+    });
+
+    stream::iter(checks)
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .for_each(|_| async {})
+        .await;
+
+    Ok(())
+}
+
+/// Returns a TLS connector negotiating HTTP/2 with ALPN.
+pub(super) fn h2_connector() -> Result<TlsConnector> {
+    let mut config = (*prepare_tls_config()?).clone();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Runs the keep-alive check against a single IP.
+async fn run_check(
+    connector: &TlsConnector,
+    domain_name: ServerName<'static>,
+    uri: &Uri,
+    ip: IpAddr,
+    port: u16,
+    duration: Duration,
+) -> H2CheckResult {
+    let mut outcome = H2CheckResult::default();
+
+    let send_request = match connect(connector, domain_name, ip, port).await {
+        Ok(send_request) => send_request,
+        Err(e) => {
+            outcome.error = Some(format!("{:#}", e));
+            return outcome;
+        }
+    };
+
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        let requests = (0..PARALLEL_STREAMS)
+            .map(|_| timeout(REQUEST_TIMEOUT, send(send_request.clone(), uri.clone())));
+
+        for response in join_all(requests).await {
+            match response {
+                Ok(Ok(())) => outcome.completed += 1,
+                Ok(Err(e)) if e.is_go_away() => outcome.goaway = true,
+                Ok(Err(e)) => {
+                    debug!(error = %e, "HTTP/2 stream failed");
+                    outcome.stream_errors += 1;
+                }
+                Err(_) => {
+                    debug!("HTTP/2 request timed out");
+                    outcome.stream_errors += 1;
+                }
+            }
+        }
+
+        if outcome.goaway {
+            break;
+        }
+        sleep(ROUND_INTERVAL).await;
+    }
+
+    outcome
+}
+
+/// Opens an HTTP/2 connection through `ip` and returns a handle for issuing requests.
+pub(super) async fn connect(
+    connector: &TlsConnector,
+    domain_name: ServerName<'static>,
+    ip: IpAddr,
+    port: u16,
+) -> Result<SendRequest<Bytes>> {
+    anyhow::ensure!(budget::try_acquire(), "The connection budget is exhausted");
+    let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect((ip, port)))
+        .await
+        .context("TCP connection timed out")??;
+    let stream = timeout(CONNECT_TIMEOUT, connector.connect(domain_name, stream))
+        .await
+        .context("TLS handshake timed out")??;
+
+    if stream.get_ref().1.alpn_protocol() != Some(&b"h2"[..]) {
+        anyhow::bail!("The server did not negotiate HTTP/2");
+    }
+
+    let (send_request, connection) = timeout(CONNECT_TIMEOUT, h2::client::handshake(stream))
+        .await
+        .context("HTTP/2 handshake timed out")?
+        .context("HTTP/2 handshake failed")?;
+
+    // The connection future drives the HTTP/2 protocol and must be polled in the background.
+    tokio::spawn(
+        async move {
+            if let Err(e) = connection.await {
+                debug!(error = %e, "HTTP/2 connection closed with an error");
+            }
+        }
+        .in_current_span(),
+    );
+
+    Ok(send_request)
+}
+
+/// Sends a single GET request and reads the complete response body.
+async fn send(send_request: SendRequest<Bytes>, uri: Uri) -> Result<(), h2::Error> {
+    let request = Request::get(uri)
+        .header("user-agent", "cloudsurf")
+        .body(())
+        .expect("a GET request with a valid URI is always valid");
+
+    let mut send_request = send_request.ready().await?;
+    let (response, _) = send_request.send_request(request, true)?;
+
+    let mut body = response.await?.into_body();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{Context, Result};
+use futures::{stream, StreamExt};
+use rustls::pki_types::ServerName;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, debug_span, info, Instrument};
+
+use super::budget;
+use super::tls_checker::prepare_tls_config;
+use crate::result::ScanResult;
+
+/// Timeout for establishing the TCP connection and completing the TLS handshake.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Timeout for sending the request and receiving the response headers.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum size of the response headers that are read.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Maximum number of IPs checked at the same time.
+const MAX_CONCURRENT_CHECKS: usize = 16;
+
+/// The parts of an HTTP response used to tell a Cloudflare edge from another server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ResponseHead {
+    /// The status code of the response.
+    status: u16,
+    /// The value of the `cf-ray` header, if present.
+    ray_id: Option<String>,
+    /// The value of the `server` header, if present.
+    server: Option<String>,
+}
+
+impl ResponseHead {
+    /// Whether the response was served by Cloudflare.
+    fn is_cloudflare(&self) -> bool {
+        self.ray_id.is_some()
+            || self
+                .server
+                .as_deref()
+                .is_some_and(|server| server.eq_ignore_ascii_case("cloudflare"))
+    }
+}
+
+/// Sends an HTTP request through each IP and keeps only the IPs answered by a Cloudflare edge.
+///
+/// A TLS handshake alone does not prove that an IP belongs to Cloudflare: transparent proxies
+/// and middleboxes may complete it and relay the traffic elsewhere. The response must carry a
+/// `cf-ray` header or a `server: cloudflare` header for the IP to be kept. The ray ID is recorded
+/// in the result and logged.
+///
+/// Requesting a `path` served by the user's own origin, together with `expect_status`, validates
+/// the whole chain through Cloudflare to the origin rather than only the edge.
+///
+/// # Arguments
+///
+/// * `results` - The results to check.
+/// * `domain` - The domain name used for the TLS connection and the `Host` header.
+/// * `port` - The port number to connect to, unless a result records the port it was reached on.
+/// * `path` - The path requested, starting with `/`.
+/// * `expect_status` - If set, the status code the response must have for the IP to be kept.
+///
+/// # Returns
+///
+/// The results answered by a Cloudflare edge, in their original order.
+pub async fn check_http(
+    results: Vec<ScanResult>,
+    domain: &str,
+    port: u16,
+    path: &str,
+    expect_status: Option<u16>,
+) -> Result<Vec<ScanResult>> {
+    if !path.starts_with('/') {
+        anyhow::bail!("The check path must start with '/', got '{}'", path);
+    }
+
+    let connector = TlsConnector::from(prepare_tls_config()?);
+    let domain_name = ServerName::try_from(domain.to_string())?;
+
+    let checks = results.into_iter().enumerate().map(|(index, result)| {
+        let connector = &connector;
+        let domain_name = domain_name.clone();
+        let port = result.port.unwrap_or(port);
+        let span = debug_span!("http_check", ip = %result.ip, port);
+
+        async move {
+            match request(connector, domain_name, domain, path, result.ip, port).await {
+                Ok(head) if !head.is_cloudflare() => {
+                    info!(
+                        ip = %result.ip,
+                        status = head.status,
+                        server = head.server.as_deref().unwrap_or_default(),
+                        "Rejecting IP whose response was not served by Cloudflare"
+                    );
+                    None
+                }
+                Ok(head) if expect_status.is_some_and(|expected| expected != head.status) => {
+                    info!(
+                        ip = %result.ip,
+                        status = head.status,
+                        ray_id = head.ray_id.as_deref().unwrap_or_default(),
+                        "Rejecting IP whose response had an unexpected status"
+                    );
+                    None
+                }
+                Ok(head) => {
+                    debug!(
+                        status = head.status,
+                        ray_id = head.ray_id.as_deref().unwrap_or_default(),
+                        "Response served by Cloudflare"
+                    );
+                    Some((
+                        index,
+                        ScanResult {
+                            ray_id: head.ray_id,
+                            ..result
+                        },
+                    ))
+                }
+                Err(e) => {
+                    info!(ip = %result.ip, error = %format!("{:#}", e), "HTTP check failed");
+                    None
+                }
+            }
+        }
+        .instrument(span)
+    });
+
+    let mut verified: Vec<(usize, ScanResult)> = stream::iter(checks)
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .filter_map(|checked| async move { checked })
+        .collect()
+        .await;
+    verified.sort_by_key(|(index, _)| *index);
+
+    Ok(verified.into_iter().map(|(_, result)| result).collect())
+}
+
+/// Sends a `GET` request for `path` on `domain` through `ip` and reads the response headers.
+async fn request(
+    connector: &TlsConnector,
+    domain_name: ServerName<'static>,
+    domain: &str,
+    path: &str,
+    ip: IpAddr,
+    port: u16,
+) -> Result<ResponseHead> {
+    anyhow::ensure!(budget::try_acquire(), "The connection budget is exhausted");
+    let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect((ip, port)))
+        .await
+        .context("TCP connection timed out")??;
+    let mut stream = timeout(CONNECT_TIMEOUT, connector.connect(domain_name, stream))
+        .await
+        .context("TLS handshake timed out")??;
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: cloudsurf\r\nConnection: close\r\n\r\n",
+        path, domain
+    );
+
+    let head = timeout(RESPONSE_TIMEOUT, async {
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut head = Vec::new();
+        let mut buffer = [0; 4096];
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+            if head.len() > MAX_HEAD_SIZE {
+                anyhow::bail!("Response headers are too large");
+            }
+            let n = stream.read(&mut buffer).await?;
+            if n == 0 {
+                anyhow::bail!("Connection closed before the response headers were received");
+            }
+            head.extend_from_slice(&buffer[..n]);
+        }
+
+        Ok(head)
+    })
+    .await
+    .context("HTTP response timed out")??;
+
+    parse_response_head(&String::from_utf8_lossy(&head))
+}
+
+/// Parses the status line and the headers relevant to the check from an HTTP/1.1 response.
+fn parse_response_head(head: &str) -> Result<ResponseHead> {
+    let mut lines = head.split("\r\n");
+
+    let status_line = lines.next().unwrap_or_default();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .with_context(|| format!("Invalid status line: {}", status_line))?;
+
+    let mut response = ResponseHead {
+        status,
+        ray_id: None,
+        server: None,
+    };
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().to_string();
+        if name.trim().eq_ignore_ascii_case("cf-ray") {
+            response.ray_id = Some(value);
+        } else if name.trim().eq_ignore_ascii_case("server") {
+            response.server = Some(value);
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cloudflare_response() {
+        let head = "HTTP/1.1 301 Moved Permanently\r\nServer: cloudflare\r\nCF-RAY: 8a1b2c3d4e5f6789-FRA\r\n\r\n";
+
+        let response = parse_response_head(head).unwrap();
+
+        assert_eq!(response.status, 301);
+        assert_eq!(response.ray_id.as_deref(), Some("8a1b2c3d4e5f6789-FRA"));
+        assert!(response.is_cloudflare());
+    }
+
+    #[test]
+    fn test_parse_foreign_response() {
+        let head = "HTTP/1.1 200 OK\r\nServer: nginx\r\nContent-Length: 0\r\n\r\n";
+
+        let response = parse_response_head(head).unwrap();
+
+        assert_eq!(response.status, 200);
+        assert!(response.ray_id.is_none());
+        assert!(!response.is_cloudflare());
+    }
+
+    #[test]
+    fn test_parse_invalid_status_line() {
+        assert!(parse_response_head("garbage\r\n\r\n").is_err());
+    }
+}
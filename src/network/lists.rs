@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tracing::{debug, warn};
+
+use super::cloudflare::SkipPrefix;
+use crate::args::parse_skip_prefix;
+use crate::paths;
+use crate::report::WarningCode;
+use crate::warnings;
+
+/// How long a fetched list is used before it is fetched again.
+const MAX_AGE: Duration = Duration::from_secs(3_600);
+
+/// Fetches the skip lists at `urls` and returns their prefixes, to be skipped along with
+/// `--skip-prefixes`.
+///
+/// Each list is a text file with one prefix or CIDR block per line, in the format of
+/// `--skip-prefixes`; blank lines and `#` comments are ignored. A list that is neither reachable
+/// nor cached is left out with a warning, rather than failing the scan.
+pub async fn fetch_skip_lists(urls: &[String]) -> Vec<SkipPrefix> {
+    let mut prefixes = Vec::new();
+    for url in urls {
+        match fetch_list(url, WarningCode::SkipListUnavailable).await {
+            Ok(contents) => prefixes.extend(parse_skip_list(url, &contents)),
+            Err(e) => warnings::record(
+                WarningCode::SkipListUnavailable,
+                format!("{:#}, scanning without the skip list {}", e, url),
+            ),
+        }
+    }
+
+    prefixes
+}
+
+/// Fetches the seed lists at `urls` and returns their IP addresses, each once, in order.
+///
+/// Each list is a text file whose lines start with an IP address, such as a plain list or a
+/// file written by `--file-path`; other lines are ignored. A list that is neither reachable nor
+/// cached is left out with a warning, rather than failing the scan.
+pub async fn fetch_seed_lists(urls: &[String]) -> Vec<IpAddr> {
+    let mut ips = Vec::new();
+    let mut seen = HashSet::new();
+    for url in urls {
+        match fetch_list(url, WarningCode::SeedListUnavailable).await {
+            Ok(contents) => {
+                ips.extend(parse_seed_list(&contents).filter(|ip| seen.insert(*ip)));
+            }
+            Err(e) => warnings::record(
+                WarningCode::SeedListUnavailable,
+                format!("{:#}, scanning without the seed list {}", e, url),
+            ),
+        }
+    }
+
+    ips
+}
+
+/// Fetches the list at `url`, through its copy in the cache directory.
+async fn fetch_list(url: &str, code: WarningCode) -> Result<String> {
+    let cache = paths::list_cache_file(url).ok();
+    fetch_list_cached(url, cache.as_deref(), code).await
+}
+
+/// Returns the list at `url` from `cache` if it was fetched within `MAX_AGE`, or else fetches
+/// it, saving it to `cache` or reading it from `cache` with a warning of `code` if the request
+/// fails.
+async fn fetch_list_cached(url: &str, cache: Option<&Path>, code: WarningCode) -> Result<String> {
+    let Some(cache) = cache else {
+        return fetch_text(url).await;
+    };
+
+    let fresh = std::fs::metadata(cache)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age < MAX_AGE));
+    if fresh {
+        if let Ok(contents) = std::fs::read_to_string(cache) {
+            debug!(url, cache = %cache.display(), "Using the cached list");
+            return Ok(contents);
+        }
+    }
+
+    match fetch_text(url).await {
+        Ok(contents) => {
+            if let Err(e) = save_list(&contents, cache) {
+                debug!("Couldn't cache the list {}: {:#}", url, e);
+            }
+            Ok(contents)
+        }
+        Err(e) => match std::fs::read_to_string(cache) {
+            Ok(contents) => {
+                warnings::record(
+                    code,
+                    format!("{:#}, using the copy cached in {}", e, cache.display()),
+                );
+                Ok(contents)
+            }
+            Err(_) => Err(e),
+        },
+    }
+}
+
+async fn fetch_text(url: &str) -> Result<String> {
+    reqwest::get(url)
+        .await
+        .with_context(|| format!("Failed to fetch the list {}", url))?
+        .error_for_status()
+        .with_context(|| format!("Failed to fetch the list {}", url))?
+        .text()
+        .await
+        .with_context(|| format!("Failed to read the list {}", url))
+}
+
+fn save_list(contents: &str, cache: &Path) -> Result<()> {
+    if let Some(dir) = cache.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(cache, contents)?;
+
+    Ok(())
+}
+
+/// Parses the prefixes of a skip list, leaving out invalid entries so that a mistake in a shared
+/// list doesn't prevent scanning.
+fn parse_skip_list(url: &str, contents: &str) -> Vec<SkipPrefix> {
+    let mut prefixes = Vec::new();
+    for line in contents.lines() {
+        let entry = line.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+        match parse_skip_prefix(entry) {
+            Ok(prefix) => prefixes.push(prefix),
+            Err(e) => warn!(url, "Ignoring an entry of the skip list: {}", e),
+        }
+    }
+
+    prefixes
+}
+
+/// Parses the IP addresses starting the lines of a seed list.
+fn parse_seed_list(contents: &str) -> impl Iterator<Item = IpAddr> + '_ {
+    contents
+        .lines()
+        .filter_map(|line| line.split_whitespace().next()?.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_parse_skip_list() {
+        let contents = "# Dead on MCI\n104.16.0.0/16\n\n172.64  # since May\nnot-a-prefix\n";
+        assert_eq!(
+            parse_skip_list("https://example.com/blocked.txt", contents),
+            vec![
+                SkipPrefix::Network("104.16.0.0/16".parse().unwrap()),
+                SkipPrefix::Text("172.64".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_seed_list() {
+        let contents = "# Working on MCI\n104.16.0.1 MCI\n\n2606:4700::1\napt.ircf.space APT\n";
+        assert_eq!(
+            parse_seed_list(contents).collect::<Vec<_>>(),
+            vec![
+                "104.16.0.1".parse::<IpAddr>().unwrap(),
+                "2606:4700::1".parse().unwrap()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cached_list() -> Result<()> {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/blocked.txt"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("104.16.0.0/16\n"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let url = format!("{}/blocked.txt", mock_server.uri());
+        let cache = std::env::temp_dir().join(format!("cloudsurf-list-{}.txt", std::process::id()));
+
+        // The second call is answered from the cache, within `MAX_AGE` of the first.
+        for _ in 0..2 {
+            let contents =
+                fetch_list_cached(&url, Some(&cache), WarningCode::SkipListUnavailable).await?;
+            assert_eq!(contents, "104.16.0.0/16\n");
+        }
+
+        std::fs::remove_file(cache)?;
+        Ok(())
+    }
+}
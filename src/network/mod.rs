@@ -1,5 +1,47 @@
 mod cloudflare;
-pub use cloudflare::fetch_and_filter_ipv4_list;
+pub use cloudflare::{
+    fetch_and_filter_ipv4_ranges, fetch_cloudflare_ipv4_cidrs, fetch_ipv6_prefixes,
+    filter_ipv6_hosts, is_skipped, unproxied_addresses, SkipPrefix,
+};
+mod fastly;
+mod provider;
+pub use provider::{Provider, ProviderRanges};
+
+mod lists;
+pub use lists::{fetch_seed_lists, fetch_skip_lists};
+
+mod adaptive_concurrency;
+mod adaptive_timeout;
+mod budget;
+pub use budget::set_max_probes;
+mod roots;
+pub use roots::use_native_certs;
+mod doh_probe;
+mod fragment;
+pub use fragment::{compare_unfragmented, FragmentHello};
+mod socket;
+pub use socket::SocketOptions;
+mod probe;
+pub use probe::ProbeKind;
+mod top_results;
+pub use top_results::read_spill;
 
 mod tls_checker;
-pub use tls_checker::check_tls_availability;
+pub use tls_checker::{
+    check_tls_availability, measure_baseline, probe_tls, select_results, CheckOptions,
+};
+
+mod http_check;
+pub use http_check::check_http;
+
+mod speedtest;
+pub use speedtest::measure_download_speed;
+
+mod h2_check;
+pub use h2_check::check_h2_keepalive;
+
+mod grpc_check;
+pub use grpc_check::check_grpc;
+
+mod request_check;
+pub use request_check::measure_request_latency;
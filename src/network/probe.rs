@@ -0,0 +1,202 @@
+use std::{future::Future, net::IpAddr, sync::Arc};
+
+use clap::ValueEnum;
+use futures::{stream, Stream, StreamExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug_span, warn, Instrument};
+
+use super::adaptive_concurrency::AdaptiveConcurrency;
+use crate::result::ScanResult;
+use crate::statsd;
+
+/// The outcome of probing a single IP address.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProbeOutcome {
+    /// The IP address passed the check, with the measurements that were taken.
+    Reachable(Box<ScanResult>),
+    /// The IP address failed the check.
+    Unreachable,
+    /// The IP address failed the check because it timed out or the connection was reset.
+    ///
+    /// A spike of these points at the local network rather than at the IP addresses, see
+    /// `--adaptive-concurrency`.
+    Interrupted,
+}
+
+/// The check an IP address must pass to be reachable.
+#[derive(
+    ValueEnum, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeKind {
+    /// A TLS handshake for the domain, see `TlsProbe`.
+    #[default]
+    Tls,
+    /// A DNS-over-HTTPS query for the domain to `cloudflare-dns.com`, see `DohProbe`.
+    Doh,
+}
+
+/// A check run against each selected IP address.
+///
+/// Implementations plug into the same pipeline as the built-in TLS check: IP addresses are
+/// selected by `sampling`, probed concurrently by `run_probes`, and the reachable ones are
+/// ranked and reported like any other result.
+pub trait Probe: Send + Sync + 'static {
+    /// Checks `ip` and returns the outcome.
+    fn check(&self, ip: IpAddr) -> impl Future<Output = ProbeOutcome> + Send;
+}
+
+/// Runs `probe` against every IP address, with at most `concurrency` checks at a time.
+///
+/// Every check runs in its own task and span, so that the events of thousands of concurrent
+/// connections can be told apart. Tasks are only spawned once a slot is free. With `adaptive`,
+/// fewer checks run at a time while many are interrupted, see `AdaptiveConcurrency`.
+///
+/// # Returns
+/// The results of the reachable IP addresses, in no particular order.
+pub async fn run_probes<P: Probe>(
+    probe: Arc<P>,
+    ips: &[IpAddr],
+    concurrency: usize,
+    adaptive: bool,
+) -> Vec<ScanResult> {
+    probe_outcomes(probe, ips, concurrency, adaptive)
+        .filter_map(|(_, outcome)| async move {
+            match outcome {
+                ProbeOutcome::Reachable(result) => Some(*result),
+                ProbeOutcome::Unreachable | ProbeOutcome::Interrupted => None,
+            }
+        })
+        .collect()
+        .await
+}
+
+/// Like `run_probes`, but yields the outcome of every IP address as it arrives.
+///
+/// This lets exhaustive scans process results without holding all of them in memory, and callers
+/// tell unreachable IP addresses apart from interrupted ones.
+pub fn probe_outcomes<'a, P: Probe>(
+    probe: Arc<P>,
+    ips: &'a [IpAddr],
+    concurrency: usize,
+    adaptive: bool,
+) -> impl Stream<Item = (IpAddr, ProbeOutcome)> + 'a {
+    let adaptive = adaptive.then(|| Arc::new(AdaptiveConcurrency::new(concurrency)));
+
+    stream::iter(ips.iter().enumerate())
+        .map(move |(attempt, &ip)| {
+            let probe = probe.clone();
+            let adaptive = adaptive.clone();
+            let span = debug_span!("probe", %ip, attempt);
+
+            tokio::spawn(
+                async move {
+                    let Some(adaptive) = adaptive else {
+                        return (ip, probe.check(ip).await);
+                    };
+                    let _slot = adaptive.acquire().await;
+                    let outcome = probe.check(ip).await;
+                    adaptive.record(matches!(outcome, ProbeOutcome::Interrupted));
+                    (ip, outcome)
+                }
+                .instrument(span),
+            )
+        })
+        .buffer_unordered(concurrency.max(1))
+        .filter_map(|outcome| async move {
+            match outcome {
+                Ok((ip, outcome)) => {
+                    match &outcome {
+                        ProbeOutcome::Reachable(result) => statsd::probe(Some(result.latency_ms)),
+                        ProbeOutcome::Unreachable | ProbeOutcome::Interrupted => {
+                            statsd::probe(None)
+                        }
+                    }
+                    Some((ip, outcome))
+                }
+                Err(e) => {
+                    warn!(error = %e, "Probe task failed");
+                    None
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    /// A probe reporting the IP addresses of one /8 as reachable, with a latency of their last
+    /// octet.
+    ///
+    /// IP addresses of 192.0.2.0/24 time out.
+    struct FakeProbe;
+
+    impl Probe for FakeProbe {
+        async fn check(&self, ip: IpAddr) -> ProbeOutcome {
+            match ip {
+                IpAddr::V4(v4) if v4.octets()[0] == 104 => ProbeOutcome::Reachable(Box::new(
+                    ScanResult::new(ip, u128::from(v4.octets()[3])),
+                )),
+                IpAddr::V4(v4) if v4.octets()[..3] == [192, 0, 2] => ProbeOutcome::Interrupted,
+                _ => ProbeOutcome::Unreachable,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_probes() {
+        let ips = [
+            Ipv4Addr::new(104, 16, 0, 1),
+            Ipv4Addr::new(198, 18, 0, 1),
+            Ipv4Addr::new(104, 16, 0, 2),
+        ]
+        .map(IpAddr::V4);
+
+        let mut results = run_probes(Arc::new(FakeProbe), &ips, 2, false).await;
+        results.sort_by_key(|result| result.latency_ms);
+
+        assert_eq!(
+            results,
+            vec![
+                ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 1),
+                ScanResult::new(Ipv4Addr::new(104, 16, 0, 2), 2),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_probes_adaptive() {
+        let mut ips: Vec<IpAddr> = (0..=255)
+            .map(|host| IpAddr::V4(Ipv4Addr::new(192, 0, 2, host)))
+            .collect();
+        ips.push(IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1)));
+
+        let results = run_probes(Arc::new(FakeProbe), &ips, 64, true).await;
+
+        assert_eq!(
+            results,
+            vec![ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe_outcomes() {
+        let ips = [Ipv4Addr::new(198, 18, 0, 1), Ipv4Addr::new(192, 0, 2, 1)].map(IpAddr::V4);
+
+        let mut outcomes: Vec<_> = probe_outcomes(Arc::new(FakeProbe), &ips, 2, false)
+            .collect()
+            .await;
+        outcomes.sort_by_key(|(ip, _)| *ip);
+
+        assert_eq!(
+            outcomes,
+            vec![
+                (ips[1], ProbeOutcome::Interrupted),
+                (ips[0], ProbeOutcome::Unreachable),
+            ]
+        );
+    }
+}
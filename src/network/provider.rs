@@ -0,0 +1,106 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use clap::ValueEnum;
+use ipnetwork::Ipv6Network;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A CDN whose published IP ranges are scanned.
+#[derive(
+    ValueEnum, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// Cloudflare, whose ranges are listed by `api.cloudflare.com/client/v4/ips`.
+    #[default]
+    Cloudflare,
+    /// Fastly, whose ranges are listed by `api.fastly.com/public-ip-list`.
+    Fastly,
+}
+
+impl Provider {
+    /// The name of the provider as given to `--provider`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::Cloudflare => "cloudflare",
+            Provider::Fastly => "fastly",
+        }
+    }
+}
+
+/// The provider each scanned range was published by, to tag the IPs sampled from them.
+#[derive(Debug, Default)]
+pub struct ProviderRanges {
+    /// The first and last address of each IPv4 block, with its provider.
+    v4: Vec<(Ipv4Addr, Ipv4Addr, Provider)>,
+    /// The IPv6 prefixes, with their provider.
+    v6: Vec<(Ipv6Network, Provider)>,
+}
+
+impl ProviderRanges {
+    /// Records the IPv4 blocks of `provider`, as returned by `fetch_and_filter_ipv4_ranges`.
+    ///
+    /// Each block is one published CIDR, so the addresses between its first and last one belong
+    /// to the same provider even when some of them were skipped.
+    pub fn add_ipv4(&mut self, provider: Provider, ranges: &[Vec<Ipv4Addr>]) {
+        for range in ranges {
+            let (Some(first), Some(last)) = (range.iter().min(), range.iter().max()) else {
+                continue;
+            };
+            self.v4.push((*first, *last, provider));
+        }
+    }
+
+    /// Records the IPv6 prefixes of `provider`.
+    pub fn add_ipv6(&mut self, provider: Provider, prefixes: &[Ipv6Network]) {
+        self.v6
+            .extend(prefixes.iter().map(|prefix| (*prefix, provider)));
+    }
+
+    /// The provider whose ranges contain `ip`, if any.
+    pub fn provider_of(&self, ip: IpAddr) -> Option<Provider> {
+        match ip {
+            IpAddr::V4(ip) => self
+                .v4
+                .iter()
+                .find(|(first, last, _)| (*first..=*last).contains(&ip))
+                .map(|(_, _, provider)| *provider),
+            IpAddr::V6(ip) => self
+                .v6
+                .iter()
+                .find(|(prefix, _)| prefix.contains(ip))
+                .map(|(_, provider)| *provider),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_of() {
+        let mut ranges = ProviderRanges::default();
+        ranges.add_ipv4(
+            Provider::Cloudflare,
+            &[vec![
+                Ipv4Addr::new(104, 16, 0, 0),
+                Ipv4Addr::new(104, 16, 0, 255),
+            ]],
+        );
+        ranges.add_ipv4(
+            Provider::Fastly,
+            &[vec![
+                Ipv4Addr::new(151, 101, 0, 0),
+                Ipv4Addr::new(151, 101, 0, 255),
+            ]],
+        );
+        ranges.add_ipv6(Provider::Fastly, &["2a04:4e40::/32".parse().unwrap()]);
+
+        let provider_of = |ip: &str| ranges.provider_of(ip.parse().unwrap());
+        assert_eq!(provider_of("104.16.0.7"), Some(Provider::Cloudflare));
+        assert_eq!(provider_of("151.101.0.12"), Some(Provider::Fastly));
+        assert_eq!(provider_of("2a04:4e40::1"), Some(Provider::Fastly));
+        assert_eq!(provider_of("8.8.8.8"), None);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{Context, Result};
+use futures::{stream, StreamExt};
+use rustls::pki_types::ServerName;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{timeout, Instant},
+};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, debug_span, info, Instrument};
+
+use super::budget;
+use super::tls_checker::prepare_tls_config;
+use crate::result::ScanResult;
+
+/// Host answering the request, served by every Cloudflare edge like the speed test download.
+const REQUEST_HOST: &str = "speed.cloudflare.com";
+
+/// Timeout for establishing the TCP connection and completing the TLS handshake.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Timeout for sending the request and receiving the response headers.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum size of the response headers that are read.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Maximum number of IPs measured at the same time.
+const MAX_CONCURRENT_CHECKS: usize = 16;
+
+/// Measures the round trip of a small `POST` request through each IP and records it in the
+/// results.
+///
+/// Unlike the handshake latency, the round trip includes the edge processing the request and
+/// sending a response, as interactive applications such as API calls experience it. The request
+/// uploads `bytes` bytes to `speed.cloudflare.com`, over a connection established beforehand, and
+/// the time until the response headers arrive is recorded. IPs whose measurement fails are kept,
+/// without a round trip.
+///
+/// # Arguments
+///
+/// * `results` - The results to measure. Their `request_ms` field is updated in place.
+/// * `port` - The port number to connect to, unless a result records the port it was reached on.
+/// * `bytes` - The size of the request body.
+pub async fn measure_request_latency(
+    results: &mut [ScanResult],
+    port: u16,
+    bytes: u64,
+) -> Result<()> {
+    let connector = TlsConnector::from(prepare_tls_config()?);
+
+    let measurements = results.iter_mut().map(|result| {
+        let connector = &connector;
+        let port = result.port.unwrap_or(port);
+        let span = debug_span!("request_check", ip = %result.ip, port);
+
+        async move {
+            match round_trip(connector, result.ip, port, bytes).await {
+                Ok(request_ms) => {
+                    debug!(request_ms, "Request answered");
+                    result.request_ms = Some(request_ms);
+                }
+                Err(e) => {
+                    info!(ip = %result.ip, error = %format!("{:#}", e), "Request check failed")
+                }
+            }
+        }
+        .instrument(span)
+    });
+
+    stream::iter(measurements)
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .for_each(|_| async {})
+        .await;
+
+    Ok(())
+}
+
+/// Posts `bytes` bytes through `ip` and returns the time until the response headers arrived, in
+/// milliseconds.
+async fn round_trip(connector: &TlsConnector, ip: IpAddr, port: u16, bytes: u64) -> Result<u128> {
+    let server_name = ServerName::try_from(REQUEST_HOST)?;
+
+    anyhow::ensure!(budget::try_acquire(), "The connection budget is exhausted");
+    let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect((ip, port)))
+        .await
+        .context("TCP connection timed out")??;
+    let mut stream = timeout(CONNECT_TIMEOUT, connector.connect(server_name, stream))
+        .await
+        .context("TLS handshake timed out")??;
+
+    let mut request = format!(
+        "POST /__up HTTP/1.1\r\nHost: {}\r\nUser-Agent: cloudsurf\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        REQUEST_HOST, bytes
+    )
+    .into_bytes();
+    request.resize(request.len() + bytes as usize, b'0');
+
+    let start = Instant::now();
+    let head = timeout(RESPONSE_TIMEOUT, async {
+        stream.write_all(&request).await?;
+
+        let mut head = Vec::new();
+        let mut buffer = [0; 4096];
+        while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+            if head.len() > MAX_HEAD_SIZE {
+                anyhow::bail!("Response headers are too large");
+            }
+            let n = stream.read(&mut buffer).await?;
+            if n == 0 {
+                anyhow::bail!("Connection closed before the response headers were received");
+            }
+            head.extend_from_slice(&buffer[..n]);
+        }
+
+        Ok(head)
+    })
+    .await
+    .context("HTTP response timed out")??;
+    let elapsed = start.elapsed().as_millis();
+
+    if !head.starts_with(b"HTTP/1.1 200") {
+        let head = String::from_utf8_lossy(&head);
+        anyhow::bail!(
+            "Unexpected response: {}",
+            head.lines().next().unwrap_or_default()
+        );
+    }
+
+    Ok(elapsed)
+}
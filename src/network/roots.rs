@@ -0,0 +1,54 @@
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use rustls::RootCertStore;
+use webpki_roots::TLS_SERVER_ROOTS;
+
+/// The root certificates handshakes are verified against, the bundled Mozilla roots unless
+/// `use_native_certs` was called first.
+static ROOTS: OnceLock<RootCertStore> = OnceLock::new();
+
+/// Verifies handshakes against the trust store of the operating system instead of the bundled
+/// Mozilla roots.
+///
+/// Networks that inspect TLS re-sign certificates with an enterprise CA which is only installed in
+/// the system store, so that every handshake fails verification otherwise. This must be called
+/// before the first handshake.
+///
+/// # Errors
+///
+/// Returns an error if the trust store can't be read or holds no usable certificate, or if this
+/// build doesn't support it.
+#[cfg(feature = "native-certs")]
+pub fn use_native_certs() -> Result<()> {
+    use anyhow::Context;
+    use tracing::debug;
+
+    let certs = rustls_native_certs::load_native_certs()
+        .context("Couldn't load the system's root certificates")?;
+    let mut roots = RootCertStore::empty();
+    let (added, ignored) = roots.add_parsable_certificates(certs);
+    debug!(added, ignored, "Loaded the system's root certificates");
+    anyhow::ensure!(
+        added > 0,
+        "The system's trust store holds no usable root certificate"
+    );
+
+    ROOTS
+        .set(roots)
+        .map_err(|_| anyhow::anyhow!("The root certificates are already in use"))
+}
+
+#[cfg(not(feature = "native-certs"))]
+pub fn use_native_certs() -> Result<()> {
+    anyhow::bail!("--native-certs requires a build with the 'native-certs' feature enabled")
+}
+
+/// Returns the root certificates to verify handshakes against.
+pub(super) fn root_store() -> RootCertStore {
+    ROOTS
+        .get_or_init(|| RootCertStore {
+            roots: TLS_SERVER_ROOTS.to_vec(),
+        })
+        .clone()
+}
@@ -0,0 +1,65 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpSocket, TcpStream};
+
+use super::fragment::{FragmentHello, FragmentedStream};
+
+/// Settings of the sockets probes connect with.
+///
+/// These influence the measured latency, so they can be set to match the proxy client the
+/// results are meant for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Whether to disable Nagle's algorithm.
+    pub nodelay: bool,
+    /// If set, the idle time before TCP keep-alive probes are sent.
+    pub keepalive: Option<Duration>,
+    /// If set, the size of the send buffer in bytes.
+    pub send_buffer_size: Option<u32>,
+    /// If set, the size of the receive buffer in bytes.
+    pub recv_buffer_size: Option<u32>,
+    /// If set, the IP time-to-live of outgoing packets, or the hop limit for IPv6.
+    pub ttl: Option<u32>,
+    /// If set, how the ClientHello is split up.
+    pub fragment: Option<FragmentHello>,
+}
+
+impl SocketOptions {
+    /// Opens a TCP connection to `addr` with these settings.
+    ///
+    /// The returned stream splits up the ClientHello written to it when `fragment` is set, in
+    /// which case Nagle's algorithm is always disabled, so that each fragment leaves in its own
+    /// segment.
+    pub(super) async fn connect(
+        &self,
+        addr: SocketAddr,
+    ) -> io::Result<FragmentedStream<TcpStream>> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(ttl) = self.ttl {
+            match addr {
+                SocketAddr::V4(_) => SockRef::from(&socket).set_ttl(ttl)?,
+                SocketAddr::V6(_) => SockRef::from(&socket).set_unicast_hops_v6(ttl)?,
+            }
+        }
+        if let Some(time) = self.keepalive {
+            SockRef::from(&socket).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+
+        let stream = socket.connect(addr).await?;
+        if self.nodelay || self.fragment.is_some() {
+            stream.set_nodelay(true)?;
+        }
+
+        Ok(FragmentedStream::new(stream, self.fragment))
+    }
+}
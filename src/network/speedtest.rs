@@ -0,0 +1,138 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::{Context, Result};
+use futures::{stream, StreamExt};
+use rustls::pki_types::ServerName;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{timeout, timeout_at, Instant},
+};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, debug_span, info, Instrument};
+
+use super::budget;
+use super::tls_checker::prepare_tls_config;
+use crate::result::ScanResult;
+
+/// Host serving the download used for throughput measurements. It is served by every
+/// Cloudflare edge, so it can be reached through any of the scanned IPs.
+const SPEEDTEST_HOST: &str = "speed.cloudflare.com";
+
+/// Timeout for establishing the TCP connection and completing the TLS handshake.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Size of the buffer used when reading the download.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Measures the download throughput through each IP address and records it in the results.
+///
+/// For each result, a TLS connection to `speed.cloudflare.com` is made through the IP, and a
+/// download of `bytes` bytes is requested. The download stops when it completes or after
+/// `max_time`, whichever happens first, and the throughput is computed from the data received
+/// so far. IPs whose measurement fails are kept, without a speed.
+///
+/// At most `concurrency` downloads run at the same time. Since parallel downloads compete for
+/// the same link, a concurrency of 1 gives the most accurate measurements.
+///
+/// # Arguments
+///
+/// * `results` - The results to measure. Their `speed_mbps` field is updated in place.
+/// * `port` - The port number to connect to, unless a result records the port it was reached on.
+/// * `bytes` - The size of the download to request.
+/// * `max_time` - The maximum duration of a single download.
+/// * `concurrency` - The maximum number of simultaneous downloads.
+pub async fn measure_download_speed(
+    results: &mut [ScanResult],
+    port: u16,
+    bytes: u64,
+    max_time: Duration,
+    concurrency: usize,
+) -> Result<()> {
+    let connector = TlsConnector::from(prepare_tls_config()?);
+
+    let measurements = results.iter_mut().map(|result| {
+        let connector = &connector;
+        let port = result.port.unwrap_or(port);
+        let span = debug_span!("speedtest", ip = %result.ip, port);
+
+        async move {
+            match download(connector, result.ip, port, bytes, max_time).await {
+                Ok(speed_mbps) => {
+                    info!(ip = %result.ip, "Measured {:.2} Mbps", speed_mbps);
+                    result.speed_mbps = Some(speed_mbps);
+                }
+                Err(e) => debug!(error = %format!("{:#}", e), "Speed test failed"),
+            }
+        }
+        .instrument(span)
+    });
+
+    stream::iter(measurements)
+        .buffer_unordered(concurrency.max(1))
+        .for_each(|_| async {})
+        .await;
+
+    Ok(())
+}
+
+/// Downloads `bytes` bytes through `ip` and returns the throughput in megabits per second.
+async fn download(
+    connector: &TlsConnector,
+    ip: IpAddr,
+    port: u16,
+    bytes: u64,
+    max_time: Duration,
+) -> Result<f64> {
+    let server_name = ServerName::try_from(SPEEDTEST_HOST)?;
+
+    anyhow::ensure!(budget::try_acquire(), "The connection budget is exhausted");
+    let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect((ip, port)))
+        .await
+        .context("TCP connection timed out")??;
+    let mut stream = timeout(CONNECT_TIMEOUT, connector.connect(server_name, stream))
+        .await
+        .context("TLS handshake timed out")??;
+
+    let request = format!(
+        "GET /__down?bytes={} HTTP/1.1\r\nHost: {}\r\nUser-Agent: cloudsurf\r\nConnection: close\r\n\r\n",
+        bytes, SPEEDTEST_HOST
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let start = Instant::now();
+    let deadline = start + max_time;
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut received: u64 = 0;
+
+    loop {
+        match timeout_at(deadline, stream.read(&mut buffer)).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(n)) => {
+                if received == 0 && !buffer[..n].starts_with(b"HTTP/1.1 200") {
+                    let status_line = String::from_utf8_lossy(&buffer[..n]);
+                    let status_line = status_line.lines().next().unwrap_or_default();
+                    anyhow::bail!("Unexpected response: {}", status_line);
+                }
+                received += n as u64;
+            }
+            // Some servers close the connection without a TLS close_notify once the body is sent.
+            Ok(Err(e)) if received > 0 => {
+                debug!(error = %e, "Connection closed during download");
+                break;
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => {
+                debug!(received, "Speed test reached its maximum duration");
+                break;
+            }
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    if received == 0 || elapsed <= 0.0 {
+        anyhow::bail!("No data received");
+    }
+
+    Ok(received as f64 * 8.0 / elapsed / 1_000_000.0)
+}
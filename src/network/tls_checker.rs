@@ -1,141 +1,638 @@
 use std::{
-    net::Ipv4Addr,
-    sync::Arc,
-    time::{Duration, Instant},
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use futures::{stream::FuturesUnordered, StreamExt};
-use rand::seq::SliceRandom;
-use rustls::{pki_types::ServerName, version, ClientConfig, RootCertStore};
-use tokio::{net::TcpStream, time::timeout};
+use rustls::{pki_types::ServerName, version, ClientConfig};
+use tokio::time::Instant;
 use tokio_rustls::TlsConnector;
-use webpki_roots::TLS_SERVER_ROOTS;
+use tracing::{debug, debug_span, info, trace, Instrument};
 
-/// Default timeout for TCP and TLS connections.
-const TIMEOUT: Duration = Duration::from_secs(1);
+use super::adaptive_timeout::AdaptiveTimeout;
+use super::budget;
+use super::doh_probe::DohProbe;
+use super::probe::{probe_outcomes, run_probes, Probe, ProbeKind, ProbeOutcome};
+use super::roots;
+use super::socket::SocketOptions;
+use super::top_results::TopResults;
+use crate::history::{subnet_of, History};
+use crate::result::{ScanResult, SortKey};
+
+/// Options controlling how IP addresses are probed and which results are returned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckOptions {
+    /// The number of successful connections to return, sorted by connection time.
+    pub n: usize,
+    /// If set, the maximum number of returned IPs that may share the same subnet, see `subnet_of`.
+    pub per_subnet: Option<usize>,
+    /// Whether to perform and discard one handshake per IP before the measured one.
+    pub warmup: bool,
+    /// If set, a fixed timeout for each probe. Otherwise the timeout adapts to the observed
+    /// latencies.
+    pub timeout: Option<Duration>,
+    /// Whether to connect to all ports of an IP at once instead of one after the other.
+    pub race_ports: bool,
+    /// The factor IPv6 latencies are multiplied by when ranking, see `ScanResult::ranking_latency`.
+    pub ipv6_bias: f64,
+    /// If set, the number of the `n` returned IPs reserved for each address family.
+    pub family_quota: Option<usize>,
+    /// If set, the maximum number of IPs probed at the same time. Otherwise all are probed at once.
+    pub concurrency: Option<usize>,
+    /// Whether to lower the concurrency while many probes time out or are reset.
+    pub adaptive_concurrency: bool,
+    /// Whether to probe the IPs that timed out or were reset once more after all others.
+    pub retry_timeouts: bool,
+    /// If set, the file every result is written to, so that only the best `n` are kept in memory.
+    pub spill_path: Option<String>,
+    /// The key the results kept with `spill_path` are ranked by.
+    pub sort_by: SortKey,
+    /// If set, the history the results kept with `spill_path` are ranked with.
+    pub history: Option<Arc<History>>,
+    /// If not empty, the server names presented in turn by successive probes instead of the
+    /// domain, each result recording the one it was reached with.
+    pub server_names: Vec<String>,
+    /// If not empty, the server names an IP is retried with, in order, when its handshake is cut
+    /// after the TCP connection was established, as SNI filtering does.
+    pub sni_fallback: Vec<String>,
+    /// The check each IP must pass.
+    pub probe: ProbeKind,
+    /// The settings of the sockets probes connect with, including how the ClientHello is split.
+    pub socket: SocketOptions,
+}
+
+impl CheckOptions {
+    /// Creates options returning the `n` fastest IPs, with no other limit.
+    pub fn new(n: usize) -> Self {
+        CheckOptions {
+            n,
+            per_subnet: None,
+            warmup: false,
+            timeout: None,
+            race_ports: false,
+            ipv6_bias: 1.0,
+            family_quota: None,
+            concurrency: None,
+            adaptive_concurrency: false,
+            retry_timeouts: false,
+            spill_path: None,
+            sort_by: SortKey::Latency,
+            history: None,
+            server_names: Vec::new(),
+            sni_fallback: Vec::new(),
+            probe: ProbeKind::Tls,
+            socket: SocketOptions::default(),
+        }
+    }
+}
 
 /// Checks the availability of TLS over a list of IP addresses for a specific domain and port.
 ///
-/// This function takes a list of IPv4 addresses, a domain, a port, and a number `n`. It then attempts
-/// to establish a TLS connection to each of the IP addresses on the specified port and domain.
-/// It measures the time taken to establish each successful connection and returns a list of the
-/// fastest `n` connections.
+/// This function takes a list of IP addresses, a domain, a list of ports, and the probe options.
+/// It then attempts to establish a TLS connection to each of the IP addresses on the specified
+/// ports and domain. Each IP is kept once, with the first port that accepted the
+/// connection. It measures the time taken to establish each successful connection and returns a
+/// list of the fastest `n` connections.
 ///
 /// # Arguments
 ///
-/// * `ips` - A list of IPv4 addresses to check for TLS availability.
+/// * `ips` - A list of IP addresses to check for TLS availability, see `sampling::select`.
 /// * `domain` - The domain name to use for the TLS connection.
-/// * `port` - The port number to use for the connection.
-/// * `n` - The number of successful connections to return, sorted by connection time.
+/// * `ports` - The port numbers to try for each IP, in order of preference.
+/// * `options` - How to probe the IPs and how many to return.
 ///
 /// # Returns
 ///
-/// A Result containing a vector of tuples, each consisting of an IPv4 address and its connection time in milliseconds,
-/// sorted by the fastest connection time. The vector is limited to the `n` fastest connections.
+/// A Result containing a vector of `ScanResult`, each consisting of an IP address and its connection time in
+/// milliseconds, sorted by the fastest connection time. The vector is limited to the `n` fastest connections.
 pub async fn check_tls_availability(
-    ips: &Vec<Ipv4Addr>,
+    ips: &[IpAddr],
+    domain: &str,
+    ports: &[u16],
+    options: &CheckOptions,
+) -> Result<Vec<ScanResult>> {
+    let reachable = probe_tls(ips, domain, ports, options).await?;
+
+    Ok(select_results(reachable, options))
+}
+
+/// Probes `ips` like `check_tls_availability`, but returns every reachable IP, sorted by ranking
+/// latency, without applying the limits of `options`.
+///
+/// With `options.probe` set to `ProbeKind::Doh`, IPs must answer a DNS-over-HTTPS query for
+/// `domain` instead of completing a handshake for it. With `options.spill_path`, only the best
+/// `n` results are returned.
+pub async fn probe_tls(
+    ips: &[IpAddr],
     domain: &str,
-    port: u16,
-    attempts: usize,
-    n: usize,
-) -> Result<Vec<(Ipv4Addr, u128)>> {
-    if ips.is_empty() {
+    ports: &[u16],
+    options: &CheckOptions,
+) -> Result<Vec<ScanResult>> {
+    if ips.is_empty() || ports.is_empty() {
         return Ok(Vec::new());
     }
 
-    // Randomly select a subset of IP addresses to test.
-    let target: Vec<_> = ips
-        .choose_multiple(&mut rand::thread_rng(), attempts)
-        .cloned()
-        .collect();
+    match options.probe {
+        ProbeKind::Tls => {
+            run(
+                Arc::new(TlsProbe::new(domain, ports, options)?),
+                ips,
+                options,
+            )
+            .await
+        }
+        ProbeKind::Doh => {
+            run(
+                Arc::new(DohProbe::new(domain, ports, options)?),
+                ips,
+                options,
+            )
+            .await
+        }
+    }
+}
 
-    // Prepare the TLS client configuration.
-    let config = prepare_tls_config()?;
-    let connector = TlsConnector::from(config);
+/// Runs `probe` against the selected IPs, then once more against those that were interrupted if
+/// `options.retry_timeouts` is set.
+async fn run<P: Probe>(
+    probe: Arc<P>,
+    ips: &[IpAddr],
+    options: &CheckOptions,
+) -> Result<Vec<ScanResult>> {
+    let concurrency = options.concurrency.unwrap_or(ips.len());
+    let mut reachable = match &options.spill_path {
+        Some(path) => Reachable::Spilled(TopResults::new(
+            path,
+            options.n,
+            options.ipv6_bias,
+            options.sort_by,
+            options.history.clone(),
+        )?),
+        None => Reachable::Collected(Vec::new()),
+    };
+    let mut interrupted = Vec::new();
+    let mut outcomes = std::pin::pin!(probe_outcomes(
+        probe.clone(),
+        ips,
+        concurrency,
+        options.adaptive_concurrency,
+    ));
+    while let Some((ip, outcome)) = outcomes.next().await {
+        match outcome {
+            ProbeOutcome::Reachable(result) => reachable.push(*result)?,
+            ProbeOutcome::Interrupted if options.retry_timeouts => interrupted.push(ip),
+            ProbeOutcome::Unreachable | ProbeOutcome::Interrupted => {}
+        }
+    }
 
-    // Attempt TLS connections to the selected IPs.
-    let mut valid_ips = create_connection_tasks(target, domain, port, connector).await?;
+    // The link is less saturated once the main wave is over, so IPs that only timed out or were
+    // reset get a second chance.
+    if !interrupted.is_empty() {
+        info!(count = interrupted.len(), "Retrying the IPs that timed out");
+        let late = run_probes(
+            probe,
+            &interrupted,
+            concurrency.min(interrupted.len()),
+            options.adaptive_concurrency,
+        )
+        .await;
+        info!(count = late.len(), "IPs were reachable on retry");
+        for result in late {
+            reachable.push(result)?;
+        }
+    }
 
-    // Sort the valid IP addresses by their connection times.
-    valid_ips.sort_by_key(|&(_, elapsed)| elapsed);
-    let end = valid_ips.len().min(n); // Limit the results to `n` entries.
+    reachable.finish(options.ipv6_bias)
+}
 
-    Ok(valid_ips[..end].to_vec())
+/// The reachable IPs found while probing.
+enum Reachable {
+    /// Every result, in no particular order.
+    Collected(Vec<ScanResult>),
+    /// The best results, with every result written to the spill file.
+    Spilled(TopResults),
 }
 
-/// Creates and executes asynchronous tasks to attempt TLS connections to a list of IP addresses.
+impl Reachable {
+    fn push(&mut self, result: ScanResult) -> Result<()> {
+        match self {
+            Reachable::Collected(results) => {
+                results.push(result);
+                Ok(())
+            }
+            Reachable::Spilled(top) => top.push(result),
+        }
+    }
+
+    /// Returns the results sorted by ranking latency.
+    fn finish(self, ipv6_bias: f64) -> Result<Vec<ScanResult>> {
+        match self {
+            Reachable::Collected(mut results) => {
+                results.sort_by(|a, b| {
+                    a.ranking_latency(ipv6_bias)
+                        .total_cmp(&b.ranking_latency(ipv6_bias))
+                });
+                Ok(results)
+            }
+            Reachable::Spilled(top) => top.finish(),
+        }
+    }
+}
+
+/// Measures the handshake latency to `domain` through the addresses its DNS records resolve to,
+/// the route taken without a scanned IP.
 ///
-/// # Arguments
-/// * `target` - A list of IP addresses to attempt connection to.
-/// * `domain` - The domain name to use for TLS connections.
-/// * `port` - The port number to connect to.
-/// * `connector` - A `TlsConnector` instance for making TLS connections.
+/// The probe settings of `options` are used, so that the baseline compares with scanned IPs.
+/// Only the limits on the returned results are left out: the fastest address is returned, ranked
+/// by latency alone and without being written to `options.spill_path`.
 ///
 /// # Returns
-/// A Result containing a vector of tuples, each with an IP address and its connection time in milliseconds.
-async fn create_connection_tasks(
-    target: Vec<Ipv4Addr>,
+/// The fastest resolved address, or `None` if none of them completed a handshake.
+pub async fn measure_baseline(
     domain: &str,
-    port: u16,
+    ports: &[u16],
+    options: &CheckOptions,
+) -> Result<Option<ScanResult>> {
+    let mut ips: Vec<IpAddr> = tokio::net::lookup_host((domain, 0))
+        .await
+        .with_context(|| format!("Couldn't resolve {}", domain))?
+        .map(|addr| addr.ip())
+        .collect();
+    ips.sort_unstable();
+    ips.dedup();
+
+    let options = CheckOptions {
+        n: 1,
+        per_subnet: None,
+        ipv6_bias: 1.0,
+        family_quota: None,
+        spill_path: None,
+        sort_by: SortKey::Latency,
+        history: None,
+        ..options.clone()
+    };
+
+    Ok(check_tls_availability(&ips, domain, ports, &options)
+        .await?
+        .into_iter()
+        .next())
+}
+
+/// Keeps the results returned according to `options`: at most `per_subnet` per subnet, the
+/// family quota, and `n` in total.
+///
+/// `results` are expected to be sorted best first, as returned by `probe_tls` or ranked with the
+/// history.
+pub fn select_results(mut valid_ips: Vec<ScanResult>, options: &CheckOptions) -> Vec<ScanResult> {
+    if let Some(per_subnet) = options.per_subnet {
+        valid_ips = limit_per_subnet(valid_ips, per_subnet);
+    }
+    match options.family_quota {
+        Some(quota) => valid_ips = limit_with_family_quota(valid_ips, options.n, quota),
+        None => valid_ips.truncate(options.n), // Limit the results to `n` entries.
+    }
+
+    valid_ips
+}
+
+/// Probes IP addresses by connecting to them and completing a TLS handshake.
+///
+/// This is the default `Probe`: an IP is reachable when a handshake for the domain completes on
+/// one of the ports, and its latency is the time taken by the TCP connection and the handshake.
+pub struct TlsProbe {
+    /// The connector used for TLS handshakes.
     connector: TlsConnector,
-) -> Result<Vec<(Ipv4Addr, u128)>> {
-    // Convert the domain to a format suitable for TLS handshake.
-    let domain_name = ServerName::try_from(domain.to_string())?;
+    /// The server names presented and verified during handshakes, used in turn, with their text.
+    server_names: Vec<(ServerName<'static>, String)>,
+    /// The number of probes started, which selects the server name of the next one.
+    started: AtomicUsize,
+    /// Whether results record the server name they were reached with.
+    record_server_name: bool,
+    /// The server names retried when a handshake looks blocked by SNI filtering, with their text.
+    fallback_names: Vec<(ServerName<'static>, String)>,
+    /// The port numbers to try for each IP, in order of preference.
+    ports: Vec<u16>,
+    /// Whether to connect to all ports of an IP at once.
+    race_ports: bool,
+    /// Whether to perform and discard one handshake per IP before the measured one.
+    warmup: bool,
+    /// The settings of the sockets connected with.
+    socket: SocketOptions,
+    /// The probe timeout, fed with the latencies of successful handshakes.
+    timeout: AdaptiveTimeout,
+}
 
-    // Shared list to hold valid IP addresses and their connection times.
-    let valid_ips = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+impl TlsProbe {
+    /// Creates a probe performing handshakes for `domain` on `ports`, or for each of
+    /// `options.server_names` in turn.
+    pub fn new(domain: &str, ports: &[u16], options: &CheckOptions) -> Result<Self> {
+        let names = match options.server_names.as_slice() {
+            [] => vec![domain.to_string()],
+            names => names.to_vec(),
+        };
 
-    // Map each IP address to an asynchronous task that attempts to establish a TLS connection.
-    let tasks: Vec<_> = target
+        Ok(TlsProbe {
+            connector: TlsConnector::from(prepare_tls_config()?),
+            server_names: parse_server_names(names)?,
+            started: AtomicUsize::new(0),
+            record_server_name: !options.server_names.is_empty(),
+            fallback_names: parse_server_names(options.sni_fallback.clone())?,
+            ports: ports.to_vec(),
+            race_ports: options.race_ports,
+            warmup: options.warmup,
+            socket: options.socket,
+            timeout: AdaptiveTimeout::new(options.timeout),
+        })
+    }
+}
+
+impl Probe for TlsProbe {
+    async fn check(&self, ip: IpAddr) -> ProbeOutcome {
+        let started = self.started.fetch_add(1, Ordering::Relaxed);
+        let (server_name, name) = &self.server_names[started % self.server_names.len()];
+        let probe = || {
+            probe(
+                &self.connector,
+                server_name,
+                ip,
+                &self.ports,
+                self.race_ports,
+                &self.socket,
+                &self.timeout,
+            )
+        };
+
+        // The first handshake is systematically slower because of TLS session
+        // setup and routing caches, so it is optionally discarded.
+        if self.warmup {
+            trace!("Performing warm-up handshake");
+            let _ = probe().await;
+        }
+
+        // A handshake cut once connected may be SNI filtering, which other names can get past.
+        let outcome = match probe().await {
+            Ok((port, timing)) => {
+                Ok((port, timing, self.record_server_name.then_some(name), false))
+            }
+            Err(
+                e @ HandshakeError::Interrupted {
+                    after_connect: true,
+                },
+            ) => match self.fallback(ip).await {
+                Some((port, timing, name)) => Ok((port, timing, Some(name), true)),
+                None => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+
+        // If the TLS handshake succeeds, record the IP and connection times.
+        match outcome {
+            Ok((port, timing, sni, sni_fallback)) => {
+                debug!(
+                    port,
+                    tcp_ms = timing.tcp_ms,
+                    tls_ms = timing.tls_ms,
+                    "TLS handshake completed"
+                );
+                ProbeOutcome::Reachable(Box::new(ScanResult {
+                    port: Some(port),
+                    tcp_ms: Some(timing.tcp_ms),
+                    tls_ms: Some(timing.tls_ms),
+                    sni: sni.cloned(),
+                    sni_fallback,
+                    ..ScanResult::new(ip, timing.tcp_ms + timing.tls_ms)
+                }))
+            }
+            Err(HandshakeError::Failed) => ProbeOutcome::Unreachable,
+            Err(HandshakeError::Interrupted { .. }) => ProbeOutcome::Interrupted,
+        }
+    }
+}
+
+impl TlsProbe {
+    /// Retries `ip` with each fallback server name in turn.
+    ///
+    /// # Returns
+    /// The port, timing and name of the first handshake that completed, if any did.
+    async fn fallback(&self, ip: IpAddr) -> Option<(u16, HandshakeTiming, &String)> {
+        for (server_name, name) in &self.fallback_names {
+            debug!(sni = name.as_str(), "Retrying with a fallback server name");
+            let handshake = probe(
+                &self.connector,
+                server_name,
+                ip,
+                &self.ports,
+                self.race_ports,
+                &self.socket,
+                &self.timeout,
+            );
+            if let Ok((port, timing)) = handshake.await {
+                return Some((port, timing, name));
+            }
+        }
+
+        None
+    }
+}
+
+/// Converts `names` to a format suitable for TLS handshakes, keeping their text.
+fn parse_server_names(names: Vec<String>) -> Result<Vec<(ServerName<'static>, String)>> {
+    names
         .into_iter()
-        .map(|ip| {
-            let connector_clone = connector.clone();
-            let domain_name_clone = domain_name.clone();
-            let valid_ips_clone = valid_ips.clone();
-            let addr = format!("{}:{}", ip, port);
-
-            tokio::spawn(async move {
-                let start = Instant::now();
-
-                // Attempt to connect with a specified timeout.
-                let stream = match timeout(TIMEOUT, TcpStream::connect(&addr)).await {
-                    Ok(Ok(s)) => s,
-                    _ => return,
-                };
-
-                // If the TLS handshake succeeds, record the IP and connection time.
-                if timeout(TIMEOUT, connector_clone.connect(domain_name_clone, stream))
-                    .await
-                    .is_ok()
-                {
-                    let duration = start.elapsed().as_millis();
-                    let mut ips = valid_ips_clone.lock().await;
-                    ips.push((ip, duration));
+        .map(|name| match ServerName::try_from(name.clone()) {
+            Ok(server_name) => Ok((server_name, name)),
+            Err(e) => Err(e).with_context(|| format!("Invalid server name '{}'", name)),
+        })
+        .collect()
+}
+
+/// Performs a handshake with `ip` on `ports` and returns the first port that succeeded.
+///
+/// Ports are tried one after the other in order, or all at once when `race` is set, in which
+/// case the remaining handshakes are abandoned as soon as one succeeds. If none succeeds, the
+/// error is `Interrupted` if any handshake was.
+async fn probe(
+    connector: &TlsConnector,
+    domain_name: &ServerName<'static>,
+    ip: IpAddr,
+    ports: &[u16],
+    race: bool,
+    socket: &SocketOptions,
+    timeout: &AdaptiveTimeout,
+) -> Result<(u16, HandshakeTiming), HandshakeError> {
+    let attempt = |port: u16| {
+        let addr = SocketAddr::new(ip, port);
+        async move {
+            handshake(connector, domain_name.clone(), addr, socket, timeout)
+                .await
+                .map(|timing| (port, timing))
+        }
+        .instrument(debug_span!("port", port))
+    };
+
+    let mut error = HandshakeError::Failed;
+    if race {
+        let mut attempts: FuturesUnordered<_> = ports.iter().map(|&port| attempt(port)).collect();
+        while let Some(outcome) = attempts.next().await {
+            match outcome {
+                Ok(outcome) => return Ok(outcome),
+                Err(e) => error = error.max(e),
+            }
+        }
+    } else {
+        for &port in ports {
+            match attempt(port).await {
+                Ok(outcome) => return Ok(outcome),
+                Err(e) => error = error.max(e),
+            }
+        }
+    }
+
+    Err(error)
+}
+
+/// Why a handshake did not complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum HandshakeError {
+    /// The connection was refused, the handshake failed, or no connection was attempted.
+    Failed,
+    /// The handshake timed out or the connection was reset.
+    ///
+    /// `after_connect` is set when the TCP connection was established and the TLS handshake was
+    /// cut, the signature of SNI filtering.
+    Interrupted { after_connect: bool },
+}
+
+impl HandshakeError {
+    /// Marks an interruption as having happened after the TCP connection was established.
+    fn after_connect(self) -> Self {
+        match self {
+            HandshakeError::Interrupted { .. } => HandshakeError::Interrupted {
+                after_connect: true,
+            },
+            HandshakeError::Failed => HandshakeError::Failed,
+        }
+    }
+}
+
+impl From<&io::Error> for HandshakeError {
+    fn from(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+                HandshakeError::Interrupted {
+                    after_connect: false,
                 }
+            }
+            _ => HandshakeError::Failed,
+        }
+    }
+}
+
+/// The time spent in each step of a successful handshake.
+#[derive(Debug, Clone, Copy)]
+struct HandshakeTiming {
+    /// Time taken to establish the TCP connection, in milliseconds.
+    tcp_ms: u128,
+    /// Time taken to complete the TLS handshake once connected, in milliseconds.
+    tls_ms: u128,
+}
+
+/// Connects to `addr` with the settings of `socket` and performs a TLS handshake.
+///
+/// Both steps together must complete within `timeout`, which is updated with the total time
+/// taken when they succeed.
+///
+/// # Returns
+/// The time taken to establish the TCP connection and to complete the handshake, or why either
+/// step failed.
+async fn handshake(
+    connector: &TlsConnector,
+    domain_name: ServerName<'static>,
+    addr: SocketAddr,
+    socket: &SocketOptions,
+    timeout: &AdaptiveTimeout,
+) -> Result<HandshakeTiming, HandshakeError> {
+    if !budget::try_acquire() {
+        debug!("Skipped, the connection budget is exhausted");
+        return Err(HandshakeError::Failed);
+    }
+    let start = Instant::now();
+
+    // Attempt to connect within the probe timeout.
+    let stream = match timeout.run(start, socket.connect(addr)).await {
+        Some(Ok(s)) => s,
+        Some(Err(e)) => {
+            debug!(error = %e, "TCP connection failed");
+            return Err(HandshakeError::from(&e));
+        }
+        None => {
+            debug!("TCP connection timed out");
+            return Err(HandshakeError::Interrupted {
+                after_connect: false,
+            });
+        }
+    };
+    let connected = Instant::now();
+    let tcp_ms = (connected - start).as_millis();
+    trace!(elapsed_ms = tcp_ms, "TCP connection established");
+
+    match timeout
+        .run(start, connector.connect(domain_name, stream))
+        .await
+    {
+        Some(Ok(_)) => {
+            timeout.observe(start.elapsed());
+            Ok(HandshakeTiming {
+                tcp_ms,
+                tls_ms: connected.elapsed().as_millis(),
             })
-        })
-        .collect();
+        }
+        Some(Err(e)) => {
+            debug!(error = %e, "TLS handshake failed");
+            Err(HandshakeError::from(&e).after_connect())
+        }
+        None => {
+            debug!(
+                timeout_ms = timeout.current().as_millis(),
+                "TLS handshake timed out"
+            );
+            Err(HandshakeError::Interrupted {
+                after_connect: true,
+            })
+        }
+    }
+}
 
-    // Wait for all tasks to complete.
-    FuturesUnordered::from_iter(tasks)
-        .for_each(|_| async {})
-        .await;
+/// Keeps at most `per_subnet` results from each subnet, preserving the order of `results`.
+///
+/// Since `results` is expected to be sorted by latency, the fastest IPs of every subnet are kept.
+fn limit_per_subnet(results: Vec<ScanResult>, per_subnet: usize) -> Vec<ScanResult> {
+    let mut seen: HashMap<IpAddr, usize> = HashMap::new();
 
-    // Retrieve the list of valid IP addresses and their connection times.
-    let valid_ips = valid_ips.lock().await;
-    Ok(valid_ips.clone())
+    results
+        .into_iter()
+        .filter(|result| {
+            let count = seen.entry(subnet_of(result.ip)).or_default();
+            *count += 1;
+            *count <= per_subnet
+        })
+        .collect()
 }
 
 /// Prepares the TLS client configuration with root certificates and TLS version.
 ///
 /// # Returns
 /// A Result containing an Arc-wrapped `ClientConfig`, which can be used to establish TLS connections.
-fn prepare_tls_config() -> Result<Arc<ClientConfig>> {
-    // Initialize the root certificate store from webpki_roots.
-    let root_store = RootCertStore {
-        roots: TLS_SERVER_ROOTS.to_vec(),
-    };
+pub(crate) fn prepare_tls_config() -> Result<Arc<ClientConfig>> {
+    // The bundled webpki roots, or the system's with `--native-certs`.
+    let root_store = roots::root_store();
 
     // Set up the client configuration with TLS version 1.3 and the root certificate store.
     let config = ClientConfig::builder_with_protocol_versions(&[&version::TLS13])
@@ -145,22 +642,54 @@ fn prepare_tls_config() -> Result<Arc<ClientConfig>> {
     Ok(Arc::new(config))
 }
 
+/// Keeps `n` results, of which the first `quota` of each address family are always kept.
+///
+/// The remaining slots go to the best of the other results, so the order of `results` is
+/// preserved. A family with fewer than `quota` results leaves its unused slots to the other.
+fn limit_with_family_quota(results: Vec<ScanResult>, n: usize, quota: usize) -> Vec<ScanResult> {
+    let v6 = results.iter().filter(|result| result.ip.is_ipv6()).count();
+    let reserved = (results.len() - v6).min(quota) + v6.min(quota);
+    let mut free = n.saturating_sub(reserved);
+    let (mut kept_v4, mut kept_v6) = (0, 0);
+
+    results
+        .into_iter()
+        .filter(|result| {
+            let kept = if result.ip.is_ipv6() {
+                &mut kept_v6
+            } else {
+                &mut kept_v4
+            };
+            if *kept < quota {
+                *kept += 1;
+                true
+            } else if free > 0 {
+                free -= 1;
+                true
+            } else {
+                false
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::Ipv4Addr;
 
     #[tokio::test]
     async fn test_check_tls_availability() {
-        let ips = vec![
+        let ips = [
             Ipv4Addr::new(104, 16, 132, 229),
             Ipv4Addr::new(198, 18, 0, 10),
-        ];
+        ]
+        .map(IpAddr::V4);
         let domain = "cloudflare.com";
         let port = 443;
-        let count = 10;
         let n = 1;
 
-        let result = check_tls_availability(&ips, domain, port, count, n).await;
+        let result = check_tls_availability(&ips, domain, &[port], &CheckOptions::new(n)).await;
         assert!(result.is_ok(), "Expected Ok result, but got an Err");
 
         let valid_ips = result.unwrap();
@@ -172,10 +701,9 @@ mod tests {
         let ips = Vec::new(); // Empty list of IPs
         let domain = "example.com";
         let port = 443;
-        let count = 10;
         let n = 1;
 
-        let result = check_tls_availability(&ips, domain, count, port, n).await;
+        let result = check_tls_availability(&ips, domain, &[port], &CheckOptions::new(n)).await;
         assert!(result.is_ok(), "Expected Ok result with empty input");
         let valid_ips = result.unwrap();
         assert!(
@@ -186,13 +714,12 @@ mod tests {
 
     #[tokio::test]
     async fn test_unreachable_domain() {
-        let ips = vec![Ipv4Addr::new(1, 8, 8, 1)]; // Example IP
+        let ips = vec![IpAddr::V4(Ipv4Addr::new(1, 8, 8, 1))]; // Example IP
         let domain = "unreachable.unreachableexample.com"; // Unreachable domain
         let port = 443;
-        let count = 10;
         let n = 1;
 
-        let result = check_tls_availability(&ips, domain, count, port, n).await;
+        let result = check_tls_availability(&ips, domain, &[port], &CheckOptions::new(n)).await;
         assert!(
             result.is_ok(),
             "Expected Ok result even with unreachable domain"
@@ -203,4 +730,46 @@ mod tests {
             "Expected no valid IPs with an unreachable domain"
         );
     }
+
+    #[test]
+    fn test_limit_per_subnet() {
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 1, 1), 10),
+            ScanResult::new(Ipv4Addr::new(104, 16, 1, 2), 20),
+            ScanResult::new(Ipv4Addr::new(104, 16, 2, 1), 30),
+            ScanResult::new(Ipv4Addr::new(104, 16, 1, 3), 40),
+        ];
+
+        let limited = limit_per_subnet(results, 1);
+        let ips: Vec<_> = limited.iter().map(|result| result.ip).collect();
+
+        assert_eq!(
+            ips,
+            vec![
+                IpAddr::V4(Ipv4Addr::new(104, 16, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(104, 16, 2, 1))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limit_with_family_quota() {
+        let v6 =
+            |latency_ms| ScanResult::new("2606:4700::1".parse::<IpAddr>().unwrap(), latency_ms);
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 10),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 2), 20),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 3), 30),
+            v6(40),
+            v6(50),
+        ];
+
+        let limited = limit_with_family_quota(results.clone(), 3, 1);
+        let latencies: Vec<_> = limited.iter().map(|result| result.latency_ms).collect();
+        assert_eq!(latencies, vec![10, 20, 40]);
+
+        let limited = limit_with_family_quota(results[..3].to_vec(), 2, 1);
+        let latencies: Vec<_> = limited.iter().map(|result| result.latency_ms).collect();
+        assert_eq!(latencies, vec![10, 20]);
+    }
 }
@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::history::History;
+use crate::result::{ScanResult, SortKey};
+
+/// Keeps the best results of a scan in bounded memory, spilling all of them to disk.
+///
+/// Exhaustive scans can find millions of reachable IPs, of which only the best few are returned.
+/// Every result is appended as a JSON line to the spill file as it arrives, while a heap holding
+/// at most `capacity` results tracks the best ones, so memory use does not grow with the scan.
+pub struct TopResults {
+    /// The number of results to keep in memory.
+    capacity: usize,
+    /// The factor IPv6 latencies are multiplied by, see `ScanResult::ranking_latency`.
+    ipv6_bias: f64,
+    /// The key results are ranked by.
+    sort_by: SortKey,
+    /// The history results are ranked with, see `History::preview`.
+    history: Option<Arc<History>>,
+    /// The best results so far, with the worst of them on top.
+    heap: BinaryHeap<Ranked>,
+    /// The file every result is written to, one JSON object per line.
+    spill: BufWriter<File>,
+    /// The path of the spill file, for error messages.
+    path: String,
+}
+
+impl TopResults {
+    /// Creates the spill file at `path` and keeps the best `capacity` results in memory.
+    ///
+    /// With `history`, results are ranked by their smoothed latency and reliability, as they will
+    /// be once the scan is recorded. The spilled results are unchanged.
+    pub fn new(
+        path: &str,
+        capacity: usize,
+        ipv6_bias: f64,
+        sort_by: SortKey,
+        history: Option<Arc<History>>,
+    ) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Couldn't create spill file {}", path))?;
+
+        Ok(TopResults {
+            capacity,
+            ipv6_bias,
+            sort_by,
+            history,
+            heap: BinaryHeap::with_capacity(capacity.saturating_add(1)),
+            spill: BufWriter::new(file),
+            path: path.to_string(),
+        })
+    }
+
+    /// Writes `result` to the spill file and keeps it if it is among the best so far.
+    pub fn push(&mut self, mut result: ScanResult) -> Result<()> {
+        serde_json::to_writer(&mut self.spill, &result)
+            .with_context(|| format!("Couldn't write to spill file {}", self.path))?;
+        self.spill
+            .write_all(b"\n")
+            .with_context(|| format!("Couldn't write to spill file {}", self.path))?;
+
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        if let Some(history) = &self.history {
+            history.preview(&mut result);
+        }
+        self.heap.push(Ranked {
+            result,
+            sort_by: self.sort_by,
+            ipv6_bias: self.ipv6_bias,
+        });
+        if self.heap.len() > self.capacity {
+            self.heap.pop();
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the spill file and returns the best results, sorted by `sort_by`.
+    pub fn finish(mut self) -> Result<Vec<ScanResult>> {
+        self.spill
+            .flush()
+            .with_context(|| format!("Couldn't write to spill file {}", self.path))?;
+
+        Ok(self
+            .heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|ranked| ranked.result)
+            .collect())
+    }
+}
+
+/// Reads back the results written to the spill file at `path`, in the order they were found.
+pub fn read_spill(path: &str) -> Result<impl Iterator<Item = Result<ScanResult>>> {
+    let file = File::open(path).with_context(|| format!("Couldn't open spill file {}", path))?;
+    let path = path.to_string();
+
+    Ok(BufReader::new(file).lines().map(move |line| {
+        let line = line.with_context(|| format!("Couldn't read spill file {}", path))?;
+        serde_json::from_str(&line)
+            .with_context(|| format!("Couldn't parse a result of spill file {}", path))
+    }))
+}
+
+/// A result ordered by `sort_by`, best first.
+struct Ranked {
+    result: ScanResult,
+    sort_by: SortKey,
+    ipv6_bias: f64,
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_by
+            .compare(&self.result, &other.result, self.ipv6_bias)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_top_results() {
+        let path = std::env::temp_dir().join("cloudsurf_top_results_test.jsonl");
+        let path = path.to_str().unwrap();
+
+        let mut top = TopResults::new(path, 2, 1.0, SortKey::Latency, None).unwrap();
+        for (host, latency_ms) in [(1, 70), (2, 30), (3, 50), (4, 90)] {
+            top.push(ScanResult::new(Ipv4Addr::new(104, 16, 0, host), latency_ms))
+                .unwrap();
+        }
+        let best = top.finish().unwrap();
+        let spilled: Vec<_> = read_spill(path).unwrap().map(Result::unwrap).collect();
+        std::fs::remove_file(path).unwrap();
+
+        let latencies: Vec<_> = best.iter().map(|result| result.latency_ms).collect();
+        assert_eq!(latencies, vec![30, 50]);
+        assert_eq!(spilled.len(), 4);
+        assert_eq!(spilled[0].latency_ms, 70);
+    }
+
+    #[test]
+    fn test_top_results_with_history() {
+        let path = std::env::temp_dir().join("cloudsurf_top_results_history_test.jsonl");
+        let path = path.to_str().unwrap();
+        let steady = Ipv4Addr::new(104, 16, 0, 1);
+        let lucky = Ipv4Addr::new(104, 16, 0, 2);
+        let mut history = History::default();
+        history.smooth_latencies(&mut [ScanResult::new(steady, 40), ScanResult::new(lucky, 200)]);
+
+        // The lucky IP is faster this time, but its smoothed latency is still the higher one.
+        let mut top =
+            TopResults::new(path, 1, 1.0, SortKey::Latency, Some(Arc::new(history))).unwrap();
+        top.push(ScanResult::new(lucky, 20)).unwrap();
+        top.push(ScanResult::new(steady, 50)).unwrap();
+        let best = top.finish().unwrap();
+        let spilled: Vec<_> = read_spill(path).unwrap().map(Result::unwrap).collect();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(best[0].ip, IpAddr::V4(steady));
+        assert_eq!(best[0].smoothed_latency_ms, Some(43));
+        assert!(spilled
+            .iter()
+            .all(|result| result.smoothed_latency_ms.is_none()));
+    }
+
+    #[test]
+    fn test_top_results_by_reliability() {
+        let path = std::env::temp_dir().join("cloudsurf_top_results_reliability_test.jsonl");
+        let path = path.to_str().unwrap();
+        let reliable = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 1));
+        let flaky = IpAddr::V4(Ipv4Addr::new(104, 16, 0, 2));
+        let mut history = History::default();
+        history.record(&[reliable, flaky], [reliable]);
+
+        // The flaky IP is faster, but it was unreachable in the previous scan.
+        let mut top =
+            TopResults::new(path, 1, 1.0, SortKey::Reliability, Some(Arc::new(history))).unwrap();
+        top.push(ScanResult::new(flaky, 20)).unwrap();
+        top.push(ScanResult::new(reliable, 80)).unwrap();
+        let best = top.finish().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(best[0].ip, reliable);
+        assert_eq!(best[0].reliability_pct, Some(100.0));
+        assert_eq!(best[0].runs_seen, Some(2));
+    }
+}
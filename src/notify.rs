@@ -0,0 +1,56 @@
+use anyhow::Result;
+
+use crate::i18n::{self, Message};
+use crate::result::ScanResult;
+
+/// Whether this build can show desktop notifications.
+pub const SUPPORTED: bool = cfg!(feature = "notify");
+
+/// Shows a desktop notification summarizing the results of a finished scan.
+#[cfg(feature = "notify")]
+pub fn scan_completed(results: &[ScanResult]) -> Result<()> {
+    use anyhow::Context;
+
+    notify_rust::Notification::new()
+        .appname("cloudsurf")
+        .summary("Scan completed")
+        .body(&summary(results))
+        .show()
+        .context("Couldn't show the desktop notification")?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+pub fn scan_completed(_results: &[ScanResult]) -> Result<()> {
+    anyhow::bail!("Desktop notifications require a build with the 'notify' feature enabled")
+}
+
+/// Describes the number of valid IPs and the best latency among `results`.
+#[cfg_attr(not(feature = "notify"), allow(dead_code))]
+fn summary(results: &[ScanResult]) -> String {
+    match results.iter().map(|result| result.latency_ms).min() {
+        Some(best_latency_ms) => i18n::format(
+            Message::NotificationSummary,
+            &[&results.len(), &best_latency_ms],
+        ),
+        None => i18n::text(Message::NoValidIp).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_summary() {
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 70),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 2), 50),
+        ];
+
+        assert_eq!(summary(&results), "2 valid IPs, best latency 50 ms");
+        assert_eq!(summary(&[]), "No valid IP found");
+    }
+}
@@ -0,0 +1,172 @@
+use std::fs::File;
+
+use anyhow::Result;
+
+use crate::report::ScanReport;
+
+/// Whether this build can write Parquet files.
+pub const SUPPORTED: bool = cfg!(feature = "parquet");
+
+/// Writes the results of `report` to `file` in the Parquet format, one row per result.
+///
+/// Every measurement of `ScanResult` gets its own column, with missing measurements stored as
+/// nulls, so that large scans can be loaded straight into dataframe libraries. The scan parameters
+/// and summary are stored as JSON in the file's key-value metadata.
+#[cfg(feature = "parquet")]
+pub fn write_report(report: &ScanReport, file: File) -> Result<()> {
+    use std::sync::Arc;
+
+    use anyhow::Context;
+    use arrow::array::{
+        ArrayRef, BooleanArray, Float64Array, StringArray, UInt16Array, UInt32Array, UInt64Array,
+    };
+    use arrow::datatypes::{DataType, Field, Schema};
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+    use parquet::format::KeyValue;
+
+    let results = &report.results;
+    let millis = |value: u128| u64::try_from(value).unwrap_or(u64::MAX);
+    let domain = report
+        .parameters
+        .as_ref()
+        .map(|parameters| parameters.domain.as_str());
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("ip", DataType::Utf8, false),
+        Field::new("family", DataType::Utf8, false),
+        Field::new("domain", DataType::Utf8, true),
+        Field::new("port", DataType::UInt16, true),
+        Field::new("latency_ms", DataType::UInt64, false),
+        Field::new("smoothed_latency_ms", DataType::UInt64, true),
+        Field::new("tcp_ms", DataType::UInt64, true),
+        Field::new("tls_ms", DataType::UInt64, true),
+        Field::new("speed_mbps", DataType::Float64, true),
+        Field::new("ray_id", DataType::Utf8, true),
+        Field::new("colo", DataType::Utf8, true),
+        Field::new("h2_completed", DataType::UInt32, true),
+        Field::new("h2_stream_errors", DataType::UInt32, true),
+        Field::new("h2_goaway", DataType::Boolean, true),
+        Field::new("h2_error", DataType::Utf8, true),
+    ]));
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            results.iter().map(|result| result.ip.to_string()),
+        )),
+        Arc::new(StringArray::from_iter_values(results.iter().map(
+            |result| {
+                if result.ip.is_ipv6() {
+                    "ipv6"
+                } else {
+                    "ipv4"
+                }
+            },
+        ))),
+        Arc::new(StringArray::from(vec![domain; results.len()])),
+        Arc::new(UInt16Array::from_iter(
+            results.iter().map(|result| result.port),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            results.iter().map(|result| millis(result.latency_ms)),
+        )),
+        Arc::new(UInt64Array::from_iter(
+            results
+                .iter()
+                .map(|result| result.smoothed_latency_ms.map(millis)),
+        )),
+        Arc::new(UInt64Array::from_iter(
+            results.iter().map(|result| result.tcp_ms.map(millis)),
+        )),
+        Arc::new(UInt64Array::from_iter(
+            results.iter().map(|result| result.tls_ms.map(millis)),
+        )),
+        Arc::new(Float64Array::from_iter(
+            results.iter().map(|result| result.speed_mbps),
+        )),
+        Arc::new(StringArray::from_iter(
+            results.iter().map(|result| result.ray_id.as_deref()),
+        )),
+        Arc::new(StringArray::from_iter(
+            results.iter().map(|result| result.colo()),
+        )),
+        Arc::new(UInt32Array::from_iter(
+            results
+                .iter()
+                .map(|result| result.h2.as_ref().map(|h2| h2.completed)),
+        )),
+        Arc::new(UInt32Array::from_iter(
+            results
+                .iter()
+                .map(|result| result.h2.as_ref().map(|h2| h2.stream_errors)),
+        )),
+        Arc::new(BooleanArray::from_iter(
+            results
+                .iter()
+                .map(|result| result.h2.as_ref().map(|h2| h2.goaway)),
+        )),
+        Arc::new(StringArray::from_iter(results.iter().map(|result| {
+            result.h2.as_ref().and_then(|h2| h2.error.as_deref())
+        }))),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)
+        .context("Couldn't build the Parquet record batch")?;
+
+    let metadata = vec![
+        KeyValue::new(
+            "cloudsurf.format_version".to_string(),
+            report.format_version.to_string(),
+        ),
+        KeyValue::new(
+            "cloudsurf.parameters".to_string(),
+            serde_json::to_string(&report.parameters)?,
+        ),
+        KeyValue::new(
+            "cloudsurf.summary".to_string(),
+            serde_json::to_string(&report.summary)?,
+        ),
+    ];
+    let properties = WriterProperties::builder()
+        .set_key_value_metadata(Some(metadata))
+        .build();
+
+    let mut writer = ArrowWriter::try_new(file, schema, Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+pub fn write_report(_report: &ScanReport, _file: File) -> Result<()> {
+    anyhow::bail!("The parquet format requires a build with the 'parquet' feature enabled")
+}
+
+#[cfg(all(test, feature = "parquet"))]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    use crate::result::ScanResult;
+
+    #[test]
+    fn test_write_report() {
+        let path = std::env::temp_dir().join("cloudsurf_parquet_test.parquet");
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 2), 70),
+        ];
+        let report = ScanReport::new(None, results, 10);
+
+        write_report(&report, File::create(&path).unwrap()).unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        assert_eq!(metadata.schema_descr().num_columns(), 15);
+    }
+}
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+
+use crate::network::Provider;
+
+/// The name of the application's directory within each platform directory.
+const APP_DIR: &str = "cloudsurf";
+
+/// Returns the platform directories of the application.
+fn project_dirs() -> Result<ProjectDirs> {
+    ProjectDirs::from("", "", APP_DIR).context("Couldn't locate the home directory")
+}
+
+/// Returns the directory for configuration, e.g. `~/.config/cloudsurf` on Linux.
+pub fn config_dir() -> Result<PathBuf> {
+    Ok(project_dirs()?.config_dir().to_path_buf())
+}
+
+/// Returns the directory for data that can be fetched again, e.g. `~/.cache/cloudsurf` on Linux.
+pub fn cache_dir() -> Result<PathBuf> {
+    Ok(project_dirs()?.cache_dir().to_path_buf())
+}
+
+/// Returns the directory for state kept between runs, e.g. `~/.local/state/cloudsurf` on Linux.
+///
+/// Follows `XDG_STATE_HOME` on Linux, and falls back to the local data directory elsewhere, as
+/// other platforms have no separate place for state.
+pub fn state_dir() -> Result<PathBuf> {
+    let dirs = project_dirs()?;
+
+    Ok(dirs
+        .state_dir()
+        .unwrap_or_else(|| dirs.data_local_dir())
+        .to_path_buf())
+}
+
+/// Returns the configuration file written by `cloudsurf init`.
+pub fn config_file() -> Result<PathBuf> {
+    Ok(config_dir()?.join("config"))
+}
+
+/// Returns the directory saved profiles are stored in.
+pub fn profiles_dir() -> Result<PathBuf> {
+    Ok(config_dir()?.join("profiles"))
+}
+
+/// Returns the file the last IP ranges fetched from `provider` are kept in.
+pub fn ranges_cache_file(provider: Provider) -> Result<PathBuf> {
+    let name = match provider {
+        Provider::Cloudflare => "ips.json".to_string(),
+        provider => format!("{}-ips.json", provider.name()),
+    };
+
+    Ok(cache_dir()?.join(name))
+}
+
+/// Returns the file the skip or seed list fetched from `url` is kept in.
+pub fn list_cache_file(url: &str) -> Result<PathBuf> {
+    let name: String = url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    Ok(cache_dir()?.join("lists").join(name))
+}
+
+/// Returns the history file used when `--history` is given without a path.
+pub fn history_file() -> Result<PathBuf> {
+    Ok(state_dir()?.join("history.json"))
+}
+
+/// Resolves the value of a `--history` option: no history, the given file, or the default one
+/// when the option is given without a path.
+pub fn resolve_history(history: &Option<Option<String>>) -> Result<Option<String>> {
+    match history {
+        None => Ok(None),
+        Some(Some(path)) => Ok(Some(path.clone())),
+        Some(None) => {
+            let path = history_file()?;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Couldn't create {}", dir.display()))?;
+            }
+            Ok(Some(path.to_string_lossy().into_owned()))
+        }
+    }
+}
+
+/// Returns what each of the application's files is, with its location.
+pub fn all() -> Result<Vec<(&'static str, PathBuf)>> {
+    Ok(vec![
+        ("Configuration", config_dir()?),
+        ("Configuration file", config_file()?),
+        ("Saved profiles", profiles_dir()?),
+        ("Cache", cache_dir()?),
+        (
+            "Cloudflare IP ranges",
+            ranges_cache_file(Provider::Cloudflare)?,
+        ),
+        ("Fastly IP ranges", ranges_cache_file(Provider::Fastly)?),
+        ("Skip and seed lists", cache_dir()?.join("lists")),
+        ("State", state_dir()?),
+        ("Default history", history_file()?),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_history() {
+        assert_eq!(resolve_history(&None).unwrap(), None);
+        assert_eq!(
+            resolve_history(&Some(Some("history.json".to_string()))).unwrap(),
+            Some("history.json".to_string())
+        );
+    }
+}
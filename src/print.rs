@@ -1,21 +1,476 @@
-use std::net::Ipv4Addr;
+use std::collections::HashSet;
+use std::path::PathBuf;
 
+#[cfg(not(feature = "table"))]
+use plain::{row, Cell, Row, Table};
+#[cfg(feature = "table")]
 use prettytable::{row, Cell, Row, Table};
 
-pub fn ips(ips: &Vec<(Ipv4Addr, u128)>) {
+use crate::aggregate::{self, BlockStats};
+use crate::bench::ListStats;
+use crate::i18n::{self, Message};
+use crate::report::{Baseline, ColoSummary, DomainMatrix, FragmentComparison};
+use crate::result::{GrpcCheckResult, H2CheckResult, ScanResult};
+use crate::selftest::CheckOutcome;
+
+/// Prints `ips` as a table, with the difference of each latency to `baseline_ms` if given.
+pub fn ips(ips: &[ScanResult], baseline_ms: Option<u128>) {
+    let ports: HashSet<_> = ips.iter().filter_map(|result| result.port).collect();
+    let show_port = ports.len() > 1;
+    let show_family = ips.iter().any(|result| result.ip.is_ipv4())
+        && ips.iter().any(|result| result.ip.is_ipv6());
+    let show_passes = ips.iter().any(|result| result.passes.is_some());
+    let show_reliability = ips.iter().any(|result| result.reliability_pct.is_some());
+    let show_smoothed = ips
+        .iter()
+        .any(|result| result.smoothed_latency_ms.is_some());
+    let show_timing = ips.iter().any(|result| result.tcp_ms.is_some());
+    let show_dns = ips.iter().any(|result| result.dns_ms.is_some());
+    let show_speed = ips.iter().any(|result| result.speed_mbps.is_some());
+    let show_request = ips.iter().any(|result| result.request_ms.is_some());
+    let show_h2 = ips.iter().any(|result| result.h2.is_some());
+    let show_grpc = ips.iter().any(|result| result.grpc.is_some());
+    let show_provider = ips.iter().any(|result| result.provider.is_some());
+    let show_source = ips.iter().any(|result| result.seeded);
+    let show_sni = ips.iter().any(|result| result.sni.is_some());
+    let show_fragment = ips.iter().any(|result| result.fragment.is_some());
+
     let mut table = Table::new();
-    table.add_row(row!["", "IP Address", "Latency (ms)"]);
+    let mut header = row!["", i18n::text(Message::IpAddress)];
+    if show_family {
+        header.add_cell(Cell::new(i18n::text(Message::Family)));
+    }
+    if show_port {
+        header.add_cell(Cell::new(i18n::text(Message::Port)));
+    }
+    if show_sni {
+        header.add_cell(Cell::new("SNI"));
+    }
+    header.add_cell(Cell::new(i18n::text(Message::LatencyMs)));
+    if baseline_ms.is_some() {
+        header.add_cell(Cell::new(i18n::text(Message::VsBaselineMs)));
+    }
+    if show_smoothed {
+        header.add_cell(Cell::new(i18n::text(Message::SmoothedMs)));
+    }
+    if show_fragment {
+        header.add_cell(Cell::new(i18n::text(Message::UnfragmentedMs)));
+    }
+    if show_passes {
+        header.add_cell(Cell::new(i18n::text(Message::Passes)));
+    }
+    if show_reliability {
+        header.add_cell(Cell::new(i18n::text(Message::Reliability)));
+    }
+    if show_timing {
+        header.add_cell(Cell::new(i18n::text(Message::TcpMs)));
+        header.add_cell(Cell::new(i18n::text(Message::TlsMs)));
+    }
+    if show_dns {
+        header.add_cell(Cell::new(i18n::text(Message::DnsMs)));
+    }
+    if show_speed {
+        header.add_cell(Cell::new(i18n::text(Message::SpeedMbps)));
+    }
+    if show_request {
+        header.add_cell(Cell::new(i18n::text(Message::RequestMs)));
+    }
+    if show_h2 {
+        header.add_cell(Cell::new("HTTP/2"));
+    }
+    if show_grpc {
+        header.add_cell(Cell::new("gRPC"));
+    }
+    if show_provider {
+        header.add_cell(Cell::new(i18n::text(Message::Provider)));
+    }
+    if show_source {
+        header.add_cell(Cell::new(i18n::text(Message::Source)));
+    }
+    table.add_row(header);
 
-    let mut row_num = 1;
-    for (ip, latency) in ips {
-        table.add_row(Row::new(vec![
+    for (row_num, result) in (1..).zip(ips) {
+        let mut row = Row::new(vec![
             Cell::new(&row_num.to_string()),
-            Cell::new(&ip.to_string()),
-            Cell::new(&latency.to_string()),
+            Cell::new(&result.ip.to_string()),
+        ]);
+        if show_family {
+            let family = if result.ip.is_ipv6() { "IPv6" } else { "IPv4" };
+            row.add_cell(Cell::new(family));
+        }
+        if show_port {
+            let port = result
+                .port
+                .map_or_else(|| "-".to_string(), |port| port.to_string());
+            row.add_cell(Cell::new(&port));
+        }
+        if show_sni {
+            let sni = match &result.sni {
+                Some(sni) if result.sni_fallback => i18n::format(Message::SniFallback, &[sni]),
+                Some(sni) => sni.clone(),
+                None => "-".to_string(),
+            };
+            row.add_cell(Cell::new(&sni));
+        }
+        row.add_cell(Cell::new(&result.latency_ms.to_string()));
+        if let Some(baseline_ms) = baseline_ms {
+            let difference = result.latency_ms as i128 - baseline_ms as i128;
+            row.add_cell(Cell::new(&format!("{:+}", difference)));
+        }
+        if show_smoothed {
+            row.add_cell(Cell::new(&latency(result.smoothed_latency_ms)));
+        }
+        if show_fragment {
+            let unfragmented = match &result.fragment {
+                Some(difference) => difference
+                    .unfragmented_ms
+                    .map_or_else(|| "✗".to_string(), |latency| latency.to_string()),
+                None => "-".to_string(),
+            };
+            row.add_cell(Cell::new(&unfragmented));
+        }
+        if show_passes {
+            let passes = result
+                .passes
+                .map_or_else(|| "-".to_string(), |passes| passes.to_string());
+            row.add_cell(Cell::new(&passes));
+        }
+        if show_reliability {
+            let reliability = match (result.reliability_pct, result.runs_seen) {
+                (Some(pct), Some(runs)) => {
+                    i18n::format(Message::ReliabilityOfRuns, &[&format!("{:.0}", pct), &runs])
+                }
+                _ => "-".to_string(),
+            };
+            row.add_cell(Cell::new(&reliability));
+        }
+        if show_timing {
+            row.add_cell(Cell::new(&latency(result.tcp_ms)));
+            row.add_cell(Cell::new(&latency(result.tls_ms)));
+        }
+        if show_dns {
+            row.add_cell(Cell::new(&latency(result.dns_ms)));
+        }
+        if show_speed {
+            let speed = result
+                .speed_mbps
+                .map_or_else(|| "-".to_string(), |speed| format!("{:.2}", speed));
+            row.add_cell(Cell::new(&speed));
+        }
+        if show_request {
+            row.add_cell(Cell::new(&latency(result.request_ms)));
+        }
+        if show_h2 {
+            row.add_cell(Cell::new(&h2_status(result.h2.as_ref())));
+        }
+        if show_grpc {
+            row.add_cell(Cell::new(&grpc_status(result.grpc.as_ref())));
+        }
+        if show_provider {
+            let provider = result.provider.map_or("-", |provider| provider.name());
+            row.add_cell(Cell::new(provider));
+        }
+        if show_source {
+            let source = if result.seeded {
+                Message::Seed
+            } else {
+                Message::Sample
+            };
+            row.add_cell(Cell::new(i18n::text(source)));
+        }
+        table.add_row(row);
+    }
+
+    table.printstd();
+}
+
+/// Prints the server name to use with each IP whose handshake only completed with a fallback one.
+pub fn sni_fallbacks(ips: &[ScanResult]) {
+    for result in ips.iter().filter(|result| result.sni_fallback) {
+        if let Some(sni) = &result.sni {
+            println!(
+                "{}",
+                i18n::format(Message::BlockedDomain, &[&result.ip, sni])
+            );
+        }
+    }
+}
+
+/// Prints how fragmenting the ClientHello changed which IPs were reachable.
+pub fn fragmentation(comparison: &FragmentComparison) {
+    println!(
+        "{}",
+        i18n::format(
+            Message::Fragmentation,
+            &[
+                &comparison.both,
+                &comparison.only_fragmented,
+                &comparison.only_unfragmented
+            ]
+        )
+    );
+}
+
+pub fn baseline(domain: &str, baseline: &Baseline) {
+    println!(
+        "{}",
+        i18n::format(
+            Message::Baseline,
+            &[&domain, &baseline.latency_ms, &baseline.ip]
+        )
+    );
+}
+
+/// Prints the latency of each IP through each domain of `matrix`, or ✗ where it failed.
+pub fn matrix(matrix: &DomainMatrix) {
+    let mut table = Table::new();
+    let mut header = row![i18n::text(Message::IpAddress)];
+    for domain in &matrix.domains {
+        header.add_cell(Cell::new(domain));
+    }
+    table.add_row(header);
+
+    for matrix_row in &matrix.rows {
+        let mut row = Row::new(vec![Cell::new(&matrix_row.ip.to_string())]);
+        for latency_ms in &matrix_row.latencies_ms {
+            let cell = latency_ms.map_or_else(|| "✗".to_string(), |latency| latency.to_string());
+            row.add_cell(Cell::new(&cell));
+        }
+        table.add_row(row);
+    }
+
+    table.printstd();
+    println!(
+        "{}",
+        i18n::format(
+            Message::MatrixSummary,
+            &[
+                &matrix.working_for_all(),
+                &matrix.rows.len(),
+                &matrix.domains.len()
+            ]
+        )
+    );
+}
+
+pub fn colos(summaries: &[ColoSummary]) {
+    let mut table = Table::new();
+    table.add_row(row![
+        i18n::text(Message::Colo),
+        i18n::text(Message::Ips),
+        i18n::text(Message::BestMs),
+        i18n::text(Message::MedianMs)
+    ]);
+
+    for summary in summaries {
+        table.add_row(Row::new(vec![
+            Cell::new(&summary.colo),
+            Cell::new(&summary.count.to_string()),
+            Cell::new(&summary.best_latency_ms.to_string()),
+            Cell::new(&summary.median_latency_ms.to_string()),
         ]));
+    }
+
+    table.printstd();
+}
 
-        row_num += 1;
+/// The number of blocks listed in the table above the heatmap.
+const TOP_BLOCKS: usize = 20;
+
+pub fn blocks(blocks: &[BlockStats]) {
+    let mut best: Vec<_> = blocks.iter().filter(|block| block.reachable > 0).collect();
+    best.sort_by(|a, b| {
+        b.success_rate()
+            .total_cmp(&a.success_rate())
+            .then(a.median_latency_ms.cmp(&b.median_latency_ms))
+    });
+
+    let mut table = Table::new();
+    table.add_row(row![
+        i18n::text(Message::Block),
+        i18n::text(Message::Probed),
+        i18n::text(Message::Reachable),
+        i18n::text(Message::SuccessPct),
+        i18n::text(Message::MedianMs)
+    ]);
+    for block in best.into_iter().take(TOP_BLOCKS) {
+        table.add_row(Row::new(vec![
+            Cell::new(&format!("{}/{}", block.network, block.prefix)),
+            Cell::new(&block.probed.to_string()),
+            Cell::new(&block.reachable.to_string()),
+            Cell::new(&format!("{:.1}", block.success_rate())),
+            Cell::new(&latency(block.median_latency_ms)),
+        ]));
+    }
+    table.printstd();
+
+    print!("{}", aggregate::heatmap(blocks));
+}
+
+pub fn paths(paths: &[(&str, PathBuf)]) {
+    let mut table = Table::new();
+    table.add_row(row![
+        i18n::text(Message::What),
+        i18n::text(Message::Location)
+    ]);
+
+    for (name, path) in paths {
+        table.add_row(Row::new(vec![
+            Cell::new(name),
+            Cell::new(&path.display().to_string()),
+        ]));
+    }
+
+    table.printstd();
+}
+
+pub fn checks(outcomes: &[CheckOutcome]) {
+    let mut table = Table::new();
+    table.add_row(row![
+        i18n::text(Message::Check),
+        i18n::text(Message::Status),
+        i18n::text(Message::Details)
+    ]);
+
+    for outcome in outcomes {
+        table.add_row(Row::new(vec![
+            Cell::new(outcome.name),
+            Cell::new(&outcome.status.to_string()),
+            Cell::new(&outcome.details),
+        ]));
     }
 
     table.printstd();
 }
+
+/// A row of the `bench` table: its label and how to compute the value for each list.
+type BenchRow = (Message, fn(&ListStats) -> String);
+
+pub fn bench(stats: &[ListStats]) {
+    let mut table = Table::new();
+
+    let mut header = vec![Cell::new("")];
+    header.extend(stats.iter().map(|list| Cell::new(&list.name)));
+    table.add_row(Row::new(header));
+
+    let rows: [BenchRow; 6] = [
+        (Message::IpAddresses, |list| list.total.to_string()),
+        (Message::Reachable, |list| list.reachable.to_string()),
+        (Message::SuccessRatePct, |list| {
+            format!("{:.1}", list.success_rate())
+        }),
+        (Message::BestLatencyMs, |list| latency(list.best_latency_ms)),
+        (Message::MedianLatencyMs, |list| {
+            latency(list.median_latency_ms)
+        }),
+        (Message::MeanLatencyMs, |list| latency(list.mean_latency_ms)),
+    ];
+
+    for (label, value) in rows {
+        let mut cells = vec![Cell::new(i18n::text(label))];
+        cells.extend(stats.iter().map(|list| Cell::new(&value(list))));
+        table.add_row(Row::new(cells));
+    }
+
+    table.printstd();
+}
+
+fn h2_status(outcome: Option<&H2CheckResult>) -> String {
+    match outcome {
+        None => "-".to_string(),
+        Some(outcome) if outcome.passed() => i18n::format(Message::H2Passed, &[&outcome.completed]),
+        Some(outcome) if outcome.goaway => "GOAWAY".to_string(),
+        Some(outcome) if outcome.stream_errors > 0 => {
+            i18n::format(Message::StreamErrors, &[&outcome.stream_errors])
+        }
+        Some(_) => i18n::text(Message::Failed).to_string(),
+    }
+}
+
+fn grpc_status(outcome: Option<&GrpcCheckResult>) -> String {
+    match outcome {
+        None => "-".to_string(),
+        Some(outcome) if outcome.passed() => {
+            let latency_ms = latency(outcome.latency_ms);
+            match outcome.status {
+                Some(status) => {
+                    i18n::format(Message::GrpcPassedWithStatus, &[&latency_ms, &status])
+                }
+                None => i18n::format(Message::GrpcPassed, &[&latency_ms]),
+            }
+        }
+        Some(_) => i18n::text(Message::Failed).to_string(),
+    }
+}
+
+fn latency(latency_ms: Option<u128>) -> String {
+    latency_ms.map_or_else(|| "-".to_string(), |latency| latency.to_string())
+}
+
+/// A minimal stand-in for `prettytable` in builds without the `table` feature, printing the
+/// columns aligned with spaces instead of framed.
+#[cfg(not(feature = "table"))]
+mod plain {
+    pub struct Cell(String);
+
+    impl Cell {
+        pub fn new(text: &str) -> Self {
+            Cell(text.to_string())
+        }
+    }
+
+    pub struct Row(Vec<Cell>);
+
+    impl Row {
+        pub fn new(cells: Vec<Cell>) -> Self {
+            Row(cells)
+        }
+
+        pub fn add_cell(&mut self, cell: Cell) {
+            self.0.push(cell);
+        }
+    }
+
+    #[derive(Default)]
+    pub struct Table(Vec<Row>);
+
+    impl Table {
+        pub fn new() -> Self {
+            Table::default()
+        }
+
+        pub fn add_row(&mut self, row: Row) {
+            self.0.push(row);
+        }
+
+        pub fn printstd(&self) {
+            let mut widths: Vec<usize> = Vec::new();
+            for row in &self.0 {
+                for (column, cell) in row.0.iter().enumerate() {
+                    let width = cell.0.chars().count();
+                    match widths.get_mut(column) {
+                        Some(max) => *max = (*max).max(width),
+                        None => widths.push(width),
+                    }
+                }
+            }
+
+            for row in &self.0 {
+                let cells: Vec<_> = row
+                    .0
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{:<width$}", cell.0, width = width))
+                    .collect();
+                println!("{}", cells.join("  ").trim_end());
+            }
+        }
+    }
+
+    macro_rules! row {
+        ($($cell:expr),* $(,)?) => {
+            Row::new(vec![$(Cell::new($cell)),*])
+        };
+    }
+    pub(super) use row;
+}
@@ -0,0 +1,319 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{CommandFactory, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::args::Args;
+use crate::network::ProbeKind;
+use crate::paths::profiles_dir;
+use crate::sampling::SampleSize;
+
+/// The profile selected with `--profile`: a built-in preset or one saved with `profile save`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProfileName {
+    /// One of the built-in presets.
+    BuiltIn(Profile),
+    /// The name of a profile saved in `paths::profiles_dir`.
+    Saved(String),
+}
+
+/// A bundle of scan settings suited to a common use, selected with `--profile`.
+///
+/// A profile only provides defaults: every option given on the command line keeps its value.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// A quick look at a small sample, with short timeouts and every IP probed at once.
+    Fast,
+    /// A large sample probed in several passes, with warm-up handshakes and retries, to rank
+    /// IPs reliably at the cost of a longer scan.
+    Thorough,
+    /// Few connections at a time and patient timeouts, to stay below the radar of throttling
+    /// ISPs and firewalls.
+    Stealth,
+}
+
+impl Profile {
+    /// Sets the options of this profile in `args`, except those for which `is_explicit` returns
+    /// `true` when given the option's field name.
+    pub fn apply(self, args: &mut Args, is_explicit: impl Fn(&str) -> bool) {
+        let settings = self.settings();
+
+        if !is_explicit("count") {
+            args.count = SampleSize::Count(settings.count);
+        }
+        if !is_explicit("timeout_ms") {
+            args.timeout_ms = settings.timeout_ms;
+        }
+        if !is_explicit("concurrency") {
+            args.concurrency = settings.concurrency;
+        }
+        if !is_explicit("adaptive_concurrency") {
+            args.adaptive_concurrency = settings.adaptive_concurrency;
+        }
+        if !is_explicit("passes") {
+            args.passes = settings.passes;
+        }
+        // DNS-over-HTTPS probes perform no warm-up handshake, see `Args::probe`.
+        if !is_explicit("warmup") && args.probe == ProbeKind::Tls {
+            args.warmup = settings.warmup;
+        }
+        if !is_explicit("retry_timeouts") {
+            args.retry_timeouts = settings.retry_timeouts;
+        }
+    }
+
+    fn settings(self) -> Settings {
+        match self {
+            Profile::Fast => Settings {
+                count: 200,
+                timeout_ms: Some(500),
+                concurrency: None,
+                adaptive_concurrency: false,
+                passes: 1,
+                warmup: false,
+                retry_timeouts: false,
+            },
+            Profile::Thorough => Settings {
+                count: 5_000,
+                timeout_ms: None,
+                concurrency: Some(500),
+                adaptive_concurrency: true,
+                passes: 3,
+                warmup: true,
+                retry_timeouts: true,
+            },
+            Profile::Stealth => Settings {
+                count: 300,
+                timeout_ms: Some(3_000),
+                concurrency: Some(4),
+                adaptive_concurrency: false,
+                passes: 1,
+                warmup: false,
+                retry_timeouts: false,
+            },
+        }
+    }
+}
+
+/// The options a profile sets.
+struct Settings {
+    count: usize,
+    timeout_ms: Option<u64>,
+    concurrency: Option<usize>,
+    adaptive_concurrency: bool,
+    passes: usize,
+    warmup: bool,
+    retry_timeouts: bool,
+}
+
+/// A set of options saved under a name, see `save`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SavedProfile {
+    /// The command-line arguments, as they were given when saving.
+    args: Vec<String>,
+}
+
+/// Saves the scan options `args` under `name`, so that `--profile <name>` applies them again.
+///
+/// # Errors
+///
+/// Returns an error if `args` are not valid scan options, select a profile themselves, or
+/// cannot be written.
+pub fn save(name: &str, args: &[String]) -> Result<PathBuf> {
+    save_in(&profiles_dir()?, name, args)
+}
+
+fn save_in(dir: &Path, name: &str, args: &[String]) -> Result<PathBuf> {
+    let parsed = Args::try_parse_with_profile(
+        std::iter::once("cloudsurf").chain(args.iter().map(String::as_str)),
+    )
+    .map_err(|e| anyhow::anyhow!("Invalid options for profile '{}': {}", name, e.render()))?;
+    if parsed.command.is_some() {
+        anyhow::bail!("A profile holds scan options, not a subcommand");
+    }
+    if parsed.profile.is_some() {
+        anyhow::bail!("A profile can't select another profile");
+    }
+
+    fs::create_dir_all(dir).with_context(|| format!("Couldn't create {}", dir.display()))?;
+    let path = dir.join(format!("{}.json", name));
+    let profile = SavedProfile {
+        args: args.to_vec(),
+    };
+    fs::write(&path, serde_json::to_string_pretty(&profile)?)
+        .with_context(|| format!("Couldn't write {}", path.display()))?;
+
+    Ok(path)
+}
+
+/// Loads the arguments of the profile saved under `name`.
+pub fn load(name: &str) -> Result<Vec<String>> {
+    load_from(&profiles_dir()?, name)
+}
+
+fn load_from(dir: &Path, name: &str) -> Result<Vec<String>> {
+    let path = dir.join(format!("{}.json", name));
+    let contents = fs::read_to_string(&path).with_context(|| {
+        format!(
+            "Unknown profile '{}', expected fast, thorough, stealth or a profile saved with `cloudsurf profile save` ({} not found)",
+            name,
+            path.display()
+        )
+    })?;
+    let profile: SavedProfile = serde_json::from_str(&contents)
+        .with_context(|| format!("Couldn't parse profile {}", path.display()))?;
+
+    Ok(profile.args)
+}
+
+/// Returns the names of the saved profiles, sorted.
+pub fn list() -> Result<Vec<String>> {
+    let dir = profiles_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Couldn't read {}", dir.display())),
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            (path.extension()? == "json").then(|| path.file_stem()?.to_str().map(str::to_string))?
+        })
+        .collect();
+    names.sort();
+
+    Ok(names)
+}
+
+/// Removes the options of `saved` for which `is_explicit` returns `true` when given the option's
+/// field name, so that options given on the command line take precedence over a saved profile.
+///
+/// Options are recognized by their long or short name, with their value inline (`--count=500`)
+/// or as the next argument. Unknown arguments are kept for clap to report.
+pub fn without_explicit(saved: &[String], is_explicit: impl Fn(&str) -> bool) -> Vec<String> {
+    let mut command = Args::command();
+    command.build();
+    let mut kept = Vec::new();
+    let mut tokens = saved.iter().peekable();
+    while let Some(token) = tokens.next() {
+        let (arg, inline_value) = if let Some(long) = token.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, _)) => (name, true),
+                None => (long, false),
+            };
+            (
+                command
+                    .get_arguments()
+                    .find(|arg| arg.get_long() == Some(name)),
+                value,
+            )
+        } else if let Some(short) = token.strip_prefix('-') {
+            let mut chars = short.chars();
+            let name = chars.next();
+            let arg = command
+                .get_arguments()
+                .find(|arg| name.is_some() && arg.get_short() == name);
+            let value =
+                arg.is_some_and(|arg| arg.get_action().takes_values()) && chars.next().is_some();
+            (arg, value)
+        } else {
+            (None, false)
+        };
+
+        let Some(arg) = arg else {
+            kept.push(token.clone());
+            continue;
+        };
+        let mut option = vec![token.clone()];
+        // An option whose value is optional, like `--history`, is followed by another option
+        // when given without one.
+        let optional_value = arg.get_num_args().is_some_and(|n| n.min_values() == 0);
+        let next_is_option = tokens.peek().is_some_and(|next| next.starts_with('-'));
+        if arg.get_action().takes_values() && !inline_value && !(optional_value && next_is_option) {
+            option.extend(tokens.next().cloned());
+        }
+        if !is_explicit(arg.get_id().as_str()) {
+            kept.extend(option);
+        }
+    }
+
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_without_explicit() {
+        let saved = strings(&[
+            "--count",
+            "500",
+            "--concurrency=50",
+            "-f",
+            "ips.txt",
+            "--warmup",
+            "--history",
+            "--skip-prefixes",
+            "104.16.",
+        ]);
+
+        let kept = without_explicit(&saved, |name| name == "count" || name == "concurrency");
+
+        assert_eq!(
+            kept,
+            strings(&[
+                "-f",
+                "ips.txt",
+                "--warmup",
+                "--history",
+                "--skip-prefixes",
+                "104.16."
+            ])
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("cloudsurf-profiles-{}", std::process::id()));
+        let args = strings(&["--count", "500", "--concurrency", "50"]);
+
+        let path = save_in(&dir, "home-isp", &args).unwrap();
+        assert!(path.ends_with("home-isp.json"));
+        assert_eq!(load_from(&dir, "home-isp").unwrap(), args);
+        assert!(load_from(&dir, "office").is_err());
+        assert!(save_in(&dir, "nested", &strings(&["--profile", "fast"])).is_err());
+        assert!(save_in(&dir, "invalid", &strings(&["--count", "many"])).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_profile_keeps_explicit_options() {
+        let mut args = Args::try_parse_with_profile([
+            "testapp",
+            "--profile",
+            "stealth",
+            "--concurrency",
+            "16",
+        ])
+        .unwrap();
+        assert_eq!(args.count, SampleSize::Count(300));
+        assert_eq!(args.timeout_ms, Some(3_000));
+        assert_eq!(args.concurrency, Some(16));
+
+        Profile::Thorough.apply(&mut args, |name| name == "count");
+        assert_eq!(args.count, SampleSize::Count(300));
+        assert_eq!(args.passes, 3);
+        assert!(args.warmup);
+        assert!(args.retry_timeouts);
+    }
+}
@@ -0,0 +1,228 @@
+use std::io::{self, BufRead, Write};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use rustls::pki_types::ServerName;
+
+use crate::config::Settings;
+use crate::file::OutputFormat;
+
+/// Asks on the terminal for the domain TLS handshakes are verified against, until a valid
+/// domain name is entered. An empty answer picks `default`.
+///
+/// # Errors
+///
+/// Returns an error if the input is closed before a domain was entered.
+pub fn domain(default: &str) -> Result<String> {
+    read_domain(io::stdin().lock(), io::stderr(), default)
+}
+
+/// Asks on the terminal for the settings written by `cloudsurf init`.
+///
+/// # Errors
+///
+/// Returns an error if the input is closed before every question was answered.
+pub fn settings(default_domain: &str) -> Result<Settings> {
+    read_settings(io::stdin().lock(), io::stderr(), default_domain)
+}
+
+/// Prompts on `output` and reads lines from `input` until one holds a valid domain name.
+fn read_domain(input: impl BufRead, output: impl Write, default: &str) -> Result<String> {
+    let mut questions = Questions { input, output };
+    questions
+        .ask(
+            "Domain to verify TLS handshakes against",
+            default,
+            validate_domain,
+        )
+        .context("No domain was entered, set one with --domain")
+}
+
+/// Prompts on `output` and reads the answers to the `cloudsurf init` questions from `input`.
+fn read_settings(
+    input: impl BufRead,
+    output: impl Write,
+    default_domain: &str,
+) -> Result<Settings> {
+    let mut questions = Questions { input, output };
+
+    let domain = questions.ask(
+        "Domain to verify TLS handshakes against",
+        default_domain,
+        validate_domain,
+    )?;
+    let ports = questions.ask("Ports to try, comma-separated", "443", validate_ports)?;
+    let output = questions.ask(
+        "File to write the results to, or 'none' to only print them",
+        "none",
+        |answer| Ok((answer != "none").then(|| answer.to_string())),
+    )?;
+    let format = match output {
+        Some(_) => questions.ask("Format of the results file", "text", |answer| {
+            OutputFormat::from_str(answer, true).map_err(|_| {
+                format!(
+                    "'{}' is not one of text, json, yaml, cfst-csv or parquet",
+                    answer
+                )
+            })
+        })?,
+        None => OutputFormat::Text,
+    };
+    let schedule_hours = questions.ask(
+        "Hours between scheduled scans, from 1 to 24, or 'none'",
+        "none",
+        validate_schedule,
+    )?;
+
+    Ok(Settings {
+        domain,
+        ports,
+        format,
+        output,
+        schedule_hours,
+    })
+}
+
+/// Questions asked on `output` and answered on `input`.
+struct Questions<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Questions<R, W> {
+    /// Asks `question` until `validate` accepts the answer. An empty answer picks `default`.
+    fn ask<T>(
+        &mut self,
+        question: &str,
+        default: &str,
+        validate: impl Fn(&str) -> Result<T, String>,
+    ) -> Result<T> {
+        loop {
+            write!(self.output, "{} [{}]: ", question, default)?;
+            self.output.flush()?;
+
+            let mut line = String::new();
+            if self
+                .input
+                .read_line(&mut line)
+                .context("Couldn't read the answer")?
+                == 0
+            {
+                anyhow::bail!("No answer was entered to '{}'", question);
+            }
+
+            let answer = match line.trim() {
+                "" => default,
+                answer => answer,
+            };
+            match validate(answer) {
+                Ok(value) => return Ok(value),
+                Err(e) => writeln!(self.output, "{}", e)?,
+            }
+        }
+    }
+}
+
+/// Checks that `domain` is a DNS name a certificate can be verified against.
+fn validate_domain(domain: &str) -> Result<String, String> {
+    match ServerName::try_from(domain.to_string()) {
+        Ok(ServerName::DnsName(_)) => Ok(domain.to_string()),
+        _ => Err(format!("'{}' is not a valid domain name", domain)),
+    }
+}
+
+/// Checks that `ports` is a comma-separated list of port numbers.
+fn validate_ports(ports: &str) -> Result<Vec<u16>, String> {
+    ports
+        .split(',')
+        .map(|port| {
+            port.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port > 0)
+                .ok_or_else(|| format!("'{}' is not a valid port number", port.trim()))
+        })
+        .collect()
+}
+
+/// Checks that `hours` is a number of hours cron can repeat a job at, or `none`.
+fn validate_schedule(hours: &str) -> Result<Option<u32>, String> {
+    if hours == "none" {
+        return Ok(None);
+    }
+    match hours.parse::<u32>() {
+        Ok(hours @ 1..=24) => Ok(Some(hours)),
+        _ => Err(format!(
+            "'{}' is not a number of hours from 1 to 24, or 'none'",
+            hours
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_domain() {
+        let input = io::Cursor::new("104.16.0.1\nexample.com\n");
+        let mut output = Vec::new();
+
+        let domain = read_domain(input, &mut output, "cloudflare.com").unwrap();
+
+        assert_eq!(domain, "example.com");
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("[cloudflare.com]"));
+        assert!(output.contains("'104.16.0.1' is not a valid domain name"));
+    }
+
+    #[test]
+    fn test_read_domain_default() {
+        let input = io::Cursor::new("\n");
+
+        let domain = read_domain(input, io::sink(), "cloudflare.com").unwrap();
+
+        assert_eq!(domain, "cloudflare.com");
+    }
+
+    #[test]
+    fn test_read_domain_closed_input() {
+        let input = io::Cursor::new("not a domain\n");
+
+        assert!(read_domain(input, io::sink(), "cloudflare.com").is_err());
+    }
+
+    #[test]
+    fn test_read_settings() {
+        let input =
+            io::Cursor::new("example.com\n443, 99999\n443,2053\nresult.json\njson\n48\n6\n");
+        let mut output = Vec::new();
+
+        let settings = read_settings(input, &mut output, "cloudflare.com").unwrap();
+
+        assert_eq!(
+            settings,
+            Settings {
+                domain: "example.com".to_string(),
+                ports: vec![443, 2053],
+                format: OutputFormat::Json,
+                output: Some("result.json".to_string()),
+                schedule_hours: Some(6),
+            }
+        );
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("'99999' is not a valid port number"));
+        assert!(output.contains("'48' is not a number of hours"));
+    }
+
+    #[test]
+    fn test_read_settings_defaults() {
+        let input = io::Cursor::new("\n\n\n\n");
+
+        let settings = read_settings(input, io::sink(), "cloudflare.com").unwrap();
+
+        assert_eq!(settings.ports, [443]);
+        assert_eq!(settings.output, None);
+        assert_eq!(settings.schedule_hours, None);
+    }
+}
@@ -0,0 +1,185 @@
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use reqwest::header;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::args::PublishArgs;
+use crate::compression::Compression;
+use crate::file::open_file;
+use crate::version;
+
+/// The GitHub API endpoint creating gists.
+const GIST_URL: &str = "https://api.github.com/gists";
+/// The upload endpoint of 0x0.st.
+const NULL_POINTER_URL: &str = "https://0x0.st";
+/// The boundary between the parts of multipart uploads, which can't occur in a part's header.
+const BOUNDARY: &str = "cloudsurf-publish-boundary";
+
+/// Services the results can be published to.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishTarget {
+    /// A GitHub gist, secret unless `--public` is given. Requires a token with the gist scope.
+    Gist,
+    /// The 0x0.st file host, which needs no account. Files expire after 30 days to a year,
+    /// depending on their size.
+    #[value(name = "0x0")]
+    NullPointer,
+}
+
+/// The part of the GitHub API response to creating a gist used here.
+#[derive(Deserialize)]
+struct Gist {
+    html_url: String,
+}
+
+/// Uploads the results file of `args` and prints its shareable URL.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, if no token is available for a gist, or if the
+/// upload fails.
+pub async fn publish(args: &PublishArgs) -> Result<()> {
+    let mut contents = String::new();
+    open_file(&args.file)?
+        .read_to_string(&mut contents)
+        .with_context(|| format!("Couldn't read file {}", args.file))?;
+    // Compressed files are uploaded decompressed, under their uncompressed name.
+    let name = Path::new(Compression::strip(&args.file))
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("ips.txt");
+
+    let url = match args.to {
+        PublishTarget::Gist => {
+            let token = args
+                .token
+                .as_deref()
+                .context("Publishing a gist requires a GitHub token in GITHUB_TOKEN or --token")?;
+            upload_gist(GIST_URL, token, name, &contents, args.public).await?
+        }
+        PublishTarget::NullPointer => {
+            upload_null_pointer(NULL_POINTER_URL, name, &contents).await?
+        }
+    };
+
+    info!(url = url.as_str(), "Published results");
+    println!("{}", url);
+
+    Ok(())
+}
+
+/// Creates a gist holding `contents` as `name` through the GitHub API at `url`, and returns
+/// its URL.
+async fn upload_gist(
+    url: &str,
+    token: &str,
+    name: &str,
+    contents: &str,
+    public: bool,
+) -> Result<String> {
+    let body = serde_json::json!({
+        "description": "Cloudflare IPs found by cloudsurf",
+        "public": public,
+        "files": { name: { "content": contents } },
+    });
+
+    let gist = reqwest::Client::new()
+        .post(url)
+        .bearer_auth(token)
+        .header(header::ACCEPT, "application/vnd.github+json")
+        .header(header::USER_AGENT, version::short())
+        .json(&body)
+        .send()
+        .await
+        .context("Failed to send request to the GitHub API")?
+        .error_for_status()
+        .context("The GitHub API rejected the gist")?
+        .json::<Gist>()
+        .await
+        .context("Failed to deserialize the GitHub API response")?;
+
+    Ok(gist.html_url)
+}
+
+/// Uploads `contents` as `name` to the 0x0.st instance at `url`, and returns its URL.
+async fn upload_null_pointer(url: &str, name: &str, contents: &str) -> Result<String> {
+    let body = format!(
+        "--{boundary}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+         Content-Type: text/plain\r\n\r\n\
+         {contents}\r\n\
+         --{boundary}--\r\n",
+        boundary = BOUNDARY,
+        name = name.replace('"', ""),
+        contents = contents,
+    );
+
+    let response = reqwest::Client::new()
+        .post(url)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .header(header::USER_AGENT, version::short())
+        .body(body)
+        .send()
+        .await
+        .context("Failed to send request to 0x0.st")?
+        .error_for_status()
+        .context("0x0.st rejected the upload")?
+        .text()
+        .await
+        .context("Failed to read the 0x0.st response")?;
+
+    Ok(response.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_upload_gist() -> Result<()> {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/gists"))
+            .and(header("authorization", "Bearer secret"))
+            .and(body_partial_json(serde_json::json!({
+                "public": false,
+                "files": { "ips.txt": { "content": "104.16.0.1 MCI\n" } },
+            })))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "html_url": "https://gist.github.com/cloudsurf/1",
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let url = format!("{}/gists", mock_server.uri());
+        let gist = upload_gist(&url, "secret", "ips.txt", "104.16.0.1 MCI\n", false).await?;
+        assert_eq!(gist, "https://gist.github.com/cloudsurf/1");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_null_pointer() -> Result<()> {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("filename=\"ips.txt\""))
+            .and(body_string_contains("104.16.0.1 MCI"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("https://0x0.st/abc.txt\n"))
+            .mount(&mock_server)
+            .await;
+
+        let url = upload_null_pointer(&mock_server.uri(), "ips.txt", "104.16.0.1 MCI\n").await?;
+        assert_eq!(url, "https://0x0.st/abc.txt");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,628 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::network::{FragmentHello, ProbeKind, Provider};
+use crate::result::{ScanResult, SortKey};
+use crate::sampling::{IpVersion, ScanOrder, Weighting};
+
+/// Version of the structured report format.
+///
+/// This must be incremented whenever a field is removed or its meaning changes, so that
+/// downstream tooling can detect reports it does not understand.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The structured report written by the `json` and `yaml` output formats.
+///
+/// Its JSON Schema can be printed with `cloudsurf schema`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ScanReport {
+    /// Version of the report format, see `FORMAT_VERSION`.
+    pub format_version: u32,
+    /// Parameters of the scan that produced this report.
+    ///
+    /// Absent for reports produced by `cloudsurf merge`, which combine several scans.
+    pub parameters: Option<ScanParameters>,
+    /// IP addresses that passed the checks, in ranking order, best first.
+    pub results: Vec<ScanResult>,
+    /// Aggregated statistics about the scan.
+    pub summary: ScanSummary,
+    /// Non-fatal problems met during the scan, which may explain missing or unexpected results.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+    /// The latency of each result through the domains of `--matrix`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<DomainMatrix>,
+}
+
+/// A non-fatal problem met during a scan, also logged as it happened.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// What kind of problem this is, for tooling to react to.
+    pub code: WarningCode,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+/// The kinds of non-fatal problems recorded in a report.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WarningCode {
+    /// The domain resolves outside Cloudflare's ranges, so it is likely not proxied.
+    DomainNotProxied,
+    /// Whether the domain is proxied by Cloudflare couldn't be checked.
+    ProxyCheckFailed,
+    /// Cloudflare's API couldn't be reached and cached IP ranges were used.
+    CachedRanges,
+    /// Fewer IPs than requested were sampled, because the ranges hold fewer addresses.
+    CountClamped,
+    /// `--max-valid-ips` was lowered to `--count`, as no more IPs than probed can be valid.
+    MaxValidIpsClamped,
+    /// The concurrency was lowered to fit the open file limit.
+    ConcurrencyLowered,
+    /// The concurrency exceeds the open file limit, so probes may fail.
+    FileLimitExceeded,
+    /// The baseline latency of the domain couldn't be measured.
+    BaselineFailed,
+    /// The `--max-probes` budget ran out, so some connections were skipped.
+    ProbeBudgetExhausted,
+    /// A `--skip-list-url` couldn't be fetched, so its cached copy was used or it was left out.
+    SkipListUnavailable,
+    /// A `--seed-list-url` couldn't be fetched, so its cached copy was used or it was left out.
+    SeedListUnavailable,
+}
+
+impl std::fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = match self {
+            WarningCode::DomainNotProxied => "domain-not-proxied",
+            WarningCode::ProxyCheckFailed => "proxy-check-failed",
+            WarningCode::CachedRanges => "cached-ranges",
+            WarningCode::CountClamped => "count-clamped",
+            WarningCode::MaxValidIpsClamped => "max-valid-ips-clamped",
+            WarningCode::ConcurrencyLowered => "concurrency-lowered",
+            WarningCode::FileLimitExceeded => "file-limit-exceeded",
+            WarningCode::BaselineFailed => "baseline-failed",
+            WarningCode::ProbeBudgetExhausted => "probe-budget-exhausted",
+            WarningCode::SkipListUnavailable => "skip-list-unavailable",
+            WarningCode::SeedListUnavailable => "seed-list-unavailable",
+        };
+        f.write_str(code)
+    }
+}
+
+/// The parameters a scan was run with.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ScanParameters {
+    /// The domain name used for TLS verification.
+    pub domain: String,
+    /// The ports TCP connections were made to, in order of preference.
+    ///
+    /// Reports written before several ports could be given record a single `port` instead.
+    #[serde(alias = "port", deserialize_with = "deserialize_ports")]
+    pub ports: Vec<u16>,
+    /// Whether all ports of an IP were connected to at once.
+    #[serde(default)]
+    pub race_ports: bool,
+    /// The number of IP addresses that were requested to be checked.
+    pub count: usize,
+    /// The share of the address space `count` was derived from, in percent, if given as such.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count_percent: Option<f64>,
+    /// The maximum number of connection attempts of the scan, if capped.
+    pub max_probes: Option<usize>,
+    /// The number of times the sampled IPs were probed, if more than once.
+    pub passes: Option<usize>,
+    /// The maximum number of IPs that were probed at the same time.
+    pub concurrency: Option<usize>,
+    /// Whether the concurrency was lowered while timeouts and resets spiked.
+    #[serde(default)]
+    pub adaptive_concurrency: bool,
+    /// Whether IPs that timed out or were reset were probed once more at the end of the scan.
+    #[serde(default)]
+    pub retry_timeouts: bool,
+    /// The CDNs whose ranges were scanned, when other than Cloudflare alone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub providers: Vec<Provider>,
+    /// The IP address families that were scanned.
+    #[serde(default)]
+    pub ip_version: IpVersion,
+    /// The number of random addresses probed in each IPv6 prefix, if IPv6 was scanned.
+    pub v6_hosts_per_prefix: Option<usize>,
+    /// The factor IPv6 latencies were multiplied by when ranking, if IPv6 was scanned.
+    pub ipv6_bias: Option<f64>,
+    /// How the IP addresses to probe were selected.
+    #[serde(default)]
+    pub order: ScanOrder,
+    /// How a random sample was distributed across the published ranges.
+    #[serde(default)]
+    pub weighting: Weighting,
+    /// The share of the sample drawn from subnets without a good history, if history was used.
+    pub exploration: Option<f64>,
+    /// The maximum number of valid IPs to return, or 0 if all were returned.
+    pub max_valid_ips: usize,
+    /// The number of valid IPs reserved for each address family, if any.
+    pub family_quota: Option<usize>,
+    /// The maximum number of returned IPs from the same subnet, if limited.
+    pub top_per_subnet: Option<usize>,
+    /// IP address prefixes excluded from the scan with `--skip-prefixes`.
+    pub skip_prefixes: Vec<String>,
+    /// The shared skip lists whose prefixes were also excluded from the scan.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_list_urls: Vec<String>,
+    /// The shared lists of candidate IPs probed ahead of the random sample.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub seed_list_urls: Vec<String>,
+    /// The server names probes rotated through instead of the domain, if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub server_names: Vec<String>,
+    /// The server names IPs were retried with when their handshake looked blocked, if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sni_fallback: Vec<String>,
+    /// The check each IP had to pass to be reachable.
+    #[serde(default)]
+    pub probe: ProbeKind,
+    /// How the ClientHello was split up during handshakes, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment_hello: Option<FragmentHello>,
+    /// The key the results were ordered by.
+    #[serde(default)]
+    pub sort_by: SortKey,
+    /// Whether certificates were verified against the system's trust store.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub native_certs: bool,
+    /// Whether Nagle's algorithm was disabled on probe sockets.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tcp_nodelay: bool,
+    /// The idle time before TCP keep-alive probes on probe sockets, in seconds, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_keepalive_secs: Option<u64>,
+    /// The send buffer size of probe sockets in bytes, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_send_buffer: Option<u32>,
+    /// The receive buffer size of probe sockets in bytes, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_recv_buffer: Option<u32>,
+    /// The IP time-to-live of probe packets, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    /// The size of the speed test download in bytes, if the speed test was run.
+    pub speedtest_bytes: Option<u64>,
+    /// The minimum download speed results had to reach, in megabits per second.
+    pub min_speed_mbps: Option<f64>,
+    /// Whether a discarded warm-up handshake preceded each measurement.
+    #[serde(default)]
+    pub warmup: bool,
+    /// The fixed probe timeout in milliseconds, or `None` if it adapted to the observed latencies.
+    pub timeout_ms: Option<u64>,
+    /// The path requested by the HTTP check, if IPs not answered by a Cloudflare edge were dropped.
+    pub http_check: Option<String>,
+    /// The status code the HTTP check response had to have, if any.
+    pub expect_status: Option<u16>,
+    /// Duration of the HTTP/2 keep-alive check per IP in seconds, if the check was run.
+    pub h2_check_duration: Option<u64>,
+    /// The gRPC service called through each IP, if the gRPC check was run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc_service: Option<String>,
+    /// Size of the body posted by the request check in bytes, if the check was run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_bytes: Option<u64>,
+}
+
+/// Aggregated statistics about a scan.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ScanSummary {
+    /// The number of IP addresses that were probed.
+    pub probed: usize,
+    /// The number of IP addresses included in `results`.
+    pub valid: usize,
+    /// The lowest latency among the results, in milliseconds.
+    pub best_latency_ms: Option<u128>,
+    /// The median latency of the results, in milliseconds.
+    pub median_latency_ms: Option<u128>,
+    /// The results grouped by the Cloudflare data center that answered them, fastest first.
+    ///
+    /// Empty when the data centers are unknown, i.e. when the HTTP check was not run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub colos: Vec<ColoSummary>,
+    /// The latency of the domain through the address its DNS records resolve to, if measured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline: Option<Baseline>,
+    /// How fragmenting the ClientHello changed which IPs were reachable, with `--fragment-hello`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragmentation: Option<FragmentComparison>,
+}
+
+/// The handshake latency of the domain over the default route, without a scanned IP.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Baseline {
+    /// The fastest address the domain resolved to.
+    pub ip: IpAddr,
+    /// The time taken to connect and complete the handshake, in milliseconds.
+    pub latency_ms: u128,
+}
+
+/// The number of IPs reachable with and without a fragmented ClientHello.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentComparison {
+    /// IPs that completed a handshake either way.
+    pub both: usize,
+    /// IPs that only completed a handshake with the ClientHello fragmented.
+    pub only_fragmented: usize,
+    /// IPs that only completed a handshake without fragmentation.
+    pub only_unfragmented: usize,
+}
+
+/// Aggregated statistics about the results answered by one Cloudflare data center.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ColoSummary {
+    /// The data center code, e.g. `FRA`.
+    pub colo: String,
+    /// The number of results answered by this data center.
+    pub count: usize,
+    /// The lowest latency among these results, in milliseconds.
+    pub best_latency_ms: u128,
+    /// The median latency of these results, in milliseconds.
+    pub median_latency_ms: u128,
+}
+
+/// Which results also work for other domains, for IPs shared by several proxied services.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct DomainMatrix {
+    /// The scanned domain, followed by the domains of `--matrix`.
+    pub domains: Vec<String>,
+    /// One row per result, those working for the most domains first.
+    pub rows: Vec<MatrixRow>,
+}
+
+/// The latencies of one IP address through each domain of a `DomainMatrix`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MatrixRow {
+    /// The IP address that was probed.
+    pub ip: IpAddr,
+    /// The latency through each domain, in the order of `DomainMatrix::domains`, in milliseconds,
+    /// or `None` if the handshake failed.
+    pub latencies_ms: Vec<Option<u128>>,
+}
+
+impl DomainMatrix {
+    /// Builds the matrix of `results`, found for `domain`, from the results of probing them
+    /// through each of the other domains.
+    pub fn new(domain: &str, results: &[ScanResult], others: &[(String, Vec<ScanResult>)]) -> Self {
+        let mut rows: Vec<_> = results
+            .iter()
+            .map(|result| {
+                let mut latencies_ms = vec![Some(result.latency_ms)];
+                latencies_ms.extend(others.iter().map(|(_, other)| {
+                    other
+                        .iter()
+                        .find(|other| other.ip == result.ip)
+                        .map(|other| other.latency_ms)
+                }));
+                MatrixRow {
+                    ip: result.ip,
+                    latencies_ms,
+                }
+            })
+            .collect();
+        // The sort is stable, so rows working for as many domains keep the order of `results`.
+        rows.sort_by_key(|row| std::cmp::Reverse(row.working()));
+
+        let mut domains = vec![domain.to_string()];
+        domains.extend(others.iter().map(|(domain, _)| domain.clone()));
+
+        DomainMatrix { domains, rows }
+    }
+
+    /// Returns the number of IP addresses that work for every domain.
+    pub fn working_for_all(&self) -> usize {
+        self.rows
+            .iter()
+            .filter(|row| row.working() == self.domains.len())
+            .count()
+    }
+}
+
+impl MatrixRow {
+    /// Returns the number of domains this IP address works for.
+    pub fn working(&self) -> usize {
+        self.latencies_ms.iter().flatten().count()
+    }
+}
+
+impl ScanReport {
+    /// Creates a report for the given results, computing its summary.
+    ///
+    /// # Arguments
+    ///
+    /// * `parameters` - The parameters of the scan, or `None` for merged reports.
+    /// * `results` - The valid IP addresses, in ranking order.
+    /// * `probed` - The number of IP addresses that were probed to obtain `results`.
+    pub fn new(
+        parameters: Option<ScanParameters>,
+        results: Vec<ScanResult>,
+        probed: usize,
+    ) -> Self {
+        let summary = ScanSummary::from_results(&results, probed);
+
+        ScanReport {
+            format_version: FORMAT_VERSION,
+            parameters,
+            results,
+            summary,
+            warnings: Vec::new(),
+            matrix: None,
+        }
+    }
+}
+
+impl ScanSummary {
+    /// Computes the summary of `results`, in whatever order they were ranked.
+    fn from_results(results: &[ScanResult], probed: usize) -> Self {
+        let mut latencies: Vec<u128> = results.iter().map(|result| result.latency_ms).collect();
+        latencies.sort_unstable();
+
+        ScanSummary {
+            probed,
+            valid: results.len(),
+            best_latency_ms: latencies.first().copied(),
+            median_latency_ms: latencies.get(latencies.len() / 2).copied(),
+            colos: colo_summaries(results),
+            baseline: None,
+            fragmentation: None,
+        }
+    }
+}
+
+/// Groups `results` by data center, sorted by best and then median latency.
+///
+/// Results without a known data center are left out.
+pub fn colo_summaries(results: &[ScanResult]) -> Vec<ColoSummary> {
+    let mut latencies: BTreeMap<&str, Vec<u128>> = BTreeMap::new();
+    for result in results {
+        if let Some(colo) = result.colo() {
+            latencies.entry(colo).or_default().push(result.latency_ms);
+        }
+    }
+
+    let mut summaries: Vec<_> = latencies
+        .into_iter()
+        .map(|(colo, mut latencies)| {
+            latencies.sort_unstable();
+            ColoSummary {
+                colo: colo.to_string(),
+                count: latencies.len(),
+                best_latency_ms: latencies[0],
+                median_latency_ms: latencies[latencies.len() / 2],
+            }
+        })
+        .collect();
+    summaries.sort_by_key(|summary| (summary.best_latency_ms, summary.median_latency_ms));
+
+    summaries
+}
+
+/// Deserializes `ScanParameters::ports`, accepting the single port of older reports as well.
+fn deserialize_ports<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u16>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Ports {
+        One(u16),
+        Many(Vec<u16>),
+    }
+
+    Ok(match Ports::deserialize(deserializer)? {
+        Ports::One(port) => vec![port],
+        Ports::Many(ports) => ports,
+    })
+}
+
+/// Returns the JSON Schema describing `ScanReport`, pretty-printed.
+pub fn json_schema() -> String {
+    let schema = schemars::schema_for!(ScanReport);
+    serde_json::to_string_pretty(&schema).expect("JSON Schema is always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_summary() {
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 2), 70),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 3), 90),
+        ];
+
+        let report = ScanReport::new(None, results, 10);
+
+        assert_eq!(report.format_version, FORMAT_VERSION);
+        assert_eq!(report.summary.probed, 10);
+        assert_eq!(report.summary.valid, 3);
+        assert_eq!(report.summary.best_latency_ms, Some(50));
+        assert_eq!(report.summary.median_latency_ms, Some(70));
+    }
+
+    #[test]
+    fn test_summary_of_unsorted_results() {
+        // Ranked by reliability or passes rather than by latency.
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 90),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 2), 20),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 3), 200),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 4), 40),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 5), 60),
+        ];
+
+        let report = ScanReport::new(None, results, 5);
+
+        assert_eq!(report.summary.best_latency_ms, Some(20));
+        assert_eq!(report.summary.median_latency_ms, Some(60));
+        assert_eq!(report.results[0].latency_ms, 90);
+    }
+
+    #[test]
+    fn test_domain_matrix() {
+        let result =
+            |host, latency_ms| ScanResult::new(Ipv4Addr::new(104, 16, 0, host), latency_ms);
+        let results = vec![result(1, 50), result(2, 60), result(3, 70)];
+        let others = vec![
+            (
+                "a.example.com".to_string(),
+                vec![result(2, 65), result(3, 80)],
+            ),
+            (
+                "b.example.com".to_string(),
+                vec![result(3, 75), result(1, 55)],
+            ),
+        ];
+
+        let matrix = DomainMatrix::new("example.com", &results, &others);
+
+        assert_eq!(
+            matrix.domains,
+            ["example.com", "a.example.com", "b.example.com"]
+        );
+        assert_eq!(
+            matrix.rows,
+            vec![
+                MatrixRow {
+                    ip: Ipv4Addr::new(104, 16, 0, 3).into(),
+                    latencies_ms: vec![Some(70), Some(80), Some(75)],
+                },
+                MatrixRow {
+                    ip: Ipv4Addr::new(104, 16, 0, 1).into(),
+                    latencies_ms: vec![Some(50), None, Some(55)],
+                },
+                MatrixRow {
+                    ip: Ipv4Addr::new(104, 16, 0, 2).into(),
+                    latencies_ms: vec![Some(60), Some(65), None],
+                },
+            ]
+        );
+        assert_eq!(matrix.working_for_all(), 1);
+    }
+
+    #[test]
+    fn test_colo_summaries() {
+        let result = |host, latency_ms, colo: Option<&str>| ScanResult {
+            ray_id: colo.map(|colo| format!("8a1b2c3d4e5f6789-{}", colo)),
+            ..ScanResult::new(Ipv4Addr::new(104, 16, 0, host), latency_ms)
+        };
+        let results = vec![
+            result(1, 50, Some("FRA")),
+            result(2, 60, Some("AMS")),
+            result(3, 70, Some("FRA")),
+            result(4, 80, None),
+            result(5, 90, Some("FRA")),
+        ];
+
+        let summaries = colo_summaries(&results);
+
+        assert_eq!(
+            summaries,
+            vec![
+                ColoSummary {
+                    colo: "FRA".to_string(),
+                    count: 3,
+                    best_latency_ms: 50,
+                    median_latency_ms: 70,
+                },
+                ColoSummary {
+                    colo: "AMS".to_string(),
+                    count: 1,
+                    best_latency_ms: 60,
+                    median_latency_ms: 60,
+                },
+            ]
+        );
+        assert!(colo_summaries(&results[3..4]).is_empty());
+    }
+
+    #[test]
+    fn test_summary_without_results() {
+        let report = ScanReport::new(None, Vec::new(), 10);
+
+        assert_eq!(report.summary.valid, 0);
+        assert_eq!(report.summary.best_latency_ms, None);
+        assert_eq!(report.summary.median_latency_ms, None);
+    }
+
+    #[test]
+    fn test_read_first_format_version() {
+        // A report as written when `FORMAT_VERSION` was introduced.
+        let json = r#"{
+            "format_version": 1,
+            "parameters": {
+                "domain": "example.com",
+                "port": 443,
+                "count": 100,
+                "max_valid_ips": 10,
+                "top_per_subnet": null,
+                "skip_prefixes": []
+            },
+            "results": [{"ip": "104.16.0.1", "latency_ms": 50}],
+            "summary": {"probed": 100, "valid": 1, "best_latency_ms": 50, "median_latency_ms": 50}
+        }"#;
+
+        let report: ScanReport = serde_json::from_str(json).unwrap();
+
+        let parameters = report.parameters.unwrap();
+        assert_eq!(parameters.ports, [443]);
+        assert!(!parameters.race_ports);
+        assert_eq!(parameters.order, ScanOrder::default());
+        assert_eq!(parameters.weighting, Weighting::default());
+        assert!(!parameters.warmup);
+        assert_eq!(
+            report.results,
+            [ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50)]
+        );
+
+        // Reports written since then list every port.
+        let json = serde_json::to_string(&ScanParameters {
+            ports: vec![443, 2053],
+            ..parameters
+        })
+        .unwrap();
+        let parameters: ScanParameters = serde_json::from_str(&json).unwrap();
+        assert_eq!(parameters.ports, [443, 2053]);
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&json_schema()).unwrap();
+        let properties = &schema["properties"];
+
+        for field in [
+            "format_version",
+            "parameters",
+            "results",
+            "summary",
+            "warnings",
+        ] {
+            assert!(properties.get(field).is_some(), "Missing field {}", field);
+        }
+    }
+
+    #[test]
+    fn test_warnings_serialization() {
+        let mut report = ScanReport::new(None, Vec::new(), 10);
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json.get("warnings").is_none());
+
+        report.warnings.push(Warning {
+            code: WarningCode::CachedRanges,
+            message: "Cloudflare's API is unreachable".to_string(),
+        });
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["warnings"][0]["code"], "cached-ranges");
+        assert_eq!(
+            WarningCode::CachedRanges.to_string(),
+            json["warnings"][0]["code"]
+        );
+    }
+}
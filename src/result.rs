@@ -0,0 +1,287 @@
+use std::cmp::Ordering;
+use std::net::IpAddr;
+
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::network::Provider;
+
+/// Represents a single IP address that successfully completed a TLS handshake.
+///
+/// This is the unit of data shared between the scanner, the table printer and the
+/// file writers, and it is also the record stored in structured reports so that
+/// separate runs can later be combined with `cloudsurf merge`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct ScanResult {
+    /// The IP address that was probed.
+    pub ip: IpAddr,
+    /// Time taken to establish the TCP connection and complete the TLS handshake, in milliseconds.
+    pub latency_ms: u128,
+    /// Moving average of the latencies measured for this IP over the runs recorded in the history,
+    /// in milliseconds, including this one.
+    ///
+    /// Absent when no history was used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smoothed_latency_ms: Option<u128>,
+    /// The port the TLS handshake completed on.
+    ///
+    /// Absent in reports written before several ports could be probed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Time taken to establish the TCP connection, in milliseconds.
+    ///
+    /// Absent in reports written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_ms: Option<u128>,
+    /// Time taken to complete the TLS handshake once connected, in milliseconds.
+    ///
+    /// Absent in reports written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_ms: Option<u128>,
+    /// Download throughput measured by the speed test, in megabits per second.
+    ///
+    /// Absent when the speed test was not run or failed for this IP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed_mbps: Option<f64>,
+    /// Time taken by the DNS-over-HTTPS query once connected, in milliseconds.
+    ///
+    /// Absent unless IPs were probed with `--probe doh`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_ms: Option<u128>,
+    /// Round trip of a small `POST` request through this IP, in milliseconds.
+    ///
+    /// Absent when the request check was not run or failed for this IP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_ms: Option<u128>,
+    /// The `cf-ray` header of the response received through this IP by the HTTP check.
+    ///
+    /// Absent when the HTTP check was not run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ray_id: Option<String>,
+    /// Outcome of the HTTP/2 keep-alive check.
+    ///
+    /// Absent when the check was not run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub h2: Option<H2CheckResult>,
+    /// Outcome of the gRPC check.
+    ///
+    /// Absent when the check was not run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<GrpcCheckResult>,
+    /// The number of probe passes this IP was reachable in, with `--passes`.
+    ///
+    /// Absent when a single pass was run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passes: Option<usize>,
+    /// The share of the scans recorded in the `--history` this IP was valid in, as a percentage.
+    ///
+    /// Absent without a history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reliability_pct: Option<f64>,
+    /// The number of recorded scans that probed this IP, which `reliability_pct` is out of.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runs_seen: Option<u64>,
+    /// The server name presented in the handshake, when rotating through `--sni-file`.
+    ///
+    /// Absent when the scanned domain was presented.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sni: Option<String>,
+    /// Whether the handshake with the scanned name was cut, as SNI filtering does, and `sni` is
+    /// the `--sni-fallback` name recommended for this IP instead.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sni_fallback: bool,
+    /// The handshake through this IP without `--fragment-hello`, to compare with.
+    ///
+    /// Absent when the ClientHello was not fragmented.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment: Option<FragmentDifference>,
+    /// The CDN whose published ranges this IP belongs to.
+    ///
+    /// Absent when only Cloudflare's ranges were scanned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+    /// Whether this IP came from a `--seed-list-url` list rather than random sampling.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub seeded: bool,
+}
+
+/// The key results are ordered by.
+#[derive(
+    ValueEnum, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// The ranking latency, see `ScanResult::ranking_latency`.
+    #[default]
+    Latency,
+    /// The reliability recorded in the history, most reliable first, then the number of scans it
+    /// is out of and the ranking latency.
+    Reliability,
+}
+
+/// Outcome of the HTTP/2 keep-alive check for a single IP.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Eq)]
+pub struct H2CheckResult {
+    /// Number of requests that completed successfully.
+    pub completed: u32,
+    /// Number of requests that failed with a stream error or timed out.
+    pub stream_errors: u32,
+    /// Whether the server sent a GOAWAY frame before the check finished.
+    pub goaway: bool,
+    /// Error that prevented the HTTP/2 connection from being established.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl H2CheckResult {
+    /// Whether the connection was sustained for the whole check without any error.
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.stream_errors == 0 && !self.goaway
+    }
+}
+
+/// How an IP probed with a fragmented ClientHello fared without fragmentation.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FragmentDifference {
+    /// The latency without fragmentation, in milliseconds, or `None` if the handshake only
+    /// completed with the ClientHello fragmented.
+    pub unfragmented_ms: Option<u128>,
+}
+
+/// Outcome of the gRPC check for a single IP.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GrpcCheckResult {
+    /// Time from sending the call until the gRPC response headers arrived, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u128>,
+    /// The `grpc-status` the server ended the call with, if it did before the check gave up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u32>,
+    /// Error that prevented a gRPC response from being received.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl GrpcCheckResult {
+    /// Whether a gRPC response was received through the IP.
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl ScanResult {
+    /// Creates a result with the given latency and no further measurements.
+    pub fn new(ip: impl Into<IpAddr>, latency_ms: u128) -> Self {
+        ScanResult {
+            ip: ip.into(),
+            latency_ms,
+            smoothed_latency_ms: None,
+            port: None,
+            tcp_ms: None,
+            tls_ms: None,
+            speed_mbps: None,
+            dns_ms: None,
+            request_ms: None,
+            ray_id: None,
+            h2: None,
+            grpc: None,
+            passes: None,
+            reliability_pct: None,
+            runs_seen: None,
+            sni: None,
+            sni_fallback: false,
+            fragment: None,
+            provider: None,
+            seeded: false,
+        }
+    }
+
+    /// The latency results are ranked by, in milliseconds.
+    ///
+    /// This is the smoothed latency when known, multiplied by `ipv6_bias` for IPv6 addresses so
+    /// that one family can be favored when both are scanned.
+    pub fn ranking_latency(&self, ipv6_bias: f64) -> f64 {
+        let latency = self.smoothed_latency_ms.unwrap_or(self.latency_ms) as f64;
+        if self.ip.is_ipv6() {
+            latency * ipv6_bias
+        } else {
+            latency
+        }
+    }
+
+    /// The Cloudflare data center that answered the HTTP check, e.g. `FRA`.
+    ///
+    /// It is the suffix of the ray ID, so it is only known when the HTTP check was run.
+    pub fn colo(&self) -> Option<&str> {
+        self.ray_id
+            .as_deref()
+            .and_then(|ray_id| ray_id.rsplit_once('-'))
+            .map(|(_, colo)| colo)
+    }
+}
+
+impl SortKey {
+    /// Compares two results, the one to list first being the lesser.
+    pub fn compare(self, a: &ScanResult, b: &ScanResult, ipv6_bias: f64) -> Ordering {
+        let latency = || {
+            a.ranking_latency(ipv6_bias)
+                .total_cmp(&b.ranking_latency(ipv6_bias))
+        };
+        match self {
+            SortKey::Latency => latency(),
+            SortKey::Reliability => {
+                let reliability = |result: &ScanResult| result.reliability_pct.unwrap_or(0.0);
+                reliability(b)
+                    .total_cmp(&reliability(a))
+                    .then_with(|| b.runs_seen.cmp(&a.runs_seen))
+                    .then_with(latency)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_colo() {
+        let mut result = ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50);
+        assert_eq!(result.colo(), None);
+
+        result.ray_id = Some("8a1b2c3d4e5f6789-FRA".to_string());
+        assert_eq!(result.colo(), Some("FRA"));
+    }
+
+    #[test]
+    fn test_ranking_latency() {
+        let mut v4 = ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50);
+        let v6 = ScanResult::new("2606:4700::1".parse::<IpAddr>().unwrap(), 60);
+
+        assert_eq!(v4.ranking_latency(0.5), 50.0);
+        assert_eq!(v6.ranking_latency(0.5), 30.0);
+
+        v4.smoothed_latency_ms = Some(40);
+        assert_eq!(v4.ranking_latency(1.0), 40.0);
+    }
+
+    #[test]
+    fn test_h2_check_passed() {
+        let mut outcome = H2CheckResult {
+            completed: 40,
+            ..Default::default()
+        };
+        assert!(outcome.passed());
+
+        outcome.goaway = true;
+        assert!(!outcome.passed());
+
+        let outcome = H2CheckResult {
+            error: Some("The server did not negotiate HTTP/2".to_string()),
+            ..Default::default()
+        };
+        assert!(!outcome.passed());
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use crate::compression::Compression;
+
+/// Length of the timestamp in rotated file names, such as `20240501T120000`.
+///
+/// The timestamp is the basic ISO 8601 format, without the `:` that Windows doesn't allow in file
+/// names.
+const TIMESTAMP_LEN: usize = "20240501T120000".len();
+
+/// Returns the path results are written to when rotating `path`, such as
+/// `ips-20240501T120000.txt` for `ips.txt`.
+pub fn rotated_path(path: &str, now: SystemTime) -> PathBuf {
+    let path = Path::new(path);
+    let timestamp: String = humantime::format_rfc3339_seconds(now)
+        .to_string()
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':'))
+        .take(TIMESTAMP_LEN)
+        .collect();
+    let (stem, extension) = split_name(path);
+
+    path.with_file_name(format!("{}-{}{}", stem, timestamp, extension))
+}
+
+/// Points `path` to the just written `rotated` file, and deletes the oldest rotated files of
+/// `path` so that only `keep` remain.
+///
+/// On Unix, `path` becomes a symbolic link; elsewhere, it is a copy of `rotated`.
+///
+/// # Errors
+///
+/// Returns an error if `path` cannot be replaced, or if the directory cannot be read.
+pub fn finish(path: &str, rotated: &Path, keep: usize) -> Result<()> {
+    let link = Path::new(path);
+    link_latest(link, rotated)
+        .with_context(|| format!("Couldn't point {} to {}", path, rotated.display()))?;
+
+    let mut rotated_files = rotated_files(link)?;
+    rotated_files.sort();
+    let excess = rotated_files.len().saturating_sub(keep);
+    for old in &rotated_files[..excess] {
+        fs::remove_file(old).with_context(|| format!("Couldn't delete {}", old.display()))?;
+        info!(path = %old.display(), "Deleted old results");
+    }
+
+    Ok(())
+}
+
+/// Replaces `link` with a link to `target`, through a temporary file so that readers never see
+/// `link` missing.
+fn link_latest(link: &Path, target: &Path) -> io::Result<()> {
+    let temporary = link.with_file_name(format!(
+        ".{}.tmp",
+        link.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let _ = fs::remove_file(&temporary);
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target.file_name().unwrap_or_default(), &temporary)?;
+    #[cfg(not(unix))]
+    fs::copy(target, &temporary)?;
+
+    fs::rename(&temporary, link)
+}
+
+/// Returns the rotated files of `path` in its directory, in no particular order.
+fn rotated_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let (stem, extension) = split_name(path);
+    let prefix = format!("{}-", stem);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Couldn't read {}", dir.display()))? {
+        let entry = entry.with_context(|| format!("Couldn't read {}", dir.display()))?;
+        let name = entry.file_name();
+        let Some(timestamp) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|name| name.strip_suffix(&extension))
+        else {
+            continue;
+        };
+        if is_timestamp(timestamp) {
+            files.push(entry.path());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Splits the file name of `path` into its stem and its extension, including the dot.
+///
+/// The extension of compressed files includes the one before it, so that `ips.txt.gz` rotates
+/// to `ips-20240501T120000.txt.gz`.
+fn split_name(path: &Path) -> (String, String) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let uncompressed = Path::new(Compression::strip(&name));
+    let stem = uncompressed
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let extension = name[stem.len()..].to_string();
+
+    (stem, extension)
+}
+
+/// Returns whether `value` is a timestamp of rotated file names, such as `20240501T120000`.
+fn is_timestamp(value: &str) -> bool {
+    value.len() == TIMESTAMP_LEN
+        && value.char_indices().all(|(i, c)| match i {
+            8 => c == 'T',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_rotated_path() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+
+        assert_eq!(
+            rotated_path("out/ips.txt", now),
+            Path::new("out/ips-20240501T120000.txt")
+        );
+        assert_eq!(rotated_path("ips", now), Path::new("ips-20240501T120000"));
+        assert_eq!(
+            rotated_path("ips.txt.gz", now),
+            Path::new("ips-20240501T120000.txt.gz")
+        );
+    }
+
+    #[test]
+    fn test_finish_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("cloudsurf-rotate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ips.txt");
+        let path = path.to_str().unwrap();
+        let hour = Duration::from_secs(3_600);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+
+        let mut latest = PathBuf::new();
+        for i in 0..4 {
+            latest = rotated_path(path, start + hour * i);
+            fs::write(&latest, i.to_string()).unwrap();
+            finish(path, &latest, 2).unwrap();
+        }
+        fs::write(dir.join("ips-notes.txt"), "").unwrap();
+        finish(path, &latest, 2).unwrap();
+
+        assert_eq!(fs::read_to_string(path).unwrap(), "3");
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "ips-20240501T140000.txt",
+                "ips-20240501T150000.txt",
+                "ips-notes.txt",
+                "ips.txt"
+            ]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_timestamp() {
+        assert!(is_timestamp("20240501T120000"));
+        assert!(!is_timestamp("20240501"));
+        assert!(!is_timestamp("2024-05-01T12:00"));
+    }
+}
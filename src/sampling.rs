@@ -0,0 +1,463 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use clap::ValueEnum;
+use ipnetwork::Ipv6Network;
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::history::subnet_of;
+
+/// The IP address families that are scanned.
+#[derive(
+    ValueEnum, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    /// Only IPv4 addresses.
+    #[default]
+    #[value(name = "4")]
+    V4,
+    /// Only IPv6 addresses.
+    #[value(name = "6")]
+    V6,
+    /// IPv4 and IPv6 addresses, interleaved.
+    Both,
+}
+
+impl IpVersion {
+    /// Whether IPv4 addresses are scanned.
+    pub fn includes_v4(self) -> bool {
+        self != IpVersion::V6
+    }
+
+    /// Whether IPv6 addresses are scanned.
+    pub fn includes_v6(self) -> bool {
+        self != IpVersion::V4
+    }
+}
+
+/// Determines which IP addresses are selected for probing, and in which order.
+#[derive(
+    ValueEnum, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanOrder {
+    /// Select a random sample of the IP addresses.
+    #[default]
+    Random,
+    /// Walk the IP addresses in order, range by range.
+    Sequential,
+    /// Walk the /24 subnets round-robin, taking one IP address from each in turn.
+    Interleaved,
+}
+
+/// Determines how a random sample is distributed across the published ranges.
+#[derive(
+    ValueEnum, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Weighting {
+    /// Every IP address is equally likely to be selected, so large ranges get most of the sample.
+    #[default]
+    Proportional,
+    /// Every range gets the same share of the sample, whatever its size.
+    UniformPerSubnet,
+}
+
+/// The number of IPv4 addresses to select, either absolute or as a share of the filtered ranges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleSize {
+    /// A fixed number of IP addresses.
+    Count(usize),
+    /// A percentage of all IP addresses left after filtering, e.g. `0.5` for 0.5%.
+    Percent(f64),
+}
+
+impl SampleSize {
+    /// Returns the number of IP addresses to select from `ranges`.
+    ///
+    /// A percentage is rounded up, so that a non-empty address space yields at least one IP.
+    pub fn resolve(self, ranges: &[Vec<Ipv4Addr>]) -> usize {
+        match self {
+            SampleSize::Count(count) => count,
+            SampleSize::Percent(percent) => {
+                let total: usize = ranges.iter().map(Vec::len).sum();
+                (total as f64 * percent / 100.0).ceil() as usize
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SampleSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SampleSize::Count(count) => write!(f, "{}", count),
+            SampleSize::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// Selects up to `count` IP addresses from `ranges` according to `order`.
+///
+/// The `sequential` and `interleaved` orders are deterministic, which makes full scans and
+/// resumed scans reproducible; `interleaved` additionally spreads a partial scan evenly across
+/// subnets instead of exhausting the first ranges. `weighting` only applies to the `random` order.
+///
+/// # Arguments
+///
+/// * `ranges` - The candidate IP addresses, grouped by the range they were published in.
+/// * `count` - The maximum number of IP addresses to select.
+/// * `order` - How to select them.
+/// * `weighting` - How a random sample is distributed across the ranges.
+pub fn select(
+    ranges: &[Vec<Ipv4Addr>],
+    count: usize,
+    order: ScanOrder,
+    weighting: Weighting,
+) -> Vec<Ipv4Addr> {
+    let ips = ranges.iter().flatten().copied();
+
+    match (order, weighting) {
+        (ScanOrder::Random, Weighting::Proportional) => {
+            ips.choose_multiple(&mut rand::thread_rng(), count)
+        }
+        (ScanOrder::Random, Weighting::UniformPerSubnet) => {
+            let sizes: Vec<_> = ranges.iter().map(Vec::len).collect();
+            ranges
+                .iter()
+                .zip(allocate_uniformly(&sizes, count))
+                .flat_map(|(range, quota)| {
+                    range
+                        .choose_multiple(&mut rand::thread_rng(), quota)
+                        .copied()
+                })
+                .collect()
+        }
+        (ScanOrder::Sequential, _) => ips.take(count).collect(),
+        (ScanOrder::Interleaved, _) => interleave_subnets(&ips.collect::<Vec<_>>())
+            .into_iter()
+            .take(count)
+            .collect(),
+    }
+}
+
+/// Selects up to `count` IP addresses, favoring the /24 subnets in `good_subnets`.
+///
+/// A share `1 - exploration` of the selection is drawn at random from the favored subnets, and
+/// the rest from the other subnets according to `order` and `weighting`, so that new ranges keep
+/// being explored. When fewer favored addresses are available, the other subnets make up for them.
+///
+/// # Arguments
+///
+/// * `ranges` - The candidate IP addresses, grouped by the range they were published in.
+/// * `count` - The maximum number of IP addresses to select.
+/// * `order` - How to select the exploration share.
+/// * `weighting` - How a random exploration share is distributed across the ranges.
+/// * `good_subnets` - The network addresses of the subnets to favor, see `subnet_of`.
+/// * `exploration` - The share of the selection drawn from the other subnets, between 0 and 1.
+pub fn select_prioritized(
+    ranges: &[Vec<Ipv4Addr>],
+    count: usize,
+    order: ScanOrder,
+    weighting: Weighting,
+    good_subnets: &HashSet<IpAddr>,
+    exploration: f64,
+) -> Vec<Ipv4Addr> {
+    let is_good = |ip: &Ipv4Addr| good_subnets.contains(&subnet_of(IpAddr::V4(*ip)));
+
+    let favored: Vec<Ipv4Addr> = ranges.iter().flatten().copied().filter(is_good).collect();
+    let others: Vec<Vec<Ipv4Addr>> = ranges
+        .iter()
+        .map(|range| range.iter().copied().filter(|ip| !is_good(ip)).collect())
+        .filter(|range: &Vec<Ipv4Addr>| !range.is_empty())
+        .collect();
+
+    let exploited = (count as f64 * (1.0 - exploration)).round() as usize;
+    let mut selected: Vec<Ipv4Addr> = favored
+        .choose_multiple(&mut rand::thread_rng(), exploited)
+        .copied()
+        .collect();
+    selected.extend(select(&others, count - selected.len(), order, weighting));
+
+    selected
+}
+
+/// Generates `hosts_per_prefix` random IPv6 addresses in each of `prefixes`.
+///
+/// IPv6 prefixes such as Cloudflare's /32 and /48 are far too large to be enumerated like IPv4
+/// ranges, so the bits after the prefix, including the interface identifier, are drawn at random
+/// instead. The prefixes are visited round-robin, so that a truncated list still covers all of
+/// them evenly.
+pub fn sample_ipv6(prefixes: &[Ipv6Network], hosts_per_prefix: usize) -> Vec<Ipv6Addr> {
+    let mut rng = rand::thread_rng();
+    let mut hosts = Vec::with_capacity(prefixes.len() * hosts_per_prefix);
+
+    for _ in 0..hosts_per_prefix {
+        for prefix in prefixes {
+            let host_mask = u128::MAX
+                .checked_shr(u32::from(prefix.prefix()))
+                .unwrap_or(0);
+            let network = u128::from(prefix.network());
+            hosts.push(Ipv6Addr::from(network | (rng.gen::<u128>() & host_mask)));
+        }
+    }
+
+    hosts
+}
+
+/// Alternates between the addresses of `v4` and `v6`, so that a scan of both families probes
+/// them evenly. The remaining addresses of the longer list come last.
+pub fn interleave_families(v4: Vec<Ipv4Addr>, v6: Vec<Ipv6Addr>) -> Vec<IpAddr> {
+    let mut v4 = v4.into_iter().map(IpAddr::V4);
+    let mut v6 = v6.into_iter().map(IpAddr::V6);
+    let mut interleaved = Vec::with_capacity(v4.len() + v6.len());
+
+    loop {
+        match (v4.next(), v6.next()) {
+            (None, None) => break,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+
+    interleaved
+}
+
+/// Puts the `seeds` ahead of the `sampled` addresses, so that they are probed first, leaving out
+/// the sampled addresses that are also seeds.
+pub fn prepend_seeds(seeds: &[IpAddr], sampled: Vec<IpAddr>) -> Vec<IpAddr> {
+    let seeded: HashSet<_> = seeds.iter().collect();
+    let mut targets = seeds.to_vec();
+    targets.extend(sampled.into_iter().filter(|ip| !seeded.contains(ip)));
+
+    targets
+}
+
+/// Splits `count` selections evenly among ranges of the given `sizes`.
+///
+/// A range never gets more selections than it has IP addresses; what it cannot take is shared
+/// among the others.
+fn allocate_uniformly(sizes: &[usize], count: usize) -> Vec<usize> {
+    let mut quotas = vec![0; sizes.len()];
+    let mut remaining = count.min(sizes.iter().sum());
+
+    while remaining > 0 {
+        let open: Vec<usize> = (0..sizes.len())
+            .filter(|&index| quotas[index] < sizes[index])
+            .collect();
+        let share = (remaining / open.len()).max(1);
+
+        for index in open {
+            let added = share.min(sizes[index] - quotas[index]).min(remaining);
+            quotas[index] += added;
+            remaining -= added;
+        }
+    }
+
+    quotas
+}
+
+/// Reorders `ips` so that consecutive addresses come from different /24 subnets.
+///
+/// Subnets are visited in the order they first appear, and addresses within a subnet keep
+/// their relative order.
+fn interleave_subnets(ips: &[Ipv4Addr]) -> Vec<Ipv4Addr> {
+    let mut subnet_index: HashMap<[u8; 3], usize> = HashMap::new();
+    let mut subnets: Vec<Vec<Ipv4Addr>> = Vec::new();
+
+    for &ip in ips {
+        let [a, b, c, _] = ip.octets();
+        let index = *subnet_index.entry([a, b, c]).or_insert_with(|| {
+            subnets.push(Vec::new());
+            subnets.len() - 1
+        });
+        subnets[index].push(ip);
+    }
+
+    let mut subnets: Vec<_> = subnets.into_iter().map(Vec::into_iter).collect();
+    let mut interleaved = Vec::with_capacity(ips.len());
+    while interleaved.len() < ips.len() {
+        interleaved.extend(subnets.iter_mut().filter_map(Iterator::next));
+    }
+
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges() -> Vec<Vec<Ipv4Addr>> {
+        vec![
+            vec![
+                Ipv4Addr::new(104, 16, 0, 1),
+                Ipv4Addr::new(104, 16, 0, 2),
+                Ipv4Addr::new(104, 16, 0, 3),
+                Ipv4Addr::new(104, 16, 1, 1),
+            ],
+            vec![Ipv4Addr::new(172, 64, 0, 1), Ipv4Addr::new(172, 64, 0, 2)],
+        ]
+    }
+
+    fn ips() -> Vec<Ipv4Addr> {
+        ranges().concat()
+    }
+
+    #[test]
+    fn test_select_sequential() {
+        let selected = select(&ranges(), 2, ScanOrder::Sequential, Weighting::Proportional);
+
+        assert_eq!(selected, ips()[..2].to_vec());
+    }
+
+    #[test]
+    fn test_select_interleaved() {
+        let selected = select(
+            &ranges(),
+            6,
+            ScanOrder::Interleaved,
+            Weighting::Proportional,
+        );
+
+        assert_eq!(
+            selected,
+            vec![
+                Ipv4Addr::new(104, 16, 0, 1),
+                Ipv4Addr::new(104, 16, 1, 1),
+                Ipv4Addr::new(172, 64, 0, 1),
+                Ipv4Addr::new(104, 16, 0, 2),
+                Ipv4Addr::new(172, 64, 0, 2),
+                Ipv4Addr::new(104, 16, 0, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_random() {
+        let selected = select(&ranges(), 4, ScanOrder::Random, Weighting::Proportional);
+
+        assert_eq!(selected.len(), 4);
+        assert!(selected.iter().all(|ip| ips().contains(ip)));
+    }
+
+    #[test]
+    fn test_select_uniform_per_subnet() {
+        let selected = select(&ranges(), 4, ScanOrder::Random, Weighting::UniformPerSubnet);
+
+        let from_second_range = selected
+            .iter()
+            .filter(|ip| ranges()[1].contains(ip))
+            .count();
+        assert_eq!(selected.len(), 4);
+        assert_eq!(from_second_range, 2);
+    }
+
+    #[test]
+    fn test_select_prioritized() {
+        let good_subnets = HashSet::from([IpAddr::V4(Ipv4Addr::new(172, 64, 0, 0))]);
+
+        let selected = select_prioritized(
+            &ranges(),
+            4,
+            ScanOrder::Sequential,
+            Weighting::Proportional,
+            &good_subnets,
+            0.5,
+        );
+
+        assert_eq!(selected.len(), 4);
+        assert!(selected.contains(&Ipv4Addr::new(172, 64, 0, 1)));
+        assert!(selected.contains(&Ipv4Addr::new(172, 64, 0, 2)));
+        assert!(selected.contains(&Ipv4Addr::new(104, 16, 0, 1)));
+        assert!(selected.contains(&Ipv4Addr::new(104, 16, 0, 2)));
+    }
+
+    #[test]
+    fn test_select_prioritized_without_history() {
+        let selected = select_prioritized(
+            &ranges(),
+            3,
+            ScanOrder::Sequential,
+            Weighting::Proportional,
+            &HashSet::new(),
+            0.2,
+        );
+
+        assert_eq!(selected, ips()[..3].to_vec());
+    }
+
+    #[test]
+    fn test_allocate_uniformly() {
+        assert_eq!(allocate_uniformly(&[1000, 4], 10), vec![6, 4]);
+        assert_eq!(allocate_uniformly(&[3, 3, 3], 4), vec![2, 1, 1]);
+        assert_eq!(allocate_uniformly(&[2, 1], 10), vec![2, 1]);
+        assert!(allocate_uniformly(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn test_sample_ipv6() {
+        let prefixes: Vec<Ipv6Network> = vec![
+            "2606:4700::/32".parse().unwrap(),
+            "2400:cb00:2049::/48".parse().unwrap(),
+        ];
+
+        let hosts = sample_ipv6(&prefixes, 3);
+
+        assert_eq!(hosts.len(), 6);
+        for (index, host) in hosts.iter().enumerate() {
+            assert!(
+                prefixes[index % 2].contains(*host),
+                "{} outside its prefix",
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let v4 = vec![Ipv4Addr::new(104, 16, 0, 1), Ipv4Addr::new(104, 16, 0, 2)];
+        let v6 = vec!["2606:4700::1".parse().unwrap()];
+
+        let interleaved = interleave_families(v4, v6);
+
+        assert_eq!(
+            interleaved,
+            vec![
+                "104.16.0.1".parse::<IpAddr>().unwrap(),
+                "2606:4700::1".parse().unwrap(),
+                "104.16.0.2".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prepend_seeds() {
+        let ip = |value: &str| value.parse::<IpAddr>().unwrap();
+        let seeds = vec![ip("104.16.0.9"), ip("104.16.0.2")];
+        let sampled = vec![ip("104.16.0.1"), ip("104.16.0.2"), ip("104.16.0.3")];
+
+        assert_eq!(
+            prepend_seeds(&seeds, sampled),
+            vec![
+                ip("104.16.0.9"),
+                ip("104.16.0.2"),
+                ip("104.16.0.1"),
+                ip("104.16.0.3")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sample_size() {
+        assert_eq!(SampleSize::Count(3).resolve(&ranges()), 3);
+        assert_eq!(SampleSize::Percent(50.0).resolve(&ranges()), 3);
+        assert_eq!(SampleSize::Percent(0.1).resolve(&ranges()), 1);
+        assert_eq!(SampleSize::Percent(100.0).resolve(&ranges()), 6);
+        assert_eq!(SampleSize::Percent(1.0).resolve(&[]), 0);
+
+        assert_eq!(SampleSize::Count(100).to_string(), "100");
+        assert_eq!(SampleSize::Percent(0.5).to_string(), "0.5%");
+    }
+}
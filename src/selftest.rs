@@ -0,0 +1,230 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::net::IpAddr;
+use std::path::Path;
+
+use tokio::net::lookup_host;
+
+use crate::args::SelftestArgs;
+use crate::limits::{open_file_limit, RESERVED_FILE_DESCRIPTORS};
+use crate::network::{check_tls_availability, fetch_cloudflare_ipv4_cidrs, CheckOptions};
+
+/// The outcome of a single diagnostic check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// The check succeeded.
+    Pass,
+    /// The check found something that may cause problems, but not a failure.
+    Warn,
+    /// The check failed.
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        };
+        f.write_str(status)
+    }
+}
+
+/// A single line of the diagnostic report.
+#[derive(Debug)]
+pub struct CheckOutcome {
+    /// Short name of the check.
+    pub name: &'static str,
+    /// Whether the check passed.
+    pub status: CheckStatus,
+    /// Human-readable details, such as the error message of a failed check.
+    pub details: String,
+}
+
+impl CheckOutcome {
+    fn new(name: &'static str, status: CheckStatus, details: impl Into<String>) -> Self {
+        CheckOutcome {
+            name,
+            status,
+            details: details.into(),
+        }
+    }
+}
+
+/// Runs all diagnostic checks and returns their outcomes in a fixed order.
+///
+/// The checks cover connectivity to the Cloudflare API, DNS resolution of the domain, a
+/// reference TLS connection, write permission on the output path (if given) and the open
+/// file limit. Checks never abort the run; failures are reported in the returned outcomes.
+pub async fn run_checks(args: &SelftestArgs) -> Vec<CheckOutcome> {
+    let mut outcomes = vec![check_cloudflare_api().await];
+
+    let (dns_outcome, addresses) = check_dns(&args.domain, args.port).await;
+    outcomes.push(dns_outcome);
+    outcomes.push(check_tls(&addresses, &args.domain, args.port).await);
+
+    if let Some(path) = &args.file_path {
+        outcomes.push(check_write_permission(path));
+    }
+
+    outcomes.push(check_file_limit(args.count));
+
+    outcomes
+}
+
+/// Verifies that Cloudflare's IP list can be fetched.
+async fn check_cloudflare_api() -> CheckOutcome {
+    const NAME: &str = "Cloudflare API";
+
+    match fetch_cloudflare_ipv4_cidrs().await {
+        Ok(cidrs) if cidrs.is_empty() => {
+            CheckOutcome::new(NAME, CheckStatus::Fail, "The API returned no IPv4 ranges")
+        }
+        Ok(cidrs) => CheckOutcome::new(
+            NAME,
+            CheckStatus::Pass,
+            format!("Fetched {} IPv4 ranges", cidrs.len()),
+        ),
+        Err(e) => CheckOutcome::new(NAME, CheckStatus::Fail, format!("{:#}", e)),
+    }
+}
+
+/// Resolves `domain` and returns its IPv4 addresses along with the outcome.
+async fn check_dns(domain: &str, port: u16) -> (CheckOutcome, Vec<IpAddr>) {
+    const NAME: &str = "DNS resolution";
+
+    let addresses: Vec<IpAddr> = match lookup_host((domain, port)).await {
+        Ok(addresses) => addresses
+            .map(|address| address.ip())
+            .filter(IpAddr::is_ipv4)
+            .collect(),
+        Err(e) => {
+            let details = format!("Couldn't resolve {}: {}", domain, e);
+            return (
+                CheckOutcome::new(NAME, CheckStatus::Fail, details),
+                Vec::new(),
+            );
+        }
+    };
+
+    let outcome = if addresses.is_empty() {
+        CheckOutcome::new(
+            NAME,
+            CheckStatus::Fail,
+            format!("{} has no IPv4 address", domain),
+        )
+    } else {
+        let list = addresses
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        CheckOutcome::new(
+            NAME,
+            CheckStatus::Pass,
+            format!("{} resolves to {}", domain, list),
+        )
+    };
+
+    (outcome, addresses)
+}
+
+/// Opens a TLS connection to the resolved addresses of `domain`.
+async fn check_tls(addresses: &[IpAddr], domain: &str, port: u16) -> CheckOutcome {
+    const NAME: &str = "TLS connection";
+
+    if addresses.is_empty() {
+        return CheckOutcome::new(NAME, CheckStatus::Fail, "No address to connect to");
+    }
+
+    let options = CheckOptions::new(1);
+    match check_tls_availability(addresses, domain, &[port], &options).await {
+        Ok(results) => match results.first() {
+            Some(result) => CheckOutcome::new(
+                NAME,
+                CheckStatus::Pass,
+                format!(
+                    "Handshake with {} completed in {} ms",
+                    result.ip, result.latency_ms
+                ),
+            ),
+            None => CheckOutcome::new(
+                NAME,
+                CheckStatus::Fail,
+                format!("No address of {} completed a TLS handshake", domain),
+            ),
+        },
+        Err(e) => CheckOutcome::new(NAME, CheckStatus::Fail, format!("{:#}", e)),
+    }
+}
+
+/// Verifies that the output file can be written, without modifying an existing file.
+fn check_write_permission(path: &str) -> CheckOutcome {
+    const NAME: &str = "Output file";
+
+    let file_path = Path::new(path);
+    let result = if file_path.exists() {
+        OpenOptions::new().append(true).open(file_path).map(drop)
+    } else {
+        File::create(file_path).and_then(|_| fs::remove_file(file_path))
+    };
+
+    match result {
+        Ok(()) => CheckOutcome::new(NAME, CheckStatus::Pass, format!("{} is writable", path)),
+        Err(e) => CheckOutcome::new(
+            NAME,
+            CheckStatus::Fail,
+            format!("Can't write to {}: {}", path, e),
+        ),
+    }
+}
+
+/// Compares the open file limit against the number of concurrent probes of a scan.
+fn check_file_limit(count: usize) -> CheckOutcome {
+    const NAME: &str = "Open file limit";
+
+    match open_file_limit() {
+        Some(limit) if limit < count.saturating_add(RESERVED_FILE_DESCRIPTORS) => CheckOutcome::new(
+            NAME,
+            CheckStatus::Warn,
+            format!(
+                "Limit of {} is too low for {} concurrent probes; raise it with `ulimit -n` or lower --count or --concurrency",
+                limit, count
+            ),
+        ),
+        Some(limit) => CheckOutcome::new(NAME, CheckStatus::Pass, format!("Limit is {}", limit)),
+        None => CheckOutcome::new(NAME, CheckStatus::Pass, "Not limited on this platform"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_write_permission() {
+        let path = std::env::temp_dir().join("cloudsurf_selftest_write_check.txt");
+        let path = path.to_str().unwrap();
+
+        let outcome = check_write_permission(path);
+
+        assert_eq!(outcome.status, CheckStatus::Pass);
+        assert!(
+            !Path::new(path).exists(),
+            "Expected the probe file to be removed"
+        );
+    }
+
+    #[test]
+    fn test_check_write_permission_missing_directory() {
+        let outcome = check_write_permission("/nonexistent-cloudsurf-directory/ips.txt");
+        assert_eq!(outcome.status, CheckStatus::Fail);
+    }
+
+    #[tokio::test]
+    async fn test_check_tls_without_addresses() {
+        let outcome = check_tls(&[], "cloudflare.com", 443).await;
+        assert_eq!(outcome.status, CheckStatus::Fail);
+    }
+}
@@ -0,0 +1,350 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::report::ScanReport;
+
+/// A database the results of each scan are appended to, given with `--output`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputSink {
+    /// A SQLite database file, given as `sqlite://PATH`.
+    Sqlite(String),
+    /// A PostgreSQL database shared by several machines, given as a `postgres://` connection URL.
+    Postgres(String),
+}
+
+impl OutputSink {
+    /// Parses a sink URL such as `sqlite://scans.db` or `postgres://user@host/scans`.
+    pub fn from_url(url: &str) -> Result<Self, String> {
+        match url.split_once("://") {
+            Some(("sqlite", path)) if !path.is_empty() => Ok(OutputSink::Sqlite(path.to_string())),
+            Some(("postgres" | "postgresql", rest)) if !rest.is_empty() => {
+                Ok(OutputSink::Postgres(url.to_string()))
+            }
+            // The URL is not echoed since it may contain a password.
+            _ => Err(
+                "Invalid output, expected a URL such as sqlite://scans.db or postgres://host/scans"
+                    .to_string(),
+            ),
+        }
+    }
+
+    /// The cargo feature this sink requires, which is also the scheme of its URL.
+    pub fn feature(&self) -> &'static str {
+        match self {
+            OutputSink::Sqlite(_) => "sqlite",
+            OutputSink::Postgres(_) => "postgres",
+        }
+    }
+
+    /// Whether this build can write to this sink.
+    pub fn supported(&self) -> bool {
+        match self {
+            OutputSink::Sqlite(_) => cfg!(feature = "sqlite"),
+            OutputSink::Postgres(_) => cfg!(feature = "postgres"),
+        }
+    }
+}
+
+/// The tables results are appended to: one row in `scans` per scan, and one row in `probes` per
+/// valid IP of that scan, in ranking order.
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+const SQLITE_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY,
+    finished_at INTEGER NOT NULL,
+    vantage TEXT,
+    domain TEXT,
+    parameters TEXT,
+    probed INTEGER NOT NULL,
+    valid INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS probes (
+    scan_id INTEGER NOT NULL REFERENCES scans (id),
+    position INTEGER NOT NULL,
+    ip TEXT NOT NULL,
+    port INTEGER,
+    latency_ms INTEGER NOT NULL,
+    smoothed_latency_ms INTEGER,
+    tcp_ms INTEGER,
+    tls_ms INTEGER,
+    speed_mbps REAL,
+    colo TEXT,
+    ray_id TEXT,
+    PRIMARY KEY (scan_id, ip)
+);
+CREATE INDEX IF NOT EXISTS probes_ip ON probes (ip);
+";
+
+/// The PostgreSQL equivalent of `SQLITE_SCHEMA`, with native timestamp, JSON and address types.
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+const POSTGRES_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id BIGSERIAL PRIMARY KEY,
+    finished_at TIMESTAMPTZ NOT NULL,
+    vantage TEXT,
+    domain TEXT,
+    parameters JSONB,
+    probed BIGINT NOT NULL,
+    valid BIGINT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS probes (
+    scan_id BIGINT NOT NULL REFERENCES scans (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    ip INET NOT NULL,
+    port INTEGER,
+    latency_ms BIGINT NOT NULL,
+    smoothed_latency_ms BIGINT,
+    tcp_ms BIGINT,
+    tls_ms BIGINT,
+    speed_mbps DOUBLE PRECISION,
+    colo TEXT,
+    ray_id TEXT,
+    PRIMARY KEY (scan_id, ip)
+);
+CREATE INDEX IF NOT EXISTS probes_ip ON probes (ip);
+CREATE INDEX IF NOT EXISTS scans_vantage ON scans (vantage, finished_at);
+";
+
+/// Appends `report` to `sink`, creating its tables if needed.
+///
+/// `vantage` labels the machine or network the scan ran from, so that the scans of several
+/// machines writing to the same database can be told apart.
+///
+/// # Errors
+///
+/// Returns an error if the database cannot be opened or written to, or if this build does not
+/// support the sink.
+pub async fn write(sink: &OutputSink, report: &ScanReport, vantage: Option<&str>) -> Result<()> {
+    let finished_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    match sink {
+        OutputSink::Sqlite(path) => {
+            let path = path.clone();
+            let report = report.clone();
+            let vantage = vantage.map(str::to_string);
+            tokio::task::spawn_blocking(move || {
+                write_sqlite(&path, &report, finished_at, vantage.as_deref())
+            })
+            .await?
+        }
+        OutputSink::Postgres(url) => write_postgres(url, report, finished_at, vantage).await,
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(
+    path: &str,
+    report: &ScanReport,
+    finished_at: u64,
+    vantage: Option<&str>,
+) -> Result<()> {
+    use anyhow::Context;
+    use rusqlite::{params, Connection};
+
+    let to_sql = |millis: u128| i64::try_from(millis).unwrap_or(i64::MAX);
+
+    let mut connection =
+        Connection::open(path).with_context(|| format!("Couldn't open database {}", path))?;
+    connection
+        .execute_batch(SQLITE_SCHEMA)
+        .with_context(|| format!("Couldn't create the tables of database {}", path))?;
+
+    let transaction = connection.transaction()?;
+    transaction.execute(
+        "INSERT INTO scans (finished_at, vantage, domain, parameters, probed, valid)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            i64::try_from(finished_at).unwrap_or(i64::MAX),
+            vantage,
+            report
+                .parameters
+                .as_ref()
+                .map(|parameters| parameters.domain.as_str()),
+            serde_json::to_string(&report.parameters)?,
+            report.summary.probed as i64,
+            report.summary.valid as i64,
+        ],
+    )?;
+    let scan_id = transaction.last_insert_rowid();
+
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO probes (scan_id, position, ip, port, latency_ms, smoothed_latency_ms,
+                                 tcp_ms, tls_ms, speed_mbps, colo, ray_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        for (position, result) in report.results.iter().enumerate() {
+            insert.execute(params![
+                scan_id,
+                position as i64 + 1,
+                result.ip.to_string(),
+                result.port,
+                to_sql(result.latency_ms),
+                result.smoothed_latency_ms.map(to_sql),
+                result.tcp_ms.map(to_sql),
+                result.tls_ms.map(to_sql),
+                result.speed_mbps,
+                result.colo(),
+                result.ray_id.as_deref(),
+            ])?;
+        }
+    }
+
+    transaction
+        .commit()
+        .with_context(|| format!("Couldn't write to database {}", path))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(
+    _path: &str,
+    _report: &ScanReport,
+    _finished_at: u64,
+    _vantage: Option<&str>,
+) -> Result<()> {
+    anyhow::bail!("SQLite output requires a build with the 'sqlite' feature enabled")
+}
+
+#[cfg(feature = "postgres")]
+async fn write_postgres(
+    url: &str,
+    report: &ScanReport,
+    finished_at: u64,
+    vantage: Option<&str>,
+) -> Result<()> {
+    use anyhow::Context;
+    use sqlx::{Connection, Executor, PgConnection};
+
+    let to_sql = |millis: u128| i64::try_from(millis).unwrap_or(i64::MAX);
+
+    let mut connection = PgConnection::connect(url)
+        .await
+        .context("Couldn't connect to the PostgreSQL database")?;
+    connection
+        .execute(POSTGRES_SCHEMA)
+        .await
+        .context("Couldn't create the tables of the PostgreSQL database")?;
+
+    let mut transaction = connection.begin().await?;
+    let scan_id: i64 = sqlx::query_scalar(
+        "INSERT INTO scans (finished_at, vantage, domain, parameters, probed, valid)
+         VALUES (to_timestamp($1), $2, $3, $4::text::jsonb, $5, $6)
+         RETURNING id",
+    )
+    .bind(finished_at as f64)
+    .bind(vantage)
+    .bind(
+        report
+            .parameters
+            .as_ref()
+            .map(|parameters| parameters.domain.as_str()),
+    )
+    .bind(serde_json::to_string(&report.parameters)?)
+    .bind(report.summary.probed as i64)
+    .bind(report.summary.valid as i64)
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Couldn't write to the PostgreSQL database")?;
+
+    for (position, result) in report.results.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO probes (scan_id, position, ip, port, latency_ms, smoothed_latency_ms,
+                                 tcp_ms, tls_ms, speed_mbps, colo, ray_id)
+             VALUES ($1, $2, $3::text::inet, $4, $5, $6, $7, $8, $9, $10, $11)",
+        )
+        .bind(scan_id)
+        .bind(position as i32 + 1)
+        .bind(result.ip.to_string())
+        .bind(result.port.map(i32::from))
+        .bind(to_sql(result.latency_ms))
+        .bind(result.smoothed_latency_ms.map(to_sql))
+        .bind(result.tcp_ms.map(to_sql))
+        .bind(result.tls_ms.map(to_sql))
+        .bind(result.speed_mbps)
+        .bind(result.colo())
+        .bind(result.ray_id.as_deref())
+        .execute(&mut *transaction)
+        .await
+        .context("Couldn't write to the PostgreSQL database")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Couldn't write to the PostgreSQL database")
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn write_postgres(
+    _url: &str,
+    _report: &ScanReport,
+    _finished_at: u64,
+    _vantage: Option<&str>,
+) -> Result<()> {
+    anyhow::bail!("PostgreSQL output requires a build with the 'postgres' feature enabled")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_url() {
+        assert_eq!(
+            OutputSink::from_url("sqlite://scans.db"),
+            Ok(OutputSink::Sqlite("scans.db".to_string()))
+        );
+        assert_eq!(
+            OutputSink::from_url("sqlite:///var/lib/cloudsurf/scans.db"),
+            Ok(OutputSink::Sqlite(
+                "/var/lib/cloudsurf/scans.db".to_string()
+            ))
+        );
+        assert!(OutputSink::from_url("sqlite://").is_err());
+        assert!(OutputSink::from_url("scans.db").is_err());
+        assert_eq!(
+            OutputSink::from_url("postgres://cloudsurf@db.example.com/scans"),
+            Ok(OutputSink::Postgres(
+                "postgres://cloudsurf@db.example.com/scans".to_string()
+            ))
+        );
+        assert!(OutputSink::from_url("mysql://localhost/scans").is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_write_sqlite() {
+        use std::net::Ipv4Addr;
+
+        use crate::result::ScanResult;
+
+        let path = std::env::temp_dir().join("cloudsurf_sink_test.db");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let sink = OutputSink::Sqlite(path.to_string());
+        let results = vec![
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 1), 50),
+            ScanResult::new(Ipv4Addr::new(104, 16, 0, 2), 70),
+        ];
+        let report = ScanReport::new(None, results, 10);
+
+        write(&sink, &report, Some("home")).await.unwrap();
+        write(&sink, &report, None).await.unwrap();
+
+        let connection = rusqlite::Connection::open(path).unwrap();
+        let count = |table: &str| -> i64 {
+            connection
+                .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(count("scans"), 2);
+        assert_eq!(count("probes"), 4);
+        drop(connection);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::report::ScanSummary;
+
+/// The client metrics are sent with, once `init` was called.
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// Sends metrics to a StatsD server over UDP.
+///
+/// Metrics are fire-and-forget: a send failure is logged and never interrupts the scan.
+struct Client {
+    /// The socket, connected to the server.
+    socket: UdpSocket,
+    /// Prepended to every metric name, followed by a dot.
+    prefix: String,
+}
+
+impl Client {
+    fn send(&self, name: &str, value: impl std::fmt::Display, kind: &str) {
+        let line = format_metric(&self.prefix, name, value, kind);
+        if let Err(e) = self.socket.send(line.as_bytes()) {
+            debug!(error = %e, metric = name, "Couldn't send StatsD metric");
+        }
+    }
+}
+
+/// Starts sending metrics to the StatsD server at `host`, given as `HOST:PORT`.
+///
+/// # Errors
+///
+/// Returns an error if `host` cannot be resolved or no socket can be opened.
+pub fn init(host: &str, prefix: &str) -> Result<()> {
+    let address = host
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addresses| addresses.next())
+        .with_context(|| format!("Couldn't resolve StatsD host {}", host))?;
+    let local = match address {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local).context("Couldn't open a socket for StatsD")?;
+    socket
+        .connect(address)
+        .with_context(|| format!("Couldn't connect to StatsD host {}", host))?;
+
+    let _ = CLIENT.set(Client {
+        socket,
+        prefix: prefix.to_string(),
+    });
+
+    Ok(())
+}
+
+/// Counts a probe as reachable, with its latency, or as unreachable.
+///
+/// Does nothing unless `init` was called.
+pub fn probe(latency_ms: Option<u128>) {
+    let Some(client) = CLIENT.get() else {
+        return;
+    };
+
+    match latency_ms {
+        Some(latency_ms) => {
+            client.send("probes.reachable", 1, "c");
+            client.send("probe.latency", latency_ms, "ms");
+        }
+        None => client.send("probes.unreachable", 1, "c"),
+    }
+}
+
+/// Sends the totals of a finished scan.
+///
+/// Does nothing unless `init` was called.
+pub fn scan_completed(summary: &ScanSummary, duration: Duration) {
+    let Some(client) = CLIENT.get() else {
+        return;
+    };
+
+    client.send("scan.duration", duration.as_millis(), "ms");
+    client.send("scan.valid_ips", summary.valid, "g");
+    if let Some(best_latency_ms) = summary.best_latency_ms {
+        client.send("scan.best_latency", best_latency_ms, "g");
+    }
+}
+
+/// Formats a metric in the StatsD line protocol, e.g. `cloudsurf.probe.latency:52|ms`.
+fn format_metric(prefix: &str, name: &str, value: impl std::fmt::Display, kind: &str) -> String {
+    if prefix.is_empty() {
+        format!("{}:{}|{}", name, value, kind)
+    } else {
+        format!("{}.{}:{}|{}", prefix, name, value, kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_metric() {
+        assert_eq!(
+            format_metric("cloudsurf", "probe.latency", 52, "ms"),
+            "cloudsurf.probe.latency:52|ms"
+        );
+        assert_eq!(
+            format_metric("", "probes.reachable", 1, "c"),
+            "probes.reachable:1|c"
+        );
+    }
+
+    #[test]
+    fn test_client_sends_metrics() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(server.local_addr().unwrap()).unwrap();
+        let client = Client {
+            socket,
+            prefix: "cloudsurf".to_string(),
+        };
+
+        client.send("probes.reachable", 1, "c");
+
+        let mut buffer = [0; 64];
+        let len = server.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], b"cloudsurf.probes.reachable:1|c");
+    }
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::logger::BoxedLayer;
+use crate::report::ScanSummary;
+
+/// Whether this build can export spans and metrics to an OpenTelemetry collector.
+pub const SUPPORTED: bool = cfg!(feature = "otel");
+
+/// The standard environment variable naming the OTLP collector, used without `--otlp-endpoint`.
+pub const ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Spans and metrics are sent to OpenTelemetry collectors under this service name.
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+const SERVICE_NAME: &str = "cloudsurf";
+
+#[cfg(feature = "otel")]
+static METER_PROVIDER: std::sync::OnceLock<opentelemetry_sdk::metrics::SdkMeterProvider> =
+    std::sync::OnceLock::new();
+
+/// Starts exporting spans and metrics to the OTLP collector at `endpoint`, e.g.
+/// `http://localhost:4317`.
+///
+/// Returns the layer forwarding spans to the collector. Every probe runs in its own span, so each
+/// connection attempt shows up in the collector with its IP and timing.
+///
+/// # Errors
+///
+/// Returns an error if the exporters cannot be created, or if this build lacks the `otel` feature.
+#[cfg(feature = "otel")]
+pub fn init(endpoint: &str) -> Result<BoxedLayer> {
+    use anyhow::Context;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+    use tracing_subscriber::Layer;
+
+    let resource = Resource::new([KeyValue::new("service.name", SERVICE_NAME)]);
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(trace::config().with_resource(resource.clone()))
+        .install_batch(runtime::Tokio)
+        .context("Couldn't create the OTLP span exporter")?;
+
+    let meter_provider = opentelemetry_otlp::new_pipeline()
+        .metrics(runtime::Tokio)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_resource(resource)
+        .build()
+        .context("Couldn't create the OTLP metrics exporter")?;
+    opentelemetry::global::set_meter_provider(meter_provider.clone());
+    let _ = METER_PROVIDER.set(meter_provider);
+
+    // Probe spans are at the debug level, which the log filter usually hides, so spans get their
+    // own filter.
+    let filter = Targets::new().with_target("cloudsurf", LevelFilter::DEBUG);
+
+    Ok(tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(filter)
+        .boxed())
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init(_endpoint: &str) -> Result<BoxedLayer> {
+    anyhow::bail!("OpenTelemetry export requires a build with the 'otel' feature enabled")
+}
+
+/// Records the metrics of a finished scan: its duration, the number of probed and valid IPs,
+/// the success rate and the best latency.
+///
+/// Does nothing unless `init` was called.
+#[cfg(feature = "otel")]
+pub fn record_scan(summary: &ScanSummary, duration: Duration) {
+    if METER_PROVIDER.get().is_none() {
+        return;
+    }
+    let meter = opentelemetry::global::meter(SERVICE_NAME);
+
+    meter
+        .f64_histogram("cloudsurf.scan.duration")
+        .with_unit(opentelemetry::metrics::Unit::new("s"))
+        .init()
+        .record(duration.as_secs_f64(), &[]);
+    meter
+        .u64_counter("cloudsurf.probes")
+        .init()
+        .add(summary.probed as u64, &[]);
+    meter
+        .u64_counter("cloudsurf.valid_ips")
+        .init()
+        .add(summary.valid as u64, &[]);
+    if summary.probed > 0 {
+        meter
+            .f64_histogram("cloudsurf.scan.success_rate")
+            .init()
+            .record(summary.valid as f64 / summary.probed as f64, &[]);
+    }
+    if let Some(best_latency_ms) = summary.best_latency_ms {
+        meter
+            .u64_histogram("cloudsurf.scan.best_latency")
+            .with_unit(opentelemetry::metrics::Unit::new("ms"))
+            .init()
+            .record(u64::try_from(best_latency_ms).unwrap_or(u64::MAX), &[]);
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn record_scan(_summary: &ScanSummary, _duration: Duration) {}
+
+/// Flushes the spans and metrics that were not exported yet.
+#[cfg(feature = "otel")]
+pub fn shutdown() {
+    if let Some(meter_provider) = METER_PROVIDER.get() {
+        if let Err(e) = meter_provider.shutdown() {
+            eprintln!("Couldn't flush OpenTelemetry metrics: {}", e);
+        }
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn shutdown() {}
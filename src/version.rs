@@ -0,0 +1,55 @@
+use clap::CommandFactory;
+
+use crate::args::Args;
+
+/// The optional cargo features, and whether this build enables them.
+const FEATURES: [(&str, bool); 12] = [
+    ("age", cfg!(feature = "age")),
+    ("filter-script", cfg!(feature = "filter-script")),
+    ("journald", cfg!(feature = "journald")),
+    ("native-certs", cfg!(feature = "native-certs")),
+    ("notify", cfg!(feature = "notify")),
+    ("otel", cfg!(feature = "otel")),
+    ("parquet", cfg!(feature = "parquet")),
+    ("postgres", cfg!(feature = "postgres")),
+    ("sqlite", cfg!(feature = "sqlite")),
+    ("syslog", cfg!(feature = "syslog")),
+    ("table", cfg!(feature = "table")),
+    ("zstd", cfg!(feature = "zstd")),
+];
+
+/// Returns the version line printed by `cloudsurf version`, as by `--version`.
+pub fn short() -> String {
+    let command = Args::command();
+    format!(
+        "{} {}",
+        command.get_name(),
+        command.get_version().unwrap_or(env!("CARGO_PKG_VERSION"))
+    )
+}
+
+/// Returns the version with the build details useful in bug reports.
+pub fn verbose() -> String {
+    let enabled: Vec<_> = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    let features = match enabled.as_slice() {
+        [] => "none".to_string(),
+        enabled => enabled.join(", "),
+    };
+
+    format!(
+        "{}\n\
+         commit: {}\n\
+         target: {}\n\
+         features: {}\n\
+         rustls: {}",
+        short(),
+        env!("CLOUDSURF_GIT_HASH"),
+        env!("CLOUDSURF_TARGET"),
+        features,
+        env!("CLOUDSURF_RUSTLS_VERSION"),
+    )
+}
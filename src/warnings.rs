@@ -0,0 +1,24 @@
+use std::sync::Mutex;
+
+use tracing::warn;
+
+use crate::report::{Warning, WarningCode};
+
+/// The warnings raised during the current scan, added to its structured report.
+static WARNINGS: Mutex<Vec<Warning>> = Mutex::new(Vec::new());
+
+/// Logs `message` as a warning, and keeps it for the structured report of the scan.
+pub fn record(code: WarningCode, message: impl Into<String>) {
+    let message = message.into();
+    warn!(code = %code, "{}", message);
+    lock().push(Warning { code, message });
+}
+
+/// Returns the warnings recorded so far, leaving none recorded.
+pub fn take() -> Vec<Warning> {
+    std::mem::take(&mut *lock())
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Vec<Warning>> {
+    WARNINGS.lock().expect("warnings lock poisoned")
+}